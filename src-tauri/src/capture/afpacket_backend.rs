@@ -0,0 +1,334 @@
+//! Linux SNIFF backend using an `AF_PACKET` socket.
+//!
+//! A `SOCK_DGRAM` packet socket bound to `ETH_P_ALL` receives a copy of every
+//! frame on every interface with the link-layer header already stripped, so
+//! the payload starts at the IP header just like WinDivert's network layer.
+//!
+//! Direction comes from `sll_pkttype`: `PACKET_OUTGOING` is upload, packets
+//! addressed to this host are download. Any other packet type (e.g. frames
+//! seen in promiscuous mode) is classified by comparing its source and
+//! destination addresses against the host's interface addresses.
+//!
+//! Packets are read one `recvfrom` at a time; at NetGuard's accounting rates a
+//! `TPACKET_V3` ring buffer is not needed.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+
+use crate::capture::backend::{CaptureBackend, CapturedPacket};
+use crate::capture::ip_addresses;
+use crate::capture::linux_sock::{self, is_timeout};
+
+const ETH_P_ALL: u16 = 0x0003;
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86DD;
+
+/// Kernel socket buffer requested for the capture socket. Larger buffers
+/// absorb bursts while the capture thread is busy.
+const SOCKET_RCVBUF_BYTES: libc::c_int = 4 * 1024 * 1024;
+
+/// How long the cached interface address set is trusted before re-reading it.
+const LOCAL_ADDR_REFRESH: Duration = Duration::from_secs(5);
+
+/// A copied packet with its direction already resolved.
+pub struct AfPacketPacket {
+    data: Vec<u8>,
+    outbound: bool,
+}
+
+impl CapturedPacket for AfPacketPacket {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn outbound(&self) -> bool {
+        self.outbound
+    }
+}
+
+/// `AF_PACKET` socket receiving copies of all IPv4/IPv6 traffic.
+pub struct AfPacketBackend {
+    fd: OwnedFd,
+    stopping: AtomicBool,
+    local_addrs: Mutex<(Instant, HashSet<IpAddr>)>,
+}
+
+impl AfPacketBackend {
+    /// Whether `addr` belongs to one of this host's interfaces.
+    fn is_local(&self, addr: &IpAddr) -> bool {
+        let mut cache = self.local_addrs.lock();
+        if cache.0.elapsed() >= LOCAL_ADDR_REFRESH {
+            *cache = (Instant::now(), local_addresses());
+        }
+        cache.1.contains(addr)
+    }
+}
+
+impl CaptureBackend for AfPacketBackend {
+    type Packet = AfPacketPacket;
+
+    const NAME: &'static str = "afpacket";
+
+    fn open_sniff() -> Result<Self> {
+        tracing::info!("Opening AF_PACKET SNIFF socket on all interfaces");
+        let fd = linux_sock::open_socket(
+            libc::AF_PACKET,
+            libc::SOCK_DGRAM,
+            ETH_P_ALL.to_be() as libc::c_int,
+        )
+        .context(
+            "Failed to open AF_PACKET socket. Run as root or grant the binary \
+             CAP_NET_RAW (setcap cap_net_raw,cap_net_admin+ep).",
+        )?;
+        linux_sock::set_recv_timeout(&fd, linux_sock::RECV_TIMEOUT)
+            .context("Failed to set AF_PACKET receive timeout")?;
+
+        // SAFETY: the value is a valid c_int of the size passed.
+        let rc = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVBUF,
+                &SOCKET_RCVBUF_BYTES as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            tracing::debug!(
+                "Failed to enlarge AF_PACKET receive buffer: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+
+        Ok(Self {
+            fd,
+            stopping: AtomicBool::new(false),
+            local_addrs: Mutex::new((Instant::now(), local_addresses())),
+        })
+    }
+
+    /// AF_PACKET only observes traffic; INTERCEPT on Linux uses NFQUEUE.
+    fn open_intercept(_filter: &str) -> Result<Self> {
+        anyhow::bail!("AF_PACKET does not support INTERCEPT mode")
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> Result<Option<Self::Packet>> {
+        loop {
+            if self.stopping.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+
+            // SAFETY: sockaddr_ll is plain old data; all-zero is a valid value.
+            let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+            let mut addr_len = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+            // SAFETY: `buf` and `addr` are valid writable buffers of the sizes passed.
+            let rc = unsafe {
+                libc::recvfrom(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                    &mut addr as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                    &mut addr_len,
+                )
+            };
+            if rc < 0 {
+                let e = std::io::Error::last_os_error();
+                if is_timeout(&e) || e.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e).context("AF_PACKET recv failed");
+            }
+
+            if !matches!(u16::from_be(addr.sll_protocol), ETH_P_IP | ETH_P_IPV6) {
+                continue;
+            }
+            let data = &buf[..(rc as usize).min(buf.len())];
+            if let Some(outbound) = classify_direction(addr.sll_pkttype, data, |a| self.is_local(a))
+            {
+                return Ok(Some(AfPacketPacket {
+                    data: data.to_vec(),
+                    outbound,
+                }));
+            }
+        }
+    }
+
+    /// SNIFF copies are never re-injected.
+    fn reinject(&self, _packet: Self::Packet) -> Result<()> {
+        Ok(())
+    }
+
+    /// Let `recv` return `Ok(None)` on its next timeout.
+    fn shutdown(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Decide whether a packet is outbound (`Some(true)`), inbound (`Some(false)`),
+/// or not to/from this host (`None`).
+fn classify_direction(
+    pkttype: u8,
+    data: &[u8],
+    is_local: impl Fn(&IpAddr) -> bool,
+) -> Option<bool> {
+    match pkttype {
+        libc::PACKET_OUTGOING => Some(true),
+        libc::PACKET_HOST | libc::PACKET_BROADCAST | libc::PACKET_MULTICAST => Some(false),
+        _ => {
+            let (src, dst) = ip_addresses(data)?;
+            if is_local(&src) {
+                Some(true)
+            } else if is_local(&dst) {
+                Some(false)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// All IPv4/IPv6 addresses currently assigned to this host's interfaces.
+fn local_addresses() -> HashSet<IpAddr> {
+    let mut addrs = HashSet::new();
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs fills `ifap` with a list we free below.
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        tracing::warn!("getifaddrs failed: {}", std::io::Error::last_os_error());
+        return addrs;
+    }
+
+    let mut cur = ifap;
+    while !cur.is_null() {
+        // SAFETY: `cur` is a node of the list returned by getifaddrs.
+        let ifa = unsafe { &*cur };
+        if !ifa.ifa_addr.is_null() {
+            // SAFETY: `ifa_addr` is non-null and its family tells us the concrete type.
+            let addr = unsafe {
+                match (*ifa.ifa_addr).sa_family as libc::c_int {
+                    libc::AF_INET => {
+                        let sin = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                        Some(IpAddr::from(sin.sin_addr.s_addr.to_ne_bytes()))
+                    }
+                    libc::AF_INET6 => {
+                        let sin6 = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                        Some(IpAddr::from(sin6.sin6_addr.s6_addr))
+                    }
+                    _ => None,
+                }
+            };
+            addrs.extend(addr);
+        }
+        cur = ifa.ifa_next;
+    }
+
+    // SAFETY: `ifap` came from a successful getifaddrs call.
+    unsafe { libc::freeifaddrs(ifap) };
+    addrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::mod_test_helpers::build_ipv4_packet;
+
+    fn ipv4_between(src: [u8; 4], dst: [u8; 4]) -> Vec<u8> {
+        let mut pkt = build_ipv4_packet(17, 5000, 53);
+        pkt[12..16].copy_from_slice(&src);
+        pkt[16..20].copy_from_slice(&dst);
+        pkt
+    }
+
+    #[test]
+    fn test_classify_direction_from_packet_type() {
+        let pkt = build_ipv4_packet(6, 5000, 443);
+        let nothing_local = |_: &IpAddr| false;
+        assert_eq!(
+            classify_direction(libc::PACKET_OUTGOING, &pkt, nothing_local),
+            Some(true)
+        );
+        assert_eq!(
+            classify_direction(libc::PACKET_HOST, &pkt, nothing_local),
+            Some(false)
+        );
+        assert_eq!(
+            classify_direction(libc::PACKET_BROADCAST, &pkt, nothing_local),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_classify_direction_falls_back_to_local_addresses() {
+        let local: HashSet<IpAddr> = [IpAddr::from([192, 168, 1, 10])].into();
+        let is_local = |a: &IpAddr| local.contains(a);
+
+        let out = ipv4_between([192, 168, 1, 10], [1, 1, 1, 1]);
+        let inbound = ipv4_between([1, 1, 1, 1], [192, 168, 1, 10]);
+        let transit = ipv4_between([10, 0, 0, 1], [10, 0, 0, 2]);
+
+        assert_eq!(
+            classify_direction(libc::PACKET_OTHERHOST, &out, is_local),
+            Some(true)
+        );
+        assert_eq!(
+            classify_direction(libc::PACKET_OTHERHOST, &inbound, is_local),
+            Some(false)
+        );
+        assert_eq!(
+            classify_direction(libc::PACKET_OTHERHOST, &transit, is_local),
+            None
+        );
+    }
+
+    /// Sniff UDP on loopback inside a throwaway network namespace and check
+    /// that both the outgoing and the delivered copy are seen with the right
+    /// direction.
+    ///
+    /// Run with `sudo -E cargo test --lib afpacket -- --ignored`.
+    #[test]
+    #[ignore = "requires root or CAP_NET_RAW + CAP_SYS_ADMIN"]
+    fn test_netns_sniff_loopback_directions() {
+        use std::net::UdpSocket;
+
+        std::thread::spawn(|| {
+            // SAFETY: unshare only affects the calling thread.
+            assert_eq!(unsafe { libc::unshare(libc::CLONE_NEWNET) }, 0);
+            assert!(std::process::Command::new("ip")
+                .args(["link", "set", "lo", "up"])
+                .status()
+                .unwrap()
+                .success());
+
+            let backend = AfPacketBackend::open_sniff().unwrap();
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let sender_port = sender.local_addr().unwrap().port();
+            sender
+                .send_to(b"ping", receiver.local_addr().unwrap())
+                .unwrap();
+
+            let mut buf = vec![0u8; 65535];
+            let mut directions = Vec::new();
+            while directions.len() < 2 {
+                let packet = backend.recv(&mut buf).unwrap().unwrap();
+                if let Some(parsed) = crate::capture::parse_ip_packet(packet.data()) {
+                    if parsed.local_port(true) == Some(sender_port) {
+                        directions.push(packet.outbound());
+                    }
+                }
+            }
+            assert_eq!(directions, vec![true, false]);
+
+            backend.shutdown();
+            assert!(backend.recv(&mut buf).unwrap().is_none());
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+//! Platform-neutral interface between the capture loops and an OS capture API.
+//!
+//! A [`CaptureBackend`] owns the OS handle (WinDivert, NFQUEUE, ...) and exposes
+//! only what the SNIFF and INTERCEPT loops need: receive a packet, re-inject it,
+//! and unblock a pending receive from another thread during shutdown.
+
+use anyhow::Result;
+
+/// A packet handed to the capture loops by a backend.
+pub trait CapturedPacket {
+    /// Raw IP packet bytes, starting at the IPv4/IPv6 header.
+    fn data(&self) -> &[u8];
+
+    /// True if the packet is leaving this host.
+    fn outbound(&self) -> bool;
+}
+
+/// An OS packet capture handle usable by [`run_sniff_loop`] and [`run_intercept_loop`].
+///
+/// The backend is shared between the capture thread (which blocks in `recv`),
+/// the INTERCEPT shaper thread (which calls `reinject`/`discard` for delayed
+/// packets) and the owning [`CaptureEngine`], which calls `shutdown` on drop.
+/// It must therefore be `Send + Sync`, and `shutdown`, `reinject` and
+/// `discard` must be safe to call concurrently with `recv`.
+///
+/// [`run_sniff_loop`]: super::pipeline::run_sniff_loop
+/// [`run_intercept_loop`]: super::pipeline::run_intercept_loop
+/// [`CaptureEngine`]: super::CaptureEngine
+pub trait CaptureBackend: Send + Sync + Sized + 'static {
+    /// Packet type returned by `recv` and accepted by `reinject`.
+    type Packet: CapturedPacket + Send;
+
+    /// Short backend name used for thread names and log messages.
+    const NAME: &'static str;
+
+    /// Size of the scratch buffer the capture loops pass to `recv`.
+    const RECV_BUF_LEN: usize = 65535;
+
+    /// Open a read-only handle that receives copies of all TCP/UDP packets.
+    fn open_sniff() -> Result<Self>;
+
+    /// Open a handle that diverts packets matching `filter` from the network
+    /// stack. Diverted packets are lost unless passed to `reinject`.
+    fn open_intercept(filter: &str) -> Result<Self>;
+
+    /// Block until the next packet arrives. `buf` is scratch space the backend
+    /// may use for the receive call.
+    ///
+    /// Returns `Ok(None)` once `shutdown` has been called.
+    fn recv(&self, buf: &mut [u8]) -> Result<Option<Self::Packet>>;
+
+    /// Return a diverted packet to the network stack (INTERCEPT mode).
+    fn reinject(&self, packet: Self::Packet) -> Result<()>;
+
+    /// Drop a diverted packet (INTERCEPT mode). Backends where an unsent
+    /// packet is simply lost (WinDivert) can rely on the default no-op;
+    /// queue-based backends (NFQUEUE) must issue an explicit drop verdict.
+    fn discard(&self, packet: Self::Packet) -> Result<()> {
+        drop(packet);
+        Ok(())
+    }
+
+    /// Unblock a pending `recv` so the capture thread can exit.
+    /// Must be idempotent and callable from any thread.
+    fn shutdown(&self);
+}
@@ -0,0 +1,456 @@
+//! Rolling "flight recorder" of recent packet headers.
+//!
+//! Every packet seen by the capture loop leaves a small fixed-size record
+//! (addresses, protocol, ports, size, direction, PID, timestamp) in a ring
+//! buffer bounded by a configurable memory budget. When something odd
+//! happens — a threshold-exceeded notification, a stalled download — the
+//! last N seconds can be dumped to JSON, or to pcapng with synthesized
+//! IP/transport headers so the dump opens in Wireshark.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::capture::pcap::PcapngWriter;
+use crate::capture::ParsedPacket;
+use crate::core::process_mapper::{ProcessMapper, Protocol};
+
+/// Header summary of one captured packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct FlightRecord {
+    /// Capture time in microseconds since the Unix epoch.
+    #[ts(type = "number")]
+    pub timestamp_us: i64,
+    pub protocol: Protocol,
+    /// Raw IP protocol number.
+    pub ip_protocol: u8,
+    #[ts(type = "string")]
+    pub src_addr: IpAddr,
+    /// 0 for packets without ports (fragments, other protocols); ICMP echo
+    /// carries its identifier in both ports.
+    pub src_port: u16,
+    #[ts(type = "string")]
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    /// IP packet length in bytes.
+    pub size: u32,
+    pub outbound: bool,
+    /// Process the packet was attributed to, if any.
+    pub pid: Option<u32>,
+}
+
+/// Output format of a flight recorder dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum DumpFormat {
+    Pcapng,
+    Json,
+}
+
+/// Current size and budget of the flight recorder.
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct FlightRecorderStatus {
+    /// Memory budget in bytes (0 = disabled).
+    #[ts(type = "number")]
+    pub max_bytes: u64,
+    /// Number of records the budget allows.
+    #[ts(type = "number")]
+    pub capacity: u64,
+    /// Number of records currently held.
+    #[ts(type = "number")]
+    pub len: u64,
+    /// Timestamp of the oldest record held, in microseconds since the epoch.
+    #[ts(type = "number | null")]
+    pub oldest_timestamp_us: Option<i64>,
+}
+
+struct Ring {
+    records: VecDeque<FlightRecord>,
+    max_bytes: u64,
+}
+
+/// Bounded ring buffer of [`FlightRecord`]s.
+pub struct FlightRecorder {
+    /// Mirror of the ring capacity, so a disabled recorder skips parsing.
+    capacity: AtomicUsize,
+    ring: Mutex<Ring>,
+}
+
+impl FlightRecorder {
+    pub fn new(max_bytes: u64) -> Self {
+        let capacity = capacity_for(max_bytes);
+        Self {
+            capacity: AtomicUsize::new(capacity),
+            ring: Mutex::new(Ring {
+                // Grows on demand up to `capacity`.
+                records: VecDeque::new(),
+                max_bytes,
+            }),
+        }
+    }
+
+    /// Record the header of a packet captured now.
+    pub fn record(&self, pid: Option<u32>, packet: &ParsedPacket, outbound: bool) {
+        if self.capacity.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let timestamp_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as i64;
+        self.record_at(timestamp_us, pid, packet, outbound);
+    }
+
+    /// Record the header of a packet captured at `timestamp_us`.
+    pub fn record_at(
+        &self,
+        timestamp_us: i64,
+        pid: Option<u32>,
+        packet: &ParsedPacket,
+        outbound: bool,
+    ) {
+        let (src_port, dst_port) = packet.ports.unwrap_or((0, 0));
+        let record = FlightRecord {
+            timestamp_us,
+            protocol: packet.protocol,
+            ip_protocol: packet.ip_protocol,
+            src_addr: packet.src_addr,
+            src_port,
+            dst_addr: packet.dst_addr,
+            dst_port,
+            size: packet.total_len.min(u32::MAX as u64) as u32,
+            outbound,
+            pid,
+        };
+
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        let mut ring = self.ring.lock();
+        if capacity == 0 {
+            return;
+        }
+        if ring.records.len() >= capacity {
+            ring.records.pop_front();
+        }
+        ring.records.push_back(record);
+    }
+
+    /// Change the memory budget, evicting the oldest records if it shrank.
+    /// A budget of 0 disables recording and frees the buffer.
+    pub fn set_max_bytes(&self, max_bytes: u64) {
+        let capacity = capacity_for(max_bytes);
+        let mut ring = self.ring.lock();
+        ring.max_bytes = max_bytes;
+        let excess = ring.records.len().saturating_sub(capacity);
+        ring.records.drain(..excess);
+        ring.records.shrink_to(capacity);
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn status(&self) -> FlightRecorderStatus {
+        let ring = self.ring.lock();
+        FlightRecorderStatus {
+            max_bytes: ring.max_bytes,
+            capacity: self.capacity.load(Ordering::Relaxed) as u64,
+            len: ring.records.len() as u64,
+            oldest_timestamp_us: ring.records.front().map(|r| r.timestamp_us),
+        }
+    }
+
+    /// Copy of every record at or after `since_us`, oldest first.
+    pub fn records_since(&self, since_us: i64) -> Vec<FlightRecord> {
+        let ring = self.ring.lock();
+        let start = ring.records.partition_point(|r| r.timestamp_us < since_us);
+        ring.records.range(start..).copied().collect()
+    }
+
+    /// Write the records of the last `window` to `path` in `format`.
+    /// Returns the number of records written.
+    pub fn dump_last(
+        &self,
+        mapper: &ProcessMapper,
+        window: Duration,
+        format: DumpFormat,
+        path: &Path,
+    ) -> Result<usize> {
+        let now_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as i64;
+        let since_us = now_us.saturating_sub(window.as_micros() as i64);
+        self.dump(mapper, since_us, format, path)
+    }
+
+    /// Write every record at or after `since_us` to `path` in `format`.
+    /// Returns the number of records written.
+    ///
+    /// pcapng dumps annotate each packet with its process name when `mapper`
+    /// still knows the PID.
+    pub fn dump(
+        &self,
+        mapper: &ProcessMapper,
+        since_us: i64,
+        format: DumpFormat,
+        path: &Path,
+    ) -> Result<usize> {
+        let records = self.records_since(since_us);
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create dump file {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+
+        match format {
+            DumpFormat::Json => {
+                serde_json::to_writer_pretty(&mut out, &records)?;
+                out.flush()?;
+            }
+            DumpFormat::Pcapng => {
+                let mut writer = PcapngWriter::new(out)?;
+                for record in &records {
+                    let comment = record.pid.map(|pid| match mapper.get_process_info(pid) {
+                        Some(info) => format!("{} (PID {pid})", info.name),
+                        None => format!("PID {pid}"),
+                    });
+                    writer.write_packet(
+                        record.timestamp_us,
+                        &synthesize_headers(record),
+                        record.size as usize,
+                        record.outbound,
+                        comment.as_deref(),
+                    )?;
+                }
+                writer.finish()?;
+            }
+        }
+
+        tracing::info!(
+            "Flight recorder dumped {} records to {}",
+            records.len(),
+            path.display()
+        );
+        Ok(records.len())
+    }
+}
+
+/// Number of records that fit in `max_bytes`.
+fn capacity_for(max_bytes: u64) -> usize {
+    (max_bytes / std::mem::size_of::<FlightRecord>() as u64) as usize
+}
+
+/// Rebuild minimal IP and TCP/UDP headers for a record. Payload bytes are not
+/// kept, so the frame is written truncated with the original length. Other
+/// protocols get the IP header only.
+fn synthesize_headers(record: &FlightRecord) -> Vec<u8> {
+    let proto_byte = record.ip_protocol;
+    let transport_len = match record.protocol {
+        Protocol::Tcp => 20,
+        Protocol::Udp => 8,
+        _ => 0,
+    };
+
+    let mut frame = match (record.src_addr, record.dst_addr) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let total_len = record.size.clamp(20, u16::MAX as u32) as u16;
+            let mut ip = vec![0u8; 20];
+            ip[0] = 0x45;
+            ip[2..4].copy_from_slice(&total_len.to_be_bytes());
+            ip[8] = 64; // TTL
+            ip[9] = proto_byte;
+            ip[12..16].copy_from_slice(&src.octets());
+            ip[16..20].copy_from_slice(&dst.octets());
+            ip
+        }
+        (src, dst) => {
+            let to_v6 = |a: IpAddr| match a {
+                IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+                IpAddr::V6(v6) => v6,
+            };
+            let payload_len = record.size.saturating_sub(40).min(u16::MAX as u32) as u16;
+            let mut ip = vec![0u8; 40];
+            ip[0] = 0x60;
+            ip[4..6].copy_from_slice(&payload_len.to_be_bytes());
+            ip[6] = proto_byte;
+            ip[7] = 64; // hop limit
+            ip[8..24].copy_from_slice(&to_v6(src).octets());
+            ip[24..40].copy_from_slice(&to_v6(dst).octets());
+            ip
+        }
+    };
+
+    let ip_len = frame.len();
+    if transport_len == 0 {
+        return frame;
+    }
+    frame.resize(ip_len + transport_len, 0);
+    let transport = &mut frame[ip_len..];
+    transport[0..2].copy_from_slice(&record.src_port.to_be_bytes());
+    transport[2..4].copy_from_slice(&record.dst_port.to_be_bytes());
+    match record.protocol {
+        Protocol::Tcp => transport[12] = 5 << 4, // data offset: 5 words
+        _ => {
+            let udp_len = record
+                .size
+                .saturating_sub(ip_len as u32)
+                .min(u16::MAX as u32) as u16;
+            transport[4..6].copy_from_slice(&udp_len.to_be_bytes());
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::mod_test_helpers::build_ipv4_packet;
+    use crate::capture::parse_ip_packet;
+    use crate::capture::pcap::{CaptureFileReader, LINKTYPE_RAW};
+
+    const T0: i64 = 1_700_000_000_000_000;
+
+    fn parsed(data: &[u8]) -> ParsedPacket {
+        parse_ip_packet(data).unwrap()
+    }
+
+    fn record_size() -> u64 {
+        std::mem::size_of::<FlightRecord>() as u64
+    }
+
+    #[test]
+    fn test_ring_evicts_oldest_at_capacity() {
+        let recorder = FlightRecorder::new(3 * record_size());
+        let pkt = build_ipv4_packet(6, 5000, 443);
+        for i in 0..5 {
+            recorder.record_at(T0 + i, Some(42), &parsed(&pkt), true);
+        }
+
+        let records = recorder.records_since(0);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].timestamp_us, T0 + 2);
+        assert_eq!(records[2].pid, Some(42));
+        assert_eq!(records[2].src_port, 5000);
+        assert_eq!(records[2].size, 24);
+    }
+
+    #[test]
+    fn test_records_since_filters_by_time() {
+        let recorder = FlightRecorder::new(1 << 20);
+        let pkt = build_ipv4_packet(17, 5000, 53);
+        for secs in 0..10 {
+            recorder.record_at(T0 + secs * 1_000_000, None, &parsed(&pkt), false);
+        }
+        assert_eq!(recorder.records_since(T0 + 7_000_000).len(), 3);
+        assert!(recorder.records_since(T0 + 20_000_000).is_empty());
+    }
+
+    #[test]
+    fn test_set_max_bytes_shrinks_and_disables() {
+        let recorder = FlightRecorder::new(10 * record_size());
+        let pkt = build_ipv4_packet(6, 5000, 443);
+        for i in 0..10 {
+            recorder.record_at(T0 + i, None, &parsed(&pkt), true);
+        }
+
+        recorder.set_max_bytes(4 * record_size());
+        let status = recorder.status();
+        assert_eq!(status.capacity, 4);
+        assert_eq!(status.len, 4);
+        assert_eq!(status.oldest_timestamp_us, Some(T0 + 6));
+
+        recorder.set_max_bytes(0);
+        recorder.record(None, &parsed(&pkt), true);
+        assert_eq!(recorder.status().len, 0);
+    }
+
+    #[test]
+    fn test_portless_packets_are_recorded() {
+        let recorder = FlightRecorder::new(1 << 20);
+        // Non-echo ICMP (destination unreachable) and GRE.
+        recorder.record_at(T0, None, &parsed(&build_ipv4_packet(1, 0x0300, 0)), false);
+        recorder.record_at(T0 + 1, None, &parsed(&build_ipv4_packet(47, 0, 0)), true);
+
+        let records = recorder.records_since(0);
+        let summary: Vec<_> = records
+            .iter()
+            .map(|r| (r.protocol, r.ip_protocol, r.src_port, r.dst_port))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(Protocol::Icmp, 1, 0, 0), (Protocol::Other, 47, 0, 0)]
+        );
+
+        let frame = synthesize_headers(&records[1]);
+        let parsed = parse_ip_packet(&frame).unwrap();
+        assert_eq!((parsed.protocol, parsed.ip_protocol), (Protocol::Other, 47));
+    }
+
+    #[test]
+    fn test_pcapng_dump_synthesizes_parseable_headers() {
+        let path =
+            std::env::temp_dir().join(format!("netguard-flight-{}.pcapng", std::process::id()));
+        let recorder = FlightRecorder::new(1 << 20);
+        let mut pkt = build_ipv4_packet(6, 5000, 443);
+        pkt[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        pkt[16..20].copy_from_slice(&[93, 184, 216, 34]);
+        pkt[2..4].copy_from_slice(&1500u16.to_be_bytes());
+        recorder.record_at(T0, Some(42), &parsed(&pkt), true);
+        recorder.record_at(
+            T0 + 1,
+            None,
+            &parsed(&build_ipv4_packet(17, 53, 6000)),
+            false,
+        );
+
+        let mapper = ProcessMapper::new();
+        let written = recorder
+            .dump(&mapper, T0, DumpFormat::Pcapng, &path)
+            .unwrap();
+        assert_eq!(written, 2);
+
+        let frames: Vec<_> = CaptureFileReader::open(&path)
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].linktype, LINKTYPE_RAW);
+        assert_eq!(frames[0].timestamp_us, T0);
+        let first = parse_ip_packet(&frames[0].frame).unwrap();
+        assert_eq!(first.protocol, Protocol::Tcp);
+        assert_eq!(first.ports, Some((5000, 443)));
+        assert_eq!(first.total_len, 1500);
+        assert_eq!(first.dst_addr, "93.184.216.34".parse::<IpAddr>().unwrap());
+        let second = parse_ip_packet(&frames[1].frame).unwrap();
+        assert_eq!(
+            (second.protocol, second.ports),
+            (Protocol::Udp, Some((53, 6000)))
+        );
+    }
+
+    #[test]
+    fn test_json_dump_lists_records() {
+        let path =
+            std::env::temp_dir().join(format!("netguard-flight-{}.json", std::process::id()));
+        let recorder = FlightRecorder::new(1 << 20);
+        recorder.record_at(T0, Some(7), &parsed(&build_ipv4_packet(17, 5000, 53)), true);
+
+        recorder
+            .dump(&ProcessMapper::new(), 0, DumpFormat::Json, &path)
+            .unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(json[0]["protocol"], "Udp");
+        assert_eq!(json[0]["dst_port"], 53);
+        assert_eq!(json[0]["pid"], 7);
+        assert_eq!(json[0]["src_addr"], "0.0.0.0");
+    }
+}
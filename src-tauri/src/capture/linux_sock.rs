@@ -0,0 +1,140 @@
+//! Small `libc` socket and netlink helpers shared by the Linux capture
+//! backends and the sock_diag process mapper source.
+//!
+//! Both backends read from a raw socket with a receive timeout so that a
+//! blocked `recv` notices `CaptureBackend::shutdown` within [`RECV_TIMEOUT`].
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+/// Receive timeout on capture sockets. Bounds how long `recv` takes to
+/// notice `shutdown`.
+pub(crate) const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Size of `struct nlmsghdr`.
+pub(crate) const NLMSG_HDRLEN: usize = 16;
+/// Netlink error / ACK message type.
+pub(crate) const NLMSG_ERROR: u16 = 2;
+
+/// Create a close-on-exec socket.
+pub(crate) fn open_socket(
+    domain: libc::c_int,
+    ty: libc::c_int,
+    protocol: libc::c_int,
+) -> io::Result<OwnedFd> {
+    // SAFETY: plain socket(2) call; the returned fd is owned below.
+    let raw = unsafe { libc::socket(domain, ty | libc::SOCK_CLOEXEC, protocol) };
+    if raw < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `raw` is a freshly created, valid fd that nothing else owns.
+    Ok(unsafe { OwnedFd::from_raw_fd(raw) })
+}
+
+/// Bind `fd` to a `sockaddr_*` struct of type `T`.
+pub(crate) fn bind<T>(fd: &OwnedFd, addr: &T) -> io::Result<()> {
+    // SAFETY: `addr` is a valid sockaddr of the size passed.
+    let rc = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            addr as *const T as *const libc::sockaddr,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Set `SO_RCVTIMEO` so blocking receives return `WouldBlock` after `timeout`.
+pub(crate) fn set_recv_timeout(fd: &OwnedFd, timeout: Duration) -> io::Result<()> {
+    let tv = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+    };
+    // SAFETY: `tv` is a valid timeval of the size passed.
+    let rc = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &tv as *const libc::timeval as *const libc::c_void,
+            std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// True for the error a receive returns when `SO_RCVTIMEO` expires or a
+/// `MSG_DONTWAIT` receive finds nothing queued.
+pub(crate) fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock
+}
+
+/// Send one datagram on a connected or netlink socket.
+pub(crate) fn send(fd: &OwnedFd, msg: &[u8]) -> io::Result<()> {
+    // SAFETY: `msg` is a valid buffer of the length passed.
+    let rc = unsafe {
+        libc::send(
+            fd.as_raw_fd(),
+            msg.as_ptr() as *const libc::c_void,
+            msg.len(),
+            0,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive one datagram into `buf`, returning its (possibly truncated) length.
+pub(crate) fn recv(fd: &OwnedFd, buf: &mut [u8], flags: libc::c_int) -> io::Result<usize> {
+    // SAFETY: `buf` is a valid writable buffer of the length passed.
+    let rc = unsafe {
+        libc::recv(
+            fd.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+            flags,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((rc as usize).min(buf.len()))
+}
+
+pub(crate) fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+pub(crate) fn read_u16_ne(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+pub(crate) fn read_u32_ne(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Iterate the netlink messages in a datagram as `(type, seq, body)` where
+/// `body` starts after the `nlmsghdr`.
+pub(crate) fn netlink_messages(data: &[u8]) -> impl Iterator<Item = (u16, u32, &[u8])> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let len = read_u32_ne(data, offset)? as usize;
+        if len < NLMSG_HDRLEN || offset + len > data.len() {
+            return None;
+        }
+        let msg_type = read_u16_ne(data, offset + 4)?;
+        let seq = read_u32_ne(data, offset + 8)?;
+        let body = &data[offset + NLMSG_HDRLEN..offset + len];
+        offset += align4(len);
+        Some((msg_type, seq, body))
+    })
+}
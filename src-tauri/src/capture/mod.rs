@@ -1,124 +1,201 @@
-//! Windows packet capture backend using WinDivert 2.x.
+//! Packet capture engine with pluggable OS backends.
 //!
 //! Provides [`CaptureEngine`] for SNIFF (read-only) and INTERCEPT (rate-limiting) modes.
-//! Implements `Drop` to release WinDivert handles on panic, preventing network freeze.
-
+//! The engine drives a [`CaptureBackend`] (WinDivert on Windows; AF_PACKET for
+//! SNIFF and NFQUEUE for INTERCEPT on Linux) from a dedicated thread and
+//! implements `Drop` to release the handle on panic, preventing network freeze.
+
+#[cfg(target_os = "linux")]
+pub mod afpacket_backend;
+pub mod backend;
+pub mod flight_recorder;
+#[cfg(target_os = "linux")]
+pub(crate) mod linux_sock;
+#[cfg(target_os = "linux")]
+pub mod nfqueue_backend;
+pub mod pcap;
+pub mod pipeline;
+pub mod recorder;
+pub mod replay;
+pub(crate) mod shaper;
+#[cfg(target_os = "windows")]
 pub mod windivert_backend;
 
+use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use crate::core::flow_table::FlowTable;
 use crate::core::process_mapper::{ProcessMapper, Protocol};
 use crate::core::rate_limiter::RateLimiterManager;
 use crate::core::traffic::TrafficTracker;
 
+pub use backend::CaptureBackend;
+use recorder::PacketRecorder;
+
+/// Default backend for SNIFF mode on this platform.
+#[cfg(target_os = "windows")]
+type SniffBackend = windivert_backend::WinDivertBackend;
+#[cfg(target_os = "linux")]
+type SniffBackend = afpacket_backend::AfPacketBackend;
+
+/// Default backend for INTERCEPT mode on this platform.
+#[cfg(target_os = "windows")]
+type InterceptBackend = windivert_backend::WinDivertBackend;
+#[cfg(target_os = "linux")]
+type InterceptBackend = nfqueue_backend::NfqueueBackend;
+
 /// Manages a background packet capture thread.
 /// Implements Drop to release resources on panic/exit (PRD safety invariant S4).
 ///
-/// Stores the raw WinDivert HANDLE so that `Drop` can call
-/// `WinDivertShutdown` to unblock a blocking `recv()` from another thread.
-/// Without this, the intercept thread keeps diverting packets after stop is
-/// requested, causing total network loss.
+/// Keeps a shutdown hook into the backend so that `Drop` can unblock a
+/// blocking `recv()` from another thread. Without this, the intercept thread
+/// keeps diverting packets after stop is requested, causing total network loss.
 pub struct CaptureEngine {
     shutdown: Arc<AtomicBool>,
-    /// Raw WinDivert HANDLE for cross-thread shutdown.
-    raw_wd_handle: Option<isize>,
+    /// Calls `CaptureBackend::shutdown` on the backend owned by the capture thread.
+    backend_shutdown: Box<dyn Fn() + Send + Sync>,
     capture_thread: Option<std::thread::JoinHandle<()>>,
 }
 
-/// Raw FFI for WinDivertShutdown — the safe wrapper requires `&mut self` which
-/// makes cross-thread shutdown impossible. The C API is explicitly thread-safe.
-mod wd_ffi {
-    pub const WINDIVERT_SHUTDOWN_RECV: u32 = 1;
+impl CaptureEngine {
+    /// Start capturing in SNIFF mode (Phase 1 — zero-risk, read-only copies)
+    /// using the platform's default backend.
+    pub fn start_sniff(
+        process_mapper: Arc<ProcessMapper>,
+        traffic_tracker: Arc<TrafficTracker>,
+        flow_table: Arc<FlowTable>,
+        packet_recorder: Arc<PacketRecorder>,
+    ) -> anyhow::Result<Self> {
+        #[cfg(any(target_os = "windows", target_os = "linux"))]
+        {
+            Self::start_sniff_with::<SniffBackend>(
+                process_mapper,
+                traffic_tracker,
+                flow_table,
+                packet_recorder,
+            )
+        }
 
-    #[link(name = "WinDivert")]
-    extern "system" {
-        pub fn WinDivertShutdown(handle: isize, how: u32) -> i32;
+        #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+        {
+            let _ = (process_mapper, traffic_tracker, flow_table, packet_recorder);
+            anyhow::bail!("No packet capture backend is available on this platform")
+        }
     }
-}
 
-/// Extract the raw WinDivert HANDLE from a `WinDivert<L>` wrapper.
-///
-/// SAFETY: Relies on `handle: HANDLE` (isize) being the first field of `WinDivert<L>`.
-/// Verified against windivert 0.6.0 source. If the crate changes its layout,
-/// the shutdown call will harmlessly fail (WinDivert returns FALSE for invalid
-/// handles) rather than cause UB.
-unsafe fn extract_wd_handle(
-    wd: &windivert::prelude::WinDivert<windivert::layer::NetworkLayer>,
-) -> isize {
-    *(wd as *const _ as *const isize)
-}
+    /// Start capturing in INTERCEPT mode for rate limiting (Phase 2) using the
+    /// platform's default backend.
+    /// `filter` should be a narrow backend filter (e.g. port 5201 only).
+    ///
+    /// **Important:** Stop the SNIFF engine before starting intercept to avoid
+    /// double-counting traffic (both loops call `record_bytes`).
+    pub fn start_intercept(
+        process_mapper: Arc<ProcessMapper>,
+        traffic_tracker: Arc<TrafficTracker>,
+        flow_table: Arc<FlowTable>,
+        rate_limiter: Arc<RateLimiterManager>,
+        packet_recorder: Arc<PacketRecorder>,
+        filter: String,
+    ) -> anyhow::Result<Self> {
+        #[cfg(any(target_os = "windows", target_os = "linux"))]
+        {
+            Self::start_intercept_with::<InterceptBackend>(
+                process_mapper,
+                traffic_tracker,
+                flow_table,
+                rate_limiter,
+                packet_recorder,
+                filter,
+            )
+        }
 
-impl CaptureEngine {
-    /// Start capturing in SNIFF mode (Phase 1 — zero-risk, read-only copies).
+        #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+        {
+            let _ = (
+                process_mapper,
+                traffic_tracker,
+                flow_table,
+                rate_limiter,
+                packet_recorder,
+                filter,
+            );
+            anyhow::bail!("No packet capture backend is available on this platform")
+        }
+    }
+
+    /// Start capturing in SNIFF mode with an explicit backend.
     ///
-    /// The WinDivert handle is created on the calling thread and moved into
-    /// the capture thread, so we can extract the raw HANDLE for clean shutdown.
-    pub fn start_sniff(
+    /// The handle is opened on the calling thread so that open errors are
+    /// returned to the caller instead of being logged from the capture thread.
+    #[cfg_attr(not(any(target_os = "windows", target_os = "linux")), allow(dead_code))]
+    pub fn start_sniff_with<B: CaptureBackend>(
         process_mapper: Arc<ProcessMapper>,
         traffic_tracker: Arc<TrafficTracker>,
+        flow_table: Arc<FlowTable>,
+        packet_recorder: Arc<PacketRecorder>,
     ) -> anyhow::Result<Self> {
+        let backend = Arc::new(B::open_sniff()?);
         let shutdown = Arc::new(AtomicBool::new(false));
         let shutdown_clone = Arc::clone(&shutdown);
-
-        // Create handle here so we can extract the raw HANDLE for shutdown.
-        let wd = windivert_backend::create_sniff_handle()?;
-        let raw_handle = unsafe { extract_wd_handle(&wd) };
+        let backend_clone = Arc::clone(&backend);
 
         let thread = std::thread::Builder::new()
-            .name("windivert-sniff".into())
+            .name(format!("{}-sniff", B::NAME))
             .spawn(move || {
-                if let Err(e) = windivert_backend::run_sniff_loop(
-                    wd,
+                if let Err(e) = pipeline::run_sniff_loop(
+                    &*backend_clone,
                     process_mapper,
                     traffic_tracker,
+                    flow_table,
+                    packet_recorder,
                     shutdown_clone,
                 ) {
-                    tracing::error!("WinDivert SNIFF capture loop exited: {e:#}");
+                    tracing::error!("{} SNIFF capture loop exited: {e:#}", B::NAME);
                 }
             })?;
 
-        tracing::info!("CaptureEngine started in SNIFF mode");
+        tracing::info!("CaptureEngine started in SNIFF mode ({})", B::NAME);
         Ok(Self {
             shutdown,
-            raw_wd_handle: Some(raw_handle),
+            backend_shutdown: Box::new(move || backend.shutdown()),
             capture_thread: Some(thread),
         })
     }
 
-    /// Start capturing in INTERCEPT mode for rate limiting (Phase 2).
-    /// `filter` should be a narrow WinDivert filter (e.g. port 5201 only).
-    ///
-    /// **Important:** Stop the SNIFF engine before starting intercept to avoid
-    /// double-counting traffic (both loops call `record_bytes`).
-    pub fn start_intercept(
+    /// Start capturing in INTERCEPT mode with an explicit backend.
+    #[cfg_attr(not(any(target_os = "windows", target_os = "linux")), allow(dead_code))]
+    pub fn start_intercept_with<B: CaptureBackend>(
         process_mapper: Arc<ProcessMapper>,
         traffic_tracker: Arc<TrafficTracker>,
+        flow_table: Arc<FlowTable>,
         rate_limiter: Arc<RateLimiterManager>,
+        packet_recorder: Arc<PacketRecorder>,
         filter: String,
     ) -> anyhow::Result<Self> {
+        let backend = Arc::new(B::open_intercept(&filter)?);
         let shutdown = Arc::new(AtomicBool::new(false));
         let shutdown_clone = Arc::clone(&shutdown);
-
-        let wd = windivert_backend::create_intercept_handle(&filter)?;
-        let raw_handle = unsafe { extract_wd_handle(&wd) };
+        let backend_clone = Arc::clone(&backend);
 
         let thread = std::thread::Builder::new()
-            .name("windivert-intercept".into())
+            .name(format!("{}-intercept", B::NAME))
             .spawn(move || {
-                windivert_backend::run_intercept_loop(
-                    wd,
+                pipeline::run_intercept_loop(
+                    &*backend_clone,
                     process_mapper,
                     traffic_tracker,
+                    flow_table,
                     rate_limiter,
+                    packet_recorder,
                     shutdown_clone,
                 );
             })?;
 
-        tracing::info!("CaptureEngine started in INTERCEPT mode");
+        tracing::info!("CaptureEngine started in INTERCEPT mode ({})", B::NAME);
         Ok(Self {
             shutdown,
-            raw_wd_handle: Some(raw_handle),
+            backend_shutdown: Box::new(move || backend.shutdown()),
             capture_thread: Some(thread),
         })
     }
@@ -128,13 +205,9 @@ impl Drop for CaptureEngine {
     fn drop(&mut self) {
         self.shutdown.store(true, Ordering::Relaxed);
 
-        // Call WinDivertShutdown to unblock the blocking recv().
+        // Unblock the backend's blocking recv().
         // Without this, the capture thread keeps diverting packets after stop.
-        if let Some(raw) = self.raw_wd_handle {
-            unsafe {
-                wd_ffi::WinDivertShutdown(raw, wd_ffi::WINDIVERT_SHUTDOWN_RECV);
-            }
-        }
+        (self.backend_shutdown)();
 
         // Wait for the capture thread to exit (with timeout).
         if let Some(thread) = self.capture_thread.take() {
@@ -152,47 +225,215 @@ impl Drop for CaptureEngine {
     }
 }
 
-/// Parse an IP packet and extract protocol + src/dst ports.
-/// Returns (protocol, src_port, dst_port, packet_length).
-pub fn parse_ip_packet(data: &[u8]) -> Option<(Protocol, u16, u16, u64)> {
-    if data.is_empty() {
-        return None;
+/// IPv6 extension headers that are walked to find the transport header.
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_AUTH: u8 = 51;
+const IPV6_DEST_OPTS: u8 = 60;
+const IPV6_MOBILITY: u8 = 135;
+const IPV6_HIP: u8 = 139;
+const IPV6_SHIM6: u8 = 140;
+
+/// Upper bound on IPv6 extension headers walked per packet, so a crafted
+/// chain cannot keep the capture thread busy.
+const MAX_IPV6_EXTENSION_HEADERS: usize = 8;
+
+/// Fragmentation details of an IPv4 or IPv6 fragment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragment {
+    /// Identification field (16 bits for IPv4, 32 bits for IPv6).
+    pub id: u32,
+    /// Offset of this fragment's payload in the original datagram, in bytes.
+    pub offset: u16,
+    /// More-fragments flag.
+    pub more: bool,
+}
+
+/// ICMP echo request/reply types, whose identifier stands in for a port.
+const ICMP_ECHO_TYPES: [u8; 2] = [0, 8];
+const ICMPV6_ECHO_TYPES: [u8; 2] = [128, 129];
+
+/// Transport-level view of an IPv4/IPv6 packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedPacket {
+    pub protocol: Protocol,
+    /// Raw IP protocol number (IPv4 protocol / final IPv6 next header).
+    pub ip_protocol: u8,
+    pub src_addr: IpAddr,
+    pub dst_addr: IpAddr,
+    /// `(src_port, dst_port)` for TCP/UDP, `(id, id)` for ICMP echo.
+    /// `None` for other protocols and for non-first fragments, which carry
+    /// no transport header.
+    pub ports: Option<(u16, u16)>,
+    /// IP packet length in bytes, taken from the header.
+    pub total_len: u64,
+    /// Offset of the transport header, past IPv4 options and IPv6 extension headers.
+    pub transport_offset: usize,
+    /// TCP flags byte, when the packet carries a (captured) TCP header.
+    pub tcp_flags: Option<u8>,
+    /// Set when the packet is a fragment of a larger datagram.
+    pub fragment: Option<Fragment>,
+}
+
+impl ParsedPacket {
+    /// The port on this host's side of the connection.
+    pub fn local_port(&self, outbound: bool) -> Option<u16> {
+        let (src_port, dst_port) = self.ports?;
+        Some(if outbound { src_port } else { dst_port })
+    }
+
+    /// The address on this host's side of the connection.
+    pub fn local_addr(&self, outbound: bool) -> IpAddr {
+        if outbound {
+            self.src_addr
+        } else {
+            self.dst_addr
+        }
+    }
+
+    /// `(local, remote)` socket addresses, when the packet carries ports.
+    pub fn endpoints(&self, outbound: bool) -> Option<(SocketAddr, SocketAddr)> {
+        let (src_port, dst_port) = self.ports?;
+        let src = SocketAddr::new(self.src_addr, src_port);
+        let dst = SocketAddr::new(self.dst_addr, dst_port);
+        Some(if outbound { (src, dst) } else { (dst, src) })
     }
+}
 
-    let version = data[0] >> 4;
-    let (protocol_byte, header_len, total_len) = match version {
+/// Parse an IPv4/IPv6 packet.
+///
+/// IPv4 options are skipped via IHL and the IPv6 extension header chain is
+/// walked to the transport header. Any IP protocol is accepted; only TCP,
+/// UDP and ICMP echo get `ports`. Returns `None` for malformed headers and
+/// for TCP/UDP packets whose ports were not captured.
+pub fn parse_ip_packet(data: &[u8]) -> Option<ParsedPacket> {
+    let (src_addr, dst_addr) = ip_addresses(data)?;
+    let (ip_protocol, transport_offset, total_len, fragment) = match data[0] >> 4 {
         4 => {
-            if data.len() < 20 {
+            let ihl = ((data[0] & 0x0F) as usize) * 4;
+            if ihl < 20 {
                 return None;
             }
-            let ihl = ((data[0] & 0x0F) as usize) * 4;
             let total = u16::from_be_bytes([data[2], data[3]]) as u64;
-            (data[9], ihl, total)
+            let flags_offset = u16::from_be_bytes([data[6], data[7]]);
+            let more = flags_offset & 0x2000 != 0;
+            let offset = (flags_offset & 0x1FFF) << 3;
+            let fragment = (more || offset != 0).then(|| Fragment {
+                id: u16::from_be_bytes([data[4], data[5]]) as u32,
+                offset,
+                more,
+            });
+            (data[9], ihl, total, fragment)
         }
         6 => {
-            if data.len() < 40 {
-                return None;
-            }
             let payload_len = u16::from_be_bytes([data[4], data[5]]) as u64;
-            (data[6], 40, payload_len + 40)
+            let (next_header, offset, fragment) = walk_ipv6_extensions(data)?;
+            (next_header, offset, payload_len + 40, fragment)
         }
         _ => return None,
     };
 
-    let proto = match protocol_byte {
-        6 => Protocol::Tcp,
-        17 => Protocol::Udp,
-        _ => return None,
+    let protocol = match (ip_protocol, src_addr) {
+        (6, _) => Protocol::Tcp,
+        (17, _) => Protocol::Udp,
+        (1, IpAddr::V4(_)) => Protocol::Icmp,
+        (58, IpAddr::V6(_)) => Protocol::Icmpv6,
+        _ => Protocol::Other,
     };
 
-    if data.len() < header_len + 4 {
-        return None;
+    let mut parsed = ParsedPacket {
+        protocol,
+        ip_protocol,
+        src_addr,
+        dst_addr,
+        ports: None,
+        total_len,
+        transport_offset,
+        tcp_flags: None,
+        fragment,
+    };
+    if fragment.is_some_and(|f| f.offset != 0) {
+        return Some(parsed);
     }
 
-    let src_port = u16::from_be_bytes([data[header_len], data[header_len + 1]]);
-    let dst_port = u16::from_be_bytes([data[header_len + 2], data[header_len + 3]]);
+    match protocol {
+        Protocol::Tcp | Protocol::Udp => {
+            let transport = data.get(transport_offset..transport_offset + 4)?;
+            parsed.ports = Some((
+                u16::from_be_bytes([transport[0], transport[1]]),
+                u16::from_be_bytes([transport[2], transport[3]]),
+            ));
+            if protocol == Protocol::Tcp {
+                parsed.tcp_flags = data.get(transport_offset + 13).copied();
+            }
+        }
+        Protocol::Icmp | Protocol::Icmpv6 => {
+            let echo_types = if protocol == Protocol::Icmp {
+                ICMP_ECHO_TYPES
+            } else {
+                ICMPV6_ECHO_TYPES
+            };
+            let header = data.get(transport_offset..transport_offset + 6);
+            if let Some(header) = header.filter(|h| echo_types.contains(&h[0])) {
+                let id = u16::from_be_bytes([header[4], header[5]]);
+                parsed.ports = Some((id, id));
+            }
+        }
+        Protocol::Other => {}
+    }
+    Some(parsed)
+}
 
-    Some((proto, src_port, dst_port, total_len))
+/// Follow the IPv6 next-header chain past extension headers.
+/// Returns (upper-layer protocol, its offset, fragment info).
+fn walk_ipv6_extensions(data: &[u8]) -> Option<(u8, usize, Option<Fragment>)> {
+    let mut next_header = data[6];
+    let mut offset = 40;
+    let mut fragment = None;
+
+    for _ in 0..=MAX_IPV6_EXTENSION_HEADERS {
+        let header_len = match next_header {
+            IPV6_FRAGMENT => {
+                let header = data.get(offset..offset + 8)?;
+                let flags_offset = u16::from_be_bytes([header[2], header[3]]);
+                fragment = Some(Fragment {
+                    id: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+                    offset: flags_offset & 0xFFF8,
+                    more: flags_offset & 0x1 != 0,
+                });
+                8
+            }
+            IPV6_AUTH => (*data.get(offset + 1)? as usize + 2) * 4,
+            IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DEST_OPTS | IPV6_MOBILITY | IPV6_HIP
+            | IPV6_SHIM6 => (*data.get(offset + 1)? as usize + 1) * 8,
+            _ => return Some((next_header, offset, fragment)),
+        };
+        next_header = *data.get(offset)?;
+        offset += header_len;
+        // The transport header of a non-first fragment is not in this packet.
+        if fragment.is_some_and(|f| f.offset != 0) {
+            return Some((next_header, offset, fragment));
+        }
+    }
+    None
+}
+
+/// Source and destination addresses of an IPv4/IPv6 packet.
+pub fn ip_addresses(data: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    match data.first()? >> 4 {
+        4 if data.len() >= 20 => {
+            let src: [u8; 4] = data[12..16].try_into().ok()?;
+            let dst: [u8; 4] = data[16..20].try_into().ok()?;
+            Some((src.into(), dst.into()))
+        }
+        6 if data.len() >= 40 => {
+            let src: [u8; 16] = data[8..24].try_into().ok()?;
+            let dst: [u8; 16] = data[24..40].try_into().ok()?;
+            Some((src.into(), dst.into()))
+        }
+        _ => None,
+    }
 }
 
 /// Test helpers shared between capture submodules.
@@ -271,11 +512,11 @@ mod tests {
         let result = parse_ip_packet(&pkt);
         assert!(result.is_some());
 
-        let (proto, src_port, dst_port, length) = result.unwrap();
-        assert_eq!(proto, Protocol::Tcp);
-        assert_eq!(src_port, 12345);
-        assert_eq!(dst_port, 443);
-        assert_eq!(length, 24); // total_length field in the header
+        let parsed = result.unwrap();
+        assert_eq!(parsed.protocol, Protocol::Tcp);
+        assert_eq!(parsed.ports, Some((12345, 443)));
+        assert_eq!(parsed.total_len, 24); // total_length field in the header
+        assert_eq!(parsed.fragment, None);
     }
 
     #[test]
@@ -284,11 +525,10 @@ mod tests {
         let result = parse_ip_packet(&pkt);
         assert!(result.is_some());
 
-        let (proto, src_port, dst_port, length) = result.unwrap();
-        assert_eq!(proto, Protocol::Udp);
-        assert_eq!(src_port, 5353);
-        assert_eq!(dst_port, 53);
-        assert_eq!(length, 24);
+        let parsed = result.unwrap();
+        assert_eq!(parsed.protocol, Protocol::Udp);
+        assert_eq!(parsed.ports, Some((5353, 53)));
+        assert_eq!(parsed.total_len, 24);
     }
 
     #[test]
@@ -297,19 +537,59 @@ mod tests {
         let result = parse_ip_packet(&pkt);
         assert!(result.is_some());
 
-        let (proto, src_port, dst_port, length) = result.unwrap();
-        assert_eq!(proto, Protocol::Tcp);
-        assert_eq!(src_port, 8080);
-        assert_eq!(dst_port, 80);
+        let parsed = result.unwrap();
+        assert_eq!(parsed.protocol, Protocol::Tcp);
+        assert_eq!(parsed.ports, Some((8080, 80)));
+        assert_eq!(parsed.transport_offset, 40);
         // IPv6 total = 40 (header) + payload_len (4) = 44
-        assert_eq!(length, 44);
+        assert_eq!(parsed.total_len, 44);
     }
 
     #[test]
-    fn test_parse_unknown_protocol() {
-        // ICMP = protocol byte 1, which parse_ip_packet does not handle.
-        let pkt = build_ipv4_packet(1, 0, 0);
-        assert!(parse_ip_packet(&pkt).is_none());
+    fn test_parse_other_protocol() {
+        // GRE = protocol byte 47: accounted, but has no ports.
+        let pkt = build_ipv4_packet(47, 0, 0);
+        let parsed = parse_ip_packet(&pkt).unwrap();
+        assert_eq!(parsed.protocol, Protocol::Other);
+        assert_eq!(parsed.ip_protocol, 47);
+        assert_eq!(parsed.ports, None);
+        assert_eq!(parsed.total_len, 24);
+    }
+
+    #[test]
+    fn test_parse_icmp_echo_identifier() {
+        // Echo request (type 8), identifier 0x1234 at transport bytes 4..6.
+        let mut request = build_ipv4_packet(1, 0x0800, 0);
+        request.extend_from_slice(&[0x12, 0x34, 0x00, 0x01]);
+        request[3] = 28;
+        let parsed = parse_ip_packet(&request).unwrap();
+        assert_eq!(parsed.protocol, Protocol::Icmp);
+        assert_eq!(parsed.ports, Some((0x1234, 0x1234)));
+        assert_eq!(parsed.local_port(false), Some(0x1234));
+
+        // Destination unreachable (type 3) is not echo → no identifier.
+        request[20] = 3;
+        assert_eq!(parse_ip_packet(&request).unwrap().ports, None);
+
+        // A truncated echo header is still accounted, just without an id.
+        let short = build_ipv4_packet(1, 0x0800, 0);
+        let parsed = parse_ip_packet(&short).unwrap();
+        assert_eq!((parsed.protocol, parsed.ports), (Protocol::Icmp, None));
+    }
+
+    #[test]
+    fn test_parse_icmpv6_echo_and_neighbour_discovery() {
+        // Echo reply (type 129) with identifier 7.
+        let mut reply = build_ipv6_packet(58, 0x8100, 0);
+        reply.extend_from_slice(&[0x00, 0x07, 0x00, 0x01]);
+        let parsed = parse_ip_packet(&reply).unwrap();
+        assert_eq!(parsed.protocol, Protocol::Icmpv6);
+        assert_eq!(parsed.ports, Some((7, 7)));
+
+        // Neighbour solicitation (type 135).
+        let ns = build_ipv6_packet(58, 0x8700, 0);
+        let parsed = parse_ip_packet(&ns).unwrap();
+        assert_eq!((parsed.protocol, parsed.ports), (Protocol::Icmpv6, None));
     }
 
     #[test]
@@ -326,27 +606,146 @@ mod tests {
         assert!(parse_ip_packet(&pkt).is_none());
     }
 
-    /// Verify that `WinDivert<NetworkLayer>` has sufficient size and alignment
-    /// for safe raw HANDLE extraction via `extract_wd_handle`.
-    ///
-    /// If the `windivert` crate changes its struct layout, this test will fail
-    /// immediately, preventing silent UB in production.
     #[test]
-    fn test_windivert_layout_assumptions() {
-        let wd_size =
-            std::mem::size_of::<windivert::prelude::WinDivert<windivert::layer::NetworkLayer>>();
-        let wd_align =
-            std::mem::align_of::<windivert::prelude::WinDivert<windivert::layer::NetworkLayer>>();
-
-        assert!(
-            wd_size >= std::mem::size_of::<isize>(),
-            "WinDivert<NetworkLayer> size ({wd_size}) must be >= size_of::<isize>() ({})",
-            std::mem::size_of::<isize>()
+    fn test_ip_addresses_ipv6() {
+        let mut pkt = vec![0u8; 40];
+        pkt[0] = 0x60;
+        pkt[23] = 1; // src ::1
+        pkt[24] = 0xfe;
+        pkt[25] = 0x80; // dst fe80::
+        let (src, dst) = ip_addresses(&pkt).unwrap();
+        assert_eq!(src, IpAddr::from(std::net::Ipv6Addr::LOCALHOST));
+        assert_eq!(dst, "fe80::".parse::<IpAddr>().unwrap());
+        assert!(ip_addresses(&pkt[..39]).is_none());
+    }
+
+    #[test]
+    fn test_parse_tcp_flags() {
+        let mut pkt = build_ipv4_packet(6, 5000, 443);
+        pkt.resize(40, 0);
+        pkt[33] = 0x12; // SYN|ACK
+        assert_eq!(parse_ip_packet(&pkt).unwrap().tcp_flags, Some(0x12));
+        // Ports captured but the flags byte is not.
+        let short = build_ipv4_packet(6, 5000, 443);
+        assert_eq!(parse_ip_packet(&short).unwrap().tcp_flags, None);
+        let udp = build_ipv4_packet(17, 5000, 53);
+        assert_eq!(parse_ip_packet(&udp).unwrap().tcp_flags, None);
+    }
+
+    #[test]
+    fn test_parse_ipv4_options() {
+        // IHL 6: 4 bytes of options between the header and the ports.
+        let mut pkt = build_ipv4_packet(17, 0, 0);
+        pkt[0] = 0x46;
+        pkt.splice(20..20, [1, 1, 1, 0]); // NOP, NOP, NOP, EOL
+        pkt[20 + 4..28].copy_from_slice(&[0x13, 0x88, 0x00, 0x35]);
+        pkt[3] = 28;
+
+        let parsed = parse_ip_packet(&pkt).unwrap();
+        assert_eq!(parsed.transport_offset, 24);
+        assert_eq!(parsed.ports, Some((5000, 53)));
+
+        // IHL below the 20-byte minimum is malformed.
+        pkt[0] = 0x44;
+        assert!(parse_ip_packet(&pkt).is_none());
+    }
+
+    #[test]
+    fn test_parse_ipv4_fragments() {
+        // First fragment: MF set, offset 0 — carries the ports.
+        let mut first = build_ipv4_packet(17, 5000, 53);
+        first[4..6].copy_from_slice(&0xBEEFu16.to_be_bytes());
+        first[6] = 0x20;
+        let parsed = parse_ip_packet(&first).unwrap();
+        assert_eq!(parsed.ports, Some((5000, 53)));
+        assert_eq!(
+            parsed.fragment,
+            Some(Fragment {
+                id: 0xBEEF,
+                offset: 0,
+                more: true
+            })
         );
-        assert!(
-            wd_align >= std::mem::align_of::<isize>(),
-            "WinDivert<NetworkLayer> align ({wd_align}) must be >= align_of::<isize>() ({})",
-            std::mem::align_of::<isize>()
+
+        // Last fragment at offset 1480 (185 * 8): payload bytes are not ports.
+        let mut last = build_ipv4_packet(17, 0xAAAA, 0xBBBB);
+        last[4..6].copy_from_slice(&0xBEEFu16.to_be_bytes());
+        last[6..8].copy_from_slice(&185u16.to_be_bytes());
+        let parsed = parse_ip_packet(&last).unwrap();
+        assert_eq!(parsed.protocol, Protocol::Udp);
+        assert_eq!(parsed.ports, None);
+        assert_eq!(parsed.local_port(true), None);
+        assert_eq!(parsed.fragment.unwrap().offset, 1480);
+        assert!(!parsed.fragment.unwrap().more);
+    }
+
+    /// Insert an 8-byte IPv6 extension header of type `header` right after
+    /// the fixed header, keeping the chain and payload length consistent.
+    fn push_ipv6_extension(pkt: &mut Vec<u8>, header: u8, body: [u8; 6]) {
+        let mut ext = vec![pkt[6], 0];
+        ext.extend_from_slice(&body);
+        pkt.splice(40..40, ext);
+        pkt[6] = header;
+        let payload_len = (pkt.len() - 40) as u16;
+        pkt[4..6].copy_from_slice(&payload_len.to_be_bytes());
+    }
+
+    #[test]
+    fn test_parse_ipv6_extension_chain() {
+        let mut pkt = build_ipv6_packet(6, 8080, 443);
+        pkt.resize(60, 0);
+        pkt[53] = 0x02; // SYN
+        pkt[4..6].copy_from_slice(&20u16.to_be_bytes());
+        push_ipv6_extension(&mut pkt, IPV6_DEST_OPTS, [1, 4, 0, 0, 0, 0]);
+        push_ipv6_extension(&mut pkt, IPV6_ROUTING, [0; 6]);
+        push_ipv6_extension(&mut pkt, IPV6_HOP_BY_HOP, [1, 4, 0, 0, 0, 0]);
+
+        let parsed = parse_ip_packet(&pkt).unwrap();
+        assert_eq!(parsed.protocol, Protocol::Tcp);
+        assert_eq!(parsed.transport_offset, 64);
+        assert_eq!(parsed.ports, Some((8080, 443)));
+        assert_eq!(parsed.tcp_flags, Some(0x02));
+        assert_eq!(parsed.total_len, 84);
+        assert_eq!(parsed.fragment, None);
+
+        // A chain cut off mid-header is rejected rather than misread.
+        assert!(parse_ip_packet(&pkt[..44]).is_none());
+    }
+
+    #[test]
+    fn test_parse_ipv6_fragments() {
+        let mut first = build_ipv6_packet(17, 5000, 53);
+        push_ipv6_extension(&mut first, IPV6_FRAGMENT, [0x00, 0x01, 0, 0, 0, 7]);
+        let parsed = parse_ip_packet(&first).unwrap();
+        assert_eq!(parsed.ports, Some((5000, 53)));
+        assert_eq!(
+            parsed.fragment,
+            Some(Fragment {
+                id: 7,
+                offset: 0,
+                more: true
+            })
         );
+
+        let mut rest = build_ipv6_packet(17, 0xAAAA, 0xBBBB);
+        push_ipv6_extension(&mut rest, IPV6_FRAGMENT, [0x05, 0xC8, 0, 0, 0, 7]);
+        let parsed = parse_ip_packet(&rest).unwrap();
+        assert_eq!(parsed.protocol, Protocol::Udp);
+        assert_eq!(parsed.ports, None);
+        assert_eq!(parsed.fragment.unwrap().offset, 1480);
+    }
+
+    #[test]
+    fn test_parse_ipv6_unknown_or_endless_chain() {
+        // No Next Header (59): accounted as another protocol.
+        let pkt = build_ipv6_packet(59, 0, 0);
+        let parsed = parse_ip_packet(&pkt).unwrap();
+        assert_eq!((parsed.protocol, parsed.ports), (Protocol::Other, None));
+
+        let mut looping = build_ipv6_packet(17, 5000, 53);
+        for _ in 0..=MAX_IPV6_EXTENSION_HEADERS {
+            push_ipv6_extension(&mut looping, IPV6_DEST_OPTS, [1, 4, 0, 0, 0, 0]);
+        }
+        assert!(parse_ip_packet(&looping).is_none());
     }
 }
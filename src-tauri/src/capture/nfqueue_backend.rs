@@ -0,0 +1,752 @@
+//! Linux INTERCEPT backend using netfilter NFQUEUE.
+//!
+//! Packets are steered into a netfilter queue by `iptables`/`ip6tables` rules
+//! installed on open, then read from a `NETLINK_NETFILTER` socket. Every packet
+//! must receive a verdict: `reinject` issues `NF_ACCEPT`, `discard` `NF_DROP`.
+//!
+//! Fail-open guarantees (the Linux counterpart of `WinDivertShutdown`):
+//! - Rules use `--queue-bypass`, so if the queue has no listener (app crashed
+//!   or stopped) the kernel accepts packets instead of dropping them.
+//! - The queue is bound with `NFQA_CFG_F_FAIL_OPEN`, so packets that overflow
+//!   the queue are accepted rather than dropped.
+//! - `shutdown` removes the rules first, then unblocks `recv`; dropping the
+//!   backend accepts every packet still waiting in the queue before unbinding.
+//!
+//! The netlink protocol is spoken directly via `libc` so that the socket can
+//! carry a receive timeout, letting `shutdown` unblock `recv` from another thread.
+
+use std::os::fd::OwnedFd;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+
+use crate::capture::backend::{CaptureBackend, CapturedPacket};
+use crate::capture::linux_sock::{
+    self, align4, is_timeout, netlink_messages, read_u16_ne, read_u32_ne, NLMSG_HDRLEN,
+};
+use crate::config::{NFQUEUE_MAX_LEN, NFQUEUE_QUEUE_NUM};
+
+/// Netlink/nfnetlink constants from `<linux/netfilter/nfnetlink_queue.h>`.
+mod nfq_sys {
+    pub const NFNL_SUBSYS_QUEUE: u16 = 3;
+    pub const NFQNL_MSG_PACKET: u16 = 0;
+    pub const NFQNL_MSG_VERDICT: u16 = 1;
+    pub const NFQNL_MSG_CONFIG: u16 = 2;
+
+    pub const NFQA_PACKET_HDR: u16 = 1;
+    pub const NFQA_VERDICT_HDR: u16 = 2;
+    pub const NFQA_PAYLOAD: u16 = 10;
+
+    pub const NFQA_CFG_CMD: u16 = 1;
+    pub const NFQA_CFG_PARAMS: u16 = 2;
+    pub const NFQA_CFG_QUEUE_MAXLEN: u16 = 3;
+    pub const NFQA_CFG_MASK: u16 = 4;
+    pub const NFQA_CFG_FLAGS: u16 = 5;
+    pub const NFQA_CFG_F_FAIL_OPEN: u32 = 1;
+
+    pub const NFQNL_CFG_CMD_BIND: u8 = 1;
+    pub const NFQNL_CFG_CMD_UNBIND: u8 = 2;
+    pub const NFQNL_COPY_PACKET: u8 = 2;
+
+    pub const NF_DROP: u32 = 0;
+    pub const NF_ACCEPT: u32 = 1;
+
+    pub const NF_INET_LOCAL_OUT: u8 = 3;
+    pub const NF_INET_POST_ROUTING: u8 = 4;
+
+    pub const NFGENMSG_LEN: usize = 4;
+    pub const NLA_HDRLEN: usize = 4;
+    pub const NLA_TYPE_MASK: u16 = 0x3FFF;
+}
+
+/// Chains whose traffic is diverted: locally generated and locally delivered,
+/// with the match arguments every rule of the chain gets. Loopback packets
+/// traverse both chains, so they are only queued on the way out, like
+/// WinDivert reports them once as outbound.
+/// Payload bytes the kernel copies into each queued packet message.
+const COPY_RANGE: u32 = 0xFFFF;
+
+/// Receive buffer for one packet message: the copied payload plus the
+/// netlink and nfgenmsg headers and the metadata attributes in front of it,
+/// which take well under 4 KiB.
+const PACKET_MSG_BUF_LEN: usize = COPY_RANGE as usize + 4096;
+
+const CHAINS: [(&str, &[&str]); 2] = [("OUTPUT", &[]), ("INPUT", &["!", "-i", "lo"])];
+
+/// A packet taken from the queue. The kernel holds the original until a
+/// verdict for `id` is sent.
+pub struct NfqueuePacket {
+    id: u32,
+    outbound: bool,
+    data: Vec<u8>,
+}
+
+impl CapturedPacket for NfqueuePacket {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn outbound(&self) -> bool {
+        self.outbound
+    }
+}
+
+/// Netlink socket bound to an NFQUEUE plus the iptables rules feeding it.
+pub struct NfqueueBackend {
+    fd: OwnedFd,
+    queue_num: u16,
+    seq: AtomicU32,
+    stopping: AtomicBool,
+    rules: IptablesRules,
+}
+
+impl NfqueueBackend {
+    /// Open a netlink socket and bind it to `queue_num` in copy-packet mode.
+    fn bind(queue_num: u16) -> Result<Self> {
+        let fd = linux_sock::open_socket(libc::AF_NETLINK, libc::SOCK_RAW, libc::NETLINK_NETFILTER)
+            .context("Failed to open NETLINK_NETFILTER socket")?;
+
+        // SAFETY: sockaddr_nl is plain old data; all-zero is a valid value.
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        linux_sock::bind(&fd, &addr).context("Failed to bind netlink socket")?;
+        linux_sock::set_recv_timeout(&fd, linux_sock::RECV_TIMEOUT)
+            .context("Failed to set netlink receive timeout")?;
+
+        let backend = Self {
+            fd,
+            queue_num,
+            seq: AtomicU32::new(1),
+            stopping: AtomicBool::new(false),
+            rules: IptablesRules::default(),
+        };
+
+        backend
+            .configure(|msg| {
+                msg.attr(
+                    nfq_sys::NFQA_CFG_CMD,
+                    &config_cmd(nfq_sys::NFQNL_CFG_CMD_BIND),
+                )
+            })
+            .with_context(|| {
+                format!("Failed to bind NFQUEUE {queue_num} (is another process using it?)")
+            })?;
+
+        let mut params = COPY_RANGE.to_be_bytes().to_vec();
+        params.push(nfq_sys::NFQNL_COPY_PACKET);
+        let flags = nfq_sys::NFQA_CFG_F_FAIL_OPEN.to_be_bytes();
+        backend
+            .configure(|msg| {
+                msg.attr(nfq_sys::NFQA_CFG_PARAMS, &params)
+                    .attr(
+                        nfq_sys::NFQA_CFG_QUEUE_MAXLEN,
+                        &NFQUEUE_MAX_LEN.to_be_bytes(),
+                    )
+                    .attr(nfq_sys::NFQA_CFG_MASK, &flags)
+                    .attr(nfq_sys::NFQA_CFG_FLAGS, &flags)
+            })
+            .context("Failed to configure NFQUEUE copy mode and fail-open")?;
+
+        Ok(backend)
+    }
+
+    /// Send an `NFQNL_MSG_CONFIG` request and wait for the kernel's ACK.
+    fn configure(&self, build: impl FnOnce(&mut NlMessage) -> &mut NlMessage) -> Result<()> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let mut msg = NlMessage::new(
+            nfq_sys::NFQNL_MSG_CONFIG,
+            (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16,
+            seq,
+            self.queue_num,
+        );
+        build(&mut msg);
+        self.send(msg.finish())?;
+
+        let mut buf = vec![0u8; 8192];
+        // The ACK normally arrives immediately; allow a few receive timeouts.
+        for _ in 0..10 {
+            let len = match self.recv_raw(&mut buf, 0) {
+                Ok(len) => len,
+                Err(e) if is_timeout(&e) => continue,
+                Err(e) => return Err(e).context("Failed to read netlink ACK"),
+            };
+            if let Some(errno) = parse_ack(&buf[..len], seq) {
+                if errno == 0 {
+                    return Ok(());
+                }
+                return Err(std::io::Error::from_raw_os_error(errno).into());
+            }
+        }
+        anyhow::bail!("Timed out waiting for netlink ACK")
+    }
+
+    fn send_verdict(&self, id: u32, verdict: u32) -> Result<()> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let mut hdr = verdict.to_be_bytes().to_vec();
+        hdr.extend_from_slice(&id.to_be_bytes());
+        let mut msg = NlMessage::new(
+            nfq_sys::NFQNL_MSG_VERDICT,
+            libc::NLM_F_REQUEST as u16,
+            seq,
+            self.queue_num,
+        );
+        msg.attr(nfq_sys::NFQA_VERDICT_HDR, &hdr);
+        self.send(msg.finish())
+            .with_context(|| format!("Failed to send verdict for packet {id}"))
+    }
+
+    fn send(&self, msg: &[u8]) -> Result<()> {
+        linux_sock::send(&self.fd, msg)?;
+        Ok(())
+    }
+
+    fn recv_raw(&self, buf: &mut [u8], flags: libc::c_int) -> std::io::Result<usize> {
+        linux_sock::recv(&self.fd, buf, flags)
+    }
+
+    /// Accept every packet still held in the queue without blocking.
+    fn drain_accept_all(&self) {
+        let mut buf = vec![0u8; PACKET_MSG_BUF_LEN];
+        let mut drained = 0usize;
+        while let Ok(len) = self.recv_raw(&mut buf, libc::MSG_DONTWAIT) {
+            let data = &buf[..len];
+            let id = parse_packet_message(data)
+                .map(|packet| packet.id)
+                .or_else(|| truncated_packet_id(data));
+            if let Some(id) = id {
+                let _ = self.send_verdict(id, nfq_sys::NF_ACCEPT);
+                drained += 1;
+            }
+        }
+        if drained > 0 {
+            tracing::info!("Accepted {drained} queued packets while closing NFQUEUE");
+        }
+    }
+}
+
+impl CaptureBackend for NfqueueBackend {
+    type Packet = NfqueuePacket;
+
+    const NAME: &'static str = "nfqueue";
+
+    const RECV_BUF_LEN: usize = PACKET_MSG_BUF_LEN;
+
+    /// NFQUEUE only diverts packets; read-only capture is handled by AF_PACKET.
+    fn open_sniff() -> Result<Self> {
+        anyhow::bail!("NFQUEUE does not support SNIFF mode")
+    }
+
+    /// Bind the queue, then install rules diverting traffic matching `filter`.
+    fn open_intercept(filter: &str) -> Result<Self> {
+        let matches = iptables_matches(filter)?;
+        let backend = Self::bind(NFQUEUE_QUEUE_NUM)?;
+
+        tracing::info!(
+            "Opening NFQUEUE {} INTERCEPT handle with filter: {filter}",
+            backend.queue_num
+        );
+        backend.rules.install(&matches, backend.queue_num)?;
+        Ok(backend)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> Result<Option<Self::Packet>> {
+        loop {
+            if self.stopping.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            match self.recv_raw(buf, 0) {
+                Ok(len) => {
+                    let data = &buf[..len];
+                    if let Some(packet) = parse_packet_message(data) {
+                        return Ok(Some(packet));
+                    }
+                    // A packet cut short by `buf` cannot be judged, but must
+                    // not be left held in the queue.
+                    if let Some(id) = truncated_packet_id(data) {
+                        tracing::warn!(
+                            "NFQUEUE packet {id} did not fit the receive buffer; accepted"
+                        );
+                        self.send_verdict(id, nfq_sys::NF_ACCEPT)?;
+                    }
+                    // Stray ACK/error messages carry no packet; keep reading.
+                }
+                Err(e) if is_timeout(&e) || e.kind() == std::io::ErrorKind::Interrupted => {}
+                // ENOBUFS: the socket buffer overflowed and the kernel dropped
+                // messages for us. Packets in the queue are unaffected.
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    tracing::warn!("NFQUEUE socket buffer overflow, some packets were not seen");
+                }
+                Err(e) => return Err(e).context("NFQUEUE recv failed"),
+            }
+        }
+    }
+
+    fn reinject(&self, packet: Self::Packet) -> Result<()> {
+        self.send_verdict(packet.id, nfq_sys::NF_ACCEPT)
+    }
+
+    fn discard(&self, packet: Self::Packet) -> Result<()> {
+        self.send_verdict(packet.id, nfq_sys::NF_DROP)
+    }
+
+    /// Stop diverting new packets, then let `recv` return `Ok(None)` on its
+    /// next timeout.
+    fn shutdown(&self) {
+        self.rules.remove();
+        self.stopping.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for NfqueueBackend {
+    fn drop(&mut self) {
+        self.rules.remove();
+        self.drain_accept_all();
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let mut msg = NlMessage::new(
+            nfq_sys::NFQNL_MSG_CONFIG,
+            libc::NLM_F_REQUEST as u16,
+            seq,
+            self.queue_num,
+        );
+        msg.attr(
+            nfq_sys::NFQA_CFG_CMD,
+            &config_cmd(nfq_sys::NFQNL_CFG_CMD_UNBIND),
+        );
+        if let Err(e) = self.send(msg.finish()) {
+            tracing::warn!("Failed to unbind NFQUEUE {}: {e:#}", self.queue_num);
+        }
+    }
+}
+
+/// `struct nfqnl_msg_config_cmd` with protocol family 0 (all).
+fn config_cmd(command: u8) -> [u8; 4] {
+    [command, 0, 0, 0]
+}
+
+/// Builder for a single nfnetlink message: `nlmsghdr` + `nfgenmsg` + attributes.
+struct NlMessage {
+    buf: Vec<u8>,
+}
+
+impl NlMessage {
+    fn new(msg_type: u16, flags: u16, seq: u32, queue_num: u16) -> Self {
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(&0u32.to_ne_bytes()); // nlmsg_len, patched in finish()
+        buf.extend_from_slice(&((nfq_sys::NFNL_SUBSYS_QUEUE << 8) | msg_type).to_ne_bytes());
+        buf.extend_from_slice(&flags.to_ne_bytes());
+        buf.extend_from_slice(&seq.to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes()); // nlmsg_pid: kernel
+        buf.push(libc::AF_UNSPEC as u8); // nfgen_family
+        buf.push(0); // NFNETLINK_V0
+        buf.extend_from_slice(&queue_num.to_be_bytes()); // res_id
+        Self { buf }
+    }
+
+    fn attr(&mut self, attr_type: u16, payload: &[u8]) -> &mut Self {
+        let len = (nfq_sys::NLA_HDRLEN + payload.len()) as u16;
+        self.buf.extend_from_slice(&len.to_ne_bytes());
+        self.buf.extend_from_slice(&attr_type.to_ne_bytes());
+        self.buf.extend_from_slice(payload);
+        self.buf.resize(align4(self.buf.len()), 0);
+        self
+    }
+
+    fn finish(&mut self) -> &[u8] {
+        let len = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&len.to_ne_bytes());
+        &self.buf
+    }
+}
+
+/// Find the `NLMSG_ERROR` reply for `seq` and return its errno (0 = ACK).
+fn parse_ack(data: &[u8], seq: u32) -> Option<i32> {
+    netlink_messages(data)
+        .filter(|&(msg_type, msg_seq, _)| msg_type == linux_sock::NLMSG_ERROR && msg_seq == seq)
+        .find_map(|(_, _, body)| {
+            let error = read_u32_ne(body, 0)? as i32;
+            Some(-error)
+        })
+}
+
+/// Netlink message type of a queued packet.
+const PACKET_MSG_TYPE: u16 = (nfq_sys::NFNL_SUBSYS_QUEUE << 8) | nfq_sys::NFQNL_MSG_PACKET;
+
+/// Extract the packet id, hook and payload from an `NFQNL_MSG_PACKET` datagram.
+fn parse_packet_message(data: &[u8]) -> Option<NfqueuePacket> {
+    let (_, _, body) = netlink_messages(data).find(|&(t, _, _)| t == PACKET_MSG_TYPE)?;
+    let (header, payload) = packet_attrs(body);
+
+    let (id, hook) = header?;
+    Some(NfqueuePacket {
+        id,
+        outbound: matches!(
+            hook,
+            nfq_sys::NF_INET_LOCAL_OUT | nfq_sys::NF_INET_POST_ROUTING
+        ),
+        data: payload.unwrap_or_default().to_vec(),
+    })
+}
+
+/// Packet id of an `NFQNL_MSG_PACKET` datagram longer than the buffer it was
+/// received into, if its packet header attribute made it in.
+fn truncated_packet_id(data: &[u8]) -> Option<u32> {
+    let len = read_u32_ne(data, 0)? as usize;
+    if read_u16_ne(data, 4)? != PACKET_MSG_TYPE || len <= data.len() {
+        return None;
+    }
+    let (header, _) = packet_attrs(data.get(NLMSG_HDRLEN..)?);
+    header.map(|(id, _)| id)
+}
+
+/// The `(id, hook)` packet header and the payload attributes of a packet
+/// message body, skipping attributes that run past its end.
+fn packet_attrs(body: &[u8]) -> (Option<(u32, u8)>, Option<&[u8]>) {
+    let mut header = None;
+    let mut payload = None;
+    let mut offset = nfq_sys::NFGENMSG_LEN;
+    while let Some(len) = read_u16_ne(body, offset) {
+        let len = len as usize;
+        if len < nfq_sys::NLA_HDRLEN || offset + len > body.len() {
+            break;
+        }
+        let Some(attr_type) = read_u16_ne(body, offset + 2) else {
+            break;
+        };
+        let attr_type = attr_type & nfq_sys::NLA_TYPE_MASK;
+        let value = &body[offset + nfq_sys::NLA_HDRLEN..offset + len];
+        match attr_type {
+            nfq_sys::NFQA_PACKET_HDR if value.len() >= 7 => {
+                let id = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+                header = Some((id, value[6]));
+            }
+            nfq_sys::NFQA_PAYLOAD => payload = Some(value),
+            _ => {}
+        }
+        offset += align4(len);
+    }
+    (header, payload)
+}
+
+/// Translate an intercept filter into per-rule iptables match arguments.
+///
+/// Supports the subset of WinDivert filter syntax used by NetGuard: `tcp`,
+/// `udp`, and `<proto>.SrcPort == N` / `<proto>.DstPort == N`, joined by `or`.
+fn iptables_matches(filter: &str) -> Result<Vec<Vec<String>>> {
+    let filter = filter.to_ascii_lowercase();
+    filter
+        .split(" or ")
+        .map(|term| {
+            let term = term.trim();
+            if term == "tcp" || term == "udp" {
+                return Ok(vec!["-p".into(), term.into()]);
+            }
+            let parsed = term.split_once("==").and_then(|(field, port)| {
+                let (proto, field) = field.trim().split_once('.')?;
+                let flag = match field {
+                    "srcport" => "--sport",
+                    "dstport" => "--dport",
+                    _ => return None,
+                };
+                let port: u16 = port.trim().parse().ok()?;
+                matches!(proto, "tcp" | "udp")
+                    .then(|| vec!["-p".into(), proto.into(), flag.into(), port.to_string()])
+            });
+            parsed.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unsupported NFQUEUE filter term '{term}' (expected tcp, udp, \
+                     or <tcp|udp>.<SrcPort|DstPort> == <port>, joined by 'or')"
+                )
+            })
+        })
+        .collect()
+}
+
+/// iptables/ip6tables rules installed by this backend, removed on shutdown.
+#[derive(Default)]
+struct IptablesRules {
+    installed: Mutex<Vec<(&'static str, Vec<String>)>>,
+}
+
+impl IptablesRules {
+    /// Insert one NFQUEUE rule per (family, chain, match). IPv4 failures are
+    /// fatal; IPv6 failures are logged since ip6tables may be unavailable.
+    fn install(&self, matches: &[Vec<String>], queue_num: u16) -> Result<()> {
+        for program in ["iptables", "ip6tables"] {
+            for chain in CHAINS {
+                for m in matches {
+                    let spec = rule_spec(chain, m, queue_num);
+
+                    // Clear a stale copy left by a previous crash before inserting.
+                    while run_iptables(program, "-D", &spec).is_ok() {}
+
+                    match run_iptables(program, "-I", &spec) {
+                        Ok(()) => self.installed.lock().push((program, spec)),
+                        Err(e) if program == "ip6tables" => {
+                            tracing::warn!("IPv6 traffic will not be intercepted: {e:#}");
+                        }
+                        Err(e) => {
+                            self.remove();
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete every installed rule. Idempotent.
+    fn remove(&self) {
+        let rules = std::mem::take(&mut *self.installed.lock());
+        for (program, spec) in rules {
+            if let Err(e) = run_iptables(program, "-D", &spec) {
+                tracing::error!("Failed to remove NFQUEUE rule ({program} {spec:?}): {e:#}");
+            }
+        }
+    }
+}
+
+/// Arguments of one NFQUEUE rule, after the `-I`/`-D` action.
+fn rule_spec(
+    (chain, chain_args): (&str, &[&str]),
+    matches: &[String],
+    queue_num: u16,
+) -> Vec<String> {
+    let mut spec = vec![chain.to_string()];
+    spec.extend(chain_args.iter().map(|a| a.to_string()));
+    spec.extend(matches.iter().cloned());
+    spec.extend(
+        [
+            "-j",
+            "NFQUEUE",
+            "--queue-num",
+            &queue_num.to_string(),
+            "--queue-bypass",
+        ]
+        .map(String::from),
+    );
+    spec
+}
+
+fn run_iptables(program: &str, action: &str, spec: &[String]) -> Result<()> {
+    let output = Command::new(program)
+        .arg("-w")
+        .arg(action)
+        .args(spec)
+        .output()
+        .with_context(|| format!("Failed to run {program}"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{program} {action} {} failed: {}",
+            spec.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build an NFQNL_MSG_PACKET datagram as the kernel would send it.
+    fn packet_message(id: u32, hook: u8, payload: &[u8]) -> Vec<u8> {
+        let mut hdr = id.to_be_bytes().to_vec();
+        hdr.extend_from_slice(&0x0800u16.to_be_bytes()); // hw_protocol
+        hdr.push(hook);
+        let mut msg = NlMessage::new(nfq_sys::NFQNL_MSG_PACKET, 0, 0, NFQUEUE_QUEUE_NUM);
+        msg.attr(nfq_sys::NFQA_PACKET_HDR, &hdr)
+            .attr(nfq_sys::NFQA_PAYLOAD, payload);
+        msg.finish().to_vec()
+    }
+
+    #[test]
+    fn test_parse_packet_message_outbound() {
+        let payload = crate::capture::mod_test_helpers::build_ipv4_packet(6, 5000, 443);
+        let data = packet_message(7, nfq_sys::NF_INET_LOCAL_OUT, &payload);
+
+        let packet = parse_packet_message(&data).expect("packet should parse");
+        assert_eq!(packet.id, 7);
+        assert!(packet.outbound());
+        assert_eq!(packet.data(), payload.as_slice());
+    }
+
+    #[test]
+    fn test_parse_packet_message_inbound_hook() {
+        let data = packet_message(9, 1 /* NF_INET_LOCAL_IN */, &[0x45; 21]);
+        let packet = parse_packet_message(&data).unwrap();
+        assert!(!packet.outbound());
+        assert_eq!(packet.data().len(), 21);
+    }
+
+    #[test]
+    fn test_parse_packet_message_rejects_truncated() {
+        let data = packet_message(1, nfq_sys::NF_INET_LOCAL_OUT, &[0x45; 20]);
+        assert!(parse_packet_message(&data[..10]).is_none());
+        assert!(parse_packet_message(&[]).is_none());
+    }
+
+    #[test]
+    fn test_truncated_packet_message_still_yields_its_id() {
+        let payload = [0x45; 200];
+        let data = packet_message(11, nfq_sys::NF_INET_LOCAL_OUT, &payload);
+        let cut = &data[..data.len() - 100];
+
+        assert!(parse_packet_message(cut).is_none());
+        assert_eq!(truncated_packet_id(cut), Some(11));
+        // Complete messages and truncated non-packet messages do not count.
+        assert_eq!(truncated_packet_id(&data), None);
+        assert_eq!(truncated_packet_id(&data[..20]), None);
+        assert!(PACKET_MSG_BUF_LEN > COPY_RANGE as usize + NLMSG_HDRLEN);
+    }
+
+    #[test]
+    fn test_parse_ack() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&36u32.to_ne_bytes());
+        data.extend_from_slice(&linux_sock::NLMSG_ERROR.to_ne_bytes());
+        data.extend_from_slice(&0u16.to_ne_bytes());
+        data.extend_from_slice(&5u32.to_ne_bytes());
+        data.extend_from_slice(&0u32.to_ne_bytes());
+        data.extend_from_slice(&(-libc::EBUSY).to_ne_bytes());
+        data.resize(36, 0);
+
+        assert_eq!(parse_ack(&data, 5), Some(libc::EBUSY));
+        assert_eq!(parse_ack(&data, 6), None);
+    }
+
+    #[test]
+    fn test_nl_message_attribute_padding() {
+        let mut msg = NlMessage::new(nfq_sys::NFQNL_MSG_CONFIG, 0, 1, 3);
+        msg.attr(nfq_sys::NFQA_CFG_PARAMS, &[0, 0, 0xFF, 0xFF, 2]);
+        let bytes = msg.finish();
+        // 16 (nlmsghdr) + 4 (nfgenmsg) + 4 (nla hdr) + 5 payload padded to 8.
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(read_u32_ne(bytes, 0), Some(32));
+        assert_eq!(&bytes[18..20], &3u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_iptables_matches_default_filter() {
+        assert_eq!(
+            iptables_matches("tcp or udp").unwrap(),
+            vec![vec!["-p", "tcp"], vec!["-p", "udp"]]
+        );
+    }
+
+    #[test]
+    fn test_iptables_matches_port_terms() {
+        assert_eq!(
+            iptables_matches("tcp.DstPort == 5201 or udp.SrcPort == 53").unwrap(),
+            vec![
+                vec!["-p", "tcp", "--dport", "5201"],
+                vec!["-p", "udp", "--sport", "53"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loopback_is_queued_on_output_only() {
+        let m = vec!["-p".to_string(), "tcp".to_string()];
+        let [output, input] = CHAINS.map(|chain| rule_spec(chain, &m, 7).join(" "));
+        assert_eq!(
+            output,
+            "OUTPUT -p tcp -j NFQUEUE --queue-num 7 --queue-bypass"
+        );
+        assert_eq!(
+            input,
+            "INPUT ! -i lo -p tcp -j NFQUEUE --queue-num 7 --queue-bypass"
+        );
+    }
+
+    #[test]
+    fn test_iptables_matches_rejects_unsupported() {
+        assert!(iptables_matches("tcp and ip.DstAddr == 1.2.3.4").is_err());
+        assert!(iptables_matches("icmp.DstPort == 1").is_err());
+        assert!(iptables_matches("tcp.DstPort == 70000").is_err());
+    }
+
+    /// End-to-end check in a throwaway network namespace: a blocked socket's
+    /// packets are dropped while intercept is active, other traffic passes,
+    /// and everything flows again once the engine is dropped.
+    ///
+    /// Run with `sudo -E cargo test --lib nfqueue -- --ignored`.
+    #[test]
+    #[ignore = "requires root, iptables and CAP_NET_ADMIN"]
+    fn test_netns_intercept_blocks_and_fails_open() {
+        use crate::capture::recorder::PacketRecorder;
+        use crate::capture::CaptureEngine;
+        use crate::core::flow_table::FlowTable;
+        use crate::core::process_mapper::{port_key, ProcessMapper, Protocol};
+        use crate::core::rate_limiter::RateLimiterManager;
+        use crate::core::traffic::TrafficTracker;
+        use std::net::UdpSocket;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        // Namespaces are per-thread; isolate the test on its own thread so
+        // iptables changes never touch the host namespace.
+        std::thread::spawn(|| {
+            // SAFETY: unshare only affects the calling thread.
+            assert_eq!(unsafe { libc::unshare(libc::CLONE_NEWNET) }, 0);
+            assert!(Command::new("ip")
+                .args(["link", "set", "lo", "up"])
+                .status()
+                .unwrap()
+                .success());
+
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver
+                .set_read_timeout(Some(Duration::from_millis(500)))
+                .unwrap();
+            let blocked = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let allowed = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let dest = receiver.local_addr().unwrap();
+            let mut buf = [0u8; 16];
+
+            let mapper = Arc::new(ProcessMapper::new());
+            let limiter = Arc::new(RateLimiterManager::new());
+            mapper.port_map.insert(
+                port_key(
+                    Protocol::Udp,
+                    blocked.local_addr().unwrap().ip(),
+                    blocked.local_addr().unwrap().port(),
+                ),
+                4242,
+            );
+            limiter.block_process(4242);
+
+            let engine = CaptureEngine::start_intercept_with::<NfqueueBackend>(
+                Arc::clone(&mapper),
+                Arc::new(TrafficTracker::new()),
+                Arc::new(FlowTable::new()),
+                limiter,
+                Arc::new(PacketRecorder::new()),
+                "udp".to_string(),
+            )
+            .unwrap();
+
+            blocked.send_to(b"blocked", dest).unwrap();
+            assert!(
+                receiver.recv_from(&mut buf).is_err(),
+                "blocked packet passed"
+            );
+
+            allowed.send_to(b"allowed", dest).unwrap();
+            let (n, _) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"allowed");
+
+            drop(engine);
+
+            let rules = Command::new("iptables").args(["-S"]).output().unwrap();
+            assert!(!String::from_utf8_lossy(&rules.stdout).contains("NFQUEUE"));
+            blocked.send_to(b"after", dest).unwrap();
+            let (n, _) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"after");
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,736 @@
+//! Minimal reader for libpcap (`.pcap`) and pcapng (`.pcapng`) capture files,
+//! plus a pcapng writer for exporting live packets.
+//!
+//! The reader covers only what offline replay needs: per-packet timestamps,
+//! the interface link type, and the captured frame bytes. [`ip_payload`]
+//! strips the link-layer header of the common link types down to the IP
+//! packet that [`parse_ip_packet`](super::parse_ip_packet) expects.
+//!
+//! [`PcapngWriter`] writes raw IP packets with an optional per-packet comment.
+
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Link types from <https://www.tcpdump.org/linktypes.html> that we can decode.
+pub const LINKTYPE_NULL: u16 = 0;
+pub const LINKTYPE_ETHERNET: u16 = 1;
+pub const LINKTYPE_RAW: u16 = 101;
+pub const LINKTYPE_LINUX_SLL: u16 = 113;
+pub const LINKTYPE_IPV4: u16 = 228;
+pub const LINKTYPE_IPV6: u16 = 229;
+pub const LINKTYPE_LINUX_SLL2: u16 = 276;
+/// `DLT_RAW` values some platforms write instead of `LINKTYPE_RAW`.
+const DLT_RAW_ALIASES: [u16; 2] = [12, 14];
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+
+const PCAPNG_SHB: u32 = 0x0A0D_0D0A;
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_LEGACY_PB: u32 = 0x0000_0002;
+const PCAPNG_SPB: u32 = 0x0000_0003;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_OPT_END: u16 = 0;
+const PCAPNG_OPT_COMMENT: u16 = 1;
+const PCAPNG_OPT_EPB_FLAGS: u16 = 2;
+const PCAPNG_OPT_IF_TSRESOL: u16 = 9;
+
+/// Upper bound on a single record/block, to reject corrupt length fields
+/// before allocating.
+const MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+
+/// One captured frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    /// Capture time in microseconds since the Unix epoch.
+    pub timestamp_us: i64,
+    /// Link type of the interface the frame was captured on.
+    pub linktype: u16,
+    /// Captured bytes, starting at the link-layer header.
+    pub frame: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    linktype: u16,
+    /// Timestamp units per second (`if_tsresol`, default 10^6).
+    ts_units_per_sec: u64,
+}
+
+enum Format {
+    Pcap {
+        big_endian: bool,
+        nanos: bool,
+        linktype: u16,
+    },
+    Pcapng {
+        big_endian: bool,
+        interfaces: Vec<Interface>,
+        last_timestamp_us: i64,
+    },
+}
+
+/// Streaming reader over the frames of a pcap or pcapng file.
+pub struct CaptureFileReader<R> {
+    reader: R,
+    format: Format,
+}
+
+impl CaptureFileReader<BufReader<File>> {
+    /// Open a capture file, detecting pcap vs pcapng from its magic number.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open capture file {}", path.display()))?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> CaptureFileReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .context("Capture file is empty or truncated")?;
+
+        let format = if u32::from_le_bytes(magic) == PCAPNG_SHB {
+            let mut rest = [0u8; 8];
+            reader.read_exact(&mut rest)?;
+            let big_endian = pcapng_big_endian(&rest[4..8])?;
+            let block_len = read_u32(&rest[0..4], big_endian) as usize;
+            skip_block_body(&mut reader, block_len, 12)?;
+            Format::Pcapng {
+                big_endian,
+                interfaces: Vec::new(),
+                last_timestamp_us: 0,
+            }
+        } else {
+            let (big_endian, nanos) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+                (PCAP_MAGIC_MICROS, _) => (false, false),
+                (PCAP_MAGIC_NANOS, _) => (false, true),
+                (_, PCAP_MAGIC_MICROS) => (true, false),
+                (_, PCAP_MAGIC_NANOS) => (true, true),
+                _ => anyhow::bail!("Not a pcap or pcapng file"),
+            };
+            let mut header = [0u8; 20];
+            reader
+                .read_exact(&mut header)
+                .context("Truncated pcap file header")?;
+            Format::Pcap {
+                big_endian,
+                nanos,
+                linktype: read_u32(&header[16..20], big_endian) as u16,
+            }
+        };
+        Ok(Self { reader, format })
+    }
+
+    fn next_pcap(&mut self) -> Result<Option<CaptureRecord>> {
+        let Format::Pcap {
+            big_endian,
+            nanos,
+            linktype,
+        } = self.format
+        else {
+            unreachable!()
+        };
+        let mut header = [0u8; 16];
+        if !read_exact_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        let secs = read_u32(&header[0..4], big_endian) as i64;
+        let frac = read_u32(&header[4..8], big_endian) as i64;
+        let incl_len = read_u32(&header[8..12], big_endian) as usize;
+        let frame = read_vec(&mut self.reader, incl_len)?;
+        let micros = if nanos { frac / 1000 } else { frac };
+        Ok(Some(CaptureRecord {
+            timestamp_us: secs * 1_000_000 + micros,
+            linktype,
+            frame,
+        }))
+    }
+
+    fn next_pcapng(&mut self) -> Result<Option<CaptureRecord>> {
+        loop {
+            let Format::Pcapng {
+                ref mut big_endian,
+                ref mut interfaces,
+                ref mut last_timestamp_us,
+            } = self.format
+            else {
+                unreachable!()
+            };
+
+            let mut head = [0u8; 8];
+            if !read_exact_or_eof(&mut self.reader, &mut head)? {
+                return Ok(None);
+            }
+            let block_type = read_u32(&head[0..4], *big_endian);
+
+            if block_type == PCAPNG_SHB {
+                // A new section may switch byte order and resets interfaces.
+                let mut magic = [0u8; 4];
+                self.reader.read_exact(&mut magic)?;
+                *big_endian = pcapng_big_endian(&magic)?;
+                interfaces.clear();
+                let block_len = read_u32(&head[4..8], *big_endian) as usize;
+                skip_block_body(&mut self.reader, block_len, 12)?;
+                continue;
+            }
+
+            let block_len = read_u32(&head[4..8], *big_endian) as usize;
+            if block_len < 12 || block_len % 4 != 0 {
+                anyhow::bail!("Corrupt pcapng block length {block_len}");
+            }
+            // Body plus the trailing copy of the block length.
+            let body = read_vec(&mut self.reader, block_len - 8)?;
+            let body = &body[..body.len() - 4];
+            let be = *big_endian;
+
+            match block_type {
+                PCAPNG_IDB => {
+                    if body.len() < 8 {
+                        anyhow::bail!("Truncated pcapng interface block");
+                    }
+                    interfaces.push(Interface {
+                        linktype: read_u16(&body[0..2], be),
+                        ts_units_per_sec: if_tsresol(&body[8..], be),
+                    });
+                }
+                PCAPNG_EPB | PCAPNG_LEGACY_PB => {
+                    if body.len() < 20 {
+                        anyhow::bail!("Truncated pcapng packet block");
+                    }
+                    let if_id = if block_type == PCAPNG_EPB {
+                        read_u32(&body[0..4], be) as usize
+                    } else {
+                        read_u16(&body[0..2], be) as usize
+                    };
+                    let iface = *interfaces
+                        .get(if_id)
+                        .with_context(|| format!("Packet references unknown interface {if_id}"))?;
+                    let ts = ((read_u32(&body[4..8], be) as u64) << 32)
+                        | read_u32(&body[8..12], be) as u64;
+                    let cap_len = read_u32(&body[12..16], be) as usize;
+                    let frame = body
+                        .get(20..20 + cap_len)
+                        .context("Truncated pcapng packet data")?;
+                    let timestamp_us =
+                        (ts as u128 * 1_000_000 / iface.ts_units_per_sec as u128) as i64;
+                    *last_timestamp_us = timestamp_us;
+                    return Ok(Some(CaptureRecord {
+                        timestamp_us,
+                        linktype: iface.linktype,
+                        frame: frame.to_vec(),
+                    }));
+                }
+                PCAPNG_SPB => {
+                    // Simple packet blocks carry no timestamp; reuse the last one.
+                    let iface = *interfaces
+                        .first()
+                        .context("Simple packet block without an interface")?;
+                    let orig_len = read_u32(body.get(0..4).context("Truncated block")?, be);
+                    let cap_len = (orig_len as usize).min(body.len() - 4);
+                    return Ok(Some(CaptureRecord {
+                        timestamp_us: *last_timestamp_us,
+                        linktype: iface.linktype,
+                        frame: body[4..4 + cap_len].to_vec(),
+                    }));
+                }
+                // Name resolution, statistics, custom blocks, ...
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for CaptureFileReader<R> {
+    type Item = Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match self.format {
+            Format::Pcap { .. } => self.next_pcap(),
+            Format::Pcapng { .. } => self.next_pcapng(),
+        };
+        result.transpose()
+    }
+}
+
+/// Strip the link-layer header from `frame`, returning the IP packet and,
+/// when the link type records it, whether the frame was outbound.
+///
+/// Returns `None` for unsupported link types and non-IP frames (ARP, ...).
+pub fn ip_payload(linktype: u16, frame: &[u8]) -> Option<(&[u8], Option<bool>)> {
+    const ETHERTYPE_IPV4: u16 = 0x0800;
+    const ETHERTYPE_IPV6: u16 = 0x86DD;
+    const ETHERTYPE_VLAN: [u16; 2] = [0x8100, 0x88A8];
+
+    let is_ip = |ethertype: u16| matches!(ethertype, ETHERTYPE_IPV4 | ETHERTYPE_IPV6);
+    // Linux `sll_pkttype`: 0-2 = to us / broadcast / multicast, 4 = outgoing.
+    let sll_direction = |pkttype: u16| match pkttype {
+        0..=2 => Some(false),
+        4 => Some(true),
+        _ => None,
+    };
+
+    let (payload, direction) = match linktype {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => (frame, None),
+        t if DLT_RAW_ALIASES.contains(&t) => (frame, None),
+        LINKTYPE_NULL => {
+            // Address family in the capturing host's byte order.
+            let family = frame.get(0..4)?;
+            let family = u32::from_le_bytes(family.try_into().ok()?)
+                .min(u32::from_be_bytes(family.try_into().ok()?));
+            // AF_INET = 2; AF_INET6 = 10 (Linux), 24/28/30 (BSDs, macOS).
+            matches!(family, 2 | 10 | 24 | 28 | 30).then_some(())?;
+            (frame.get(4..)?, None)
+        }
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes(frame.get(offset..offset + 2)?.try_into().ok()?);
+            while ETHERTYPE_VLAN.contains(&ethertype) {
+                offset += 4;
+                ethertype = u16::from_be_bytes(frame.get(offset..offset + 2)?.try_into().ok()?);
+            }
+            is_ip(ethertype).then_some(())?;
+            (frame.get(offset + 2..)?, None)
+        }
+        LINKTYPE_LINUX_SLL => {
+            let pkttype = u16::from_be_bytes(frame.get(0..2)?.try_into().ok()?);
+            let ethertype = u16::from_be_bytes(frame.get(14..16)?.try_into().ok()?);
+            is_ip(ethertype).then_some(())?;
+            (frame.get(16..)?, sll_direction(pkttype))
+        }
+        LINKTYPE_LINUX_SLL2 => {
+            let ethertype = u16::from_be_bytes(frame.get(0..2)?.try_into().ok()?);
+            let pkttype = *frame.get(10)? as u16;
+            is_ip(ethertype).then_some(())?;
+            (frame.get(20..)?, sll_direction(pkttype))
+        }
+        _ => return None,
+    };
+    (!payload.is_empty()).then_some((payload, direction))
+}
+
+/// Streaming pcapng writer with a single raw-IP interface (`LINKTYPE_RAW`,
+/// microsecond timestamps). All blocks are written little-endian.
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+    bytes_written: u64,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Write the section header and interface description blocks.
+    pub fn new(writer: W) -> Result<Self> {
+        let mut this = Self {
+            writer,
+            bytes_written: 0,
+        };
+
+        let mut shb = PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        shb.extend_from_slice(&1u16.to_le_bytes()); // major version
+        shb.extend_from_slice(&0u16.to_le_bytes()); // minor version
+        shb.extend_from_slice(&u64::MAX.to_le_bytes()); // section length unknown
+        this.write_block(PCAPNG_SHB, &shb)?;
+
+        let mut idb = LINKTYPE_RAW.to_le_bytes().to_vec();
+        idb.extend_from_slice(&0u16.to_le_bytes()); // reserved
+        idb.extend_from_slice(&0u32.to_le_bytes()); // snaplen: unlimited
+        this.write_block(PCAPNG_IDB, &idb)?;
+
+        Ok(this)
+    }
+
+    /// Size in bytes of the block [`write_packet`](Self::write_packet) would
+    /// write for a packet of `data_len` bytes with `comment`.
+    pub fn packet_block_len(data_len: usize, comment: Option<&str>) -> u64 {
+        let padded = |len: usize| (len + 3) & !3;
+        let comment_opt = comment.map_or(0, |c| 4 + padded(c.len()));
+        // Header + EPB fields, data, epb_flags, comment, opt_endofopt, trailer.
+        (8 + 20 + padded(data_len) + 8 + comment_opt + 4 + 4) as u64
+    }
+
+    /// Append one enhanced packet block. `original_len` is the packet's length
+    /// on the wire (`data.len()` unless `data` is truncated). `outbound` is
+    /// recorded in the `epb_flags` direction bits and `comment` in an
+    /// `opt_comment` option.
+    pub fn write_packet(
+        &mut self,
+        timestamp_us: i64,
+        data: &[u8],
+        original_len: usize,
+        outbound: bool,
+        comment: Option<&str>,
+    ) -> Result<()> {
+        let ts = timestamp_us.max(0) as u64;
+        let mut epb = 0u32.to_le_bytes().to_vec(); // interface id
+        epb.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(ts as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes()); // captured length
+        let original_len = original_len.max(data.len()) as u32;
+        epb.extend_from_slice(&original_len.to_le_bytes());
+        epb.extend_from_slice(data);
+        pad_to_u32(&mut epb);
+
+        // Direction bits: 01 = inbound, 10 = outbound.
+        let flags: u32 = if outbound { 0b10 } else { 0b01 };
+        push_option(&mut epb, PCAPNG_OPT_EPB_FLAGS, &flags.to_le_bytes());
+        if let Some(comment) = comment {
+            push_option(&mut epb, PCAPNG_OPT_COMMENT, comment.as_bytes());
+        }
+        push_option(&mut epb, PCAPNG_OPT_END, &[]);
+
+        self.write_block(PCAPNG_EPB, &epb)
+    }
+
+    /// Total bytes written so far, including the file header blocks.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Flush buffered output and return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.writer.flush().context("Failed to flush pcapng file")?;
+        Ok(self.writer)
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> Result<()> {
+        debug_assert_eq!(body.len() % 4, 0);
+        let total = (12 + body.len()) as u32;
+        self.writer.write_all(&block_type.to_le_bytes())?;
+        self.writer.write_all(&total.to_le_bytes())?;
+        self.writer.write_all(body)?;
+        self.writer.write_all(&total.to_le_bytes())?;
+        self.bytes_written += total as u64;
+        Ok(())
+    }
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad_to_u32(body);
+}
+
+fn pad_to_u32(body: &mut Vec<u8>) {
+    body.resize((body.len() + 3) & !3, 0);
+}
+
+fn pcapng_big_endian(magic: &[u8]) -> Result<bool> {
+    let magic: [u8; 4] = magic.try_into()?;
+    if u32::from_le_bytes(magic) == PCAPNG_BYTE_ORDER_MAGIC {
+        Ok(false)
+    } else if u32::from_be_bytes(magic) == PCAPNG_BYTE_ORDER_MAGIC {
+        Ok(true)
+    } else {
+        anyhow::bail!("Corrupt pcapng section header")
+    }
+}
+
+/// Parse the `if_tsresol` option out of an interface block's options.
+fn if_tsresol(mut options: &[u8], big_endian: bool) -> u64 {
+    const DEFAULT: u64 = 1_000_000;
+    while options.len() >= 4 {
+        let code = read_u16(&options[0..2], big_endian);
+        let len = read_u16(&options[2..4], big_endian) as usize;
+        if code == PCAPNG_OPT_END {
+            break;
+        }
+        let Some(value) = options.get(4..4 + len) else {
+            break;
+        };
+        if code == PCAPNG_OPT_IF_TSRESOL && len == 1 {
+            let resol = value[0];
+            let exp = (resol & 0x7F) as u32;
+            let units = if resol & 0x80 == 0 {
+                10u64.checked_pow(exp)
+            } else {
+                1u64.checked_shl(exp)
+            };
+            return units.filter(|&u| u > 0).unwrap_or(DEFAULT);
+        }
+        options = options.get(4 + ((len + 3) & !3)..).unwrap_or(&[]);
+    }
+    DEFAULT
+}
+
+/// Skip the rest of a block whose first `consumed` bytes were already read.
+fn skip_block_body<R: Read>(reader: &mut R, block_len: usize, consumed: usize) -> Result<()> {
+    if block_len < consumed + 4 || block_len % 4 != 0 {
+        anyhow::bail!("Corrupt pcapng block length {block_len}");
+    }
+    read_vec(reader, block_len - consumed)?;
+    Ok(())
+}
+
+fn read_vec<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>> {
+    if len > MAX_RECORD_LEN {
+        anyhow::bail!("Capture record of {len} bytes exceeds limit");
+    }
+    let mut buf = vec![0u8; len];
+    reader
+        .read_exact(&mut buf)
+        .context("Capture file is truncated")?;
+    Ok(buf)
+}
+
+/// `read_exact` that returns `Ok(false)` on a clean EOF before the first byte.
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => anyhow::bail!("Capture file is truncated"),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+fn read_u16(bytes: &[u8], big_endian: bool) -> u16 {
+    let bytes: [u8; 2] = bytes[..2].try_into().unwrap();
+    if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    }
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes: [u8; 4] = bytes[..4].try_into().unwrap();
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Builders for synthetic capture files, shared with the replay tests.
+#[cfg(test)]
+pub(crate) mod test_files {
+    use super::*;
+
+    /// A little-endian microsecond pcap file containing `packets`.
+    pub fn pcap_file(linktype: u16, packets: &[(i64, Vec<u8>)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&PCAP_MAGIC_MICROS.to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&4u16.to_le_bytes());
+        out.extend_from_slice(&[0u8; 8]); // thiszone + sigfigs
+        out.extend_from_slice(&65535u32.to_le_bytes());
+        out.extend_from_slice(&(linktype as u32).to_le_bytes());
+        for (ts_us, data) in packets {
+            out.extend_from_slice(&((ts_us / 1_000_000) as u32).to_le_bytes());
+            out.extend_from_slice(&((ts_us % 1_000_000) as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    fn block(out: &mut Vec<u8>, block_type: u32, body: &[u8], big_endian: bool) {
+        let u32_bytes = |v: u32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let padded = (body.len() + 3) & !3;
+        let total = (12 + padded) as u32;
+        out.extend_from_slice(&u32_bytes(block_type));
+        out.extend_from_slice(&u32_bytes(total));
+        out.extend_from_slice(body);
+        out.resize(out.len() + padded - body.len(), 0);
+        out.extend_from_slice(&u32_bytes(total));
+    }
+
+    /// A pcapng file with one interface of `linktype` using nanosecond
+    /// resolution, containing one enhanced packet block per packet.
+    pub fn pcapng_file(linktype: u16, packets: &[(i64, Vec<u8>)], big_endian: bool) -> Vec<u8> {
+        let u16_bytes = |v: u16| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let u32_bytes = |v: u32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+
+        let mut out = Vec::new();
+        let mut shb = u32_bytes(PCAPNG_BYTE_ORDER_MAGIC).to_vec();
+        shb.extend_from_slice(&u16_bytes(1));
+        shb.extend_from_slice(&u16_bytes(0));
+        shb.extend_from_slice(&u64::MAX.to_le_bytes()); // section length unknown
+        block(&mut out, PCAPNG_SHB, &shb, big_endian);
+
+        let mut idb = u16_bytes(linktype).to_vec();
+        idb.extend_from_slice(&u16_bytes(0));
+        idb.extend_from_slice(&u32_bytes(0));
+        idb.extend_from_slice(&u16_bytes(PCAPNG_OPT_IF_TSRESOL));
+        idb.extend_from_slice(&u16_bytes(1));
+        idb.extend_from_slice(&[9, 0, 0, 0]); // 10^-9, padded
+        idb.extend_from_slice(&[0, 0, 0, 0]); // opt_endofopt
+        block(&mut out, PCAPNG_IDB, &idb, big_endian);
+
+        for (ts_us, data) in packets {
+            let ts = (*ts_us as u64) * 1000;
+            let mut epb = u32_bytes(0).to_vec();
+            epb.extend_from_slice(&u32_bytes((ts >> 32) as u32));
+            epb.extend_from_slice(&u32_bytes(ts as u32));
+            epb.extend_from_slice(&u32_bytes(data.len() as u32));
+            epb.extend_from_slice(&u32_bytes(data.len() as u32));
+            epb.extend_from_slice(data);
+            block(&mut out, PCAPNG_EPB, &epb, big_endian);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_files::{pcap_file, pcapng_file};
+    use super::*;
+    use crate::capture::mod_test_helpers::build_ipv4_packet;
+
+    fn read_all(bytes: Vec<u8>) -> Vec<CaptureRecord> {
+        CaptureFileReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_pcap_records() {
+        let pkt = build_ipv4_packet(6, 5000, 443);
+        let records = read_all(pcap_file(
+            LINKTYPE_RAW,
+            &[
+                (1_700_000_000_250_000, pkt.clone()),
+                (1_700_000_001_000_000, pkt.clone()),
+            ],
+        ));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp_us, 1_700_000_000_250_000);
+        assert_eq!(records[0].linktype, LINKTYPE_RAW);
+        assert_eq!(records[1].frame, pkt);
+    }
+
+    #[test]
+    fn test_read_pcapng_both_byte_orders() {
+        let pkt = build_ipv4_packet(17, 53, 5353);
+        for big_endian in [false, true] {
+            let records = read_all(pcapng_file(
+                LINKTYPE_RAW,
+                &[(1_700_000_000_123_456, pkt.clone())],
+                big_endian,
+            ));
+            assert_eq!(records.len(), 1, "big_endian={big_endian}");
+            assert_eq!(records[0].timestamp_us, 1_700_000_000_123_456);
+            assert_eq!(records[0].frame, pkt);
+        }
+    }
+
+    #[test]
+    fn test_rejects_unknown_magic_and_truncation() {
+        assert!(CaptureFileReader::new(&b"not a capture file"[..]).is_err());
+
+        let mut bytes = pcap_file(LINKTYPE_RAW, &[(0, build_ipv4_packet(6, 1, 2))]);
+        bytes.truncate(bytes.len() - 3);
+        let mut reader = CaptureFileReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_ip_payload_ethernet_with_vlan() {
+        let pkt = build_ipv4_packet(6, 5000, 443);
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x05, 0x08, 0x00]);
+        frame.extend_from_slice(&pkt);
+        assert_eq!(
+            ip_payload(LINKTYPE_ETHERNET, &frame),
+            Some((pkt.as_slice(), None))
+        );
+
+        let mut arp = vec![0u8; 12];
+        arp.extend_from_slice(&[0x08, 0x06, 0, 0]);
+        assert!(ip_payload(LINKTYPE_ETHERNET, &arp).is_none());
+    }
+
+    #[test]
+    fn test_ip_payload_linux_sll_direction() {
+        let pkt = build_ipv4_packet(6, 5000, 443);
+        let mut frame = vec![0u8; 16];
+        frame[1] = 4; // PACKET_OUTGOING
+        frame[14..16].copy_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&pkt);
+        assert_eq!(
+            ip_payload(LINKTYPE_LINUX_SLL, &frame),
+            Some((pkt.as_slice(), Some(true)))
+        );
+
+        frame[1] = 0; // PACKET_HOST
+        assert_eq!(
+            ip_payload(LINKTYPE_LINUX_SLL, &frame).map(|(_, d)| d),
+            Some(Some(false))
+        );
+    }
+
+    #[test]
+    fn test_ip_payload_null_and_unsupported() {
+        let pkt = build_ipv4_packet(6, 5000, 443);
+        let mut frame = 2u32.to_le_bytes().to_vec();
+        frame.extend_from_slice(&pkt);
+        assert_eq!(
+            ip_payload(LINKTYPE_NULL, &frame).map(|(p, _)| p),
+            Some(&pkt[..])
+        );
+        assert!(ip_payload(105 /* 802.11 */, &frame).is_none());
+    }
+
+    #[test]
+    fn test_pcapng_writer_round_trips_through_reader() {
+        let pkt = build_ipv4_packet(17, 5000, 53);
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        let header_len = writer.bytes_written();
+        writer
+            .write_packet(
+                1_700_000_000_123_456,
+                &pkt,
+                pkt.len(),
+                true,
+                Some("curl (PID 42)"),
+            )
+            .unwrap();
+        assert_eq!(
+            writer.bytes_written() - header_len,
+            PcapngWriter::<Vec<u8>>::packet_block_len(pkt.len(), Some("curl (PID 42)"))
+        );
+        writer
+            .write_packet(1_700_000_000_200_000, &pkt, pkt.len(), false, None)
+            .unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let records = read_all(bytes.clone());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp_us, 1_700_000_000_123_456);
+        assert_eq!(records[0].linktype, LINKTYPE_RAW);
+        assert_eq!(records[1].frame, pkt);
+        assert!(bytes
+            .windows(b"curl (PID 42)".len())
+            .any(|w| w == b"curl (PID 42)"));
+    }
+}
@@ -0,0 +1,990 @@
+//! Backend-agnostic SNIFF and INTERCEPT capture loops.
+//!
+//! Supports two modes:
+//! - SNIFF: read-only packet copies for monitoring (Phase 1, zero risk)
+//! - INTERCEPT: captures and re-injects packets for rate limiting (Phase 2+)
+//!
+//! The loops only talk to the OS through [`CaptureBackend`], so accounting and
+//! pass/drop decisions are identical on every platform.
+//!
+//! SAFETY: In intercept mode, packets are diverted from the network stack.
+//! Always use the narrowest possible filter during development.
+//! See PRD section 8.2 for mandatory safeguards.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Result;
+
+use crate::capture::backend::{CaptureBackend, CapturedPacket};
+use crate::capture::recorder::PacketRecorder;
+use crate::capture::shaper::{run_shaper_loop, Admit, Shaper};
+use crate::capture::{parse_ip_packet, ParsedPacket};
+use crate::core::flow_table::{FlowKey, FlowTable};
+use crate::core::process_mapper::{port_key, ProcessMapper, Protocol};
+use crate::core::rate_limiter::{RateLimiterManager, Shaping};
+use crate::core::traffic::{TrafficTracker, UnattributedReason};
+
+/// Main SNIFF capture loop running in a dedicated OS thread.
+/// Packets are copied, never intercepted — zero risk to network connectivity.
+///
+/// Accepts a pre-opened backend (created by `CaptureBackend::open_sniff`).
+pub fn run_sniff_loop<B: CaptureBackend>(
+    backend: &B,
+    process_mapper: Arc<ProcessMapper>,
+    traffic_tracker: Arc<TrafficTracker>,
+    flow_table: Arc<FlowTable>,
+    packet_recorder: Arc<PacketRecorder>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
+    tracing::info!("{} SNIFF capture loop started", B::NAME);
+
+    let mut buf = vec![0u8; B::RECV_BUF_LEN];
+
+    while !shutdown.load(Ordering::Relaxed) {
+        match backend.recv(&mut buf) {
+            Ok(Some(packet)) => {
+                let data = packet.data();
+                let outbound = packet.outbound();
+                let parsed = parse_ip_packet(data);
+                let pid = process_sniff_packet(
+                    &process_mapper,
+                    &traffic_tracker,
+                    data,
+                    parsed.as_ref(),
+                    outbound,
+                );
+                if let Some(parsed) = &parsed {
+                    record_flow(&flow_table, pid, parsed, outbound);
+                }
+                packet_recorder.record(&process_mapper, pid, data, parsed.as_ref(), outbound);
+            }
+            Ok(None) => {
+                tracing::info!("{} SNIFF recv got shutdown signal", B::NAME);
+                break;
+            }
+            Err(e) => {
+                if shutdown.load(Ordering::Relaxed) {
+                    break;
+                }
+                tracing::error!("{} recv error: {e:#}", B::NAME);
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+        }
+    }
+
+    tracing::info!("{} SNIFF capture stopped", B::NAME);
+    Ok(())
+}
+
+/// Intercept capture loop. Packets matching the filter are diverted from the
+/// network stack, passed through the rate limiter, and re-injected.
+///
+/// Policing limits drop over-budget packets, so this loop never blocks; TCP
+/// congestion control reduces throughput when packets are dropped. Packets
+/// of processes with a shaping limit are handed to a [`Shaper`] instead, and
+/// a scoped `<backend>-shaper` thread re-injects them as tokens refill. Held
+/// packets are re-injected when the loop exits.
+///
+/// Accepts a pre-opened backend (created by `CaptureBackend::open_intercept`).
+///
+/// SAFETY: Uses a narrow filter (specific port) during Phase 2a development.
+/// See PRD S2 — never use "tcp or udp" in intercept mode during development.
+pub fn run_intercept_loop<B: CaptureBackend>(
+    backend: &B,
+    process_mapper: Arc<ProcessMapper>,
+    traffic_tracker: Arc<TrafficTracker>,
+    flow_table: Arc<FlowTable>,
+    rate_limiter: Arc<RateLimiterManager>,
+    packet_recorder: Arc<PacketRecorder>,
+    shutdown: Arc<AtomicBool>,
+) {
+    tracing::info!("{} INTERCEPT capture loop started", B::NAME);
+
+    let mut buf = vec![0u8; B::RECV_BUF_LEN];
+    let shaper = Shaper::new(Arc::clone(&rate_limiter));
+    let shaper_stop = AtomicBool::new(false);
+
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name(format!("{}-shaper", B::NAME))
+            .spawn_scoped(scope, || run_shaper_loop(backend, &shaper, &shaper_stop))
+            .expect("failed to spawn shaper thread");
+
+        while !shutdown.load(Ordering::Relaxed) {
+            match backend.recv(&mut buf) {
+                Ok(Some(packet)) => {
+                    // If shutdown was requested while we were blocked on recv,
+                    // re-inject this packet and exit cleanly.
+                    if shutdown.load(Ordering::Relaxed) {
+                        let _ = backend.reinject(packet);
+                        break;
+                    }
+
+                    let data = packet.data();
+                    let outbound = packet.outbound();
+                    // Parsed once; every step below works on the same view.
+                    let parsed = parse_ip_packet(data);
+
+                    // Account traffic (same as SNIFF mode).
+                    let pid = process_sniff_packet(
+                        &process_mapper,
+                        &traffic_tracker,
+                        data,
+                        parsed.as_ref(),
+                        outbound,
+                    );
+                    if let Some(parsed) = &parsed {
+                        record_flow(&flow_table, pid, parsed, outbound);
+                    }
+                    packet_recorder.record(&process_mapper, pid, data, parsed.as_ref(), outbound);
+
+                    // Decide: pass, drop, or hand to the shaper.
+                    // Non-rate-limited / non-blocked packets pass immediately.
+                    // Blocked or over-budget policed packets are silently dropped.
+                    let disposition = packet_disposition(
+                        &process_mapper,
+                        &rate_limiter,
+                        parsed.as_ref(),
+                        outbound,
+                    );
+                    let packet = match disposition {
+                        Disposition::Pass => Ok(packet),
+                        Disposition::Drop => Err(packet),
+                        Disposition::Shape {
+                            pid,
+                            bytes,
+                            shaping,
+                        } => {
+                            match shaper.admit(
+                                pid,
+                                bytes,
+                                outbound,
+                                shaping,
+                                packet,
+                                Instant::now(),
+                            ) {
+                                Admit::Send(packet) => Ok(packet),
+                                Admit::Drop(packet) => Err(packet),
+                                Admit::Queued => continue,
+                            }
+                        }
+                    };
+                    match packet {
+                        // Re-inject the packet back into the network stack.
+                        Ok(packet) => {
+                            if let Err(e) = backend.reinject(packet) {
+                                tracing::error!("{} send error: {e:#}", B::NAME);
+                            }
+                        }
+                        // Packet dropped (blocked, rate exceeded or shaper queue full).
+                        Err(packet) => {
+                            if let Err(e) = backend.discard(packet) {
+                                tracing::error!("{} drop error: {e:#}", B::NAME);
+                            }
+                        }
+                    }
+                }
+                Ok(None) => {
+                    tracing::info!("{} INTERCEPT recv got shutdown signal", B::NAME);
+                    break;
+                }
+                Err(e) => {
+                    if shutdown.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    tracing::error!("{} recv error in intercept mode: {e:#}", B::NAME);
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+            }
+        }
+
+        // The scope joins the shaper thread, which re-injects held packets.
+        shaper_stop.store(true, Ordering::Relaxed);
+        shaper.notify();
+    });
+
+    tracing::info!("{} INTERCEPT capture stopped", B::NAME);
+}
+
+/// Attribute a packet to its owning process and record its bytes. `packet`
+/// is `data` as parsed by [`parse_ip_packet`] (`None` if unparseable).
+/// Returns the PID the packet was credited to, if any.
+///
+/// ICMP echo is attributed through the ping socket that owns its identifier.
+/// A TCP/UDP flow missing from the port map is looked up on demand where the
+/// platform supports it (see [`ProcessMapper::lookup_flow_pid`]).
+/// TCP/UDP bytes on a port without a known owner are deferred until the next
+/// scan (see [`TrafficTracker::defer_bytes`]). Everything else without an
+/// owner is credited to the matching [`UnattributedReason`] bucket. `None` is
+/// returned in both cases.
+pub(crate) fn process_sniff_packet(
+    mapper: &ProcessMapper,
+    tracker: &TrafficTracker,
+    data: &[u8],
+    packet: Option<&ParsedPacket>,
+    outbound: bool,
+) -> Option<u32> {
+    let split = |len: u64| if outbound { (len, 0) } else { (0, len) };
+
+    let Some(packet) = packet else {
+        if !data.is_empty() {
+            let (sent, recv) = split(data.len() as u64);
+            tracker.record_unattributed(UnattributedReason::Unparseable, sent, recv);
+        }
+        return None;
+    };
+    let (sent, recv) = split(packet.total_len);
+
+    let endpoints = packet.endpoints(outbound);
+    let owner = endpoints
+        .and_then(|(local, remote)| mapper.lookup_flow_pid(packet.protocol, local, remote));
+    let Some(pid) = owner else {
+        match (packet.protocol, endpoints) {
+            // The owner may not have been scanned yet; retry after the next scan.
+            (Protocol::Tcp | Protocol::Udp, Some((local, _))) => {
+                let key = port_key(packet.protocol, local.ip(), local.port());
+                tracker.defer_bytes(key, sent, recv);
+            }
+            (Protocol::Tcp | Protocol::Udp, None) => {
+                tracker.record_unattributed(UnattributedReason::NoPortMatch, sent, recv);
+            }
+            _ => tracker.record_unattributed(UnattributedReason::NonTcpUdp, sent, recv),
+        }
+        return None;
+    };
+    tracker.record_bytes(pid, sent, recv);
+    Some(pid)
+}
+
+/// Record a packet in the 5-tuple flow table. `pid` is the process the
+/// packet was attributed to, if any. Packets without ports (non-first
+/// fragments, non-echo ICMP, other protocols) are ignored.
+pub(crate) fn record_flow(
+    flow_table: &FlowTable,
+    pid: Option<u32>,
+    packet: &ParsedPacket,
+    outbound: bool,
+) {
+    let Some((src_port, dst_port)) = packet.ports else {
+        return;
+    };
+    let key = FlowKey::from_packet(
+        packet.protocol,
+        (packet.src_addr, src_port),
+        (packet.dst_addr, dst_port),
+        outbound,
+    );
+    flow_table.record(key, pid, packet.total_len, outbound, packet.tcp_flags);
+}
+
+/// What the intercept loop does with a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Disposition {
+    Pass,
+    Drop,
+    /// The owning process has a shaping limit: queue in the [`Shaper`].
+    Shape {
+        pid: u32,
+        bytes: u64,
+        shaping: Shaping,
+    },
+}
+
+/// Decide whether a packet should be passed, dropped or shaped.
+/// Pass for: unparseable packets, non-first fragments, unknown PIDs, non-limited processes,
+/// and policed processes within their budget.
+/// Drop for: blocked PIDs and policed processes over budget, and in allowlist
+/// mode for processes the list does not allow and for every packet no process
+/// is found for, bar the later fragments of a datagram whose first fragment
+/// passed (see [`RateLimiterManager::admits_portless`]).
+/// Shape for: processes with a shaping limit in this direction.
+pub(crate) fn packet_disposition(
+    mapper: &ProcessMapper,
+    rate_limiter: &RateLimiterManager,
+    packet: Option<&ParsedPacket>,
+    outbound: bool,
+) -> Disposition {
+    let now = Instant::now();
+    let fragment = packet.and_then(|p| {
+        p.fragment
+            .map(|f| (p.src_addr, p.dst_addr, p.ip_protocol, f.id))
+    });
+    let local_port = packet.and_then(|p| p.local_port(outbound));
+    let (Some(packet), Some(local_port)) = (packet, local_port) else {
+        // can't parse, or no ports (fragment, non-echo ICMP, other protocol)
+        // → pass through, unless only allowed applications may pass
+        return if rate_limiter.admits_portless(fragment, now) {
+            Disposition::Pass
+        } else {
+            Disposition::Drop
+        };
+    };
+
+    let disposition = port_disposition(mapper, rate_limiter, packet, local_port, outbound);
+    if let Some(key) = fragment.filter(|_| disposition != Disposition::Drop) {
+        rate_limiter.admit_fragments(key, now);
+    }
+    disposition
+}
+
+/// [`packet_disposition`] of a packet with ports.
+fn port_disposition(
+    mapper: &ProcessMapper,
+    rate_limiter: &RateLimiterManager,
+    packet: &ParsedPacket,
+    local_port: u16,
+    outbound: bool,
+) -> Disposition {
+    let local_addr = packet.local_addr(outbound);
+    let Some(pid) = mapper.lookup_pid(packet.protocol, local_addr, local_port) else {
+        // unknown PID → pass through, unless only allowed applications may
+        // pass: then a new connection waits for a scan to map its port, and
+        // its dropped first packets are retransmitted
+        return if rate_limiter.allowlist_enabled() {
+            Disposition::Drop
+        } else {
+            Disposition::Pass
+        };
+    };
+
+    if let Some(shaping) = rate_limiter.shaping(pid, outbound) {
+        return Disposition::Shape {
+            pid,
+            bytes: packet.total_len,
+            shaping,
+        };
+    }
+    if rate_limiter.should_pass_packet(pid, packet.total_len, outbound) {
+        Disposition::Pass
+    } else {
+        Disposition::Drop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::mod_test_helpers::build_ipv4_packet;
+    use crate::core::rate_limiter::{LimitMode, PriorityClass};
+    use parking_lot::Mutex;
+    use std::collections::VecDeque;
+    use std::net::Ipv4Addr;
+
+    /// In-memory backend that replays queued packets, then reports shutdown.
+    #[derive(Default)]
+    struct MockBackend {
+        queue: Mutex<VecDeque<MockPacket>>,
+        reinjected: Mutex<Vec<Vec<u8>>>,
+        discarded: Mutex<Vec<Vec<u8>>>,
+    }
+
+    struct MockPacket {
+        data: Vec<u8>,
+        outbound: bool,
+    }
+
+    impl CapturedPacket for MockPacket {
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+
+        fn outbound(&self) -> bool {
+            self.outbound
+        }
+    }
+
+    impl MockBackend {
+        fn with_packets(packets: Vec<(Vec<u8>, bool)>) -> Self {
+            let backend = Self::default();
+            backend.queue.lock().extend(
+                packets
+                    .into_iter()
+                    .map(|(data, outbound)| MockPacket { data, outbound }),
+            );
+            backend
+        }
+    }
+
+    impl CaptureBackend for MockBackend {
+        type Packet = MockPacket;
+
+        const NAME: &'static str = "mock";
+
+        fn open_sniff() -> Result<Self> {
+            Ok(Self::default())
+        }
+
+        fn open_intercept(_filter: &str) -> Result<Self> {
+            Ok(Self::default())
+        }
+
+        fn recv(&self, _buf: &mut [u8]) -> Result<Option<Self::Packet>> {
+            Ok(self.queue.lock().pop_front())
+        }
+
+        fn reinject(&self, packet: Self::Packet) -> Result<()> {
+            self.reinjected.lock().push(packet.data);
+            Ok(())
+        }
+
+        fn discard(&self, packet: Self::Packet) -> Result<()> {
+            self.discarded.lock().push(packet.data);
+            Ok(())
+        }
+
+        fn shutdown(&self) {
+            self.queue.lock().clear();
+        }
+    }
+
+    fn sniff(
+        mapper: &ProcessMapper,
+        tracker: &TrafficTracker,
+        data: &[u8],
+        outbound: bool,
+    ) -> Option<u32> {
+        process_sniff_packet(
+            mapper,
+            tracker,
+            data,
+            parse_ip_packet(data).as_ref(),
+            outbound,
+        )
+    }
+
+    fn disposition_of(
+        mapper: &ProcessMapper,
+        limiter: &RateLimiterManager,
+        data: &[u8],
+        outbound: bool,
+    ) -> Disposition {
+        packet_disposition(mapper, limiter, parse_ip_packet(data).as_ref(), outbound)
+    }
+
+    #[test]
+    fn test_sniff_loop_records_backend_packets() {
+        let mapper = Arc::new(ProcessMapper::new());
+        let tracker = Arc::new(TrafficTracker::new());
+        let flows = Arc::new(FlowTable::new());
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 5000),
+            42,
+        );
+        let backend = MockBackend::with_packets(vec![
+            (build_ipv4_packet(6, 5000, 443), true),
+            (build_ipv4_packet(6, 443, 5000), false),
+        ]);
+
+        run_sniff_loop(
+            &backend,
+            Arc::clone(&mapper),
+            Arc::clone(&tracker),
+            Arc::clone(&flows),
+            Arc::new(PacketRecorder::new()),
+            Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap.len(), 1);
+        assert_eq!(snap[0].bytes_sent, 24);
+        assert_eq!(snap[0].bytes_recv, 24);
+
+        // Both directions land in the same flow.
+        let conns = flows.connections_for(42);
+        assert_eq!(conns.len(), 1);
+        assert_eq!((conns[0].local_port, conns[0].remote_port), (5000, 443));
+        assert_eq!((conns[0].packets_sent, conns[0].packets_recv), (1, 1));
+    }
+
+    #[test]
+    fn test_intercept_loop_reinjects_only_passing_packets() {
+        let mapper = Arc::new(ProcessMapper::new());
+        let tracker = Arc::new(TrafficTracker::new());
+        let limiter = Arc::new(RateLimiterManager::new());
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 5000),
+            42,
+        );
+        limiter.block_process(42);
+        let allowed = build_ipv4_packet(6, 6000, 443);
+        let blocked = build_ipv4_packet(6, 5000, 443);
+        let backend =
+            MockBackend::with_packets(vec![(blocked.clone(), true), (allowed.clone(), true)]);
+
+        run_intercept_loop(
+            &backend,
+            mapper,
+            tracker,
+            Arc::new(FlowTable::new()),
+            limiter,
+            Arc::new(PacketRecorder::new()),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        assert_eq!(*backend.reinjected.lock(), vec![allowed]);
+        assert_eq!(*backend.discarded.lock(), vec![blocked]);
+    }
+
+    #[test]
+    fn test_sniff_outbound_records_upload() {
+        let mapper = ProcessMapper::new();
+        let tracker = TrafficTracker::new();
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 12345),
+            42,
+        );
+
+        let pkt = build_ipv4_packet(6, 12345, 443);
+        sniff(&mapper, &tracker, &pkt, true); // outbound
+
+        let snap = tracker.snapshot(&mapper);
+        let proc = snap.iter().find(|s| s.pid == 42);
+        assert!(proc.is_some(), "PID 42 should appear in snapshot");
+        assert!(
+            proc.unwrap().bytes_sent > 0,
+            "outbound bytes should be recorded as sent"
+        );
+        assert_eq!(proc.unwrap().bytes_recv, 0);
+    }
+
+    #[test]
+    fn test_sniff_inbound_records_download() {
+        let mapper = ProcessMapper::new();
+        let tracker = TrafficTracker::new();
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 443),
+            42,
+        );
+
+        let pkt = build_ipv4_packet(6, 12345, 443);
+        sniff(&mapper, &tracker, &pkt, false); // inbound
+
+        let snap = tracker.snapshot(&mapper);
+        let proc = snap.iter().find(|s| s.pid == 42);
+        assert!(proc.is_some(), "PID 42 should appear in snapshot");
+        assert_eq!(proc.unwrap().bytes_sent, 0);
+        assert!(
+            proc.unwrap().bytes_recv > 0,
+            "inbound bytes should be recorded as recv"
+        );
+    }
+
+    #[test]
+    fn test_sniff_malformed_packet_no_panic() {
+        let mapper = ProcessMapper::new();
+        let tracker = TrafficTracker::new();
+        sniff(&mapper, &tracker, &[0xFF, 0x00], true);
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap.len(), 1);
+        assert_eq!(snap[0].unattributed, Some(UnattributedReason::Unparseable));
+        assert_eq!(snap[0].bytes_sent, 2, "counted at captured length");
+    }
+
+    #[test]
+    fn test_sniff_unknown_pid_recorded_as_unattributed() {
+        let mapper = ProcessMapper::new();
+        let tracker = TrafficTracker::new();
+        let pkt = build_ipv4_packet(6, 9999, 80);
+        assert_eq!(sniff(&mapper, &tracker, &pkt, true), None);
+        assert!(tracker.snapshot(&mapper).is_empty(), "deferred first");
+        tracker.expire_pending();
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap.len(), 1);
+        assert_eq!(snap[0].pid, UnattributedReason::NoPortMatch.pid());
+        assert_eq!(snap[0].name, "Unattributed (no port match)");
+        assert_eq!((snap[0].bytes_sent, snap[0].bytes_recv), (24, 0));
+    }
+
+    #[test]
+    fn test_sniff_deferred_bytes_credited_after_scan() {
+        let mapper = ProcessMapper::new();
+        let tracker = TrafficTracker::new();
+        let pkt = build_ipv4_packet(6, 40000, 443);
+        sniff(&mapper, &tracker, &pkt, true);
+        sniff(&mapper, &tracker, &build_ipv4_packet(6, 443, 40000), false);
+
+        // The next scan finds the socket.
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 40000),
+            42,
+        );
+        tracker.resolve_pending(&mapper);
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap.len(), 1);
+        assert_eq!(
+            (snap[0].pid, snap[0].bytes_sent, snap[0].bytes_recv),
+            (42, 24, 24)
+        );
+        assert_eq!(tracker.deferred_attribution_stats().recovered_bytes, 48);
+    }
+
+    #[test]
+    fn test_sniff_non_first_fragment_not_misattributed() {
+        let mapper = ProcessMapper::new();
+        let tracker = TrafficTracker::new();
+        mapper.port_map.insert(
+            port_key(Protocol::Udp, Ipv4Addr::UNSPECIFIED.into(), 5000),
+            42,
+        );
+        // Payload bytes of a later fragment happen to look like port 5000.
+        let mut pkt = build_ipv4_packet(17, 5000, 53);
+        pkt[6..8].copy_from_slice(&185u16.to_be_bytes());
+
+        assert_eq!(sniff(&mapper, &tracker, &pkt, true), None);
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap.len(), 1);
+        assert_eq!(snap[0].unattributed, Some(UnattributedReason::NoPortMatch));
+        let limiter = RateLimiterManager::new();
+        limiter.block_process(42);
+        assert_eq!(
+            disposition_of(&mapper, &limiter, &pkt, true),
+            Disposition::Pass
+        );
+    }
+
+    #[test]
+    fn test_sniff_icmp_echo_attributed_via_identifier() {
+        let mapper = ProcessMapper::new();
+        let tracker = TrafficTracker::new();
+        mapper.port_map.insert(
+            port_key(Protocol::Icmp, Ipv4Addr::UNSPECIFIED.into(), 0x1234),
+            42,
+        );
+        // Echo request (type 8) with identifier 0x1234.
+        let mut pkt = build_ipv4_packet(1, 0x0800, 0);
+        pkt.extend_from_slice(&[0x12, 0x34, 0x00, 0x01]);
+        pkt[3] = 28;
+
+        assert_eq!(sniff(&mapper, &tracker, &pkt, true), Some(42));
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap.len(), 1);
+        assert_eq!((snap[0].pid, snap[0].bytes_sent), (42, 28));
+    }
+
+    #[test]
+    fn test_sniff_other_protocols_bucket() {
+        let mapper = ProcessMapper::new();
+        let tracker = TrafficTracker::new();
+        // GRE, and an ICMP echo whose identifier no socket owns.
+        sniff(&mapper, &tracker, &build_ipv4_packet(47, 0, 0), false);
+        let mut ping = build_ipv4_packet(1, 0x0800, 0);
+        ping.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        ping[3] = 28;
+        assert_eq!(sniff(&mapper, &tracker, &ping, true), None);
+
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap.len(), 1);
+        assert_eq!(snap[0].unattributed, Some(UnattributedReason::NonTcpUdp));
+        assert_eq!(snap[0].name, "Other protocols");
+        assert_eq!((snap[0].bytes_sent, snap[0].bytes_recv), (28, 24));
+
+        // Bucket traffic is never dropped in intercept mode.
+        let limiter = RateLimiterManager::new();
+        limiter.block_process(UnattributedReason::NonTcpUdp.pid());
+        assert_eq!(
+            disposition_of(&mapper, &limiter, &ping, true),
+            Disposition::Pass
+        );
+    }
+
+    #[test]
+    fn test_sniff_empty_packet_no_panic() {
+        let mapper = ProcessMapper::new();
+        let tracker = TrafficTracker::new();
+        sniff(&mapper, &tracker, &[], true);
+        assert!(tracker.snapshot(&mapper).is_empty());
+    }
+
+    #[test]
+    fn test_should_pass_unparseable_returns_true() {
+        let mapper = ProcessMapper::new();
+        let limiter = RateLimiterManager::new();
+        assert_eq!(
+            disposition_of(&mapper, &limiter, &[0xFF], true),
+            Disposition::Pass
+        );
+    }
+
+    #[test]
+    fn test_should_pass_unknown_pid_returns_true() {
+        let mapper = ProcessMapper::new();
+        let limiter = RateLimiterManager::new();
+        let pkt = build_ipv4_packet(6, 9999, 80);
+        assert_eq!(
+            disposition_of(&mapper, &limiter, &pkt, true),
+            Disposition::Pass
+        );
+    }
+
+    #[test]
+    fn test_allowlist_mode_drops_unknown_and_unlisted_pids() {
+        use crate::core::allowlist::{AllowEntry, Allowlist, AllowlistMatcher};
+        use crate::core::process_mapper::ProcessInfo;
+        use crate::core::rule_match::MatchType;
+
+        let mapper = ProcessMapper::new();
+        let limiter = RateLimiterManager::new();
+        for (port, pid) in [(5000, 42), (6000, 43)] {
+            mapper.port_map.insert(
+                port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), port),
+                pid,
+            );
+        }
+        let process = |exe_path: &str| ProcessInfo {
+            name: "app".into(),
+            exe_path: exe_path.into(),
+            args: String::new(),
+        };
+        let list = Allowlist {
+            allowed: vec![AllowEntry {
+                match_type: MatchType::Exact,
+                pattern: "/usr/bin/allowed".into(),
+            }],
+            always_allow: Vec::new(),
+        };
+        limiter.set_allowlist(
+            Some(AllowlistMatcher::new(&list).unwrap()),
+            &[
+                (42, process("/usr/bin/allowed")),
+                (43, process("/usr/bin/other")),
+            ],
+        );
+
+        let disposition = |pkt: &[u8]| disposition_of(&mapper, &limiter, pkt, true);
+        assert_eq!(
+            disposition(&build_ipv4_packet(6, 5000, 80)),
+            Disposition::Pass
+        );
+        assert_eq!(
+            disposition(&build_ipv4_packet(6, 6000, 80)),
+            Disposition::Drop
+        );
+        // Unknown ports are dropped, and so is traffic without ports: GRE,
+        // non-echo ICMP and packets that do not parse.
+        assert_eq!(
+            disposition(&build_ipv4_packet(6, 9999, 80)),
+            Disposition::Drop
+        );
+        assert_eq!(disposition(&build_ipv4_packet(47, 0, 0)), Disposition::Drop);
+        let unreachable = build_ipv4_packet(1, 0x0303, 0);
+        assert_eq!(disposition(&unreachable), Disposition::Drop);
+        assert_eq!(disposition(&[0x45, 0x00]), Disposition::Drop);
+
+        // The later fragments of an allowed first fragment follow it; those of
+        // a dropped one do not.
+        let fragment = |port, id: u16, flags_offset: u16| {
+            let mut pkt = build_ipv4_packet(6, port, 80);
+            pkt[4..6].copy_from_slice(&id.to_be_bytes());
+            pkt[6..8].copy_from_slice(&flags_offset.to_be_bytes());
+            pkt
+        };
+        assert_eq!(disposition(&fragment(5000, 1, 0x2000)), Disposition::Pass);
+        assert_eq!(disposition(&fragment(5000, 1, 185)), Disposition::Pass);
+        assert_eq!(disposition(&fragment(6000, 2, 0x2000)), Disposition::Drop);
+        assert_eq!(disposition(&fragment(6000, 2, 185)), Disposition::Drop);
+
+        limiter.set_allowlist(None, &[]);
+        assert_eq!(
+            disposition(&build_ipv4_packet(6, 9999, 80)),
+            Disposition::Pass
+        );
+        assert_eq!(disposition(&build_ipv4_packet(47, 0, 0)), Disposition::Pass);
+        assert_eq!(disposition(&fragment(6000, 2, 185)), Disposition::Pass);
+    }
+
+    #[test]
+    fn test_allowlist_mode_drops_new_ports_until_mapped() {
+        use crate::core::allowlist::{AllowEntry, Allowlist, AllowlistMatcher};
+        use crate::core::process_mapper::ProcessInfo;
+        use crate::core::rule_match::MatchType;
+
+        let mapper = ProcessMapper::new();
+        let limiter = RateLimiterManager::new();
+        let process = |exe_path: &str| ProcessInfo {
+            name: "app".into(),
+            exe_path: exe_path.into(),
+            args: String::new(),
+        };
+        let list = Allowlist {
+            allowed: vec![AllowEntry {
+                match_type: MatchType::Exact,
+                pattern: "/usr/bin/allowed".into(),
+            }],
+            always_allow: Vec::new(),
+        };
+        limiter.set_allowlist(
+            Some(AllowlistMatcher::new(&list).unwrap()),
+            &[
+                (42, process("/usr/bin/allowed")),
+                (43, process("/usr/bin/other")),
+            ],
+        );
+        let disposition =
+            |port| disposition_of(&mapper, &limiter, &build_ipv4_packet(6, port, 80), true);
+
+        // An unlisted process gets nothing through by opening one new
+        // connection after another before the scanner sees them.
+        for port in 6000..6100 {
+            assert_eq!(disposition(port), Disposition::Drop, "port {port}");
+        }
+        // Neither does an allowed one, until a scan maps its port.
+        assert_eq!(disposition(5000), Disposition::Drop);
+
+        for (port, pid) in [(5000, 42), (6000, 43)] {
+            mapper.port_map.insert(
+                port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), port),
+                pid,
+            );
+        }
+        // The retransmitted SYN now passes; the unlisted process stays cut off.
+        assert_eq!(disposition(5000), Disposition::Pass);
+        assert_eq!(disposition(6000), Disposition::Drop);
+    }
+
+    #[test]
+    fn test_should_pass_no_limit_returns_true() {
+        let mapper = ProcessMapper::new();
+        let limiter = RateLimiterManager::new();
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 5000),
+            42,
+        );
+        let pkt = build_ipv4_packet(6, 5000, 80);
+        assert_eq!(
+            disposition_of(&mapper, &limiter, &pkt, true),
+            Disposition::Pass
+        );
+    }
+
+    #[test]
+    fn test_should_pass_blocked_pid_returns_false() {
+        let mapper = ProcessMapper::new();
+        let limiter = RateLimiterManager::new();
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 5000),
+            42,
+        );
+        limiter.block_process(42);
+        let pkt = build_ipv4_packet(6, 5000, 80);
+        assert_eq!(
+            disposition_of(&mapper, &limiter, &pkt, true),
+            Disposition::Drop
+        );
+    }
+
+    #[test]
+    fn test_should_pass_within_rate_budget() {
+        let mapper = ProcessMapper::new();
+        let limiter = RateLimiterManager::new();
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 5000),
+            42,
+        );
+        limiter.set_limit(
+            42,
+            crate::core::rate_limiter::BandwidthLimit {
+                download_bps: 1_000_000,
+                upload_bps: 1_000_000,
+                ..Default::default()
+            },
+        );
+        let pkt = build_ipv4_packet(6, 5000, 80);
+        assert_eq!(
+            disposition_of(&mapper, &limiter, &pkt, true),
+            Disposition::Pass
+        );
+    }
+
+    #[test]
+    fn test_disposition_shapes_only_limited_direction() {
+        let mapper = ProcessMapper::new();
+        let limiter = RateLimiterManager::new();
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 5000),
+            42,
+        );
+        limiter.set_limit(
+            42,
+            crate::core::rate_limiter::BandwidthLimit {
+                download_bps: 0,
+                upload_bps: 1_000,
+                mode: LimitMode::ShapeCodel,
+                priority: PriorityClass::High,
+                weight: 3,
+                ..Default::default()
+            },
+        );
+        let outbound = build_ipv4_packet(6, 5000, 80);
+        assert_eq!(
+            disposition_of(&mapper, &limiter, &outbound, true),
+            Disposition::Shape {
+                pid: 42,
+                bytes: 24,
+                shaping: Shaping {
+                    mode: LimitMode::ShapeCodel,
+                    priority: PriorityClass::High,
+                    weight: 3,
+                }
+            }
+        );
+        let inbound = build_ipv4_packet(6, 80, 5000);
+        assert_eq!(
+            disposition_of(&mapper, &limiter, &inbound, false),
+            Disposition::Pass
+        );
+
+        // A block overrides shaping.
+        limiter.block_process(42);
+        assert_eq!(
+            disposition_of(&mapper, &limiter, &outbound, true),
+            Disposition::Drop
+        );
+    }
+
+    #[test]
+    fn test_intercept_loop_delays_instead_of_dropping_shaped_packets() {
+        let mapper = Arc::new(ProcessMapper::new());
+        let limiter = Arc::new(RateLimiterManager::new());
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 5000),
+            42,
+        );
+        // 1000 B/s with a 2000-byte burst: the third 1000-byte packet is held.
+        limiter.set_limit(
+            42,
+            crate::core::rate_limiter::BandwidthLimit {
+                download_bps: 1_000,
+                upload_bps: 1_000,
+                mode: LimitMode::ShapeDropTail,
+                ..Default::default()
+            },
+        );
+        let packets: Vec<Vec<u8>> = (0..3u16)
+            .map(|seq| {
+                let mut pkt = build_ipv4_packet(6, 5000, 443);
+                pkt[2..4].copy_from_slice(&1000u16.to_be_bytes());
+                pkt[4..6].copy_from_slice(&seq.to_be_bytes());
+                pkt
+            })
+            .collect();
+        let backend =
+            MockBackend::with_packets(packets.iter().map(|p| (p.clone(), true)).collect());
+
+        run_intercept_loop(
+            &backend,
+            mapper,
+            Arc::new(TrafficTracker::new()),
+            Arc::new(FlowTable::new()),
+            limiter,
+            Arc::new(PacketRecorder::new()),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        // Nothing is dropped; the held packet is re-injected on exit, in order.
+        assert_eq!(*backend.reinjected.lock(), packets);
+        assert!(backend.discarded.lock().is_empty());
+    }
+}
@@ -0,0 +1,518 @@
+//! Packet taps fed by the capture loops.
+//!
+//! The capture loops hand every packet to [`PacketRecorder::record`] after
+//! attribution. Each packet's header goes into the [`FlightRecorder`] ring,
+//! and while a per-process export ("capture this app") is active, packets
+//! attributed to its target PID or executable are appended to a pcapng file,
+//! each annotated with the owning process name and PID in the packet comment.
+//! An export ends when it reaches its duration or size bound, or when it is
+//! stopped explicitly.
+//!
+//! With no export active, the export tap is a single atomic load, so the
+//! capture hot path pays nothing for it.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::capture::flight_recorder::FlightRecorder;
+use crate::capture::pcap::PcapngWriter;
+use crate::capture::ParsedPacket;
+use crate::config;
+use crate::core::process_mapper::{ProcessInfo, ProcessMapper};
+
+/// Which packets an export session keeps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureTarget {
+    /// Packets attributed to this PID.
+    Pid(u32),
+    /// Packets attributed to any process running this executable.
+    ExePath(String),
+}
+
+impl CaptureTarget {
+    fn matches(&self, pid: u32, info: Option<&ProcessInfo>) -> bool {
+        match self {
+            CaptureTarget::Pid(target) => *target == pid,
+            CaptureTarget::ExePath(path) => info.is_some_and(|i| i.exe_path == *path),
+        }
+    }
+}
+
+/// Why an export session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum ExportStopReason {
+    /// The maximum duration elapsed.
+    Duration,
+    /// The next packet would have pushed the file past its size limit.
+    Size,
+    /// Stopped on request.
+    Stopped,
+    /// Writing to the output file failed.
+    WriteError,
+}
+
+/// Progress of an export session, active or finished.
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct ProcessCaptureStatus {
+    pub id: u32,
+    pub target_pid: Option<u32>,
+    pub target_exe_path: Option<String>,
+    pub output_path: String,
+    /// Unix timestamp (seconds) the session started.
+    #[ts(type = "number")]
+    pub started_at: i64,
+    #[ts(type = "number")]
+    pub packets: u64,
+    /// Size of the pcapng file so far, including its header blocks.
+    #[ts(type = "number")]
+    pub bytes_written: u64,
+    /// `None` while the session is still recording.
+    pub stop_reason: Option<ExportStopReason>,
+}
+
+struct ExportSession {
+    status: ProcessCaptureStatus,
+    target: CaptureTarget,
+    writer: PcapngWriter<BufWriter<File>>,
+    deadline: Instant,
+    max_bytes: u64,
+}
+
+/// Packet taps shared by the capture loops and the recorder commands.
+pub struct PacketRecorder {
+    flight_recorder: FlightRecorder,
+    /// Fast-path flag: true while at least one export session is recording.
+    active: AtomicBool,
+    next_id: AtomicU32,
+    sessions: Mutex<Vec<ExportSession>>,
+    /// Most recently finished sessions, newest last.
+    finished: Mutex<VecDeque<ProcessCaptureStatus>>,
+}
+
+impl PacketRecorder {
+    pub fn new() -> Self {
+        Self {
+            flight_recorder: FlightRecorder::new(config::FLIGHT_RECORDER_DEFAULT_BYTES),
+            active: AtomicBool::new(false),
+            next_id: AtomicU32::new(1),
+            sessions: Mutex::new(Vec::new()),
+            finished: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn flight_recorder(&self) -> &FlightRecorder {
+        &self.flight_recorder
+    }
+
+    /// Start exporting packets for `target` to a new pcapng file at `path`.
+    /// Returns the session id.
+    ///
+    /// A watchdog thread ends the session at `max_duration` even if no further
+    /// packets arrive, so the file is always flushed and closed on time.
+    pub fn start_export(
+        self: &Arc<Self>,
+        target: CaptureTarget,
+        path: &Path,
+        max_duration: Duration,
+        max_bytes: u64,
+    ) -> Result<u32> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create export file {}", path.display()))?;
+        let writer = PcapngWriter::new(BufWriter::new(file))?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (target_pid, target_exe_path) = match &target {
+            CaptureTarget::Pid(pid) => (Some(*pid), None),
+            CaptureTarget::ExePath(path) => (None, Some(path.clone())),
+        };
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let deadline = Instant::now() + max_duration;
+
+        self.sessions.lock().push(ExportSession {
+            status: ProcessCaptureStatus {
+                id,
+                target_pid,
+                target_exe_path,
+                output_path: path.display().to_string(),
+                started_at,
+                packets: 0,
+                bytes_written: writer.bytes_written(),
+                stop_reason: None,
+            },
+            target,
+            writer,
+            deadline,
+            max_bytes,
+        });
+        self.active.store(true, Ordering::Relaxed);
+        tracing::info!("Packet export {id} started → {}", path.display());
+
+        let recorder = Arc::clone(self);
+        std::thread::Builder::new()
+            .name(format!("pcap-export-{id}"))
+            .spawn(move || {
+                // Sleep in short steps so an early stop ends the thread promptly.
+                while Instant::now() < deadline {
+                    if !recorder.sessions.lock().iter().any(|s| s.status.id == id) {
+                        return;
+                    }
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                recorder.end_session(id, ExportStopReason::Duration);
+            })
+            .context("Failed to spawn export watchdog thread")?;
+
+        Ok(id)
+    }
+
+    /// Stop an active export session. Returns its final status, or `None` if
+    /// no session with `id` is recording.
+    pub fn stop_export(&self, id: u32) -> Option<ProcessCaptureStatus> {
+        self.end_session(id, ExportStopReason::Stopped)
+    }
+
+    /// Status of every active session followed by recently finished ones.
+    pub fn export_statuses(&self) -> Vec<ProcessCaptureStatus> {
+        let mut statuses: Vec<_> = self
+            .sessions
+            .lock()
+            .iter()
+            .map(|s| s.status.clone())
+            .collect();
+        statuses.extend(self.finished.lock().iter().rev().cloned());
+        statuses
+    }
+
+    /// Offer one captured packet to the flight recorder and the active export
+    /// sessions. `pid` is the process the packet was attributed to, if any;
+    /// `packet` is `data` as parsed by the capture loop. Unparseable packets
+    /// are exported but not kept by the flight recorder.
+    pub fn record(
+        &self,
+        mapper: &ProcessMapper,
+        pid: Option<u32>,
+        data: &[u8],
+        packet: Option<&ParsedPacket>,
+        outbound: bool,
+    ) {
+        if let Some(packet) = packet {
+            self.flight_recorder.record(pid, packet, outbound);
+        }
+
+        if !self.active.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some(pid) = pid else {
+            return;
+        };
+
+        let now = Instant::now();
+        let timestamp_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as i64;
+        // Looked up at most once, and only if a session needs it.
+        let mut info: Option<Option<ProcessInfo>> = None;
+        let mut ended = Vec::new();
+
+        let mut sessions = self.sessions.lock();
+        for (idx, session) in sessions.iter_mut().enumerate() {
+            if now >= session.deadline {
+                ended.push((idx, ExportStopReason::Duration));
+                continue;
+            }
+            let info = info.get_or_insert_with(|| mapper.get_process_info(pid));
+            if !session.target.matches(pid, info.as_ref()) {
+                continue;
+            }
+
+            let comment = match info {
+                Some(info) => format!("{} (PID {pid})", info.name),
+                None => format!("PID {pid}"),
+            };
+            let block_len =
+                PcapngWriter::<BufWriter<File>>::packet_block_len(data.len(), Some(&comment));
+            if session.writer.bytes_written() + block_len > session.max_bytes {
+                ended.push((idx, ExportStopReason::Size));
+                continue;
+            }
+            if let Err(e) = session.writer.write_packet(
+                timestamp_us,
+                data,
+                data.len(),
+                outbound,
+                Some(&comment),
+            ) {
+                tracing::warn!("Packet export {} write failed: {e:#}", session.status.id);
+                ended.push((idx, ExportStopReason::WriteError));
+                continue;
+            }
+            session.status.packets += 1;
+            session.status.bytes_written = session.writer.bytes_written();
+        }
+
+        for (idx, reason) in ended.into_iter().rev() {
+            let session = sessions.remove(idx);
+            self.finish(session, reason);
+        }
+        self.active.store(!sessions.is_empty(), Ordering::Relaxed);
+    }
+
+    fn end_session(&self, id: u32, reason: ExportStopReason) -> Option<ProcessCaptureStatus> {
+        let mut sessions = self.sessions.lock();
+        let idx = sessions.iter().position(|s| s.status.id == id)?;
+        let session = sessions.remove(idx);
+        self.active.store(!sessions.is_empty(), Ordering::Relaxed);
+        Some(self.finish(session, reason))
+    }
+
+    /// Flush and close a session's file and move it to the finished list.
+    fn finish(&self, session: ExportSession, reason: ExportStopReason) -> ProcessCaptureStatus {
+        let mut status = session.status;
+        status.stop_reason = Some(reason);
+        if let Err(e) = session.writer.finish() {
+            tracing::warn!("Packet export {} flush failed: {e:#}", status.id);
+            status.stop_reason = Some(ExportStopReason::WriteError);
+        }
+        tracing::info!(
+            "Packet export {} finished ({:?}): {} packets, {} bytes",
+            status.id,
+            status.stop_reason,
+            status.packets,
+            status.bytes_written
+        );
+
+        let mut finished = self.finished.lock();
+        if finished.len() >= config::FINISHED_EXPORTS_KEPT {
+            finished.pop_front();
+        }
+        finished.push_back(status.clone());
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::mod_test_helpers::build_ipv4_packet;
+    use crate::capture::parse_ip_packet;
+    use crate::capture::pcap::{CaptureFileReader, LINKTYPE_RAW};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("netguard-export-{}-{name}", std::process::id()))
+    }
+
+    fn mapper_with(pid: u32, name: &str, exe_path: &str) -> ProcessMapper {
+        let mapper = ProcessMapper::new();
+        mapper.process_info.insert(
+            pid,
+            ProcessInfo {
+                name: name.into(),
+                exe_path: exe_path.into(),
+                args: String::new(),
+            },
+        );
+        mapper
+    }
+
+    fn read_frames(path: &Path) -> Vec<Vec<u8>> {
+        CaptureFileReader::open(path)
+            .unwrap()
+            .map(|r| {
+                let r = r.unwrap();
+                assert_eq!(r.linktype, LINKTYPE_RAW);
+                r.frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_export_keeps_only_target_pid_with_comment() {
+        let path = temp_path("pid.pcapng");
+        let recorder = Arc::new(PacketRecorder::new());
+        let mapper = mapper_with(42, "curl", "/usr/bin/curl");
+        let ours = build_ipv4_packet(6, 5000, 443);
+        let theirs = build_ipv4_packet(6, 6000, 443);
+
+        let id = recorder
+            .start_export(
+                CaptureTarget::Pid(42),
+                &path,
+                Duration::from_secs(60),
+                1 << 20,
+            )
+            .unwrap();
+        recorder.record(
+            &mapper,
+            Some(42),
+            &ours,
+            parse_ip_packet(&ours).as_ref(),
+            true,
+        );
+        recorder.record(
+            &mapper,
+            Some(7),
+            &theirs,
+            parse_ip_packet(&theirs).as_ref(),
+            true,
+        );
+        recorder.record(
+            &mapper,
+            None,
+            &theirs,
+            parse_ip_packet(&theirs).as_ref(),
+            false,
+        );
+        let status = recorder.stop_export(id).unwrap();
+
+        assert_eq!(status.packets, 1);
+        assert_eq!(status.stop_reason, Some(ExportStopReason::Stopped));
+        assert_eq!(read_frames(&path), vec![ours]);
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len() as u64, status.bytes_written);
+        assert!(bytes
+            .windows(b"curl (PID 42)".len())
+            .any(|w| w == b"curl (PID 42)"));
+        assert!(recorder.stop_export(id).is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_unparseable_packets_skip_the_flight_recorder() {
+        let recorder = PacketRecorder::new();
+        let mapper = ProcessMapper::new();
+        let pkt = build_ipv4_packet(6, 5000, 443);
+        recorder.record(&mapper, None, &[0xFF, 0x00], None, true);
+        recorder.record(&mapper, None, &pkt, parse_ip_packet(&pkt).as_ref(), true);
+        assert_eq!(recorder.flight_recorder().status().len, 1);
+    }
+
+    #[test]
+    fn test_export_by_exe_path_matches_every_instance() {
+        let path = temp_path("exe.pcapng");
+        let recorder = Arc::new(PacketRecorder::new());
+        let mapper = mapper_with(42, "app", "/opt/app");
+        mapper.process_info.insert(
+            43,
+            ProcessInfo {
+                name: "app".into(),
+                exe_path: "/opt/app".into(),
+                args: String::new(),
+            },
+        );
+        let pkt = build_ipv4_packet(17, 5000, 53);
+
+        let id = recorder
+            .start_export(
+                CaptureTarget::ExePath("/opt/app".into()),
+                &path,
+                Duration::from_secs(60),
+                1 << 20,
+            )
+            .unwrap();
+        recorder.record(
+            &mapper,
+            Some(42),
+            &pkt,
+            parse_ip_packet(&pkt).as_ref(),
+            true,
+        );
+        recorder.record(
+            &mapper,
+            Some(43),
+            &pkt,
+            parse_ip_packet(&pkt).as_ref(),
+            false,
+        );
+        recorder.record(
+            &mapper,
+            Some(99),
+            &pkt,
+            parse_ip_packet(&pkt).as_ref(),
+            false,
+        );
+        assert_eq!(recorder.stop_export(id).unwrap().packets, 2);
+        assert_eq!(read_frames(&path).len(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_export_stops_before_exceeding_size() {
+        let path = temp_path("size.pcapng");
+        let recorder = Arc::new(PacketRecorder::new());
+        let mapper = mapper_with(42, "curl", "/usr/bin/curl");
+        let pkt = build_ipv4_packet(6, 5000, 443);
+        let block = PcapngWriter::<Vec<u8>>::packet_block_len(pkt.len(), Some("curl (PID 42)"));
+        let header = PcapngWriter::new(Vec::new()).unwrap().bytes_written();
+
+        recorder
+            .start_export(
+                CaptureTarget::Pid(42),
+                &path,
+                Duration::from_secs(60),
+                header + 2 * block + block / 2,
+            )
+            .unwrap();
+        for _ in 0..5 {
+            recorder.record(
+                &mapper,
+                Some(42),
+                &pkt,
+                parse_ip_packet(&pkt).as_ref(),
+                true,
+            );
+        }
+
+        let statuses = recorder.export_statuses();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].stop_reason, Some(ExportStopReason::Size));
+        assert_eq!(statuses[0].packets, 2);
+        assert_eq!(read_frames(&path).len(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_export_ends_at_deadline_without_traffic() {
+        let path = temp_path("duration.pcapng");
+        let recorder = Arc::new(PacketRecorder::new());
+
+        recorder
+            .start_export(
+                CaptureTarget::Pid(42),
+                &path,
+                Duration::from_millis(100),
+                1 << 20,
+            )
+            .unwrap();
+        let start = Instant::now();
+        while recorder.export_statuses()[0].stop_reason.is_none() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "export never ended"
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        assert_eq!(
+            recorder.export_statuses()[0].stop_reason,
+            Some(ExportStopReason::Duration)
+        );
+        assert!(read_frames(&path).is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,590 @@
+//! Offline replay of pcap/pcapng files through the live accounting path.
+//!
+//! Each packet goes through [`process_sniff_packet`] (→ `parse_ip_packet` →
+//! `TrafficTracker::record_bytes`) exactly as in SNIFF mode. Instead of the
+//! live `ProcessMapper` scan, ports are attributed from a JSON mapping file,
+//! and speeds/history are computed on a virtual clock driven by the packet
+//! timestamps, so history rows carry the original capture times.
+//!
+//! Mapping file format:
+//!
+//! ```json
+//! {
+//!   "local_addresses": ["192.168.1.10", "fe80::1"],
+//!   "processes": [
+//!     { "pid": 4242, "name": "chrome.exe", "exe_path": "C:\\chrome.exe",
+//!       "ports": [{ "protocol": "tcp", "port": 50123 },
+//!                 { "protocol": "udp", "port": 5353, "address": "192.168.1.10" }] }
+//!   ]
+//! }
+//! ```
+//!
+//! Packet direction is taken from the capture itself when the link type
+//! records it (Linux cooked captures), otherwise from `local_addresses`, and
+//! finally from which side's port appears in the mapping. Ports without an
+//! `address` are treated as bound to the wildcard address.
+//!
+//! Replayed history belongs to another host and another time, so it is never
+//! written to the live history database: [`replay_capture_file`] writes it to
+//! a database of its own.
+
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::capture::pcap::{ip_payload, CaptureFileReader, CaptureRecord};
+use crate::capture::pipeline::process_sniff_packet;
+use crate::capture::{parse_ip_packet, ParsedPacket};
+use crate::config;
+use crate::core::process_mapper::{port_key, ProcessInfo, ProcessMapper, Protocol};
+use crate::core::traffic::{ProcessTrafficSnapshot, TrafficTracker};
+use crate::db::Database;
+use crate::services::history_records;
+
+/// Port→PID attribution used in place of the live process scan.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReplayMapping {
+    /// Addresses of the capturing host, used to tell upload from download.
+    #[serde(default)]
+    pub local_addresses: Vec<IpAddr>,
+    pub processes: Vec<ReplayProcess>,
+}
+
+/// One process of the capturing host and the local ports it owned.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplayProcess {
+    pub pid: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub exe_path: String,
+    #[serde(default)]
+    pub ports: Vec<ReplayPort>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ReplayPort {
+    #[serde(deserialize_with = "deserialize_protocol")]
+    pub protocol: Protocol,
+    pub port: u16,
+    /// Local bind address; `None` means the wildcard (`0.0.0.0`).
+    #[serde(default)]
+    pub address: Option<IpAddr>,
+}
+
+/// Accept protocol names in any case (`"tcp"`, `"TCP"`, `"Tcp"`).
+/// ICMP entries map a ping socket's echo identifier.
+fn deserialize_protocol<'de, D>(deserializer: D) -> std::result::Result<Protocol, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    match name.to_ascii_lowercase().as_str() {
+        "tcp" => Ok(Protocol::Tcp),
+        "udp" => Ok(Protocol::Udp),
+        "icmp" => Ok(Protocol::Icmp),
+        "icmpv6" => Ok(Protocol::Icmpv6),
+        _ => Err(serde::de::Error::unknown_variant(
+            &name,
+            &["tcp", "udp", "icmp", "icmpv6"],
+        )),
+    }
+}
+
+impl ReplayMapping {
+    /// Load a mapping file (JSON).
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read mapping file {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid mapping file {}", path.display()))
+    }
+
+    /// Build a static `ProcessMapper` holding this mapping. It is never
+    /// scanned, so entries stay fixed for the whole replay.
+    fn to_mapper(&self) -> ProcessMapper {
+        let mapper = ProcessMapper::offline();
+        for process in &self.processes {
+            let name = if process.name.is_empty() {
+                format!("PID {}", process.pid)
+            } else {
+                process.name.clone()
+            };
+            mapper.process_info.insert(
+                process.pid,
+                ProcessInfo {
+                    name,
+                    exe_path: process.exe_path.clone(),
+                    args: String::new(),
+                },
+            );
+            for port in &process.ports {
+                let addr = port.address.unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+                mapper
+                    .port_map
+                    .insert(port_key(port.protocol, addr, port.port), process.pid);
+            }
+        }
+        mapper
+    }
+}
+
+/// Outcome of a replay, returned to the frontend.
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct ReplaySummary {
+    /// Frames read from the capture file.
+    #[ts(type = "number")]
+    pub total_packets: u64,
+    /// Packets credited to a mapped process.
+    #[ts(type = "number")]
+    pub attributed_packets: u64,
+    /// Frames skipped because they were not IP or used an unsupported link type.
+    #[ts(type = "number")]
+    pub unsupported_packets: u64,
+    /// Packets skipped because neither the link layer, the local addresses
+    /// nor a mapped port told which way they went.
+    #[ts(type = "number")]
+    pub undirected_packets: u64,
+    /// Unix timestamp (seconds) of the first packet, 0 if the file was empty.
+    #[ts(type = "number")]
+    pub first_timestamp: i64,
+    /// Unix timestamp (seconds) of the last packet, 0 if the file was empty.
+    #[ts(type = "number")]
+    pub last_timestamp: i64,
+    /// Rows written to the replay's history database.
+    #[ts(type = "number")]
+    pub history_records: u64,
+    /// Final per-process totals.
+    pub processes: Vec<ProcessTrafficSnapshot>,
+}
+
+/// Replay `capture_path` attributed by `mapping_path`. History is written to
+/// a separate database at `history_path`, or kept in memory and discarded if
+/// none is given.
+pub fn replay_capture_file(
+    capture_path: &Path,
+    mapping_path: &Path,
+    history_path: Option<&Path>,
+) -> Result<ReplaySummary> {
+    let mapping = ReplayMapping::load(mapping_path)?;
+    let reader = CaptureFileReader::open(capture_path)?;
+    let db = Database::open(history_path.unwrap_or(Path::new(":memory:")))
+        .context("Failed to open the replay history database")?;
+    tracing::info!(
+        "Replaying {} with {} mapped processes",
+        capture_path.display(),
+        mapping.processes.len()
+    );
+    replay(reader, &mapping, Some(&db))
+}
+
+/// Replay a stream of capture records. History rows are written to `db`
+/// when one is given.
+pub fn replay(
+    records: impl IntoIterator<Item = Result<CaptureRecord>>,
+    mapping: &ReplayMapping,
+    db: Option<&Database>,
+) -> Result<ReplaySummary> {
+    let mut replayer = Replayer::new(mapping, db);
+    for record in records {
+        replayer.packet(&record?)?;
+    }
+    replayer.finish()
+}
+
+/// Virtual-clock replay state.
+struct Replayer<'a> {
+    mapper: ProcessMapper,
+    tracker: TrafficTracker,
+    local_addresses: HashSet<IpAddr>,
+    db: Option<&'a Database>,
+    /// Wall-clock instant standing in for the first packet's timestamp.
+    base: Instant,
+    first_us: Option<i64>,
+    next_tick_us: i64,
+    tick_count: u64,
+    bytes_since_tick: bool,
+    summary: ReplaySummary,
+}
+
+const TICK_US: i64 = config::STATS_INTERVAL_SECS as i64 * 1_000_000;
+
+/// Aggregator ticks per history row, mirroring the live recorder.
+const TICKS_PER_RECORD: u64 = {
+    let ticks = config::HISTORY_RECORD_INTERVAL_SECS / config::STATS_INTERVAL_SECS;
+    if ticks == 0 {
+        1
+    } else {
+        ticks
+    }
+};
+
+impl<'a> Replayer<'a> {
+    fn new(mapping: &ReplayMapping, db: Option<&'a Database>) -> Self {
+        Self {
+            mapper: mapping.to_mapper(),
+            tracker: TrafficTracker::new(),
+            local_addresses: mapping.local_addresses.iter().copied().collect(),
+            db,
+            base: Instant::now(),
+            first_us: None,
+            next_tick_us: 0,
+            tick_count: 0,
+            bytes_since_tick: false,
+            summary: ReplaySummary {
+                total_packets: 0,
+                attributed_packets: 0,
+                unsupported_packets: 0,
+                undirected_packets: 0,
+                first_timestamp: 0,
+                last_timestamp: 0,
+                history_records: 0,
+                processes: Vec::new(),
+            },
+        }
+    }
+
+    fn packet(&mut self, record: &CaptureRecord) -> Result<()> {
+        self.summary.total_packets += 1;
+        if self.first_us.is_none() {
+            self.first_us = Some(record.timestamp_us);
+            self.summary.first_timestamp = record.timestamp_us.div_euclid(1_000_000);
+            self.next_tick_us = record.timestamp_us + TICK_US;
+            // Establish the speed baseline at the first packet.
+            self.tracker.tick_speeds_at(self.base);
+        }
+        self.summary.last_timestamp = record.timestamp_us.div_euclid(1_000_000);
+        self.advance_to(record.timestamp_us)?;
+
+        let Some((data, direction)) = ip_payload(record.linktype, &record.frame) else {
+            self.summary.unsupported_packets += 1;
+            return Ok(());
+        };
+        let packet = parse_ip_packet(data);
+        let inferred = || packet.as_ref().and_then(|p| self.infer_direction(p));
+        let Some(outbound) = direction.or_else(inferred) else {
+            self.summary.undirected_packets += 1;
+            return Ok(());
+        };
+        if process_sniff_packet(&self.mapper, &self.tracker, data, packet.as_ref(), outbound)
+            .is_some()
+        {
+            self.summary.attributed_packets += 1;
+            self.bytes_since_tick = true;
+        }
+        Ok(())
+    }
+
+    /// Direction from local addresses, falling back to whichever port is mapped.
+    fn infer_direction(&self, packet: &ParsedPacket) -> Option<bool> {
+        if self.local_addresses.contains(&packet.src_addr) {
+            return Some(true);
+        }
+        if self.local_addresses.contains(&packet.dst_addr) {
+            return Some(false);
+        }
+        let (src_port, dst_port) = packet.ports?;
+        let mapped = |addr, port| {
+            self.mapper
+                .lookup_pid(packet.protocol, addr, port)
+                .is_some()
+        };
+        if mapped(packet.src_addr, src_port) {
+            Some(true)
+        } else if mapped(packet.dst_addr, dst_port) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Run every aggregator tick due before `timestamp_us`.
+    fn advance_to(&mut self, timestamp_us: i64) -> Result<()> {
+        while timestamp_us >= self.next_tick_us {
+            let quiet = !self.bytes_since_tick;
+            self.tick()?;
+
+            // After a quiet tick every speed is zero, so further ticks in an
+            // idle gap cannot produce history rows. Jump over them, keeping
+            // the tick phase so history timestamps stay aligned.
+            let idle_ticks = (timestamp_us - self.next_tick_us) / TICK_US;
+            if quiet && idle_ticks > 0 {
+                self.next_tick_us += idle_ticks * TICK_US;
+                self.tick_count += idle_ticks as u64;
+            }
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        let tick_us = self.next_tick_us;
+        self.tracker.resolve_pending(&self.mapper);
+        self.tracker.tick_speeds_at(self.virtual_instant(tick_us));
+        self.tick_count += 1;
+        self.next_tick_us += TICK_US;
+        self.bytes_since_tick = false;
+
+        if self.tick_count % TICKS_PER_RECORD == 0 {
+            if let Some(db) = self.db {
+                // Sort so rows sharing a timestamp are inserted in a stable order.
+                let mut snapshot = self.tracker.snapshot(&self.mapper);
+                snapshot.sort_by_key(|p| p.pid);
+                let records = history_records(&snapshot, tick_us.div_euclid(1_000_000));
+                if !records.is_empty() {
+                    db.insert_traffic_batch(&records)?;
+                    self.summary.history_records += records.len() as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn virtual_instant(&self, timestamp_us: i64) -> Instant {
+        let offset = timestamp_us - self.first_us.unwrap_or(timestamp_us);
+        self.base + Duration::from_micros(offset.max(0) as u64)
+    }
+
+    /// Flush ticks up to the next history boundary so trailing traffic is
+    /// recorded, then return the summary.
+    fn finish(mut self) -> Result<ReplaySummary> {
+        if self.first_us.is_some() {
+            // The mapping never changes, so nothing left pending can resolve.
+            self.tracker.expire_pending();
+            loop {
+                self.tick()?;
+                if self.tick_count % TICKS_PER_RECORD == 0 {
+                    break;
+                }
+            }
+        }
+        self.summary.processes = self.tracker.snapshot(&self.mapper);
+        self.summary
+            .processes
+            .sort_by_key(|p| std::cmp::Reverse(p.bytes_sent + p.bytes_recv));
+        Ok(self.summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::mod_test_helpers::build_ipv4_packet;
+    use crate::capture::pcap::test_files::{pcap_file, pcapng_file};
+    use crate::capture::pcap::LINKTYPE_RAW;
+
+    const T0: i64 = 1_700_000_000_000_000;
+
+    const MAPPING: &str = r#"{
+        "local_addresses": ["10.0.0.2"],
+        "processes": [
+            { "pid": 42, "name": "browser", "exe_path": "/usr/bin/browser",
+              "ports": [{ "protocol": "tcp", "port": 5000 }] },
+            { "pid": 7, "name": "dns",
+              "ports": [{ "protocol": "Udp", "port": 5353 }] }
+        ]
+    }"#;
+
+    fn mapping() -> ReplayMapping {
+        serde_json::from_str(MAPPING).unwrap()
+    }
+
+    fn packet(protocol: u8, src: ([u8; 4], u16), dst: ([u8; 4], u16)) -> Vec<u8> {
+        let mut pkt = build_ipv4_packet(protocol, src.1, dst.1);
+        pkt[12..16].copy_from_slice(&src.0);
+        pkt[16..20].copy_from_slice(&dst.0);
+        pkt
+    }
+
+    fn open_memory_db() -> Database {
+        Database::open(Path::new(":memory:")).unwrap()
+    }
+
+    /// Ten seconds of traffic: one upload packet per 100 ms for PID 42 and
+    /// one download packet per second for PID 7.
+    fn ten_seconds() -> Vec<(i64, Vec<u8>)> {
+        ten_seconds_from(T0)
+    }
+
+    fn ten_seconds_from(start: i64) -> Vec<(i64, Vec<u8>)> {
+        let local = [10, 0, 0, 2];
+        let remote = [93, 184, 216, 34];
+        let mut packets = Vec::new();
+        for i in 0..100 {
+            packets.push((start + i * 100_000, packet(6, (local, 5000), (remote, 443))));
+            if i % 10 == 0 {
+                packets.push((
+                    start + i * 100_000 + 50_000,
+                    packet(17, (remote, 53), (local, 5353)),
+                ));
+            }
+        }
+        packets
+    }
+
+    fn replay_bytes(bytes: Vec<u8>, db: Option<&Database>) -> ReplaySummary {
+        let reader = CaptureFileReader::new(bytes.as_slice()).unwrap();
+        replay(reader, &mapping(), db).unwrap()
+    }
+
+    #[test]
+    fn test_replay_attributes_bytes_per_process() {
+        let summary = replay_bytes(pcap_file(LINKTYPE_RAW, &ten_seconds()), None);
+
+        assert_eq!(summary.total_packets, 110);
+        assert_eq!(summary.attributed_packets, 110);
+        assert_eq!(summary.unsupported_packets, 0);
+        assert_eq!(summary.first_timestamp, T0 / 1_000_000);
+        assert_eq!(summary.last_timestamp, T0 / 1_000_000 + 9);
+
+        let browser = summary.processes.iter().find(|p| p.pid == 42).unwrap();
+        assert_eq!(browser.name, "browser");
+        assert_eq!(browser.bytes_sent, 100 * 24);
+        assert_eq!(browser.bytes_recv, 0);
+        let dns = summary.processes.iter().find(|p| p.pid == 7).unwrap();
+        assert_eq!(dns.bytes_recv, 10 * 24);
+    }
+
+    #[test]
+    fn test_replay_history_uses_capture_timestamps() {
+        let db = open_memory_db();
+        let summary = replay_bytes(pcapng_file(LINKTYPE_RAW, &ten_seconds(), false), Some(&db));
+
+        let t0 = T0 / 1_000_000;
+        let rows = db.query_history(0, i64::MAX, Some("browser")).unwrap();
+        let timestamps: Vec<i64> = rows.iter().map(|r| r.timestamp).collect();
+        assert_eq!(timestamps, vec![t0 + 5, t0 + 10]);
+        // Steady 10 packets/s × 24 bytes between virtual ticks.
+        assert!((rows[0].upload_speed - 240.0).abs() < 1e-6);
+        assert_eq!(rows[1].bytes_sent, 100 * 24);
+        assert_eq!(summary.history_records, 4);
+
+        let top = db.top_consumers(t0, t0 + 10, 10).unwrap();
+        assert_eq!(top[0].process_name, "browser");
+    }
+
+    #[test]
+    fn test_replay_file_writes_history_to_its_own_database() {
+        let path = |name: &str| {
+            std::env::temp_dir().join(format!("netguard-replay-{}-{name}", std::process::id()))
+        };
+        let (capture, mapping_file, history) =
+            (path("in.pcap"), path("mapping.json"), path("history.db"));
+        std::fs::write(&capture, pcap_file(LINKTYPE_RAW, &ten_seconds())).unwrap();
+        std::fs::write(&mapping_file, MAPPING).unwrap();
+        let _ = std::fs::remove_file(&history);
+
+        let summary = replay_capture_file(&capture, &mapping_file, Some(&history)).unwrap();
+        assert_eq!(summary.history_records, 4);
+        let rows = Database::open(&history)
+            .unwrap()
+            .query_history(0, i64::MAX, None)
+            .unwrap();
+        assert_eq!(rows.len(), 4);
+
+        // Without a history database the rows are discarded with the summary.
+        let summary = replay_capture_file(&capture, &mapping_file, None).unwrap();
+        assert_eq!(summary.history_records, 4);
+
+        let wal = [path("history.db-wal"), path("history.db-shm")];
+        for file in [capture, mapping_file, history].into_iter().chain(wal) {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn test_replay_is_deterministic() {
+        let file = pcap_file(LINKTYPE_RAW, &ten_seconds());
+        let a = open_memory_db();
+        let b = open_memory_db();
+        replay_bytes(file.clone(), Some(&a));
+        replay_bytes(file, Some(&b));
+
+        let rows = |db: &Database| -> Vec<(i64, String, u64, u64)> {
+            db.query_history(0, i64::MAX, None)
+                .unwrap()
+                .into_iter()
+                .map(|r| (r.timestamp, r.process_name, r.bytes_sent, r.bytes_recv))
+                .collect()
+        };
+        assert_eq!(rows(&a), rows(&b));
+    }
+
+    #[test]
+    fn test_replay_skips_idle_gap_without_spurious_rows() {
+        // Two ten-second bursts a day apart.
+        let day_us = 86_400_000_000;
+        let mut packets = ten_seconds();
+        packets.extend(ten_seconds_from(T0 + day_us));
+        let db = open_memory_db();
+        let summary = replay_bytes(pcap_file(LINKTYPE_RAW, &packets), Some(&db));
+
+        assert_eq!(summary.attributed_packets, 220);
+        let t0 = T0 / 1_000_000;
+        let rows = db.query_history(0, i64::MAX, Some("browser")).unwrap();
+        let timestamps: Vec<i64> = rows.iter().map(|r| r.timestamp).collect();
+        assert_eq!(timestamps, vec![t0 + 5, t0 + 10, t0 + 86_405, t0 + 86_410]);
+        assert!(rows.iter().all(|r| (r.upload_speed - 240.0).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_replay_direction_falls_back_to_mapped_port() {
+        let mut mapping = mapping();
+        mapping.local_addresses.clear();
+        let pkt = packet(6, ([1, 1, 1, 1], 443), ([2, 2, 2, 2], 5000));
+        let unmapped = packet(6, ([1, 1, 1, 1], 443), ([2, 2, 2, 2], 6000));
+        let reader = CaptureFileReader::new(
+            pcap_file(
+                LINKTYPE_RAW,
+                &[(T0, pkt.clone()), (T0 + 1, pkt), (T0 + 2, unmapped)],
+            )
+            .as_slice(),
+        )
+        .unwrap()
+        .collect::<Vec<_>>();
+        let summary = replay(reader, &mapping, None).unwrap();
+
+        let browser = summary.processes.iter().find(|p| p.pid == 42).unwrap();
+        assert_eq!(browser.bytes_recv, 48);
+        assert_eq!(browser.bytes_sent, 0);
+        // No way to tell the direction of the unmapped packet: counted, not guessed.
+        assert_eq!(summary.total_packets, 3);
+        assert_eq!(summary.undirected_packets, 1);
+    }
+
+    #[test]
+    fn test_replay_counts_unsupported_frames() {
+        let summary = replay_bytes(pcap_file(105, &[(T0, vec![0u8; 30])]), None);
+        assert_eq!(summary.total_packets, 1);
+        assert_eq!(summary.unsupported_packets, 1);
+        assert!(summary.processes.is_empty());
+    }
+
+    #[test]
+    fn test_mapping_rejects_unknown_protocol() {
+        let result: Result<ReplayMapping, _> = serde_json::from_str(
+            r#"{ "processes": [{ "pid": 1, "ports": [{ "protocol": "sctp", "port": 1 }] }] }"#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_mapping_port_address_defaults_to_wildcard() {
+        let mapping: ReplayMapping = serde_json::from_str(
+            r#"{ "processes": [
+                { "pid": 1, "ports": [{ "protocol": "tcp", "port": 80 }] },
+                { "pid": 2, "ports": [{ "protocol": "tcp", "port": 80, "address": "10.0.0.2" }] }
+            ] }"#,
+        )
+        .unwrap();
+        let mapper = mapping.to_mapper();
+        let lookup = |addr: [u8; 4]| mapper.lookup_pid(Protocol::Tcp, IpAddr::from(addr), 80);
+        assert_eq!(lookup([10, 0, 0, 2]), Some(2));
+        assert_eq!(lookup([127, 0, 0, 1]), Some(1));
+    }
+}
@@ -0,0 +1,652 @@
+//! Per-process delay queues for INTERCEPT shaping.
+//!
+//! Packets of processes with a shaping limit are held here instead of being
+//! dropped when their token bucket runs dry. A dedicated shaper thread
+//! ([`run_shaper_loop`]) releases them as tokens refill and re-injects them.
+//! Each process and direction has its own FIFO queue, bounded by
+//! `SHAPER_MAX_QUEUE_PACKETS` (drop-tail); [`LimitMode::ShapeCodel`] queues
+//! additionally run CoDel so that a standing queue makes TCP back off.
+//!
+//! When several queues wait on the same group or global limit, a weighted fair
+//! queueing scheduler decides who goes first: strict priority between
+//! [`PriorityClass`]es, and shares proportional to rule weights within a class.
+//!
+//! On shutdown every held packet is re-injected (fail-open).
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::capture::backend::CaptureBackend;
+use crate::config::{SHAPER_CODEL_INTERVAL_MS, SHAPER_CODEL_TARGET_MS, SHAPER_MAX_QUEUE_PACKETS};
+use crate::core::rate_limiter::{
+    LimitMode, PriorityClass, RateLimiterManager, ShapedSend, Shaping,
+};
+
+/// Longest the shaper thread sleeps between checks of its stop flag.
+const MAX_WAIT: Duration = Duration::from_millis(50);
+
+/// Result of offering a packet to the shaper.
+pub(crate) enum Admit<P> {
+    /// Within budget and nothing queued ahead of it: send now.
+    Send(P),
+    /// Held until tokens are available.
+    Queued,
+    /// The queue is full: drop the packet.
+    Drop(P),
+}
+
+/// Packets collected by one [`Shaper::release`] pass.
+pub(crate) struct Released<P> {
+    pub send: Vec<P>,
+    pub drop: Vec<P>,
+    /// Earliest time a still-queued packet can be sent.
+    pub next_deadline: Option<Instant>,
+}
+
+struct Held<P> {
+    packet: P,
+    bytes: u64,
+    enqueued: Instant,
+    /// Virtual finish time: packets are released in increasing order of it.
+    finish: f64,
+}
+
+struct ShapeQueue<P> {
+    packets: VecDeque<Held<P>>,
+    codel: Option<Codel>,
+    priority: PriorityClass,
+    weight: u32,
+    /// Finish time of the last packet queued here.
+    last_finish: f64,
+}
+
+impl<P> ShapeQueue<P> {
+    fn new(shaping: Shaping, virtual_time: f64) -> Self {
+        let mut queue = Self {
+            packets: VecDeque::new(),
+            codel: None,
+            priority: shaping.priority,
+            weight: shaping.weight,
+            last_finish: virtual_time,
+        };
+        queue.configure(shaping);
+        queue
+    }
+
+    /// Follow changes of the process's rule.
+    fn configure(&mut self, shaping: Shaping) {
+        let codel = shaping.mode == LimitMode::ShapeCodel;
+        if self.codel.is_some() != codel {
+            self.codel = codel.then(Codel::default);
+        }
+        self.priority = shaping.priority;
+        self.weight = shaping.weight.max(1);
+    }
+
+    fn push(&mut self, packet: P, bytes: u64, now: Instant, virtual_time: f64) {
+        let finish = self.last_finish.max(virtual_time) + bytes as f64 / f64::from(self.weight);
+        self.last_finish = finish;
+        self.packets.push_back(Held {
+            packet,
+            bytes,
+            enqueued: now,
+            finish,
+        });
+    }
+
+    /// Scheduling order of the head packet: class first, then finish time.
+    fn head_rank(&self) -> Option<(PriorityClass, f64)> {
+        self.packets.front().map(|h| (self.priority, h.finish))
+    }
+}
+
+struct ShaperState<P> {
+    /// (pid, is_upload) → queue. Empty queues are removed.
+    queues: HashMap<(u32, bool), ShapeQueue<P>>,
+    /// Finish time of the last packet released (self-clocked fair queueing).
+    virtual_time: f64,
+    /// Set when a new queue appears, so a sleeping shaper thread re-plans.
+    woken: bool,
+}
+
+/// Delay queues shared by the intercept loop (producer) and the shaper thread.
+pub(crate) struct Shaper<P> {
+    rate_limiter: Arc<RateLimiterManager>,
+    state: Mutex<ShaperState<P>>,
+    wake: Condvar,
+}
+
+impl<P> Shaper<P> {
+    pub fn new(rate_limiter: Arc<RateLimiterManager>) -> Self {
+        Self {
+            rate_limiter,
+            state: Mutex::new(ShaperState {
+                queues: HashMap::new(),
+                virtual_time: 0.0,
+                woken: false,
+            }),
+            wake: Condvar::new(),
+        }
+    }
+
+    /// Offer a packet of a shaped process. It is sent straight away only if
+    /// its bucket covers it and nothing is queued ahead of it: neither in its
+    /// own queue (which stays FIFO) nor in a queue of the same or a higher
+    /// priority class, which must get the tokens first.
+    pub fn admit(
+        &self,
+        pid: u32,
+        bytes: u64,
+        is_upload: bool,
+        shaping: Shaping,
+        packet: P,
+        now: Instant,
+    ) -> Admit<P> {
+        let mut state = self.state.lock();
+        let ShaperState {
+            queues,
+            virtual_time,
+            woken,
+        } = &mut *state;
+        let key = (pid, is_upload);
+
+        if let Some(queue) = queues.get_mut(&key) {
+            if queue.packets.len() >= SHAPER_MAX_QUEUE_PACKETS {
+                return Admit::Drop(packet);
+            }
+            queue.configure(shaping);
+            queue.push(packet, bytes, now, *virtual_time);
+            return Admit::Queued;
+        }
+
+        // Only queues in the same direction compete for the same buckets.
+        let contended = queues
+            .iter()
+            .any(|(&(_, up), q)| up == is_upload && q.priority <= shaping.priority);
+        if !contended {
+            match self
+                .rate_limiter
+                .try_send_shaped(pid, bytes, is_upload, now)
+            {
+                ShapedSend::Send => return Admit::Send(packet),
+                ShapedSend::Drop => return Admit::Drop(packet),
+                ShapedSend::Wait(_) => {}
+            }
+        }
+        let mut queue = ShapeQueue::new(shaping, *virtual_time);
+        queue.push(packet, bytes, now, *virtual_time);
+        queues.insert(key, queue);
+        *woken = true;
+        self.wake.notify_one();
+        Admit::Queued
+    }
+
+    /// Take every packet whose tokens have arrived, plus the ones CoDel or a
+    /// block decided to drop.
+    ///
+    /// Queues are served highest priority class first and, within a class,
+    /// in order of virtual finish time (weighted fair queueing), so that
+    /// processes competing for a shared group or global limit get it in
+    /// proportion to their weights. A queue whose tokens have run out sits
+    /// out the rest of the pass without holding up the others.
+    pub fn release(&self, now: Instant) -> Released<P> {
+        let mut out = Released {
+            send: Vec::new(),
+            drop: Vec::new(),
+            next_deadline: None,
+        };
+        let mut state = self.state.lock();
+        let ShaperState {
+            queues,
+            virtual_time,
+            ..
+        } = &mut *state;
+        let mut waiting = HashSet::new();
+
+        loop {
+            let next = queues
+                .iter()
+                .filter(|(key, _)| !waiting.contains(*key))
+                .filter_map(|(key, queue)| Some((*key, queue.head_rank()?)))
+                .min_by(|(_, a), (_, b)| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+            let Some(((pid, is_upload), _)) = next else {
+                break;
+            };
+            let queue = queues.get_mut(&(pid, is_upload)).expect("queue exists");
+            let (bytes, sojourn) = match queue.packets.front() {
+                Some(head) => (head.bytes, now.saturating_duration_since(head.enqueued)),
+                None => continue,
+            };
+
+            let len = queue.packets.len();
+            let codel_drop = queue
+                .codel
+                .as_mut()
+                .is_some_and(|codel| codel.should_drop(sojourn, len, now));
+            if codel_drop {
+                out.drop.extend(queue.packets.pop_front().map(|h| h.packet));
+            } else {
+                match self
+                    .rate_limiter
+                    .try_send_shaped(pid, bytes, is_upload, now)
+                {
+                    ShapedSend::Send => {
+                        if let Some(head) = queue.packets.pop_front() {
+                            *virtual_time = head.finish;
+                            out.send.push(head.packet);
+                        }
+                    }
+                    ShapedSend::Drop => {
+                        out.drop.extend(queue.packets.pop_front().map(|h| h.packet))
+                    }
+                    ShapedSend::Wait(wait) => {
+                        let at = now + wait;
+                        out.next_deadline = Some(out.next_deadline.map_or(at, |d| d.min(at)));
+                        waiting.insert((pid, is_upload));
+                    }
+                }
+            }
+            if queue.packets.is_empty() {
+                queues.remove(&(pid, is_upload));
+            }
+        }
+
+        // Restart virtual time whenever the shaper idles.
+        if queues.is_empty() {
+            *virtual_time = 0.0;
+        }
+        out
+    }
+
+    /// Sleep until `deadline`, a new queue appears, [`Self::notify`] is
+    /// called, or `MAX_WAIT` passes, whichever is first.
+    pub fn wait(&self, deadline: Option<Instant>) {
+        let mut state = self.state.lock();
+        if !state.woken {
+            let max = Instant::now() + MAX_WAIT;
+            let until = deadline.map_or(max, |d| d.min(max));
+            self.wake.wait_until(&mut state, until);
+        }
+        state.woken = false;
+    }
+
+    /// Wake the shaper thread (e.g. to notice its stop flag).
+    pub fn notify(&self) {
+        self.state.lock().woken = true;
+        self.wake.notify_one();
+    }
+
+    /// Remove and return every held packet.
+    pub fn drain(&self) -> Vec<P> {
+        let mut state = self.state.lock();
+        state.virtual_time = 0.0;
+        state
+            .queues
+            .drain()
+            .flat_map(|(_, queue)| queue.packets.into_iter().map(|h| h.packet))
+            .collect()
+    }
+}
+
+/// Shaper thread body: re-inject released packets until `stop` is set, then
+/// re-inject everything still held.
+pub(crate) fn run_shaper_loop<B: CaptureBackend>(
+    backend: &B,
+    shaper: &Shaper<B::Packet>,
+    stop: &AtomicBool,
+) {
+    while !stop.load(Ordering::Relaxed) {
+        let released = shaper.release(Instant::now());
+        for packet in released.send {
+            if let Err(e) = backend.reinject(packet) {
+                tracing::error!("{} shaper send error: {e:#}", B::NAME);
+            }
+        }
+        for packet in released.drop {
+            if let Err(e) = backend.discard(packet) {
+                tracing::error!("{} shaper drop error: {e:#}", B::NAME);
+            }
+        }
+        shaper.wait(released.next_deadline);
+    }
+
+    let held = shaper.drain();
+    if !held.is_empty() {
+        tracing::info!("Re-injecting {} shaped packets on shutdown", held.len());
+    }
+    for packet in held {
+        let _ = backend.reinject(packet);
+    }
+}
+
+/// CoDel (RFC 8289) drop state for one queue.
+#[derive(Debug, Default)]
+struct Codel {
+    /// Deadline after which a sojourn time still above target allows dropping.
+    first_above: Option<Instant>,
+    dropping: bool,
+    drop_next: Option<Instant>,
+    count: u32,
+    last_count: u32,
+}
+
+impl Codel {
+    /// Decide whether to drop the head packet, which has waited `sojourn`
+    /// in a queue of `queue_len` packets.
+    fn should_drop(&mut self, sojourn: Duration, queue_len: usize, now: Instant) -> bool {
+        let target = Duration::from_millis(SHAPER_CODEL_TARGET_MS);
+        let interval = Duration::from_millis(SHAPER_CODEL_INTERVAL_MS);
+
+        let ok_to_drop = if sojourn < target || queue_len <= 1 {
+            self.first_above = None;
+            false
+        } else {
+            match self.first_above {
+                None => {
+                    self.first_above = Some(now + interval);
+                    false
+                }
+                Some(at) => now >= at,
+            }
+        };
+
+        if self.dropping {
+            if !ok_to_drop {
+                self.dropping = false;
+                return false;
+            }
+            let drop_next = self.drop_next.unwrap_or(now);
+            if now < drop_next {
+                return false;
+            }
+            self.count += 1;
+            self.drop_next = Some(control_law(drop_next, self.count));
+            return true;
+        }
+        if !ok_to_drop {
+            return false;
+        }
+
+        // Resume near the previous drop rate if the last dropping spell ended recently.
+        self.dropping = true;
+        let delta = self.count.saturating_sub(self.last_count);
+        let recent = self
+            .drop_next
+            .is_some_and(|t| now.saturating_duration_since(t) < interval * 16);
+        self.count = if delta > 1 && recent { delta } else { 1 };
+        self.last_count = self.count;
+        self.drop_next = Some(control_law(now, self.count));
+        true
+    }
+}
+
+/// Next drop time: drops get closer together as `interval / sqrt(count)`.
+fn control_law(t: Instant, count: u32) -> Instant {
+    t + Duration::from_millis(SHAPER_CODEL_INTERVAL_MS).div_f64(f64::from(count).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::rate_limiter::BandwidthLimit;
+
+    fn shaped_limiter(pid: u32, bps: u64, mode: LimitMode) -> Arc<RateLimiterManager> {
+        let limiter = Arc::new(RateLimiterManager::new());
+        limiter.set_limit(
+            pid,
+            BandwidthLimit {
+                download_bps: bps,
+                upload_bps: bps,
+                mode,
+                ..Default::default()
+            },
+        );
+        limiter
+    }
+
+    fn shaping(mode: LimitMode) -> Shaping {
+        Shaping {
+            mode,
+            priority: PriorityClass::Normal,
+            weight: 1,
+        }
+    }
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_admit_queues_over_budget_packets_in_order() {
+        // 1000 B/s with a 2000-byte burst.
+        let limiter = shaped_limiter(1, 1000, LimitMode::ShapeDropTail);
+        let shaper = Shaper::new(Arc::clone(&limiter));
+        let now = Instant::now();
+        let admit = |id: u32, bytes| {
+            shaper.admit(1, bytes, true, shaping(LimitMode::ShapeDropTail), id, now)
+        };
+
+        assert!(matches!(admit(1, 1500), Admit::Send(1)));
+        assert!(matches!(admit(2, 1000), Admit::Queued));
+        // Would fit the remaining 500 tokens, but must not overtake packet 2.
+        assert!(matches!(admit(3, 100), Admit::Queued));
+
+        let released = shaper.release(now);
+        assert!(released.send.is_empty());
+        let deadline = released.next_deadline.unwrap();
+        assert!(deadline >= now + ms(490) && deadline <= now + ms(510));
+
+        let released = shaper.release(now + ms(510));
+        assert_eq!(released.send, vec![2]);
+        let released = shaper.release(now + ms(700));
+        assert_eq!(released.send, vec![3]);
+        assert!(released.next_deadline.is_none());
+    }
+
+    #[test]
+    fn test_drop_tail_bounds_queue() {
+        let limiter = shaped_limiter(1, 1000, LimitMode::ShapeDropTail);
+        let shaper = Shaper::new(Arc::clone(&limiter));
+        let now = Instant::now();
+        let admit = |id: usize, bytes| {
+            shaper.admit(1, bytes, false, shaping(LimitMode::ShapeDropTail), id, now)
+        };
+
+        assert!(matches!(admit(0, 2000), Admit::Send(_)));
+        for id in 1..=SHAPER_MAX_QUEUE_PACKETS {
+            assert!(matches!(admit(id, 100), Admit::Queued));
+        }
+        assert!(matches!(admit(usize::MAX, 100), Admit::Drop(usize::MAX)));
+        // The other direction has its own queue.
+        assert!(matches!(
+            shaper.admit(1, 100, true, shaping(LimitMode::ShapeDropTail), 0, now),
+            Admit::Send(0)
+        ));
+        assert_eq!(shaper.drain().len(), SHAPER_MAX_QUEUE_PACKETS);
+    }
+
+    #[test]
+    fn test_queue_flushes_when_limit_removed_and_drops_when_blocked() {
+        let limiter = shaped_limiter(1, 1000, LimitMode::ShapeCodel);
+        let shaper = Shaper::new(Arc::clone(&limiter));
+        let now = Instant::now();
+        for id in 0..3 {
+            shaper.admit(1, 1000, true, shaping(LimitMode::ShapeCodel), id, now);
+        }
+        limiter.block_process(1);
+        assert_eq!(shaper.release(now).drop, vec![2]);
+
+        limiter.unblock_process(1);
+        assert!(matches!(
+            shaper.admit(1, 1000, true, shaping(LimitMode::ShapeCodel), 3, now),
+            Admit::Queued
+        ));
+        limiter.remove_limit(1);
+        assert_eq!(shaper.release(now).send, vec![3]);
+        assert!(shaper.drain().is_empty());
+    }
+
+    #[test]
+    fn test_codel_drops_only_after_a_standing_interval() {
+        let mut codel = Codel::default();
+        let t0 = Instant::now();
+        let high = ms(SHAPER_CODEL_TARGET_MS * 4);
+
+        // Below target, or a single packet: never drop.
+        assert!(!codel.should_drop(ms(1), 10, t0));
+        assert!(!codel.should_drop(high, 1, t0));
+
+        // Above target: the first drop waits one interval.
+        assert!(!codel.should_drop(high, 10, t0));
+        assert!(!codel.should_drop(high, 10, t0 + ms(SHAPER_CODEL_INTERVAL_MS - 1)));
+        let first = t0 + ms(SHAPER_CODEL_INTERVAL_MS);
+        assert!(codel.should_drop(high, 10, first));
+
+        // Then drops come every interval / sqrt(count).
+        assert!(!codel.should_drop(high, 10, first + ms(50)));
+        assert!(codel.should_drop(high, 10, first + ms(SHAPER_CODEL_INTERVAL_MS)));
+        let second_gap = Duration::from_millis(SHAPER_CODEL_INTERVAL_MS).div_f64(2f64.sqrt());
+        let third = first + ms(SHAPER_CODEL_INTERVAL_MS) + second_gap;
+        assert!(!codel.should_drop(high, 10, third - ms(1)));
+        assert!(codel.should_drop(high, 10, third));
+
+        // Delay back under target ends the dropping state.
+        assert!(!codel.should_drop(ms(1), 10, third + ms(1)));
+        assert!(!codel.dropping);
+    }
+
+    #[test]
+    fn test_codel_queue_drops_stale_head_packets() {
+        let limiter = shaped_limiter(1, 1000, LimitMode::ShapeCodel);
+        let shaper = Shaper::new(Arc::clone(&limiter));
+        let now = Instant::now();
+        assert!(matches!(
+            shaper.admit(1, 2000, true, shaping(LimitMode::ShapeCodel), 0, now),
+            Admit::Send(0)
+        ));
+        for id in 1..=20 {
+            shaper.admit(1, 1000, true, shaping(LimitMode::ShapeCodel), id, now);
+        }
+
+        // The standing queue is noticed, then the head is dropped one interval later.
+        let later = now + ms(SHAPER_CODEL_INTERVAL_MS);
+        assert!(shaper.release(now + ms(50)).drop.is_empty());
+        let released = shaper.release(later + ms(50));
+        assert_eq!(released.drop, vec![1]);
+        assert!(released.send.is_empty());
+    }
+
+    /// A limiter where every process shapes under a shared 1000 B/s upload
+    /// cap (2000-byte burst), already used up at the returned instant.
+    fn contended_limiter(pids: &[u32]) -> (Arc<RateLimiterManager>, Instant) {
+        let limiter = Arc::new(RateLimiterManager::new());
+        limiter.set_global_limit(BandwidthLimit {
+            upload_bps: 1000,
+            ..Default::default()
+        });
+        for &pid in pids {
+            limiter.set_limit(
+                pid,
+                BandwidthLimit {
+                    mode: LimitMode::ShapeDropTail,
+                    ..Default::default()
+                },
+            );
+        }
+        let now = Instant::now();
+        assert_eq!(
+            limiter.try_send_shaped(pids[0], 2000, true, now),
+            ShapedSend::Send
+        );
+        (limiter, now)
+    }
+
+    #[test]
+    fn test_higher_priority_class_is_served_first() {
+        let (limiter, now) = contended_limiter(&[1, 2]);
+        let shaper = Shaper::new(Arc::clone(&limiter));
+        let high = Shaping {
+            priority: PriorityClass::High,
+            ..shaping(LimitMode::ShapeDropTail)
+        };
+        let low = Shaping {
+            priority: PriorityClass::Low,
+            ..shaping(LimitMode::ShapeDropTail)
+        };
+        // The low-priority backlog builds up first.
+        for seq in 0..4 {
+            assert!(matches!(
+                shaper.admit(2, 500, true, low, (2, seq), now),
+                Admit::Queued
+            ));
+        }
+        for seq in 0..4 {
+            assert!(matches!(
+                shaper.admit(1, 500, true, high, (1, seq), now),
+                Admit::Queued
+            ));
+        }
+
+        // Each second buys two 500-byte packets, all of which go to PID 1.
+        assert_eq!(shaper.release(now + ms(1000)).send, vec![(1, 0), (1, 1)]);
+        assert_eq!(shaper.release(now + ms(2000)).send, vec![(1, 2), (1, 3)]);
+        assert_eq!(shaper.release(now + ms(3000)).send, vec![(2, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn test_weights_split_contended_capacity() {
+        let (limiter, now) = contended_limiter(&[1, 2]);
+        let shaper = Shaper::new(Arc::clone(&limiter));
+        let heavy = Shaping {
+            weight: 4,
+            ..shaping(LimitMode::ShapeDropTail)
+        };
+        let light = shaping(LimitMode::ShapeDropTail);
+        for _ in 0..20 {
+            shaper.admit(1, 100, true, heavy, 1, now);
+            shaper.admit(2, 100, true, light, 2, now);
+        }
+
+        // 1000 bytes of tokens: ten 100-byte packets, shared 4:1.
+        let sent = shaper.release(now + ms(1000)).send;
+        assert_eq!(sent.len(), 10);
+        assert_eq!(sent.iter().filter(|&&pid| pid == 1).count(), 8);
+
+        // An idle competitor leaves the whole capacity to the other.
+        shaper.drain();
+        for _ in 0..5 {
+            shaper.admit(2, 100, true, light, 2, now + ms(1000));
+        }
+        assert_eq!(shaper.release(now + ms(1500)).send, vec![2; 5]);
+    }
+
+    #[test]
+    fn test_lower_class_backlog_does_not_delay_higher_class() {
+        let (limiter, now) = contended_limiter(&[1, 2, 3]);
+        let shaper = Shaper::new(Arc::clone(&limiter));
+        let low = Shaping {
+            priority: PriorityClass::Low,
+            ..shaping(LimitMode::ShapeDropTail)
+        };
+        assert!(matches!(
+            shaper.admit(2, 1000, true, low, 2, now),
+            Admit::Queued
+        ));
+
+        // Tokens arrived but the shaper thread has not run yet: a normal
+        // packet may take them directly, a second low one may not.
+        let later = now + ms(1000);
+        assert!(matches!(
+            shaper.admit(3, 100, true, low, 3, later),
+            Admit::Queued
+        ));
+        assert!(matches!(
+            shaper.admit(1, 100, true, shaping(LimitMode::ShapeDropTail), 1, later),
+            Admit::Send(1)
+        ));
+    }
+}
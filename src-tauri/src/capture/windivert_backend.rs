@@ -1,328 +1,144 @@
-//! Windows packet capture using WinDivert 2.x.
+//! Windows packet capture backend using WinDivert 2.x.
 //!
-//! Supports two modes:
-//! - SNIFF: read-only packet copies for monitoring (Phase 1, zero risk)
-//! - INTERCEPT: captures and re-injects packets for rate limiting (Phase 2+)
-//!
-//! Handle creation is separated from the capture loop so that the caller
-//! (CaptureEngine) can extract the raw HANDLE for cross-thread shutdown.
-//!
-//! SAFETY: In intercept mode, packets are diverted from the network stack.
-//! Always use the narrowest possible filter during development.
-//! See PRD section 8.2 for mandatory safeguards.
-
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+//! Implements [`CaptureBackend`] on top of a `WinDivert<NetworkLayer>` handle.
+//! The raw HANDLE is kept alongside the wrapper so that `shutdown` can call
+//! `WinDivertShutdown` from another thread while `recv` is blocked.
 
 use anyhow::{Context, Result};
 use windivert::prelude::*;
 
-use crate::capture::parse_ip_packet;
-use crate::core::process_mapper::ProcessMapper;
-use crate::core::rate_limiter::RateLimiterManager;
-use crate::core::traffic::TrafficTracker;
+use crate::capture::backend::{CaptureBackend, CapturedPacket};
 
-/// Create a WinDivert handle in SNIFF mode (read-only packet copies).
-pub fn create_sniff_handle() -> Result<WinDivert<windivert::layer::NetworkLayer>> {
-    let filter = "tcp or udp";
-    let flags = WinDivertFlags::new().set_sniff();
+/// Raw FFI for WinDivertShutdown — the safe wrapper requires `&mut self` which
+/// makes cross-thread shutdown impossible. The C API is explicitly thread-safe.
+mod wd_ffi {
+    pub const WINDIVERT_SHUTDOWN_RECV: u32 = 1;
 
-    tracing::info!("Opening WinDivert SNIFF handle with filter: {filter}");
-    WinDivert::network(filter, 0, flags).map_err(|e| {
-        tracing::error!("WinDivert::network() SNIFF failed: {e:?}");
-        anyhow::anyhow!(
-            "Failed to open WinDivert SNIFF handle (filter={filter}): {e:?}. \
-             Ensure WinDivert.dll and WinDivert64.sys are next to the executable \
-             and the app is running as administrator."
-        )
-    })
+    #[link(name = "WinDivert")]
+    extern "system" {
+        pub fn WinDivertShutdown(handle: isize, how: u32) -> i32;
+    }
 }
 
-/// Create a WinDivert handle in INTERCEPT mode (diverts packets from the stack).
-pub fn create_intercept_handle(filter: &str) -> Result<WinDivert<windivert::layer::NetworkLayer>> {
-    let flags = WinDivertFlags::new(); // default = intercept mode
-
-    tracing::info!("Opening WinDivert INTERCEPT handle with filter: {filter}");
-    WinDivert::network(filter, 0, flags)
-        .context("Failed to open WinDivert handle for intercept mode")
+/// Extract the raw WinDivert HANDLE from a `WinDivert<L>` wrapper.
+///
+/// SAFETY: Relies on `handle: HANDLE` (isize) being the first field of `WinDivert<L>`.
+/// Verified against windivert 0.6.0 source. If the crate changes its layout,
+/// the shutdown call will harmlessly fail (WinDivert returns FALSE for invalid
+/// handles) rather than cause UB.
+unsafe fn extract_wd_handle(wd: &WinDivert<windivert::layer::NetworkLayer>) -> isize {
+    *(wd as *const _ as *const isize)
 }
 
-/// Main SNIFF capture loop running in a dedicated OS thread.
-/// Packets are copied, never intercepted — zero risk to network connectivity.
-///
-/// Accepts a pre-created WinDivert handle (created by `create_sniff_handle`).
-pub fn run_sniff_loop(
+/// WinDivert network-layer handle plus its raw HANDLE for cross-thread shutdown.
+pub struct WinDivertBackend {
     wd: WinDivert<windivert::layer::NetworkLayer>,
-    process_mapper: Arc<ProcessMapper>,
-    traffic_tracker: Arc<TrafficTracker>,
-    shutdown: Arc<AtomicBool>,
-) -> Result<()> {
-    tracing::info!("WinDivert SNIFF capture loop started");
-
-    let mut buf = vec![0u8; 65535];
+    raw_handle: isize,
+}
 
-    while !shutdown.load(Ordering::Relaxed) {
-        match wd.recv(Some(&mut buf)) {
-            Ok(packet) => {
-                let outbound = packet.address.outbound();
-                process_sniff_packet(&process_mapper, &traffic_tracker, &packet.data, outbound);
-            }
-            Err(e) => {
-                if shutdown.load(Ordering::Relaxed) {
-                    break;
-                }
-                // NoData error means WinDivertShutdown was called — clean exit.
-                let err_str = format!("{e}");
-                if err_str.contains("NoData") || err_str.contains("232") {
-                    tracing::info!("WinDivert SNIFF recv got shutdown signal");
-                    break;
-                }
-                tracing::error!("WinDivert recv error: {e}");
-                std::thread::sleep(std::time::Duration::from_millis(100));
-            }
-        }
+impl WinDivertBackend {
+    fn from_handle(wd: WinDivert<windivert::layer::NetworkLayer>) -> Self {
+        let raw_handle = unsafe { extract_wd_handle(&wd) };
+        Self { wd, raw_handle }
     }
-
-    tracing::info!("WinDivert SNIFF capture stopped");
-    Ok(())
 }
 
-/// Intercept capture loop. Packets matching the filter are diverted from the
-/// network stack, passed through the rate limiter, and re-injected.
-///
-/// Uses drop-based policing: packets exceeding the rate limit are dropped
-/// rather than delayed, so the single-threaded loop never blocks. TCP
-/// congestion control naturally reduces throughput when packets are dropped.
-///
-/// Accepts a pre-created WinDivert handle (created by `create_intercept_handle`).
-///
-/// SAFETY: Uses a narrow filter (specific port) during Phase 2a development.
-/// See PRD S2 — never use "tcp or udp" in intercept mode during development.
-pub fn run_intercept_loop(
-    wd: WinDivert<windivert::layer::NetworkLayer>,
-    process_mapper: Arc<ProcessMapper>,
-    traffic_tracker: Arc<TrafficTracker>,
-    rate_limiter: Arc<RateLimiterManager>,
-    shutdown: Arc<AtomicBool>,
-) {
-    tracing::info!("WinDivert INTERCEPT capture loop started");
+impl CapturedPacket for WinDivertPacket<'static, windivert::layer::NetworkLayer> {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
 
-    let mut buf = vec![0u8; 65535];
+    fn outbound(&self) -> bool {
+        self.address.outbound()
+    }
+}
 
-    while !shutdown.load(Ordering::Relaxed) {
-        match wd.recv(Some(&mut buf)) {
-            Ok(packet) => {
-                // If shutdown was requested while we were blocked on recv,
-                // re-inject this packet and exit cleanly.
-                if shutdown.load(Ordering::Relaxed) {
-                    let _ = wd.send(&packet);
-                    break;
-                }
+impl CaptureBackend for WinDivertBackend {
+    type Packet = WinDivertPacket<'static, windivert::layer::NetworkLayer>;
+
+    const NAME: &'static str = "windivert";
+
+    /// Open a WinDivert handle in SNIFF mode (read-only packet copies).
+    fn open_sniff() -> Result<Self> {
+        let filter = "tcp or udp";
+        let flags = WinDivertFlags::new().set_sniff();
+
+        tracing::info!("Opening WinDivert SNIFF handle with filter: {filter}");
+        WinDivert::network(filter, 0, flags)
+            .map(Self::from_handle)
+            .map_err(|e| {
+                tracing::error!("WinDivert::network() SNIFF failed: {e:?}");
+                anyhow::anyhow!(
+                    "Failed to open WinDivert SNIFF handle (filter={filter}): {e:?}. \
+                     Ensure WinDivert.dll and WinDivert64.sys are next to the executable \
+                     and the app is running as administrator."
+                )
+            })
+    }
 
-                let outbound = packet.address.outbound();
+    /// Open a WinDivert handle in INTERCEPT mode (diverts packets from the stack).
+    fn open_intercept(filter: &str) -> Result<Self> {
+        let flags = WinDivertFlags::new(); // default = intercept mode
 
-                // Account traffic (same as SNIFF mode).
-                process_sniff_packet(&process_mapper, &traffic_tracker, &packet.data, outbound);
+        tracing::info!("Opening WinDivert INTERCEPT handle with filter: {filter}");
+        WinDivert::network(filter, 0, flags)
+            .map(Self::from_handle)
+            .context("Failed to open WinDivert handle for intercept mode")
+    }
 
-                // Decide: pass or drop.
-                // Non-rate-limited / non-blocked packets pass immediately.
-                // Blocked or over-budget packets are silently dropped.
-                if should_pass_packet(&process_mapper, &rate_limiter, &packet.data, outbound) {
-                    // Re-inject the packet back into the network stack.
-                    if let Err(e) = wd.send(&packet) {
-                        tracing::error!("WinDivert send error: {e}");
-                    }
-                }
-                // else: packet dropped (blocked or rate exceeded)
-            }
+    fn recv(&self, buf: &mut [u8]) -> Result<Option<Self::Packet>> {
+        match self.wd.recv(Some(buf)) {
+            Ok(packet) => Ok(Some(packet.into_owned())),
             Err(e) => {
-                if shutdown.load(Ordering::Relaxed) {
-                    break;
-                }
                 // NoData error means WinDivertShutdown was called — clean exit.
                 let err_str = format!("{e}");
                 if err_str.contains("NoData") || err_str.contains("232") {
-                    tracing::info!("WinDivert INTERCEPT recv got shutdown signal");
-                    break;
+                    return Ok(None);
                 }
-                tracing::error!("WinDivert recv error in intercept mode: {e}");
-                std::thread::sleep(std::time::Duration::from_millis(100));
+                Err(anyhow::anyhow!("{e}"))
             }
         }
     }
 
-    tracing::info!("WinDivert INTERCEPT capture stopped");
-}
-
-pub(crate) fn process_sniff_packet(
-    mapper: &ProcessMapper,
-    tracker: &TrafficTracker,
-    data: &[u8],
-    outbound: bool,
-) {
-    let Some((proto, src_port, dst_port, total_len)) = parse_ip_packet(data) else {
-        return;
-    };
-
-    let local_port = if outbound { src_port } else { dst_port };
+    fn reinject(&self, packet: Self::Packet) -> Result<()> {
+        self.wd
+            .send(&packet)
+            .map(|_| ())
+            .map_err(|e| anyhow::anyhow!("{e}"))
+    }
 
-    if let Some(pid) = mapper.lookup_pid(proto, local_port) {
-        if outbound {
-            tracker.record_bytes(pid, total_len, 0);
-        } else {
-            tracker.record_bytes(pid, 0, total_len);
+    /// Call WinDivertShutdown to unblock the blocking recv().
+    /// Without this, the capture thread keeps diverting packets after stop.
+    fn shutdown(&self) {
+        unsafe {
+            wd_ffi::WinDivertShutdown(self.raw_handle, wd_ffi::WINDIVERT_SHUTDOWN_RECV);
         }
     }
 }
 
-/// Decide whether a packet should be passed or dropped.
-/// Returns true (pass) for: unparseable packets, unknown PIDs, non-limited processes,
-/// and rate-limited processes within their budget.
-/// Returns false (drop) for: blocked PIDs and rate-limited processes over budget.
-pub(crate) fn should_pass_packet(
-    mapper: &ProcessMapper,
-    rate_limiter: &RateLimiterManager,
-    data: &[u8],
-    outbound: bool,
-) -> bool {
-    let Some((proto, src_port, dst_port, total_len)) = parse_ip_packet(data) else {
-        return true; // can't parse → pass through safely
-    };
-
-    let local_port = if outbound { src_port } else { dst_port };
-
-    let Some(pid) = mapper.lookup_pid(proto, local_port) else {
-        return true; // unknown PID → pass through
-    };
-
-    rate_limiter.should_pass_packet(pid, total_len, outbound)
-}
-
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::capture::mod_test_helpers::build_ipv4_packet;
-
+    /// Verify that `WinDivert<NetworkLayer>` has sufficient size and alignment
+    /// for safe raw HANDLE extraction via `extract_wd_handle`.
+    ///
+    /// If the `windivert` crate changes its struct layout, this test will fail
+    /// immediately, preventing silent UB in production.
     #[test]
-    fn test_sniff_outbound_records_upload() {
-        let mapper = ProcessMapper::new();
-        let tracker = TrafficTracker::new();
-        mapper
-            .port_map
-            .insert((crate::core::process_mapper::Protocol::Tcp, 12345), 42);
+    fn test_windivert_layout_assumptions() {
+        let wd_size =
+            std::mem::size_of::<windivert::prelude::WinDivert<windivert::layer::NetworkLayer>>();
+        let wd_align =
+            std::mem::align_of::<windivert::prelude::WinDivert<windivert::layer::NetworkLayer>>();
 
-        let pkt = build_ipv4_packet(6, 12345, 443);
-        process_sniff_packet(&mapper, &tracker, &pkt, true); // outbound
-
-        let snap = tracker.snapshot(&mapper);
-        let proc = snap.iter().find(|s| s.pid == 42);
-        assert!(proc.is_some(), "PID 42 should appear in snapshot");
         assert!(
-            proc.unwrap().bytes_sent > 0,
-            "outbound bytes should be recorded as sent"
+            wd_size >= std::mem::size_of::<isize>(),
+            "WinDivert<NetworkLayer> size ({wd_size}) must be >= size_of::<isize>() ({})",
+            std::mem::size_of::<isize>()
         );
-        assert_eq!(proc.unwrap().bytes_recv, 0);
-    }
-
-    #[test]
-    fn test_sniff_inbound_records_download() {
-        let mapper = ProcessMapper::new();
-        let tracker = TrafficTracker::new();
-        mapper
-            .port_map
-            .insert((crate::core::process_mapper::Protocol::Tcp, 443), 42);
-
-        let pkt = build_ipv4_packet(6, 12345, 443);
-        process_sniff_packet(&mapper, &tracker, &pkt, false); // inbound
-
-        let snap = tracker.snapshot(&mapper);
-        let proc = snap.iter().find(|s| s.pid == 42);
-        assert!(proc.is_some(), "PID 42 should appear in snapshot");
-        assert_eq!(proc.unwrap().bytes_sent, 0);
         assert!(
-            proc.unwrap().bytes_recv > 0,
-            "inbound bytes should be recorded as recv"
-        );
-    }
-
-    #[test]
-    fn test_sniff_malformed_packet_no_panic() {
-        let mapper = ProcessMapper::new();
-        let tracker = TrafficTracker::new();
-        process_sniff_packet(&mapper, &tracker, &[0xFF, 0x00], true);
-        assert!(tracker.snapshot(&mapper).is_empty());
-    }
-
-    #[test]
-    fn test_sniff_unknown_pid_no_record() {
-        let mapper = ProcessMapper::new();
-        let tracker = TrafficTracker::new();
-        let pkt = build_ipv4_packet(6, 9999, 80);
-        process_sniff_packet(&mapper, &tracker, &pkt, true);
-        assert!(tracker.snapshot(&mapper).is_empty());
-    }
-
-    #[test]
-    fn test_sniff_empty_packet_no_panic() {
-        let mapper = ProcessMapper::new();
-        let tracker = TrafficTracker::new();
-        process_sniff_packet(&mapper, &tracker, &[], true);
-        assert!(tracker.snapshot(&mapper).is_empty());
-    }
-
-    #[test]
-    fn test_should_pass_unparseable_returns_true() {
-        let mapper = ProcessMapper::new();
-        let limiter = RateLimiterManager::new();
-        assert!(should_pass_packet(&mapper, &limiter, &[0xFF], true));
-    }
-
-    #[test]
-    fn test_should_pass_unknown_pid_returns_true() {
-        let mapper = ProcessMapper::new();
-        let limiter = RateLimiterManager::new();
-        let pkt = build_ipv4_packet(6, 9999, 80);
-        assert!(should_pass_packet(&mapper, &limiter, &pkt, true));
-    }
-
-    #[test]
-    fn test_should_pass_no_limit_returns_true() {
-        let mapper = ProcessMapper::new();
-        let limiter = RateLimiterManager::new();
-        mapper
-            .port_map
-            .insert((crate::core::process_mapper::Protocol::Tcp, 5000), 42);
-        let pkt = build_ipv4_packet(6, 5000, 80);
-        assert!(should_pass_packet(&mapper, &limiter, &pkt, true));
-    }
-
-    #[test]
-    fn test_should_pass_blocked_pid_returns_false() {
-        let mapper = ProcessMapper::new();
-        let limiter = RateLimiterManager::new();
-        mapper
-            .port_map
-            .insert((crate::core::process_mapper::Protocol::Tcp, 5000), 42);
-        limiter.block_process(42);
-        let pkt = build_ipv4_packet(6, 5000, 80);
-        assert!(!should_pass_packet(&mapper, &limiter, &pkt, true));
-    }
-
-    #[test]
-    fn test_should_pass_within_rate_budget() {
-        let mapper = ProcessMapper::new();
-        let limiter = RateLimiterManager::new();
-        mapper
-            .port_map
-            .insert((crate::core::process_mapper::Protocol::Tcp, 5000), 42);
-        limiter.set_limit(
-            42,
-            crate::core::rate_limiter::BandwidthLimit {
-                download_bps: 1_000_000,
-                upload_bps: 1_000_000,
-            },
+            wd_align >= std::mem::align_of::<isize>(),
+            "WinDivert<NetworkLayer> align ({wd_align}) must be >= align_of::<isize>() ({})",
+            std::mem::align_of::<isize>()
         );
-        let pkt = build_ipv4_packet(6, 5000, 80);
-        assert!(should_pass_packet(&mapper, &limiter, &pkt, true));
     }
 }
@@ -0,0 +1,99 @@
+//! Default-deny allowlist mode commands.
+
+use tauri::State;
+
+use crate::core::allowlist::{Allowlist, AllowlistPreview, AllowlistStatus};
+use crate::error::AppError;
+
+use super::logic::build_allowlist_matcher;
+use super::state::AppState;
+
+/// Whether allowlist mode is on, and the stored lists.
+#[tauri::command]
+pub fn get_allowlist(state: State<'_, AppState>) -> Result<AllowlistStatus, AppError> {
+    let (list, _) = state
+        .database
+        .load_allowlist()
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(AllowlistStatus {
+        enabled: state.rate_limiter.allowlist_enabled(),
+        list,
+    })
+}
+
+/// Replace the allowed applications and the always-allow list. If the mode
+/// is on, the new lists take effect at once.
+#[tauri::command]
+pub fn set_allowlist(state: State<'_, AppState>, list: Allowlist) -> Result<(), AppError> {
+    let matcher = build_allowlist_matcher(&list)?;
+    let enabled = state.rate_limiter.allowlist_enabled();
+    state
+        .database
+        .save_allowlist(&list, enabled)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    if enabled {
+        state
+            .rate_limiter
+            .set_allowlist(Some(matcher), &state.process_mapper.processes());
+    }
+    tracing::info!(
+        "Set allowlist: {} allowed, {} always allowed",
+        list.allowed.len(),
+        list.always_allow.len()
+    );
+    Ok(())
+}
+
+/// Turn allowlist mode on or off. While on, intercept mode drops the traffic
+/// of every application the stored lists do not allow.
+#[tauri::command]
+pub fn set_allowlist_mode(state: State<'_, AppState>, enabled: bool) -> Result<(), AppError> {
+    let (list, _) = state
+        .database
+        .load_allowlist()
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    let matcher = enabled
+        .then(|| build_allowlist_matcher(&list))
+        .transpose()?;
+    state
+        .database
+        .save_allowlist(&list, enabled)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    state
+        .rate_limiter
+        .set_allowlist(matcher, &state.process_mapper.processes());
+    tracing::info!("Allowlist mode {}", if enabled { "on" } else { "off" });
+    Ok(())
+}
+
+/// Dry run: the running processes that `list` (or the stored lists, if not
+/// given) would block, and the traffic without a known owner that would be
+/// dropped with them, without changing anything.
+#[tauri::command]
+pub fn preview_allowlist(
+    state: State<'_, AppState>,
+    list: Option<Allowlist>,
+) -> Result<AllowlistPreview, AppError> {
+    let list = match list {
+        Some(list) => list,
+        None => {
+            state
+                .database
+                .load_allowlist()
+                .map_err(|e| AppError::Database(e.to_string()))?
+                .0
+        }
+    };
+    let matcher = build_allowlist_matcher(&list)?;
+    let unowned_bytes = state
+        .traffic_tracker
+        .snapshot(&state.process_mapper)
+        .iter()
+        .filter(|p| p.unattributed.is_some())
+        .map(|p| p.bytes_sent + p.bytes_recv)
+        .sum();
+    Ok(AllowlistPreview {
+        would_block: matcher.would_block(&state.process_mapper.processes()),
+        unowned_bytes,
+    })
+}
@@ -0,0 +1,98 @@
+//! Per-process pcapng export ("capture this app") and packet flight recorder
+//! commands.
+
+use tauri::State;
+
+use crate::capture::flight_recorder::{DumpFormat, FlightRecorderStatus};
+use crate::capture::recorder::ProcessCaptureStatus;
+use crate::error::AppError;
+
+use super::logic::{
+    resolve_export_bounds, validate_capture_target, validate_dump_window, validate_file_path,
+    validate_flight_recorder_bytes,
+};
+use super::state::AppState;
+
+/// Start recording the packets of one PID, or of every process running
+/// `exe_path`, into a pcapng file. Returns the export session id.
+///
+/// The export ends after `max_duration_secs` or once the file would exceed
+/// `max_bytes`, whichever comes first; at least one bound is required.
+#[tauri::command]
+pub fn start_process_capture(
+    state: State<'_, AppState>,
+    pid: Option<u32>,
+    exe_path: Option<String>,
+    output_path: String,
+    max_duration_secs: Option<u64>,
+    max_bytes: Option<u64>,
+) -> Result<u32, AppError> {
+    let target = validate_capture_target(pid, exe_path)?;
+    let output_path = validate_file_path(&output_path, "Output file")?;
+    let (max_duration, max_bytes) = resolve_export_bounds(max_duration_secs, max_bytes)?;
+    state
+        .packet_recorder
+        .start_export(target, &output_path, max_duration, max_bytes)
+        .map_err(|e| AppError::Io(format!("{e:#}")))
+}
+
+/// Stop an export early. Returns its final status.
+#[tauri::command]
+pub fn stop_process_capture(
+    state: State<'_, AppState>,
+    id: u32,
+) -> Result<ProcessCaptureStatus, AppError> {
+    state
+        .packet_recorder
+        .stop_export(id)
+        .ok_or_else(|| AppError::InvalidInput(format!("No active packet export with id {id}")))
+}
+
+/// Status of active exports followed by recently finished ones.
+#[tauri::command]
+pub fn get_process_captures(
+    state: State<'_, AppState>,
+) -> Result<Vec<ProcessCaptureStatus>, AppError> {
+    Ok(state.packet_recorder.export_statuses())
+}
+
+/// Write the packet headers of the last `last_secs` seconds to a pcapng or
+/// JSON file. Returns the number of packets written.
+#[tauri::command]
+pub fn dump_flight_recorder(
+    state: State<'_, AppState>,
+    last_secs: u64,
+    format: DumpFormat,
+    output_path: String,
+) -> Result<u64, AppError> {
+    let window = validate_dump_window(last_secs)?;
+    let output_path = validate_file_path(&output_path, "Output file")?;
+    state
+        .packet_recorder
+        .flight_recorder()
+        .dump_last(&state.process_mapper, window, format, &output_path)
+        .map(|count| count as u64)
+        .map_err(|e| AppError::Io(format!("{e:#}")))
+}
+
+/// Set the flight recorder memory budget in bytes (0 = disabled).
+#[tauri::command]
+pub fn set_flight_recorder_limit(
+    state: State<'_, AppState>,
+    max_bytes: u64,
+) -> Result<(), AppError> {
+    validate_flight_recorder_bytes(max_bytes)?;
+    state
+        .packet_recorder
+        .flight_recorder()
+        .set_max_bytes(max_bytes);
+    tracing::info!("Flight recorder budget set to {max_bytes} bytes");
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_flight_recorder_status(
+    state: State<'_, AppState>,
+) -> Result<FlightRecorderStatus, AppError> {
+    Ok(state.packet_recorder.flight_recorder().status())
+}
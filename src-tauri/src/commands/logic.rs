@@ -4,31 +4,37 @@
 //! can be unit-tested without a Tauri runtime.
 
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
+use crate::capture::recorder::CaptureTarget;
+use crate::config::{
+    FLIGHT_RECORDER_MAX_BYTES, MAX_ALLOWLIST_ENTRIES, MAX_BURST_BYTES, MAX_BURST_MS,
+    MAX_EXPORT_BYTES, MAX_EXPORT_DURATION_SECS, MAX_RULE_PATTERN_LEN, MAX_RULE_WEIGHT,
+    MAX_SCHEDULE_WINDOWS, MAX_TEMPORARY_RULE_SECS, MIN_BURST_BYTES, MIN_EXPORT_BYTES,
+};
+use crate::core::allowlist::{Allowlist, AllowlistMatcher};
+use crate::core::app_rules::{AppAction, AppMatch, AppRule};
+use crate::core::quota::{Quota, QuotaAction};
+use crate::core::rate_limiter::{EffectivePolicy, LimitMode, PriorityClass, DEFAULT_WEIGHT};
+use crate::core::rule_match::{MatchType, PolicyExplanation, RuleCandidate, RuleMatcher};
+use crate::core::schedule::{LocalClock, ScheduleWindow, MINUTES_PER_DAY};
 use crate::core::{BandwidthLimit, ProcessTrafficSnapshot};
 use crate::db;
 use crate::error::AppError;
 
-/// A rule entry to be persisted to the database.
-#[derive(Debug, Clone, PartialEq)]
-pub struct RuleEntry {
-    pub exe_path: String,
-    pub process_name: String,
-    pub download_bps: u64,
-    pub upload_bps: u64,
-    pub blocked: bool,
-}
-
-/// An action to be applied to a running process when activating a profile.
+/// An action to be applied to a running process when activating a profile,
+/// with the id of the saved rule it comes from.
 #[derive(Debug, Clone, PartialEq)]
 pub enum ApplyAction {
     Block {
         pid: u32,
+        rule_id: i64,
     },
     Limit {
         pid: u32,
-        download_bps: u64,
-        upload_bps: u64,
+        limit: BandwidthLimit,
+        rule_id: i64,
     },
 }
 
@@ -37,7 +43,7 @@ pub fn build_profile_rules(
     limits: &HashMap<u32, BandwidthLimit>,
     blocked_pids: &[u32],
     snapshot: &[ProcessTrafficSnapshot],
-) -> Vec<RuleEntry> {
+) -> Vec<db::SavedRule> {
     let pid_to_info: HashMap<u32, &ProcessTrafficSnapshot> =
         snapshot.iter().map(|s| (s.pid, s)).collect();
 
@@ -45,24 +51,46 @@ pub fn build_profile_rules(
 
     for (pid, limit) in limits {
         if let Some(info) = pid_to_info.get(pid) {
-            rules.push(RuleEntry {
+            rules.push(db::SavedRule {
+                id: 0,
                 exe_path: info.exe_path.clone(),
+                match_type: MatchType::Exact,
+                precedence: 0,
                 process_name: info.name.clone(),
                 download_bps: limit.download_bps,
                 upload_bps: limit.upload_bps,
                 blocked: false,
+                mode: limit.mode,
+                guaranteed_download_bps: limit.guaranteed_download_bps,
+                guaranteed_upload_bps: limit.guaranteed_upload_bps,
+                priority: limit.priority,
+                weight: limit.weight,
+                burst_bytes: limit.burst_bytes,
+                burst_ms: limit.burst_ms,
+                schedule: Vec::new(),
             });
         }
     }
 
     for pid in blocked_pids {
         if let Some(info) = pid_to_info.get(pid) {
-            rules.push(RuleEntry {
+            rules.push(db::SavedRule {
+                id: 0,
                 exe_path: info.exe_path.clone(),
+                match_type: MatchType::Exact,
+                precedence: 0,
                 process_name: info.name.clone(),
                 download_bps: 0,
                 upload_bps: 0,
                 blocked: true,
+                mode: LimitMode::Police,
+                guaranteed_download_bps: 0,
+                guaranteed_upload_bps: 0,
+                priority: PriorityClass::Normal,
+                weight: DEFAULT_WEIGHT,
+                burst_bytes: 0,
+                burst_ms: 0,
+                schedule: Vec::new(),
             });
         }
     }
@@ -71,31 +99,80 @@ pub fn build_profile_rules(
 }
 
 /// Match saved rules against running processes and produce a list of actions.
+/// Each process gets at most the one unscheduled rule that wins among those
+/// matching it (see [`RuleSet::find_active`]). Scheduled rules are left out of
+/// the ranking: the persistent-rules applier turns them on and off at their
+/// window boundaries.
 pub fn match_rules_to_processes(
     rules: &[db::SavedRule],
     snapshot: &[ProcessTrafficSnapshot],
 ) -> Vec<ApplyAction> {
+    let rule_set = db::SavedRule::rule_set(rules);
     let mut actions = Vec::new();
 
-    for rule in rules {
-        for proc in snapshot {
-            if proc.exe_path == rule.exe_path {
-                if rule.blocked {
-                    actions.push(ApplyAction::Block { pid: proc.pid });
-                } else if rule.download_bps > 0 || rule.upload_bps > 0 {
-                    actions.push(ApplyAction::Limit {
-                        pid: proc.pid,
-                        download_bps: rule.download_bps,
-                        upload_bps: rule.upload_bps,
-                    });
-                }
-            }
+    for proc in snapshot {
+        let Some(index) =
+            rule_set.find_active(&proc.exe_path, &proc.name, |i| rules[i].schedule.is_empty())
+        else {
+            continue;
+        };
+        let rule = &rules[index];
+        if rule.blocked {
+            actions.push(ApplyAction::Block {
+                pid: proc.pid,
+                rule_id: rule.id,
+            });
+        } else if !rule.limit().is_unlimited() {
+            actions.push(ApplyAction::Limit {
+                pid: proc.pid,
+                limit: rule.limit(),
+                rule_id: rule.id,
+            });
         }
     }
 
     actions
 }
 
+/// Explain which of `rules` decides the policy of a process, given what the
+/// rate limiter currently enforces for it (`effective`). Every matching rule
+/// is listed, winner first, with the evaluation step it lost on. The winner
+/// is the highest-ranked rule in force at `clock`.
+pub fn build_policy_explanation(
+    pid: u32,
+    process_name: &str,
+    exe_path: &str,
+    rules: &[db::SavedRule],
+    clock: &LocalClock,
+    effective: EffectivePolicy,
+) -> PolicyExplanation {
+    let candidates: Vec<RuleCandidate> = db::SavedRule::rule_set(rules)
+        .evaluate(exe_path, process_name, |i| rules[i].is_active(clock))
+        .into_iter()
+        .map(|(index, lost_on)| {
+            let rule = &rules[index];
+            RuleCandidate {
+                rule_id: rule.id,
+                pattern: rule.exe_path.clone(),
+                match_type: rule.match_type,
+                precedence: rule.precedence,
+                blocked: rule.blocked,
+                active: rule.is_active(clock),
+                lost_on,
+            }
+        })
+        .collect();
+
+    PolicyExplanation {
+        pid,
+        process_name: process_name.to_string(),
+        exe_path: exe_path.to_string(),
+        effective,
+        winning_rule_id: candidates.first().filter(|c| c.active).map(|c| c.rule_id),
+        candidates,
+    }
+}
+
 /// Validate that intercept mode can be enabled (not already active).
 pub fn validate_intercept_enable(is_active: bool) -> Result<(), AppError> {
     if is_active {
@@ -170,21 +247,30 @@ pub fn validate_timestamps(from: i64, to: i64) -> Result<(), AppError> {
     Ok(())
 }
 
-/// Maximum allowed length for a profile name.
+/// Maximum allowed length for a profile or limit group name.
 const MAX_PROFILE_NAME_LEN: usize = 64;
 
 /// Validate a profile name. Allows ASCII alphanumeric, hyphens, underscores, spaces.
 /// Returns the trimmed name on success for consistent storage.
 pub fn validate_profile_name(name: &str) -> Result<String, AppError> {
+    validate_name("Profile", name)
+}
+
+/// Validate a limit group name. Same rules as profile names.
+pub fn validate_group_name(name: &str) -> Result<String, AppError> {
+    validate_name("Group", name)
+}
+
+fn validate_name(kind: &str, name: &str) -> Result<String, AppError> {
     let trimmed = name.trim();
     if trimmed.is_empty() {
-        return Err(AppError::InvalidInput(
-            "Profile name cannot be empty".into(),
-        ));
+        return Err(AppError::InvalidInput(format!(
+            "{kind} name cannot be empty"
+        )));
     }
     if trimmed.len() > MAX_PROFILE_NAME_LEN {
         return Err(AppError::InvalidInput(format!(
-            "Profile name too long ({} chars, max {MAX_PROFILE_NAME_LEN})",
+            "{kind} name too long ({} chars, max {MAX_PROFILE_NAME_LEN})",
             trimmed.len()
         )));
     }
@@ -192,17 +278,312 @@ pub fn validate_profile_name(name: &str) -> Result<String, AppError> {
         .chars()
         .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' ')
     {
+        return Err(AppError::InvalidInput(format!(
+            "{kind} name may only contain letters, digits, hyphens, underscores, and spaces"
+        )));
+    }
+    Ok(trimmed.to_string())
+}
+
+/// Validate a bandwidth limit: a guaranteed rate cannot exceed the ceiling
+/// of the same direction, unless that direction is unlimited, the weight
+/// must be between 1 and `MAX_RULE_WEIGHT`, and the burst must be valid
+/// (see [`validate_burst`]).
+pub fn validate_bandwidth_limit(limit: &BandwidthLimit) -> Result<(), AppError> {
+    validate_burst(limit)?;
+    if !(1..=MAX_RULE_WEIGHT).contains(&limit.weight) {
+        return Err(AppError::InvalidInput(format!(
+            "Weight must be between 1 and {MAX_RULE_WEIGHT}, got {}",
+            limit.weight
+        )));
+    }
+    for (direction, ceiling, guaranteed) in [
+        (
+            "download",
+            limit.download_bps,
+            limit.guaranteed_download_bps,
+        ),
+        ("upload", limit.upload_bps, limit.guaranteed_upload_bps),
+    ] {
+        if ceiling > 0 && guaranteed > ceiling {
+            return Err(AppError::InvalidInput(format!(
+                "Guaranteed {direction} rate ({guaranteed} B/s) exceeds the {direction} limit ({ceiling} B/s)"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Validate the token bucket parameters of a limit. A burst is given either in
+/// bytes (`MIN_BURST_BYTES..=MAX_BURST_BYTES`) or as a duration
+/// (up to `MAX_BURST_MS`), not both. Rejected with `AppError::RateLimiter`.
+fn validate_burst(limit: &BandwidthLimit) -> Result<(), AppError> {
+    if limit.burst_bytes > 0 && limit.burst_ms > 0 {
+        return Err(AppError::RateLimiter(
+            "Burst can be set in bytes or milliseconds, not both".to_string(),
+        ));
+    }
+    if limit.burst_bytes > 0 && !(MIN_BURST_BYTES..=MAX_BURST_BYTES).contains(&limit.burst_bytes) {
+        return Err(AppError::RateLimiter(format!(
+            "Burst must be between {MIN_BURST_BYTES} and {MAX_BURST_BYTES} bytes, got {}",
+            limit.burst_bytes
+        )));
+    }
+    if limit.burst_ms > MAX_BURST_MS {
+        return Err(AppError::RateLimiter(format!(
+            "Burst duration must be at most {MAX_BURST_MS} ms, got {}",
+            limit.burst_ms
+        )));
+    }
+    Ok(())
+}
+
+/// Validate a data quota: it needs an executable and a non-zero budget, and a
+/// throttling quota needs a non-zero throttle rate.
+pub fn validate_quota(quota: &Quota) -> Result<(), AppError> {
+    if quota.exe_path.trim().is_empty() {
         return Err(AppError::InvalidInput(
-            "Profile name may only contain letters, digits, hyphens, underscores, and spaces"
-                .into(),
+            "Quota executable path cannot be empty".to_string(),
         ));
     }
-    Ok(trimmed.to_string())
+    if quota.limit_bytes == 0 {
+        return Err(AppError::InvalidInput(
+            "Quota must allow at least one byte".to_string(),
+        ));
+    }
+    if quota.action == QuotaAction::Throttle && quota.throttle_bps == 0 {
+        return Err(AppError::InvalidInput(
+            "Throttling quota needs a throttle rate".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Validate the duration of a temporary limit or block (`None` = permanent):
+/// between one second and `MAX_TEMPORARY_RULE_SECS`.
+pub fn validate_rule_duration(duration_secs: Option<u64>) -> Result<Option<Duration>, AppError> {
+    match duration_secs {
+        None => Ok(None),
+        Some(secs) if (1..=MAX_TEMPORARY_RULE_SECS).contains(&secs) => {
+            Ok(Some(Duration::from_secs(secs)))
+        }
+        Some(secs) => Err(AppError::InvalidInput(format!(
+            "Duration must be between 1 and {MAX_TEMPORARY_RULE_SECS} seconds, got {secs}"
+        ))),
+    }
+}
+
+/// Build an app rule from user input. The executable path is required, empty
+/// argument filters are dropped, and a hash must be a hex SHA-256 digest (it
+/// is stored lowercase). The display name is the executable's file name.
+pub fn build_app_rule(app: AppMatch, action: AppAction) -> Result<AppRule, AppError> {
+    let exe_path = app.exe_path.trim().to_string();
+    if exe_path.is_empty() {
+        return Err(AppError::InvalidInput(
+            "App rule executable path cannot be empty".to_string(),
+        ));
+    }
+    let args = app.args.filter(|a| !a.trim().is_empty());
+    let sha256 = match app.sha256.map(|h| h.trim().to_ascii_lowercase()) {
+        Some(h) if h.len() != 64 || !h.chars().all(|c| c.is_ascii_hexdigit()) => {
+            return Err(AppError::InvalidInput(format!(
+                "'{h}' is not a SHA-256 digest (64 hex characters)"
+            )));
+        }
+        other => other,
+    };
+    if let AppAction::Limit(limit) = &action {
+        validate_bandwidth_limit(limit)?;
+    }
+    let process_name = exe_path
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    Ok(AppRule {
+        app: AppMatch {
+            exe_path,
+            args,
+            sha256,
+        },
+        process_name,
+        action,
+    })
+}
+
+/// Validate a rule schedule: at most `MAX_SCHEDULE_WINDOWS` windows, each on
+/// at least one day, starting before midnight and ending no later than
+/// midnight, and not empty (start equal to end).
+pub fn validate_schedule(schedule: &[ScheduleWindow]) -> Result<(), AppError> {
+    if schedule.len() > MAX_SCHEDULE_WINDOWS {
+        return Err(AppError::InvalidInput(format!(
+            "Too many schedule windows ({}, max {MAX_SCHEDULE_WINDOWS})",
+            schedule.len()
+        )));
+    }
+    for window in schedule {
+        if window.days.is_empty() {
+            return Err(AppError::InvalidInput(
+                "Schedule window needs at least one day".to_string(),
+            ));
+        }
+        if window.start_minute >= MINUTES_PER_DAY || window.end_minute > MINUTES_PER_DAY {
+            return Err(AppError::InvalidInput(format!(
+                "Schedule window times must be within the day (0-{MINUTES_PER_DAY} minutes)"
+            )));
+        }
+        if window.start_minute == window.end_minute {
+            return Err(AppError::InvalidInput(
+                "Schedule window cannot start and end at the same time".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Validate a saved rule entered by the user: its pattern must be non-empty,
+/// at most `MAX_RULE_PATTERN_LEN` bytes and compile for its match type, and
+/// its limit and schedule must be valid.
+pub fn validate_saved_rule(rule: &db::SavedRule) -> Result<(), AppError> {
+    if rule.exe_path.trim().is_empty() {
+        return Err(AppError::InvalidInput(
+            "Rule path or pattern cannot be empty".to_string(),
+        ));
+    }
+    if rule.exe_path.len() > MAX_RULE_PATTERN_LEN {
+        return Err(AppError::InvalidInput(format!(
+            "Rule pattern too long (max {MAX_RULE_PATTERN_LEN} bytes)"
+        )));
+    }
+    RuleMatcher::new(rule.match_type, &rule.exe_path)
+        .map_err(|e| AppError::InvalidInput(format!("{e:#}")))?;
+    validate_bandwidth_limit(&rule.limit())?;
+    validate_schedule(&rule.schedule)
+}
+
+/// Compile an allowlist entered by the user. Each list holds at most
+/// `MAX_ALLOWLIST_ENTRIES` entries, whose patterns must be non-empty, at most
+/// `MAX_RULE_PATTERN_LEN` bytes and compile for their match type.
+pub fn build_allowlist_matcher(list: &Allowlist) -> Result<AllowlistMatcher, AppError> {
+    for (kind, entries) in [
+        ("allowed", &list.allowed),
+        ("always-allow", &list.always_allow),
+    ] {
+        if entries.len() > MAX_ALLOWLIST_ENTRIES {
+            return Err(AppError::InvalidInput(format!(
+                "Too many {kind} entries (max {MAX_ALLOWLIST_ENTRIES})"
+            )));
+        }
+        for entry in entries {
+            if entry.pattern.trim().is_empty() {
+                return Err(AppError::InvalidInput(format!(
+                    "An {kind} entry has an empty path or pattern"
+                )));
+            }
+            if entry.pattern.len() > MAX_RULE_PATTERN_LEN {
+                return Err(AppError::InvalidInput(format!(
+                    "Allowlist pattern too long (max {MAX_RULE_PATTERN_LEN} bytes)"
+                )));
+            }
+        }
+    }
+    AllowlistMatcher::new(list).map_err(|e| AppError::InvalidInput(format!("{e:#}")))
+}
+
+/// Validate a user-supplied file path (capture or mapping file).
+/// Requires a non-empty absolute path without null bytes.
+pub fn validate_file_path(path: &str, what: &str) -> Result<PathBuf, AppError> {
+    if path.trim().is_empty() {
+        return Err(AppError::InvalidInput(format!(
+            "{what} path cannot be empty"
+        )));
+    }
+    if path.contains('\0') {
+        return Err(AppError::InvalidInput(format!(
+            "{what} path contains null bytes"
+        )));
+    }
+    let path = PathBuf::from(path);
+    if !path.is_absolute() {
+        return Err(AppError::InvalidInput(format!(
+            "{what} path must be absolute"
+        )));
+    }
+    Ok(path)
+}
+
+/// Build the target of a packet export from exactly one of `pid` or `exe_path`.
+pub fn validate_capture_target(
+    pid: Option<u32>,
+    exe_path: Option<String>,
+) -> Result<CaptureTarget, AppError> {
+    match (pid, exe_path) {
+        (Some(pid), None) => Ok(CaptureTarget::Pid(pid)),
+        (None, Some(path)) => {
+            if path.trim().is_empty() || path.contains('\0') {
+                return Err(AppError::InvalidInput(
+                    "Executable path must be non-empty and contain no null bytes".into(),
+                ));
+            }
+            Ok(CaptureTarget::ExePath(path))
+        }
+        _ => Err(AppError::InvalidInput(
+            "Specify exactly one of pid or exe_path".into(),
+        )),
+    }
+}
+
+/// Resolve the duration and size bounds of a packet export.
+/// At least one must be given; a missing bound defaults to its maximum.
+pub fn resolve_export_bounds(
+    max_duration_secs: Option<u64>,
+    max_bytes: Option<u64>,
+) -> Result<(Duration, u64), AppError> {
+    if max_duration_secs.is_none() && max_bytes.is_none() {
+        return Err(AppError::InvalidInput(
+            "A packet export needs a duration or size limit".into(),
+        ));
+    }
+    let secs = max_duration_secs.unwrap_or(MAX_EXPORT_DURATION_SECS);
+    if secs == 0 || secs > MAX_EXPORT_DURATION_SECS {
+        return Err(AppError::InvalidInput(format!(
+            "Export duration must be between 1 and {MAX_EXPORT_DURATION_SECS} seconds"
+        )));
+    }
+    let bytes = max_bytes.unwrap_or(MAX_EXPORT_BYTES);
+    if !(MIN_EXPORT_BYTES..=MAX_EXPORT_BYTES).contains(&bytes) {
+        return Err(AppError::InvalidInput(format!(
+            "Export size limit must be between {MIN_EXPORT_BYTES} and {MAX_EXPORT_BYTES} bytes"
+        )));
+    }
+    Ok((Duration::from_secs(secs), bytes))
+}
+
+/// Validate a flight recorder memory budget (0 disables the recorder).
+pub fn validate_flight_recorder_bytes(max_bytes: u64) -> Result<(), AppError> {
+    if max_bytes > FLIGHT_RECORDER_MAX_BYTES {
+        return Err(AppError::InvalidInput(format!(
+            "Flight recorder budget too large ({max_bytes} bytes, max {FLIGHT_RECORDER_MAX_BYTES})"
+        )));
+    }
+    Ok(())
+}
+
+/// Validate the window of a flight recorder dump (seconds, must be positive).
+pub fn validate_dump_window(last_secs: u64) -> Result<Duration, AppError> {
+    if last_secs == 0 {
+        return Err(AppError::InvalidInput(
+            "Dump window must be at least 1 second".into(),
+        ));
+    }
+    Ok(Duration::from_secs(last_secs))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::quota::QuotaPeriod;
+    use crate::core::schedule::Weekday;
 
     fn make_snapshot(pid: u32, name: &str, exe_path: &str) -> ProcessTrafficSnapshot {
         ProcessTrafficSnapshot {
@@ -214,16 +595,28 @@ mod tests {
             bytes_sent: 0,
             bytes_recv: 0,
             connection_count: 0,
+            unattributed: None,
         }
     }
 
     fn make_rule(exe_path: &str, name: &str, dl: u64, ul: u64, blocked: bool) -> db::SavedRule {
         db::SavedRule {
+            id: 0,
             exe_path: exe_path.to_string(),
+            match_type: MatchType::Exact,
+            precedence: 0,
             process_name: name.to_string(),
             download_bps: dl,
             upload_bps: ul,
             blocked,
+            mode: LimitMode::Police,
+            guaranteed_download_bps: 0,
+            guaranteed_upload_bps: 0,
+            priority: PriorityClass::Normal,
+            weight: DEFAULT_WEIGHT,
+            burst_bytes: 0,
+            burst_ms: 0,
+            schedule: Vec::new(),
         }
     }
 
@@ -235,6 +628,8 @@ mod tests {
             BandwidthLimit {
                 download_bps: 1000,
                 upload_bps: 500,
+                mode: LimitMode::ShapeCodel,
+                ..Default::default()
             },
         );
         let blocked = vec![2];
@@ -251,6 +646,7 @@ mod tests {
             .find(|r| r.exe_path == r"C:\chrome.exe")
             .unwrap();
         assert_eq!(chrome_rule.download_bps, 1000);
+        assert_eq!(chrome_rule.mode, LimitMode::ShapeCodel);
         assert!(!chrome_rule.blocked);
 
         let firefox_rule = rules
@@ -274,6 +670,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 1000,
                 upload_bps: 500,
+                ..Default::default()
             },
         );
         let snapshot = vec![make_snapshot(1, "chrome.exe", r"C:\chrome.exe")];
@@ -294,7 +691,13 @@ mod tests {
         let rules = vec![make_rule(r"C:\firefox.exe", "firefox.exe", 0, 0, true)];
         let snapshot = vec![make_snapshot(42, "firefox.exe", r"C:\firefox.exe")];
         let actions = match_rules_to_processes(&rules, &snapshot);
-        assert_eq!(actions, vec![ApplyAction::Block { pid: 42 }]);
+        assert_eq!(
+            actions,
+            vec![ApplyAction::Block {
+                pid: 42,
+                rule_id: 0
+            }]
+        );
     }
 
     #[test]
@@ -306,8 +709,13 @@ mod tests {
             actions,
             vec![ApplyAction::Limit {
                 pid: 10,
-                download_bps: 1000,
-                upload_bps: 500
+                limit: BandwidthLimit {
+                    download_bps: 1000,
+                    upload_bps: 500,
+                    mode: LimitMode::Police,
+                    ..Default::default()
+                },
+                rule_id: 0
             }]
         );
     }
@@ -348,6 +756,253 @@ mod tests {
         assert_eq!(match_rules_to_processes(&rules, &snapshot).len(), 2);
     }
 
+    #[test]
+    fn test_match_rules_pattern_and_precedence() {
+        let versioned = db::SavedRule {
+            id: 1,
+            match_type: MatchType::PathGlob,
+            ..make_rule(r"C:\Apps\app-*\app.exe", "app.exe", 1000, 0, false)
+        };
+        let by_name = db::SavedRule {
+            id: 2,
+            match_type: MatchType::NameRegex,
+            ..make_rule(r"app(-helper)?\.exe", "app.exe", 0, 0, true)
+        };
+        let exact = db::SavedRule {
+            id: 3,
+            ..make_rule(r"C:\Apps\app-2.0\app.exe", "app.exe", 5000, 0, false)
+        };
+        let rules = vec![by_name, versioned, exact];
+        let snapshot = vec![
+            make_snapshot(1, "app.exe", r"C:\Apps\app-1.9\app.exe"),
+            make_snapshot(2, "app.exe", r"C:\Apps\app-2.0\app.exe"),
+            make_snapshot(3, "app-helper.exe", r"C:\Apps\app-2.0\app-helper.exe"),
+            make_snapshot(4, "other.exe", r"C:\other.exe"),
+        ];
+
+        let actions = match_rules_to_processes(&rules, &snapshot);
+        let limit = |download_bps| BandwidthLimit {
+            download_bps,
+            ..Default::default()
+        };
+        assert_eq!(
+            actions,
+            vec![
+                ApplyAction::Limit {
+                    pid: 1,
+                    limit: limit(1000),
+                    rule_id: 1
+                },
+                ApplyAction::Limit {
+                    pid: 2,
+                    limit: limit(5000),
+                    rule_id: 3
+                },
+                ApplyAction::Block { pid: 3, rule_id: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_policy_explanation() {
+        use crate::core::rate_limiter::PolicySource;
+
+        let rules = vec![
+            db::SavedRule {
+                id: 7,
+                ..make_rule(r"C:\Apps\app.exe", "app.exe", 1000, 0, false)
+            },
+            db::SavedRule {
+                id: 8,
+                match_type: MatchType::NameGlob,
+                precedence: 1,
+                schedule: vec![ScheduleWindow {
+                    days: vec![Weekday::Sat],
+                    start_minute: 0,
+                    end_minute: 60,
+                }],
+                ..make_rule("app*", "app.exe", 0, 0, true)
+            },
+            db::SavedRule {
+                id: 9,
+                ..make_rule(r"C:\other.exe", "other.exe", 0, 0, true)
+            },
+        ];
+        let clock = LocalClock {
+            date: "2026-03-04".into(),
+            weekday: Weekday::Wed,
+            minute: 600,
+        };
+        let effective = EffectivePolicy {
+            blocked: false,
+            limit: Some(rules[0].limit()),
+            source: PolicySource::Process,
+            expires_in_secs: None,
+            group: None,
+        };
+
+        let explained = build_policy_explanation(
+            5,
+            "app.exe",
+            r"C:\Apps\app.exe",
+            &rules,
+            &clock,
+            effective.clone(),
+        );
+        assert_eq!(explained.effective, effective);
+        assert_eq!(explained.winning_rule_id, Some(7));
+        let outcome: Vec<_> = explained
+            .candidates
+            .iter()
+            .map(|c| (c.rule_id, c.active, c.lost_on))
+            .collect();
+        assert_eq!(outcome, vec![(7, true, None), (8, false, None)]);
+
+        // With rule 7 gone, the only matching rule is out of force.
+        let idle = build_policy_explanation(
+            5,
+            "app.exe",
+            r"C:\Appspp.exe",
+            &rules[1..],
+            &clock,
+            effective.clone(),
+        );
+        assert_eq!(idle.winning_rule_id, None);
+        assert_eq!(idle.candidates.len(), 1);
+
+        let unmatched =
+            build_policy_explanation(6, "x.exe", r"C:\x.exe", &rules, &clock, effective);
+        assert_eq!(unmatched.winning_rule_id, None);
+        assert!(unmatched.candidates.is_empty());
+    }
+
+    #[test]
+    fn test_build_allowlist_matcher() {
+        use crate::core::allowlist::AllowEntry;
+
+        let entry = |match_type, pattern: &str| AllowEntry {
+            match_type,
+            pattern: pattern.to_string(),
+        };
+        let list = |allowed: Vec<AllowEntry>| Allowlist {
+            allowed,
+            ..Allowlist::default()
+        };
+        assert!(build_allowlist_matcher(&Allowlist::default()).is_ok());
+        assert!(
+            build_allowlist_matcher(&list(vec![entry(MatchType::Exact, "/usr/bin/app")])).is_ok()
+        );
+        assert!(build_allowlist_matcher(&list(vec![entry(MatchType::Exact, " ")])).is_err());
+        assert!(build_allowlist_matcher(&list(vec![entry(MatchType::NameRegex, "(")])).is_err());
+        let long = "a".repeat(MAX_RULE_PATTERN_LEN + 1);
+        assert!(build_allowlist_matcher(&list(vec![entry(MatchType::Exact, &long)])).is_err());
+        let many = vec![entry(MatchType::Exact, "/usr/bin/app"); MAX_ALLOWLIST_ENTRIES + 1];
+        assert!(build_allowlist_matcher(&list(many)).is_err());
+    }
+
+    #[test]
+    fn test_validate_saved_rule() {
+        let rule = |match_type, pattern: &str| db::SavedRule {
+            match_type,
+            ..make_rule(pattern, "app", 1000, 0, false)
+        };
+        assert!(validate_saved_rule(&rule(MatchType::Exact, "/usr/bin/app")).is_ok());
+        assert!(validate_saved_rule(&rule(MatchType::NameGlob, "app-*")).is_ok());
+        assert!(validate_saved_rule(&rule(MatchType::PathRegex, "/opt/.*")).is_ok());
+        assert!(validate_saved_rule(&rule(MatchType::PathRegex, "(")).is_err());
+        assert!(validate_saved_rule(&rule(MatchType::NameGlob, "[")).is_err());
+        assert!(validate_saved_rule(&rule(MatchType::Exact, " ")).is_err());
+        let long = "a".repeat(MAX_RULE_PATTERN_LEN + 1);
+        assert!(validate_saved_rule(&rule(MatchType::NameGlob, &long)).is_err());
+    }
+
+    #[test]
+    fn test_match_rules_skips_scheduled_rules() {
+        let scheduled = db::SavedRule {
+            schedule: vec![office_hours()],
+            ..make_rule(r"C:\steam.exe", "steam.exe", 1000, 0, false)
+        };
+        let snapshot = vec![make_snapshot(1, "steam.exe", r"C:\steam.exe")];
+        assert!(match_rules_to_processes(std::slice::from_ref(&scheduled), &snapshot).is_empty());
+
+        // A scheduled rule does not hide an always-on one it outranks.
+        let always = db::SavedRule {
+            id: 2,
+            ..make_rule(r"C:\steam.exe", "steam.exe", 500, 0, false)
+        };
+        let scheduled = db::SavedRule {
+            precedence: 5,
+            blocked: true,
+            ..scheduled
+        };
+        let actions = match_rules_to_processes(&[scheduled, always], &snapshot);
+        assert!(
+            matches!(
+                actions[..],
+                [ApplyAction::Limit {
+                    pid: 1,
+                    rule_id: 2,
+                    ..
+                }]
+            ),
+            "{actions:?}"
+        );
+    }
+
+    fn office_hours() -> ScheduleWindow {
+        ScheduleWindow {
+            days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+            start_minute: 9 * 60,
+            end_minute: 18 * 60,
+        }
+    }
+
+    #[test]
+    fn test_validate_schedule() {
+        assert!(validate_schedule(&[]).is_ok());
+        assert!(validate_schedule(&[office_hours()]).is_ok());
+        let overnight = ScheduleWindow {
+            start_minute: 22 * 60,
+            end_minute: 6 * 60,
+            ..office_hours()
+        };
+        assert!(validate_schedule(&[overnight]).is_ok());
+        let whole_day = ScheduleWindow {
+            start_minute: 0,
+            end_minute: MINUTES_PER_DAY,
+            ..office_hours()
+        };
+        assert!(validate_schedule(&[whole_day]).is_ok());
+
+        for invalid in [
+            ScheduleWindow {
+                days: vec![],
+                ..office_hours()
+            },
+            ScheduleWindow {
+                start_minute: MINUTES_PER_DAY,
+                ..office_hours()
+            },
+            ScheduleWindow {
+                end_minute: MINUTES_PER_DAY + 1,
+                ..office_hours()
+            },
+            ScheduleWindow {
+                end_minute: 9 * 60,
+                ..office_hours()
+            },
+        ] {
+            assert!(validate_schedule(&[invalid]).is_err());
+        }
+        assert!(validate_schedule(&vec![office_hours(); MAX_SCHEDULE_WINDOWS + 1]).is_err());
+    }
+
     #[test]
     fn test_validate_intercept_enable_ok() {
         assert!(validate_intercept_enable(false).is_ok());
@@ -463,6 +1118,124 @@ mod tests {
         assert!(validate_profile_name("profile_αβγ").is_err());
     }
 
+    #[test]
+    fn test_validate_group_name() {
+        assert_eq!(validate_group_name(" backups ").unwrap(), "backups");
+        let err = validate_group_name("").unwrap_err();
+        assert!(err.to_string().contains("Group name"));
+        assert!(validate_group_name("a/b").is_err());
+    }
+
+    #[test]
+    fn test_validate_bandwidth_limit_guarantee_within_ceiling() {
+        let limit = |ceiling, guaranteed| BandwidthLimit {
+            upload_bps: ceiling,
+            guaranteed_upload_bps: guaranteed,
+            ..Default::default()
+        };
+        assert!(validate_bandwidth_limit(&limit(1000, 1000)).is_ok());
+        // Unlimited ceiling: any guarantee is fine.
+        assert!(validate_bandwidth_limit(&limit(0, 5000)).is_ok());
+        assert!(validate_bandwidth_limit(&limit(1000, 1001)).is_err());
+    }
+
+    #[test]
+    fn test_validate_bandwidth_limit_weight_range() {
+        let weighted = |weight| BandwidthLimit {
+            weight,
+            ..Default::default()
+        };
+        assert!(validate_bandwidth_limit(&weighted(1)).is_ok());
+        assert!(validate_bandwidth_limit(&weighted(MAX_RULE_WEIGHT)).is_ok());
+        assert!(validate_bandwidth_limit(&weighted(0)).is_err());
+        assert!(validate_bandwidth_limit(&weighted(MAX_RULE_WEIGHT + 1)).is_err());
+    }
+
+    #[test]
+    fn test_validate_bandwidth_limit_burst() {
+        let burst = |burst_bytes, burst_ms| BandwidthLimit {
+            upload_bps: 1000,
+            burst_bytes,
+            burst_ms,
+            ..Default::default()
+        };
+        assert!(validate_bandwidth_limit(&burst(0, 0)).is_ok());
+        assert!(validate_bandwidth_limit(&burst(MIN_BURST_BYTES, 0)).is_ok());
+        assert!(validate_bandwidth_limit(&burst(0, MAX_BURST_MS)).is_ok());
+        for invalid in [
+            burst(MIN_BURST_BYTES - 1, 0),
+            burst(MAX_BURST_BYTES + 1, 0),
+            burst(0, MAX_BURST_MS + 1),
+            burst(MIN_BURST_BYTES, 100),
+        ] {
+            let err = validate_bandwidth_limit(&invalid).unwrap_err();
+            assert_eq!(err.kind(), "RateLimiter");
+        }
+    }
+
+    #[test]
+    fn test_validate_rule_duration() {
+        assert_eq!(validate_rule_duration(None).unwrap(), None);
+        assert_eq!(
+            validate_rule_duration(Some(1800)).unwrap(),
+            Some(Duration::from_secs(1800))
+        );
+        assert!(validate_rule_duration(Some(0)).is_err());
+        assert!(validate_rule_duration(Some(MAX_TEMPORARY_RULE_SECS + 1)).is_err());
+    }
+
+    #[test]
+    fn test_build_app_rule() {
+        let app = |exe_path: &str, args: Option<&str>, sha256: Option<&str>| AppMatch {
+            exe_path: exe_path.to_string(),
+            args: args.map(str::to_string),
+            sha256: sha256.map(str::to_string),
+        };
+        let digest = "AB".repeat(32);
+
+        let rule = build_app_rule(
+            app(r" C:\Apps\sync.exe ", Some(" "), Some(&digest)),
+            AppAction::Block,
+        )
+        .unwrap();
+        assert_eq!(rule.app.exe_path, r"C:\Apps\sync.exe");
+        assert_eq!(rule.app.args, None);
+        assert_eq!(rule.app.sha256, Some("ab".repeat(32)));
+        assert_eq!(rule.process_name, "sync.exe");
+        assert_eq!(
+            build_app_rule(app("/usr/bin/app", Some("--x"), None), AppAction::Block)
+                .unwrap()
+                .process_name,
+            "app"
+        );
+
+        assert!(build_app_rule(app(" ", None, None), AppAction::Block).is_err());
+        assert!(build_app_rule(app("/a", None, Some("abc")), AppAction::Block).is_err());
+        assert!(build_app_rule(app("/a", None, Some(&"zz".repeat(32))), AppAction::Block).is_err());
+        let bad_limit = BandwidthLimit {
+            weight: 0,
+            ..Default::default()
+        };
+        assert!(build_app_rule(app("/a", None, None), AppAction::Limit(bad_limit)).is_err());
+    }
+
+    #[test]
+    fn test_validate_quota() {
+        let quota = |exe_path: &str, limit_bytes, action, throttle_bps| Quota {
+            exe_path: exe_path.to_string(),
+            process_name: String::new(),
+            period: QuotaPeriod::Daily,
+            limit_bytes,
+            action,
+            throttle_bps,
+        };
+        assert!(validate_quota(&quota("C:\\a.exe", 1, QuotaAction::Block, 0)).is_ok());
+        assert!(validate_quota(&quota("C:\\a.exe", 1, QuotaAction::Throttle, 10)).is_ok());
+        assert!(validate_quota(&quota(" ", 1, QuotaAction::Notify, 0)).is_err());
+        assert!(validate_quota(&quota("C:\\a.exe", 0, QuotaAction::Notify, 0)).is_err());
+        assert!(validate_quota(&quota("C:\\a.exe", 1, QuotaAction::Throttle, 0)).is_err());
+    }
+
     #[test]
     fn test_validate_timestamps_accepts_valid() {
         assert!(validate_timestamps(0, 100).is_ok());
@@ -481,4 +1254,68 @@ mod tests {
     fn test_validate_timestamps_rejects_inverted_range() {
         assert!(validate_timestamps(200, 100).is_err());
     }
+
+    #[test]
+    fn test_validate_file_path() {
+        let abs = if cfg!(windows) {
+            r"C:\captures\trace.pcapng"
+        } else {
+            "/tmp/trace.pcapng"
+        };
+        assert_eq!(
+            validate_file_path(abs, "Capture").unwrap(),
+            PathBuf::from(abs)
+        );
+        assert!(validate_file_path("", "Capture").is_err());
+        assert!(validate_file_path("trace.pcap", "Capture").is_err());
+        assert!(validate_file_path("/tmp/a\0b", "Capture").is_err());
+    }
+
+    #[test]
+    fn test_validate_capture_target() {
+        assert_eq!(
+            validate_capture_target(Some(42), None).unwrap(),
+            CaptureTarget::Pid(42)
+        );
+        assert_eq!(
+            validate_capture_target(None, Some("/usr/bin/curl".into())).unwrap(),
+            CaptureTarget::ExePath("/usr/bin/curl".into())
+        );
+        assert!(validate_capture_target(None, None).is_err());
+        assert!(validate_capture_target(Some(42), Some("/usr/bin/curl".into())).is_err());
+        assert!(validate_capture_target(None, Some("  ".into())).is_err());
+    }
+
+    #[test]
+    fn test_resolve_export_bounds() {
+        assert_eq!(
+            resolve_export_bounds(Some(30), None).unwrap(),
+            (Duration::from_secs(30), MAX_EXPORT_BYTES)
+        );
+        assert_eq!(
+            resolve_export_bounds(None, Some(MIN_EXPORT_BYTES)).unwrap(),
+            (
+                Duration::from_secs(MAX_EXPORT_DURATION_SECS),
+                MIN_EXPORT_BYTES
+            )
+        );
+        assert!(resolve_export_bounds(None, None).is_err());
+        assert!(resolve_export_bounds(Some(0), None).is_err());
+        assert!(resolve_export_bounds(Some(MAX_EXPORT_DURATION_SECS + 1), None).is_err());
+        assert!(resolve_export_bounds(None, Some(MIN_EXPORT_BYTES - 1)).is_err());
+        assert!(resolve_export_bounds(None, Some(MAX_EXPORT_BYTES + 1)).is_err());
+    }
+
+    #[test]
+    fn test_validate_flight_recorder_bytes() {
+        assert!(validate_flight_recorder_bytes(0).is_ok());
+        assert!(validate_flight_recorder_bytes(FLIGHT_RECORDER_MAX_BYTES).is_ok());
+        assert!(validate_flight_recorder_bytes(FLIGHT_RECORDER_MAX_BYTES + 1).is_err());
+    }
+
+    #[test]
+    fn test_validate_dump_window() {
+        assert_eq!(validate_dump_window(60).unwrap(), Duration::from_secs(60));
+        assert!(validate_dump_window(0).is_err());
+    }
 }
@@ -1,12 +1,18 @@
 //! Tauri IPC command handlers, organized by functional domain.
 //!
-//! - `traffic`: F1 monitoring, F4 history, AC-1.6 icons
+//! - `traffic`: F1 monitoring, F4 history, AC-1.6 icons, capture replay
+//! - `capture`: Per-process pcapng export, packet flight recorder
 //! - `rules`: F2 bandwidth limiting, F3 blocking, F5 profiles
+//! - `quotas`: Daily/monthly data quotas per application
+//! - `allowlist`: Default-deny allowlist mode
 //! - `system`: F6 notifications, F7 auto-start, intercept mode
 //! - `logic`: Pure business logic functions (unit-testable)
 //! - `state`: Shared `AppState` definition
 
+pub(crate) mod allowlist;
+pub(crate) mod capture;
 mod logic;
+pub(crate) mod quotas;
 pub(crate) mod rules;
 mod state;
 pub(crate) mod system;
@@ -0,0 +1,53 @@
+//! Daily/monthly data quota commands.
+
+use tauri::State;
+
+use crate::core::quota::{Quota, QuotaPeriod, QuotaStatus};
+use crate::error::AppError;
+
+use super::logic::validate_quota;
+use super::state::AppState;
+
+/// Create or update the quota of an executable for a period. Usage so far
+/// in the current period is kept.
+#[tauri::command]
+pub fn set_quota(state: State<'_, AppState>, quota: Quota) -> Result<(), AppError> {
+    validate_quota(&quota)?;
+    state
+        .database
+        .save_quota(&quota)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    tracing::info!(
+        "Set {} quota for {}: {} bytes, then {}",
+        quota.period.as_str(),
+        quota.process_name,
+        quota.limit_bytes,
+        quota.action.as_str()
+    );
+    state.quotas.set_quota(quota, &state.rate_limiter);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn remove_quota(
+    state: State<'_, AppState>,
+    exe_path: String,
+    period: QuotaPeriod,
+) -> Result<(), AppError> {
+    state
+        .database
+        .delete_quota(&exe_path, period)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    if state
+        .quotas
+        .remove_quota(&exe_path, period, &state.rate_limiter)
+    {
+        tracing::info!("Removed {} quota for {exe_path}", period.as_str());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_quotas(state: State<'_, AppState>) -> Result<Vec<QuotaStatus>, AppError> {
+    Ok(state.quotas.statuses())
+}
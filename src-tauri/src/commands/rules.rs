@@ -1,35 +1,52 @@
-//! F2 bandwidth limiting, F3 connection blocking, and F5 rule profile commands.
+//! F2 bandwidth limiting, F3 connection blocking, app-level rules, and F5 rule
+//! profile commands.
 
 use std::collections::HashMap;
+use std::time::Instant;
 
 use tauri::State;
 
+use crate::core::app_rules::{AppAction, AppMatch, AppRuleStatus};
+use crate::core::rate_limiter::{ActiveLimit, BlockedProcess};
+use crate::core::rule_match::{PolicyExplanation, RuleMatcher};
+use crate::core::schedule::ScheduleWindow;
 use crate::core::BandwidthLimit;
 use crate::db;
 use crate::error::AppError;
 
 use super::logic::{
-    build_profile_rules, match_rules_to_processes, validate_profile_name, ApplyAction,
+    build_app_rule, build_policy_explanation, build_profile_rules, match_rules_to_processes,
+    validate_bandwidth_limit, validate_group_name, validate_profile_name, validate_rule_duration,
+    validate_saved_rule, validate_schedule, ApplyAction,
 };
 use super::state::AppState;
 
 // ---- F2: Bandwidth Limiting ----
 
+/// Set a bandwidth limit for a process, lapsing after `duration_secs` if given.
 #[tauri::command]
 pub fn set_bandwidth_limit(
     state: State<'_, AppState>,
     pid: u32,
-    download_bps: u64,
-    upload_bps: u64,
+    limit: BandwidthLimit,
+    duration_secs: Option<u64>,
 ) -> Result<(), AppError> {
-    state.rate_limiter.set_limit(
-        pid,
-        BandwidthLimit {
-            download_bps,
-            upload_bps,
-        },
+    validate_bandwidth_limit(&limit)?;
+    let duration = validate_rule_duration(duration_secs)?;
+    tracing::info!(
+        "Set bandwidth limit for PID {pid}: DL={} B/s, UL={} B/s, mode={}, guaranteed DL={} UL={}, duration={duration:?}",
+        limit.download_bps,
+        limit.upload_bps,
+        limit.mode.as_str(),
+        limit.guaranteed_download_bps,
+        limit.guaranteed_upload_bps
     );
-    tracing::info!("Set bandwidth limit for PID {pid}: DL={download_bps} B/s, UL={upload_bps} B/s");
+    match duration {
+        Some(duration) => state
+            .rate_limiter
+            .set_limit_until(pid, limit, Instant::now() + duration),
+        None => state.rate_limiter.set_limit(pid, limit),
+    }
     Ok(())
 }
 
@@ -40,19 +57,118 @@ pub fn remove_bandwidth_limit(state: State<'_, AppState>, pid: u32) -> Result<()
     Ok(())
 }
 
+/// All process limits, with the seconds left on temporary ones.
 #[tauri::command]
 pub fn get_bandwidth_limits(
     state: State<'_, AppState>,
-) -> Result<HashMap<u32, BandwidthLimit>, AppError> {
-    Ok(state.rate_limiter.get_all_limits())
+) -> Result<HashMap<u32, ActiveLimit>, AppError> {
+    Ok(state.rate_limiter.get_active_limits(Instant::now()))
+}
+
+#[tauri::command]
+pub fn set_global_limit(state: State<'_, AppState>, limit: BandwidthLimit) -> Result<(), AppError> {
+    validate_bandwidth_limit(&limit)?;
+    tracing::info!(
+        "Set global bandwidth limit: DL={} B/s, UL={} B/s",
+        limit.download_bps,
+        limit.upload_bps
+    );
+    state.rate_limiter.set_global_limit(limit);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn remove_global_limit(state: State<'_, AppState>) -> Result<(), AppError> {
+    state.rate_limiter.remove_global_limit();
+    tracing::info!("Removed global bandwidth limit");
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_global_limit(state: State<'_, AppState>) -> Result<Option<BandwidthLimit>, AppError> {
+    Ok(state.rate_limiter.get_global_limit())
+}
+
+#[tauri::command]
+pub fn set_limit_group(
+    state: State<'_, AppState>,
+    name: String,
+    limit: BandwidthLimit,
+) -> Result<(), AppError> {
+    let name = validate_group_name(&name)?;
+    validate_bandwidth_limit(&limit)?;
+    tracing::info!(
+        "Set limit group '{name}': DL={} B/s, UL={} B/s, guaranteed DL={} UL={}",
+        limit.download_bps,
+        limit.upload_bps,
+        limit.guaranteed_download_bps,
+        limit.guaranteed_upload_bps
+    );
+    state.rate_limiter.set_group_limit(&name, limit);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn remove_limit_group(state: State<'_, AppState>, name: String) -> Result<(), AppError> {
+    let name = validate_group_name(&name)?;
+    state.rate_limiter.remove_group(&name);
+    tracing::info!("Removed limit group '{name}'");
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_limit_groups(
+    state: State<'_, AppState>,
+) -> Result<HashMap<String, BandwidthLimit>, AppError> {
+    Ok(state.rate_limiter.get_group_limits())
+}
+
+/// Put a process in a limit group, or take it out when `group` is `None`.
+#[tauri::command]
+pub fn assign_limit_group(
+    state: State<'_, AppState>,
+    pid: u32,
+    group: Option<String>,
+) -> Result<(), AppError> {
+    let group = group.as_deref().map(validate_group_name).transpose()?;
+    if !state.rate_limiter.assign_group(pid, group.as_deref()) {
+        return Err(AppError::InvalidInput(format!(
+            "Limit group '{}' does not exist",
+            group.unwrap_or_default()
+        )));
+    }
+    tracing::info!("Assigned PID {pid} to limit group {group:?}");
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_limit_group_members(
+    state: State<'_, AppState>,
+) -> Result<HashMap<u32, String>, AppError> {
+    Ok(state.rate_limiter.get_group_members())
 }
 
 // ---- F3: Connection Blocking ----
 
+/// Block a process, lapsing after `duration_secs` if given.
 #[tauri::command]
-pub fn block_process(state: State<'_, AppState>, pid: u32) -> Result<(), AppError> {
-    state.rate_limiter.block_process(pid);
-    tracing::info!("Blocked PID {pid}");
+pub fn block_process(
+    state: State<'_, AppState>,
+    pid: u32,
+    duration_secs: Option<u64>,
+) -> Result<(), AppError> {
+    match validate_rule_duration(duration_secs)? {
+        Some(duration) => {
+            state
+                .rate_limiter
+                .block_process_until(pid, Instant::now() + duration);
+            tracing::info!("Blocked PID {pid} for {}s", duration.as_secs());
+        }
+        None => {
+            state.rate_limiter.block_process(pid);
+            tracing::info!("Blocked PID {pid}");
+        }
+    }
     Ok(())
 }
 
@@ -63,9 +179,69 @@ pub fn unblock_process(state: State<'_, AppState>, pid: u32) -> Result<(), AppEr
     Ok(())
 }
 
+/// All blocked processes, with the seconds left on temporary blocks.
+#[tauri::command]
+pub fn get_blocked_pids(state: State<'_, AppState>) -> Result<Vec<BlockedProcess>, AppError> {
+    Ok(state.rate_limiter.get_blocked_processes(Instant::now()))
+}
+
+// ---- App-level rules ----
+
+/// Save an app rule and apply it to the app's running processes right away.
+fn set_app_rule(state: &AppState, app: AppMatch, action: AppAction) -> Result<(), AppError> {
+    let rule = build_app_rule(app, action)?;
+    state
+        .database
+        .save_app_rule(&rule)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    state.rate_limiter.set_app_rule(rule);
+    let mapper = &state.process_mapper;
+    state
+        .rate_limiter
+        .resolve_apps(&mapper.processes(), |exe| mapper.exe_sha256(exe));
+    Ok(())
+}
+
+/// Limit every process of an application, including ones started later.
+#[tauri::command]
+pub fn set_app_limit(
+    state: State<'_, AppState>,
+    app: AppMatch,
+    limit: BandwidthLimit,
+) -> Result<(), AppError> {
+    tracing::info!(
+        "Set app limit for {}: DL={} B/s, UL={} B/s, mode={}",
+        app.exe_path,
+        limit.download_bps,
+        limit.upload_bps,
+        limit.mode.as_str()
+    );
+    set_app_rule(&state, app, AppAction::Limit(limit))
+}
+
+/// Block every process of an application, including ones started later.
+#[tauri::command]
+pub fn block_app(state: State<'_, AppState>, app: AppMatch) -> Result<(), AppError> {
+    tracing::info!("Blocked app {}", app.exe_path);
+    set_app_rule(&state, app, AppAction::Block)
+}
+
+#[tauri::command]
+pub fn remove_app_rule(state: State<'_, AppState>, exe_path: String) -> Result<(), AppError> {
+    state
+        .database
+        .delete_app_rule(&exe_path)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    if state.rate_limiter.remove_app_rule(&exe_path) {
+        tracing::info!("Removed app rule for {exe_path}");
+    }
+    Ok(())
+}
+
+/// All app rules with the PIDs they currently apply to.
 #[tauri::command]
-pub fn get_blocked_pids(state: State<'_, AppState>) -> Result<Vec<u32>, AppError> {
-    Ok(state.rate_limiter.get_blocked_pids())
+pub fn get_app_rules(state: State<'_, AppState>) -> Result<Vec<AppRuleStatus>, AppError> {
+    Ok(state.rate_limiter.get_app_rules())
 }
 
 // ---- F5: Rule Profiles ----
@@ -73,22 +249,29 @@ pub fn get_blocked_pids(state: State<'_, AppState>) -> Result<Vec<u32>, AppError
 #[tauri::command]
 pub fn save_profile(state: State<'_, AppState>, profile_name: String) -> Result<(), AppError> {
     let profile_name = validate_profile_name(&profile_name)?;
-    let limits = state.rate_limiter.get_all_limits();
-    let blocked_pids = state.rate_limiter.get_blocked_pids();
+    // Temporary limits and blocks are not saved.
+    let now = Instant::now();
+    let limits: HashMap<u32, BandwidthLimit> = state
+        .rate_limiter
+        .get_active_limits(now)
+        .into_iter()
+        .filter(|(_, active)| active.expires_in_secs.is_none())
+        .map(|(pid, active)| (pid, active.limit))
+        .collect();
+    let blocked_pids: Vec<u32> = state
+        .rate_limiter
+        .get_blocked_processes(now)
+        .into_iter()
+        .filter(|blocked| blocked.expires_in_secs.is_none())
+        .map(|blocked| blocked.pid)
+        .collect();
     let snapshot = state.traffic_tracker.snapshot(&state.process_mapper);
     let rules = build_profile_rules(&limits, &blocked_pids, &snapshot);
 
     for rule in &rules {
         state
             .database
-            .save_rule(
-                &profile_name,
-                &rule.exe_path,
-                &rule.process_name,
-                rule.download_bps,
-                rule.upload_bps,
-                rule.blocked,
-            )
+            .save_rule(&profile_name, rule)
             .map_err(|e| AppError::Database(e.to_string()))?;
     }
 
@@ -106,25 +289,24 @@ pub fn apply_profile(state: State<'_, AppState>, profile_name: String) -> Result
 
     state.rate_limiter.clear_all();
     *state.persistent_rules.lock() = rules.clone();
+    *state.active_profile.lock() = Some(profile_name.clone());
 
     let snapshot = state.traffic_tracker.snapshot(&state.process_mapper);
     let actions = match_rules_to_processes(&rules, &snapshot);
 
     for action in &actions {
         match action {
-            ApplyAction::Block { pid } => state.rate_limiter.block_process(*pid),
+            ApplyAction::Block { pid, rule_id } => {
+                tracing::debug!("Rule {rule_id} blocks PID {pid}");
+                state.rate_limiter.block_process(*pid);
+            }
             ApplyAction::Limit {
                 pid,
-                download_bps,
-                upload_bps,
+                limit,
+                rule_id,
             } => {
-                state.rate_limiter.set_limit(
-                    *pid,
-                    BandwidthLimit {
-                        download_bps: *download_bps,
-                        upload_bps: *upload_bps,
-                    },
-                );
+                tracing::debug!("Rule {rule_id} limits PID {pid}");
+                state.rate_limiter.set_limit(*pid, limit.clone());
             }
         }
     }
@@ -167,3 +349,163 @@ pub fn get_profile_rules(
         .load_rules(&profile_name)
         .map_err(|e| AppError::Database(e.to_string()))
 }
+
+/// Set the time windows in which a saved rule is in force (empty = always).
+/// Takes effect within one applier interval if the profile is the active one.
+#[tauri::command]
+pub fn set_rule_schedule(
+    state: State<'_, AppState>,
+    profile_name: String,
+    exe_path: String,
+    schedule: Vec<ScheduleWindow>,
+) -> Result<(), AppError> {
+    let profile_name = validate_profile_name(&profile_name)?;
+    validate_schedule(&schedule)?;
+    let updated = state
+        .database
+        .set_rule_schedule(&profile_name, &exe_path, &schedule)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    if updated == 0 {
+        return Err(AppError::InvalidInput(format!(
+            "Profile '{profile_name}' has no rule for {exe_path}"
+        )));
+    }
+
+    let rescheduled = state
+        .persistent_rules
+        .lock()
+        .iter()
+        .find(|rule| rule.exe_path == exe_path)
+        .map(|rule| db::SavedRule {
+            schedule: schedule.clone(),
+            ..rule.clone()
+        });
+    if let Some(rule) = rescheduled {
+        replace_active_rule(&state, &profile_name, &exe_path, Some(rule));
+    }
+    tracing::info!(
+        "Set schedule of {exe_path} in profile '{profile_name}': {} windows",
+        schedule.len()
+    );
+    Ok(())
+}
+
+/// Create or replace a rule of a profile, matching an exact executable path
+/// or a glob or regex pattern, with the rule's precedence over other matching
+/// rules. Takes effect within one applier interval if the profile is the
+/// active one.
+#[tauri::command]
+pub fn save_profile_rule(
+    state: State<'_, AppState>,
+    profile_name: String,
+    rule: db::SavedRule,
+) -> Result<(), AppError> {
+    let profile_name = validate_profile_name(&profile_name)?;
+    validate_saved_rule(&rule)?;
+    state
+        .database
+        .save_rule(&profile_name, &rule)
+        .and_then(|()| {
+            state
+                .database
+                .set_rule_precedence(&profile_name, &rule.exe_path, rule.precedence)
+        })
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    // An existing rule keeps its stored schedule; reload it as saved.
+    let saved = state
+        .database
+        .load_rules(&profile_name)
+        .map_err(|e| AppError::Database(e.to_string()))?
+        .into_iter()
+        .find(|r| r.exe_path == rule.exe_path);
+    replace_active_rule(&state, &profile_name, &rule.exe_path, saved);
+    tracing::info!(
+        "Saved {} rule '{}' in profile '{profile_name}' with precedence {}",
+        rule.match_type.as_str(),
+        rule.exe_path,
+        rule.precedence
+    );
+    Ok(())
+}
+
+/// Delete one rule of a profile, by its executable path or pattern.
+#[tauri::command]
+pub fn remove_profile_rule(
+    state: State<'_, AppState>,
+    profile_name: String,
+    exe_path: String,
+) -> Result<(), AppError> {
+    let profile_name = validate_profile_name(&profile_name)?;
+    let deleted = state
+        .database
+        .delete_rule(&profile_name, &exe_path)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    if deleted == 0 {
+        return Err(AppError::InvalidInput(format!(
+            "Profile '{profile_name}' has no rule for {exe_path}"
+        )));
+    }
+    replace_active_rule(&state, &profile_name, &exe_path, None);
+    tracing::info!("Removed rule '{exe_path}' from profile '{profile_name}'");
+    Ok(())
+}
+
+/// Explain the effective policy of a process: what the rate limiter enforces
+/// for it, and which rules of the active profile match it and why the winner
+/// beats the others.
+#[tauri::command]
+pub fn explain_policy(state: State<'_, AppState>, pid: u32) -> Result<PolicyExplanation, AppError> {
+    let info = state
+        .process_mapper
+        .get_process_info(pid)
+        .ok_or_else(|| AppError::InvalidInput(format!("No running process with PID {pid}")))?;
+    let clock = state
+        .database
+        .local_clock()
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    let effective = state.rate_limiter.effective_policy(pid, Instant::now());
+    let rules = state.persistent_rules.lock();
+    Ok(build_policy_explanation(
+        pid,
+        &info.name,
+        &info.exe_path,
+        &rules,
+        &clock,
+        effective,
+    ))
+}
+
+/// If `profile_name` is the active profile, replace its rule for `exe_path`
+/// in the persistent rules with `updated` (or drop it) and lift what the old
+/// rule applied, unless a block or limit of another origin has replaced it;
+/// the persistent-rules applier then applies the new rules.
+fn replace_active_rule(
+    state: &AppState,
+    profile_name: &str,
+    exe_path: &str,
+    updated: Option<db::SavedRule>,
+) {
+    if state.active_profile.lock().as_deref() != Some(profile_name) {
+        return;
+    }
+    let snapshot = state.traffic_tracker.snapshot(&state.process_mapper);
+    let mut rules = state.persistent_rules.lock();
+    if let Some(index) = rules.iter().position(|r| r.exe_path == exe_path) {
+        let old = rules.remove(index);
+        if let Ok(matcher) = RuleMatcher::new(old.match_type, &old.exe_path) {
+            for proc in snapshot
+                .iter()
+                .filter(|p| matcher.matches(&p.exe_path, &p.name))
+            {
+                if old.blocked {
+                    state.rate_limiter.unblock_if_permanent(proc.pid);
+                } else {
+                    state
+                        .rate_limiter
+                        .remove_limit_if_unchanged(proc.pid, &old.limit());
+                }
+            }
+        }
+    }
+    rules.extend(updated);
+}
@@ -2,8 +2,11 @@
 
 use std::sync::Arc;
 
+use crate::capture::recorder::PacketRecorder;
 use crate::capture::CaptureEngine;
+use crate::core::flow_table::FlowTable;
 use crate::core::process_mapper::ProcessMapper;
+use crate::core::quota::QuotaManager;
 use crate::core::rate_limiter::RateLimiterManager;
 use crate::core::traffic::TrafficTracker;
 use crate::db::{self, Database};
@@ -12,12 +15,20 @@ use crate::db::{self, Database};
 pub struct AppState {
     pub process_mapper: Arc<ProcessMapper>,
     pub traffic_tracker: Arc<TrafficTracker>,
+    /// Per-connection (5-tuple) counters fed by the capture engine.
+    pub flow_table: Arc<FlowTable>,
     pub rate_limiter: Arc<RateLimiterManager>,
+    /// Data quotas with their usage in the current period.
+    pub quotas: Arc<QuotaManager>,
+    /// Packet tap fed by whichever capture engine is running (per-process export).
+    pub packet_recorder: Arc<PacketRecorder>,
     pub database: Arc<Database>,
     /// Bandwidth threshold for notifications (bytes/sec, 0 = disabled). (AC-6.4)
     pub notification_threshold_bps: Arc<std::sync::atomic::AtomicU64>,
     /// Persistent rules from the active profile, auto-applied to new processes. (F7)
     pub persistent_rules: Arc<parking_lot::Mutex<Vec<db::SavedRule>>>,
+    /// Name of the profile `persistent_rules` was loaded from.
+    pub active_profile: parking_lot::Mutex<Option<String>>,
     /// Active SNIFF engine (stopped when intercept is active to avoid double-counting).
     pub sniff_engine: parking_lot::Mutex<Option<CaptureEngine>>,
     /// Active intercept engine (None when in SNIFF-only mode). (Phase 2)
@@ -95,7 +95,9 @@ pub fn enable_intercept_mode(
     let engine = CaptureEngine::start_intercept(
         Arc::clone(&state.process_mapper),
         Arc::clone(&state.traffic_tracker),
+        Arc::clone(&state.flow_table),
         Arc::clone(&state.rate_limiter),
+        Arc::clone(&state.packet_recorder),
         filter,
     )
     .map_err(|e| AppError::Capture(e.to_string()))?;
@@ -116,6 +118,8 @@ pub fn disable_intercept_mode(state: State<'_, AppState>) -> Result<(), AppError
     match CaptureEngine::start_sniff(
         Arc::clone(&state.process_mapper),
         Arc::clone(&state.traffic_tracker),
+        Arc::clone(&state.flow_table),
+        Arc::clone(&state.packet_recorder),
     ) {
         Ok(engine) => {
             *state.sniff_engine.lock() = Some(engine);
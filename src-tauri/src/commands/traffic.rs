@@ -1,12 +1,16 @@
-//! F1 traffic monitoring, F4 traffic history, and AC-1.6 process icon commands.
+//! F1 traffic monitoring (per process and per connection), F4 traffic history,
+//! AC-1.6 process icon, and offline capture replay commands.
 
 use tauri::State;
 
+use crate::capture::replay::{self, ReplaySummary};
+use crate::core::flow_table::ConnectionSnapshot;
+use crate::core::traffic::DeferredAttributionStats;
 use crate::core::ProcessTrafficSnapshot;
 use crate::db::{self, TrafficSummary};
 use crate::error::AppError;
 
-use super::logic::validate_timestamps;
+use super::logic::{validate_file_path, validate_timestamps};
 use super::state::AppState;
 
 /// Maximum number of top consumers that can be requested.
@@ -20,6 +24,24 @@ pub fn get_traffic_stats(
     Ok(state.traffic_tracker.snapshot(&state.process_mapper))
 }
 
+/// Returns how many bytes seen before a port map refresh were later
+/// attributed versus counted as unattributed.
+#[tauri::command]
+pub fn get_deferred_attribution_stats(
+    state: State<'_, AppState>,
+) -> Result<DeferredAttributionStats, AppError> {
+    Ok(state.traffic_tracker.deferred_attribution_stats())
+}
+
+/// Returns the live connections of one process, busiest first.
+#[tauri::command]
+pub fn get_process_connections(
+    state: State<'_, AppState>,
+    pid: u32,
+) -> Result<Vec<ConnectionSnapshot>, AppError> {
+    Ok(state.flow_table.connections_for(pid))
+}
+
 /// Get the base64-encoded icon data URI for a process executable.
 #[tauri::command]
 pub fn get_process_icon(
@@ -62,3 +84,23 @@ pub fn get_top_consumers(
         .top_consumers(from_timestamp, to_timestamp, limit)
         .map_err(|e| AppError::Database(e.to_string()))
 }
+
+/// Replay a pcap/pcapng file, attributing ports to processes from a JSON
+/// mapping file instead of the live process scan. History rows keep the
+/// capture's original timestamps and go to a separate database at
+/// `history_path` (created if missing), never to the live history; without
+/// one, only the summary is returned.
+#[tauri::command]
+pub fn replay_capture_file(
+    capture_path: String,
+    mapping_path: String,
+    history_path: Option<String>,
+) -> Result<ReplaySummary, AppError> {
+    let capture_path = validate_file_path(&capture_path, "Capture file")?;
+    let mapping_path = validate_file_path(&mapping_path, "Mapping file")?;
+    let history_path = history_path
+        .map(|path| validate_file_path(&path, "Replay history"))
+        .transpose()?;
+    replay::replay_capture_file(&capture_path, &mapping_path, history_path.as_deref())
+        .map_err(|e| AppError::Io(format!("{e:#}")))
+}
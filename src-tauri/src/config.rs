@@ -15,6 +15,37 @@ pub const TRAY_UPDATE_INTERVAL_SECS: u64 = 2;
 /// Interval at which persistent profile rules are auto-applied to newly launched processes (seconds).
 pub const PERSISTENT_RULES_INTERVAL_SECS: u64 = 3;
 
+/// Interval at which data quota usage is accounted, persisted and enforced (seconds).
+pub const QUOTA_CHECK_INTERVAL_SECS: u64 = 5;
+
+/// Interval at which temporary limits and blocks are checked for expiry (seconds).
+pub const RULE_EXPIRY_INTERVAL_SECS: u64 = 1;
+
+/// Longest duration of a temporary limit or block (seconds, 7 days).
+pub const MAX_TEMPORARY_RULE_SECS: u64 = 7 * 24 * 3600;
+
+/// Maximum number of time windows in one rule schedule.
+pub const MAX_SCHEDULE_WINDOWS: usize = 16;
+
+/// Maximum length of a saved rule's glob or regex pattern.
+pub const MAX_RULE_PATTERN_LEN: usize = 1024;
+
+/// Maximum number of entries in each list of allowlist mode.
+pub const MAX_ALLOWLIST_ENTRIES: usize = 512;
+
+/// Process names always allowed in allowlist mode unless the user edits the
+/// list (regexes): the Windows kernel, which owns SMB and other system sockets.
+pub const DEFAULT_ALWAYS_ALLOW_NAMES: &[&str] = &["System"];
+
+/// Executable paths always allowed in allowlist mode unless the user edits the
+/// list (regexes): the services that name resolution, DHCP, time sync and
+/// authentication depend on.
+pub const DEFAULT_ALWAYS_ALLOW_PATHS: &[&str] = &[
+    r"(?i)[a-z]:\\Windows\\System32\\(svchost|lsass|services|wininit)\.exe",
+    r"(/usr)?/lib/systemd/systemd-(resolved|networkd|timesyncd)",
+    r"/usr/s?bin/(NetworkManager|dhclient|dhcpcd|chronyd)",
+];
+
 /// Maximum age of traffic history records before they are pruned (days).
 pub const PRUNE_MAX_AGE_DAYS: u64 = 90;
 
@@ -31,10 +62,116 @@ pub const TRAY_TOP_CONSUMERS_COUNT: usize = 5;
 /// Interval at which the process scanner refreshes PID ↔ port mappings (milliseconds).
 pub const PROCESS_SCAN_INTERVAL_MS: u64 = 500;
 
+/// In allowlist mode, how long the later fragments of a datagram whose first
+/// fragment passed are let through after it (seconds). Matches the usual IP
+/// reassembly timeout.
+pub const ALLOWLIST_FRAGMENT_TTL_SECS: u64 = 30;
+
+/// Maximum number of fragmented datagrams remembered in allowlist mode. The
+/// later fragments of further datagrams are dropped.
+pub const MAX_ALLOWLIST_FRAGMENTS: usize = 4096;
+
 /// Number of scan cycles between dead-process cleanup sweeps.
 /// At 500ms intervals, 10 cycles = 5 seconds.
 pub const STALE_PID_CLEANUP_INTERVAL: u64 = 10;
 
+/// Stats-aggregator ticks a packet whose port has no known owner waits in the
+/// deferred attribution queue before it is counted as unattributed.
+/// Entries expire on the third tick after they are queued: at 1-second ticks
+/// and 500 ms scans, just over 2 seconds or four port map refreshes.
+pub const PENDING_ATTRIBUTION_MAX_TICKS: u32 = 3;
+
+/// Maximum number of (protocol, port) keys waiting in the deferred attribution
+/// queue; bytes for further ports are counted as unattributed right away.
+pub const PENDING_ATTRIBUTION_MAX_ENTRIES: usize = 4096;
+
+/// Idle timeout of an open TCP flow in the connection table (seconds).
+pub const FLOW_TCP_IDLE_TIMEOUT_SECS: u64 = 300;
+
+/// Idle timeout of a TCP flow after FIN in both directions or RST (seconds).
+pub const FLOW_TCP_CLOSED_TIMEOUT_SECS: u64 = 10;
+
+/// Idle timeout of a UDP flow in the connection table (seconds).
+pub const FLOW_UDP_IDLE_TIMEOUT_SECS: u64 = 60;
+
+/// Interval at which idle flows are swept from the connection table (seconds).
+pub const FLOW_SWEEP_INTERVAL_SECS: u64 = 5;
+
+/// Maximum number of flows tracked at once; new flows beyond this are only
+/// counted per process.
+pub const FLOW_TABLE_MAX_ENTRIES: usize = 65_536;
+
+/// Maximum packets held per process and direction by the INTERCEPT shaper.
+/// Further packets are dropped (drop-tail).
+pub const SHAPER_MAX_QUEUE_PACKETS: usize = 256;
+
+/// CoDel target queueing delay for shaped traffic (milliseconds).
+pub const SHAPER_CODEL_TARGET_MS: u64 = 5;
+
+/// CoDel interval: how long the delay must stay above target before the
+/// first drop (milliseconds).
+pub const SHAPER_CODEL_INTERVAL_MS: u64 = 100;
+
+/// Largest scheduling weight a shaped rule may carry. Weights are relative,
+/// so this only bounds how lopsided a split between two processes can be.
+pub const MAX_RULE_WEIGHT: u32 = 100;
+
+/// Token bucket size of a limit that does not configure one, as a duration
+/// at the limit's rate (milliseconds).
+pub const DEFAULT_BURST_MS: u64 = 2000;
+
+/// Smallest token bucket size: one MTU-sized packet, so low limits still
+/// pass full-size packets (bytes).
+pub const MIN_BURST_BYTES: u64 = 1500;
+
+/// Largest burst a rule may configure as a duration (milliseconds).
+pub const MAX_BURST_MS: u64 = 60_000;
+
+/// Largest burst a rule may configure in bytes.
+pub const MAX_BURST_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Upper bound on the duration of a per-process packet export (seconds).
+pub const MAX_EXPORT_DURATION_SECS: u64 = 3600;
+
+/// Upper bound on the size of a per-process packet export file (bytes).
+pub const MAX_EXPORT_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Lower bound on the size limit of a packet export, so the file can hold
+/// more than its header blocks (bytes).
+pub const MIN_EXPORT_BYTES: u64 = 64 * 1024;
+
+/// Number of finished packet export sessions kept for status queries.
+pub const FINISHED_EXPORTS_KEPT: usize = 16;
+
+/// Default memory budget of the packet-header flight recorder (bytes).
+/// At ~56 bytes per record this holds roughly 300k packets.
+pub const FLIGHT_RECORDER_DEFAULT_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Upper bound on the flight recorder memory budget (bytes).
+pub const FLIGHT_RECORDER_MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Window dumped by the tray's "Save recent packets" item (seconds).
+pub const FLIGHT_RECORDER_TRAY_DUMP_SECS: u64 = 60;
+
+/// Netfilter queue number used by the Linux INTERCEPT backend (`--queue-num`).
+#[cfg(target_os = "linux")]
+pub const NFQUEUE_QUEUE_NUM: u16 = 5201;
+
+/// Maximum number of packets the kernel holds for the NFQUEUE backend before
+/// fail-open kicks in and further packets are accepted without a verdict.
+#[cfg(target_os = "linux")]
+pub const NFQUEUE_MAX_LEN: u32 = 4096;
+
+/// Minimum time between sock_diag queries for a local endpoint the port map
+/// has no owner for (milliseconds).
+#[cfg(target_os = "linux")]
+pub const SOCK_DIAG_RETRY_BACKOFF_MS: u64 = 1000;
+
+/// Maximum number of flows waiting for a sock_diag query. Further misses
+/// wait for the next scan.
+#[cfg(target_os = "linux")]
+pub const SOCK_DIAG_MAX_QUEUED: usize = 1024;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -56,10 +193,42 @@ mod tests {
         const _: () = assert!(HISTORY_RECORD_INTERVAL_SECS > 0);
         const _: () = assert!(TRAY_UPDATE_INTERVAL_SECS > 0);
         const _: () = assert!(PERSISTENT_RULES_INTERVAL_SECS > 0);
+        const _: () = assert!(QUOTA_CHECK_INTERVAL_SECS > 0);
+        const _: () = assert!(RULE_EXPIRY_INTERVAL_SECS > 0);
+        const _: () = assert!(MAX_TEMPORARY_RULE_SECS > RULE_EXPIRY_INTERVAL_SECS);
+        const _: () = assert!(MAX_SCHEDULE_WINDOWS > 0);
+        const _: () = assert!(MAX_RULE_PATTERN_LEN > 0);
+        const _: () = assert!(MAX_ALLOWLIST_ENTRIES > 0);
         const _: () = assert!(PRUNE_MAX_AGE_DAYS > 0);
         const _: () = assert!(PRUNE_CHECK_INTERVAL_TICKS > 0);
         const _: () = assert!(TRAY_TOP_CONSUMERS_COUNT > 0);
         const _: () = assert!(PROCESS_SCAN_INTERVAL_MS > 0);
+        const _: () = assert!(ALLOWLIST_FRAGMENT_TTL_SECS > 0);
+        const _: () = assert!(MAX_ALLOWLIST_FRAGMENTS > 0);
+        const _: () = assert!(PENDING_ATTRIBUTION_MAX_TICKS > 0);
+        const _: () = assert!(PENDING_ATTRIBUTION_MAX_ENTRIES > 0);
+        const _: () = assert!(FLOW_TCP_CLOSED_TIMEOUT_SECS > 0);
+        const _: () = assert!(FLOW_TCP_CLOSED_TIMEOUT_SECS < FLOW_UDP_IDLE_TIMEOUT_SECS);
+        const _: () = assert!(FLOW_UDP_IDLE_TIMEOUT_SECS < FLOW_TCP_IDLE_TIMEOUT_SECS);
+        const _: () = assert!(FLOW_SWEEP_INTERVAL_SECS > 0);
+        const _: () = assert!(FLOW_TABLE_MAX_ENTRIES > 0);
+        const _: () = assert!(SHAPER_MAX_QUEUE_PACKETS > 0);
+        const _: () = assert!(SHAPER_CODEL_TARGET_MS > 0);
+        const _: () = assert!(SHAPER_CODEL_TARGET_MS < SHAPER_CODEL_INTERVAL_MS);
+        const _: () = assert!(MAX_RULE_WEIGHT >= 1);
+        const _: () = assert!(DEFAULT_BURST_MS > 0 && DEFAULT_BURST_MS <= MAX_BURST_MS);
+        const _: () = assert!(MIN_BURST_BYTES > 0 && MIN_BURST_BYTES <= MAX_BURST_BYTES);
+        const _: () = assert!(MAX_EXPORT_DURATION_SECS > 0);
+        const _: () = assert!(MIN_EXPORT_BYTES > 0 && MIN_EXPORT_BYTES <= MAX_EXPORT_BYTES);
+        const _: () = assert!(FINISHED_EXPORTS_KEPT > 0);
+        const _: () = assert!(FLIGHT_RECORDER_DEFAULT_BYTES <= FLIGHT_RECORDER_MAX_BYTES);
+        const _: () = assert!(FLIGHT_RECORDER_TRAY_DUMP_SECS > 0);
+        #[cfg(target_os = "linux")]
+        const _: () = assert!(NFQUEUE_MAX_LEN > 0);
+        #[cfg(target_os = "linux")]
+        const _: () = assert!(SOCK_DIAG_RETRY_BACKOFF_MS > 0);
+        #[cfg(target_os = "linux")]
+        const _: () = assert!(SOCK_DIAG_MAX_QUEUED > 0);
         // f64 cannot use const assert, so skip STALE_PROCESS_TIMEOUT_SECS
     }
 }
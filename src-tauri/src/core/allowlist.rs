@@ -0,0 +1,192 @@
+//! Default-deny allowlist mode: only listed executables may use the network.
+//!
+//! An [`Allowlist`] has two lists of patterns, matched like saved rules (see
+//! [`rule_match`](super::rule_match)): the applications the user allows, and
+//! the system processes that are always allowed so that name resolution,
+//! DHCP and the like keep working. While the mode is on,
+//! [`RateLimiterManager`](super::RateLimiterManager) drops the traffic of
+//! every process that matches neither, as well as traffic no process can be
+//! found for. A new connection of an allowed application thus loses its
+//! first packets until a scan maps its port, which TCP retransmits and
+//! resolvers retry.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::process_mapper::ProcessInfo;
+use super::rule_match::{MatchType, RuleMatcher};
+use crate::config::{DEFAULT_ALWAYS_ALLOW_NAMES, DEFAULT_ALWAYS_ALLOW_PATHS};
+
+/// One allowed executable, or a pattern on the path or process name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct AllowEntry {
+    #[serde(default)]
+    pub match_type: MatchType,
+    pub pattern: String,
+}
+
+/// The applications allowed network access in allowlist mode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct Allowlist {
+    /// Applications the user allows.
+    pub allowed: Vec<AllowEntry>,
+    /// Essential system processes, allowed whatever `allowed` says.
+    pub always_allow: Vec<AllowEntry>,
+}
+
+impl Default for Allowlist {
+    /// No allowed applications, and the built-in list of system processes.
+    fn default() -> Self {
+        let entries = |match_type, patterns: &'static [&'static str]| {
+            patterns.iter().map(move |pattern| AllowEntry {
+                match_type,
+                pattern: pattern.to_string(),
+            })
+        };
+        Self {
+            allowed: Vec::new(),
+            always_allow: entries(MatchType::NameRegex, DEFAULT_ALWAYS_ALLOW_NAMES)
+                .chain(entries(MatchType::PathRegex, DEFAULT_ALWAYS_ALLOW_PATHS))
+                .collect(),
+        }
+    }
+}
+
+/// Whether allowlist mode is on, and its lists.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct AllowlistStatus {
+    pub enabled: bool,
+    pub list: Allowlist,
+}
+
+/// A process allowlist mode would cut off, as reported by a preview.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct WouldBlock {
+    pub pid: u32,
+    pub name: String,
+    pub exe_path: String,
+}
+
+/// What allowlist mode would cut off, as reported by a preview.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct AllowlistPreview {
+    /// Running processes the list does not allow.
+    pub would_block: Vec<WouldBlock>,
+    /// Bytes seen so far that no process was found for. The mode drops such
+    /// traffic too.
+    #[ts(type = "number")]
+    pub unowned_bytes: u64,
+}
+
+/// A compiled [`Allowlist`].
+#[derive(Debug)]
+pub struct AllowlistMatcher {
+    matchers: Vec<RuleMatcher>,
+}
+
+impl AllowlistMatcher {
+    /// Compile both lists. Fails on an invalid glob or regex.
+    pub fn new(list: &Allowlist) -> Result<Self> {
+        let matchers = list
+            .allowed
+            .iter()
+            .chain(&list.always_allow)
+            .map(|entry| RuleMatcher::new(entry.match_type, &entry.pattern))
+            .collect::<Result<_>>()?;
+        Ok(Self { matchers })
+    }
+
+    /// True if the process `info` may use the network.
+    pub fn allows(&self, info: &ProcessInfo) -> bool {
+        self.matchers
+            .iter()
+            .any(|m| m.matches(&info.exe_path, &info.name))
+    }
+
+    /// The `processes` this list would block, by PID.
+    pub fn would_block(&self, processes: &[(u32, ProcessInfo)]) -> Vec<WouldBlock> {
+        let mut blocked: Vec<WouldBlock> = processes
+            .iter()
+            .filter(|(_, info)| !self.allows(info))
+            .map(|(pid, info)| WouldBlock {
+                pid: *pid,
+                name: info.name.clone(),
+                exe_path: info.exe_path.clone(),
+            })
+            .collect();
+        blocked.sort_by_key(|b| b.pid);
+        blocked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, name: &str, exe_path: &str) -> (u32, ProcessInfo) {
+        (
+            pid,
+            ProcessInfo {
+                name: name.to_string(),
+                exe_path: exe_path.to_string(),
+                args: String::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_default_list_allows_system_processes_only() {
+        let matcher = AllowlistMatcher::new(&Allowlist::default()).unwrap();
+        let processes = [
+            process(4, "System", ""),
+            process(800, "svchost.exe", r"C:\WINDOWS\system32\svchost.exe"),
+            process(900, "systemd-resolved", "/usr/lib/systemd/systemd-resolved"),
+            process(1000, "System.exe", r"C:\Users\me\System.exe"),
+            process(1100, "svchost.exe", r"C:\Users\me\svchost.exe"),
+            process(1200, "firefox", "/usr/bin/firefox"),
+        ];
+        let blocked: Vec<u32> = matcher
+            .would_block(&processes)
+            .iter()
+            .map(|b| b.pid)
+            .collect();
+        assert_eq!(blocked, vec![1000, 1100, 1200]);
+    }
+
+    #[test]
+    fn test_allowed_entries_extend_the_system_list() {
+        let list = Allowlist {
+            allowed: vec![
+                AllowEntry {
+                    match_type: MatchType::Exact,
+                    pattern: "/usr/bin/firefox".into(),
+                },
+                AllowEntry {
+                    match_type: MatchType::NameGlob,
+                    pattern: "steam*".into(),
+                },
+            ],
+            ..Allowlist::default()
+        };
+        let matcher = AllowlistMatcher::new(&list).unwrap();
+        assert!(matcher.allows(&process(1, "firefox", "/usr/bin/firefox").1));
+        assert!(matcher.allows(&process(2, "steamwebhelper", "/opt/steam/helper").1));
+        assert!(matcher.allows(&process(3, "System", "").1));
+        assert!(!matcher.allows(&process(4, "firefox", "/tmp/firefox").1));
+
+        let invalid = Allowlist {
+            allowed: vec![AllowEntry {
+                match_type: MatchType::PathRegex,
+                pattern: "(".into(),
+            }],
+            always_allow: Vec::new(),
+        };
+        assert!(AllowlistMatcher::new(&invalid).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Bandwidth rules keyed by application identity rather than PID.
+//!
+//! An app rule names an executable, optionally narrowed to processes whose
+//! command line contains given arguments or whose executable has a given
+//! SHA-256 digest. [`RateLimiterManager`](super::RateLimiterManager) resolves
+//! each rule to the matching PIDs on every process scan, so the rule follows
+//! the application across restarts.
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::process_mapper::ProcessInfo;
+use super::rate_limiter::BandwidthLimit;
+
+/// Which processes an app rule applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct AppMatch {
+    /// Full path of the executable.
+    pub exe_path: String,
+    /// Only match processes whose arguments contain this text.
+    #[serde(default)]
+    pub args: Option<String>,
+    /// Only match if the executable's SHA-256 digest (lowercase hex) is this.
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+impl AppMatch {
+    /// True if the process `info` is one of this app's. `exe_hash` computes
+    /// the digest of an executable and is only called when a hash is required.
+    pub fn matches(
+        &self,
+        info: &ProcessInfo,
+        exe_hash: impl FnOnce(&str) -> Option<String>,
+    ) -> bool {
+        if info.exe_path != self.exe_path {
+            return false;
+        }
+        if let Some(args) = &self.args {
+            if !info.args.contains(args.as_str()) {
+                return false;
+            }
+        }
+        match &self.sha256 {
+            Some(sha256) => exe_hash(&info.exe_path).is_some_and(|h| h == *sha256),
+            None => true,
+        }
+    }
+}
+
+/// What an app rule does to the matching processes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum AppAction {
+    Limit(BandwidthLimit),
+    Block,
+}
+
+/// A limit or block on every process of an application.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct AppRule {
+    pub app: AppMatch,
+    /// Display name of the application.
+    #[serde(default)]
+    pub process_name: String,
+    pub action: AppAction,
+}
+
+/// An app rule with the PIDs it currently applies to.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct AppRuleStatus {
+    pub rule: AppRule,
+    pub pids: Vec<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(exe_path: &str, args: &str) -> ProcessInfo {
+        ProcessInfo {
+            name: "app".to_string(),
+            exe_path: exe_path.to_string(),
+            args: args.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_match_by_exe_path_and_args() {
+        let any = AppMatch {
+            exe_path: "/usr/bin/app".into(),
+            args: None,
+            sha256: None,
+        };
+        assert!(any.matches(&process("/usr/bin/app", "--sync"), |_| None));
+        assert!(!any.matches(&process("/usr/bin/other", ""), |_| None));
+
+        let sync = AppMatch {
+            args: Some("--sync".into()),
+            ..any
+        };
+        assert!(sync.matches(&process("/usr/bin/app", "--quiet --sync"), |_| None));
+        assert!(!sync.matches(&process("/usr/bin/app", "--quiet"), |_| None));
+    }
+
+    #[test]
+    fn test_match_by_hash_only_hashes_when_required() {
+        let plain = AppMatch {
+            exe_path: "/usr/bin/app".into(),
+            args: None,
+            sha256: None,
+        };
+        assert!(plain.matches(&process("/usr/bin/app", ""), |_| panic!("hashed")));
+
+        let pinned = AppMatch {
+            sha256: Some("ab12".into()),
+            ..plain
+        };
+        assert!(pinned.matches(&process("/usr/bin/app", ""), |_| Some("ab12".into())));
+        assert!(!pinned.matches(&process("/usr/bin/app", ""), |_| Some("cd34".into())));
+        assert!(!pinned.matches(&process("/usr/bin/app", ""), |_| None));
+        assert!(!pinned.matches(&process("/usr/bin/other", ""), |_| Some("ab12".into())));
+    }
+}
@@ -0,0 +1,473 @@
+//! Per-connection accounting keyed by the full 5-tuple.
+//!
+//! The capture loop records every attributed packet here as well as in the
+//! per-PID [`TrafficTracker`](super::TrafficTracker), so the UI can show which
+//! remote endpoints each process talks to and how much. TCP state is inferred
+//! from the flags seen in both directions; flows are expired after a
+//! state-dependent idle timeout by a background sweeper.
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use dashmap::DashMap;
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::config;
+use crate::core::process_mapper::Protocol;
+
+/// TCP header flag bits.
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_ACK: u8 = 0x10;
+
+/// A connection as seen from this host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub protocol: Protocol,
+    pub local_addr: IpAddr,
+    pub local_port: u16,
+    pub remote_addr: IpAddr,
+    pub remote_port: u16,
+}
+
+impl FlowKey {
+    /// Orient a packet's source/destination as local/remote.
+    pub fn from_packet(
+        protocol: Protocol,
+        (src_addr, src_port): (IpAddr, u16),
+        (dst_addr, dst_port): (IpAddr, u16),
+        outbound: bool,
+    ) -> Self {
+        let ((local_addr, local_port), (remote_addr, remote_port)) = if outbound {
+            ((src_addr, src_port), (dst_addr, dst_port))
+        } else {
+            ((dst_addr, dst_port), (src_addr, src_port))
+        };
+        Self {
+            protocol,
+            local_addr,
+            local_port,
+            remote_addr,
+            remote_port,
+        }
+    }
+}
+
+/// TCP connection state inferred from observed flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum TcpState {
+    /// We sent a SYN; no handshake reply seen yet.
+    SynSent,
+    /// We received a SYN (incoming connection).
+    SynReceived,
+    /// Handshake completed, or the flow was first seen mid-stream.
+    Established,
+    /// A FIN was seen in one direction.
+    Closing,
+    /// FINs were seen in both directions.
+    Closed,
+    /// A RST was seen.
+    Reset,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TcpTracker {
+    state: TcpState,
+    fin_sent: bool,
+    fin_recv: bool,
+}
+
+impl TcpTracker {
+    fn new(flags: u8, outbound: bool) -> Self {
+        let state = if flags & TCP_SYN != 0 {
+            // A SYN-ACK comes from the passive side.
+            match (flags & TCP_ACK != 0, outbound) {
+                (false, true) | (true, false) => TcpState::SynSent,
+                (false, false) | (true, true) => TcpState::SynReceived,
+            }
+        } else {
+            TcpState::Established
+        };
+        let mut tracker = Self {
+            state,
+            fin_sent: false,
+            fin_recv: false,
+        };
+        tracker.apply_close_flags(flags, outbound);
+        tracker
+    }
+
+    fn update(&mut self, flags: u8, outbound: bool) {
+        if flags & TCP_SYN != 0 && flags & TCP_ACK == 0 && self.is_finished() {
+            // Port reuse: a fresh SYN on a finished flow starts over.
+            *self = Self::new(flags, outbound);
+            return;
+        }
+        if flags & (TCP_SYN | TCP_FIN | TCP_RST) == 0
+            && flags & TCP_ACK != 0
+            && matches!(self.state, TcpState::SynSent | TcpState::SynReceived)
+        {
+            self.state = TcpState::Established;
+        }
+        self.apply_close_flags(flags, outbound);
+    }
+
+    fn apply_close_flags(&mut self, flags: u8, outbound: bool) {
+        if flags & TCP_RST != 0 {
+            self.state = TcpState::Reset;
+            return;
+        }
+        if flags & TCP_FIN != 0 {
+            if outbound {
+                self.fin_sent = true;
+            } else {
+                self.fin_recv = true;
+            }
+            self.state = if self.fin_sent && self.fin_recv {
+                TcpState::Closed
+            } else {
+                TcpState::Closing
+            };
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.state, TcpState::Closed | TcpState::Reset)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FlowEntry {
+    pid: Option<u32>,
+    bytes_sent: u64,
+    bytes_recv: u64,
+    packets_sent: u64,
+    packets_recv: u64,
+    first_seen_ms: i64,
+    last_seen_ms: i64,
+    last_seen: Instant,
+    tcp: Option<TcpTracker>,
+}
+
+impl FlowEntry {
+    fn idle_timeout(&self) -> Duration {
+        let secs = match self.tcp {
+            Some(tcp) if tcp.is_finished() => config::FLOW_TCP_CLOSED_TIMEOUT_SECS,
+            Some(_) => config::FLOW_TCP_IDLE_TIMEOUT_SECS,
+            None => config::FLOW_UDP_IDLE_TIMEOUT_SECS,
+        };
+        Duration::from_secs(secs)
+    }
+}
+
+/// One connection of a process, serializable for the frontend.
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct ConnectionSnapshot {
+    pub protocol: Protocol,
+    #[ts(type = "string")]
+    pub local_addr: IpAddr,
+    pub local_port: u16,
+    #[ts(type = "string")]
+    pub remote_addr: IpAddr,
+    pub remote_port: u16,
+    pub pid: Option<u32>,
+    #[ts(type = "number")]
+    pub bytes_sent: u64,
+    #[ts(type = "number")]
+    pub bytes_recv: u64,
+    #[ts(type = "number")]
+    pub packets_sent: u64,
+    #[ts(type = "number")]
+    pub packets_recv: u64,
+    /// Unix timestamp (milliseconds) of the first packet.
+    #[ts(type = "number")]
+    pub first_seen_ms: i64,
+    /// Unix timestamp (milliseconds) of the latest packet.
+    #[ts(type = "number")]
+    pub last_seen_ms: i64,
+    /// `None` for non-TCP flows.
+    pub tcp_state: Option<TcpState>,
+}
+
+/// Thread-safe flow table. Keyed by [`FlowKey`].
+pub struct FlowTable {
+    flows: DashMap<FlowKey, FlowEntry>,
+    /// Wall-clock anchor so timestamps derive from the monotonic `Instant`.
+    epoch: Instant,
+    epoch_ms: i64,
+}
+
+impl FlowTable {
+    pub fn new() -> Self {
+        Self {
+            flows: DashMap::new(),
+            epoch: Instant::now(),
+            epoch_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as i64,
+        }
+    }
+
+    /// Record one packet of `len` bytes. `tcp_flags` is `Some` for TCP.
+    /// Called from the capture loop.
+    pub fn record(
+        &self,
+        key: FlowKey,
+        pid: Option<u32>,
+        len: u64,
+        outbound: bool,
+        tcp_flags: Option<u8>,
+    ) {
+        self.record_at(key, pid, len, outbound, tcp_flags, Instant::now());
+    }
+
+    /// [`record`](Self::record) with an explicit clock, for tests.
+    pub fn record_at(
+        &self,
+        key: FlowKey,
+        pid: Option<u32>,
+        len: u64,
+        outbound: bool,
+        tcp_flags: Option<u8>,
+        now: Instant,
+    ) {
+        let now_ms = self.epoch_ms + now.saturating_duration_since(self.epoch).as_millis() as i64;
+        if let Some(mut flow) = self.flows.get_mut(&key) {
+            if pid.is_some() {
+                flow.pid = pid;
+            }
+            if outbound {
+                flow.bytes_sent = flow.bytes_sent.saturating_add(len);
+                flow.packets_sent += 1;
+            } else {
+                flow.bytes_recv = flow.bytes_recv.saturating_add(len);
+                flow.packets_recv += 1;
+            }
+            flow.last_seen = now;
+            flow.last_seen_ms = now_ms;
+            match (&mut flow.tcp, tcp_flags) {
+                (Some(tcp), Some(flags)) => tcp.update(flags, outbound),
+                (tcp @ None, Some(flags)) => *tcp = Some(TcpTracker::new(flags, outbound)),
+                _ => {}
+            }
+            return;
+        }
+
+        // Bound memory under scans/floods: untracked flows are still counted
+        // per PID by the traffic tracker.
+        if self.flows.len() >= config::FLOW_TABLE_MAX_ENTRIES {
+            return;
+        }
+        let (sent, recv) = if outbound { (len, 0) } else { (0, len) };
+        self.flows.insert(
+            key,
+            FlowEntry {
+                pid,
+                bytes_sent: sent,
+                bytes_recv: recv,
+                packets_sent: outbound as u64,
+                packets_recv: !outbound as u64,
+                first_seen_ms: now_ms,
+                last_seen_ms: now_ms,
+                last_seen: now,
+                tcp: tcp_flags.map(|flags| TcpTracker::new(flags, outbound)),
+            },
+        );
+    }
+
+    /// Drop flows idle for longer than their state's timeout.
+    /// Returns the number of flows removed.
+    pub fn expire_idle(&self) -> usize {
+        self.expire_idle_at(Instant::now())
+    }
+
+    pub fn expire_idle_at(&self, now: Instant) -> usize {
+        let before = self.flows.len();
+        self.flows
+            .retain(|_, flow| now.saturating_duration_since(flow.last_seen) < flow.idle_timeout());
+        before.saturating_sub(self.flows.len())
+    }
+
+    /// Connections attributed to `pid`, busiest first.
+    pub fn connections_for(&self, pid: u32) -> Vec<ConnectionSnapshot> {
+        let mut connections: Vec<_> = self
+            .flows
+            .iter()
+            .filter(|entry| entry.value().pid == Some(pid))
+            .map(|entry| {
+                let (key, flow) = (entry.key(), entry.value());
+                ConnectionSnapshot {
+                    protocol: key.protocol,
+                    local_addr: key.local_addr,
+                    local_port: key.local_port,
+                    remote_addr: key.remote_addr,
+                    remote_port: key.remote_port,
+                    pid: flow.pid,
+                    bytes_sent: flow.bytes_sent,
+                    bytes_recv: flow.bytes_recv,
+                    packets_sent: flow.packets_sent,
+                    packets_recv: flow.packets_recv,
+                    first_seen_ms: flow.first_seen_ms,
+                    last_seen_ms: flow.last_seen_ms,
+                    tcp_state: flow.tcp.map(|t| t.state),
+                }
+            })
+            .collect();
+        connections.sort_by_key(|c| std::cmp::Reverse(c.bytes_sent + c.bytes_recv));
+        connections
+    }
+}
+
+impl Default for FlowTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(local_port: u16, remote: &str, remote_port: u16) -> FlowKey {
+        FlowKey {
+            protocol: Protocol::Tcp,
+            local_addr: "10.0.0.2".parse().unwrap(),
+            local_port,
+            remote_addr: remote.parse().unwrap(),
+            remote_port,
+        }
+    }
+
+    fn state_of(table: &FlowTable, pid: u32) -> Option<TcpState> {
+        table.connections_for(pid)[0].tcp_state
+    }
+
+    #[test]
+    fn test_from_packet_orients_by_direction() {
+        let local: IpAddr = "10.0.0.2".parse().unwrap();
+        let remote: IpAddr = "1.1.1.1".parse().unwrap();
+        let out = FlowKey::from_packet(Protocol::Udp, (local, 5000), (remote, 53), true);
+        let back = FlowKey::from_packet(Protocol::Udp, (remote, 53), (local, 5000), false);
+        assert_eq!(out, back);
+        assert_eq!(out.local_port, 5000);
+        assert_eq!(out.remote_addr, remote);
+    }
+
+    #[test]
+    fn test_counts_bytes_and_packets_per_direction() {
+        let table = FlowTable::new();
+        let now = Instant::now();
+        let later = |secs: u64| now + Duration::from_secs(secs);
+        let k = key(5000, "93.184.216.34", 443);
+        table.record_at(k, Some(42), 100, true, Some(TCP_ACK), later(1));
+        table.record_at(k, Some(42), 1500, false, Some(TCP_ACK), later(2));
+        table.record_at(k, Some(42), 1500, false, Some(TCP_ACK), later(3));
+        table.record_at(
+            key(5001, "1.1.1.1", 443),
+            Some(42),
+            10,
+            true,
+            None,
+            later(3),
+        );
+
+        let conns = table.connections_for(42);
+        assert_eq!(conns.len(), 2);
+        let c = &conns[0];
+        assert_eq!(c.remote_port, 443);
+        assert_eq!((c.bytes_sent, c.bytes_recv), (100, 3000));
+        assert_eq!((c.packets_sent, c.packets_recv), (1, 2));
+        assert_eq!(c.last_seen_ms - c.first_seen_ms, 2_000);
+        assert!(table.connections_for(7).is_empty());
+    }
+
+    #[test]
+    fn test_tcp_state_follows_handshake_and_close() {
+        let table = FlowTable::new();
+        let now = Instant::now();
+        let k = key(5000, "93.184.216.34", 443);
+        let step = |flags: u8, outbound: bool| {
+            table.record_at(k, Some(1), 60, outbound, Some(flags), now);
+            state_of(&table, 1)
+        };
+
+        assert_eq!(step(TCP_SYN, true), Some(TcpState::SynSent));
+        assert_eq!(step(TCP_SYN | TCP_ACK, false), Some(TcpState::SynSent));
+        assert_eq!(step(TCP_ACK, true), Some(TcpState::Established));
+        assert_eq!(step(TCP_FIN | TCP_ACK, true), Some(TcpState::Closing));
+        assert_eq!(step(TCP_FIN | TCP_ACK, false), Some(TcpState::Closed));
+        // Port reuse starts a new handshake.
+        assert_eq!(step(TCP_SYN, true), Some(TcpState::SynSent));
+        assert_eq!(step(TCP_RST, false), Some(TcpState::Reset));
+    }
+
+    #[test]
+    fn test_inbound_and_midstream_flows() {
+        let table = FlowTable::new();
+        let now = Instant::now();
+        table.record_at(
+            key(22, "192.168.1.5", 51000),
+            Some(1),
+            60,
+            false,
+            Some(TCP_SYN),
+            now,
+        );
+        assert_eq!(state_of(&table, 1), Some(TcpState::SynReceived));
+
+        table.record_at(
+            key(5000, "1.1.1.1", 443),
+            Some(2),
+            60,
+            true,
+            Some(TCP_ACK),
+            now,
+        );
+        assert_eq!(state_of(&table, 2), Some(TcpState::Established));
+    }
+
+    #[test]
+    fn test_late_pid_attribution_updates_flow() {
+        let table = FlowTable::new();
+        let now = Instant::now();
+        let k = key(5000, "1.1.1.1", 443);
+        table.record_at(k, None, 60, true, Some(TCP_SYN), now);
+        table.record_at(k, Some(42), 60, true, Some(TCP_ACK), now);
+        table.record_at(k, None, 60, false, Some(TCP_ACK), now);
+        assert_eq!(table.connections_for(42)[0].packets_sent, 2);
+    }
+
+    #[test]
+    fn test_idle_timeouts_depend_on_state() {
+        let table = FlowTable::new();
+        let start = Instant::now();
+        let established = key(5000, "1.1.1.1", 443);
+        let closed = key(5001, "1.1.1.1", 443);
+        let mut udp = key(5002, "1.1.1.1", 53);
+        udp.protocol = Protocol::Udp;
+        table.record_at(established, Some(1), 60, true, Some(TCP_ACK), start);
+        table.record_at(closed, Some(1), 60, true, Some(TCP_RST), start);
+        table.record_at(udp, Some(1), 60, true, None, start);
+
+        let after = |secs: u64| start + Duration::from_secs(secs);
+        assert_eq!(
+            table.expire_idle_at(after(config::FLOW_TCP_CLOSED_TIMEOUT_SECS)),
+            1
+        );
+        assert_eq!(
+            table.expire_idle_at(after(config::FLOW_UDP_IDLE_TIMEOUT_SECS)),
+            1
+        );
+        assert_eq!(table.flows.len(), 1);
+        assert_eq!(
+            table.expire_idle_at(after(config::FLOW_TCP_IDLE_TIMEOUT_SECS)),
+            1
+        );
+        assert!(table.flows.is_empty());
+    }
+}
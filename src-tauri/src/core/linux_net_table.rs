@@ -0,0 +1,408 @@
+//! Linux procfs reader for TCP/UDP/ICMP port-to-PID tables.
+//!
+//! Parses `/proc/net/{tcp,tcp6,udp,udp6,icmp,icmp6}` for
+//! `(local_addr, local_port, socket inode)` rows, then resolves inodes to PIDs
+//! by reading the `socket:[inode]` links under `/proc/<pid>/fd`. This is the
+//! Linux counterpart of [`super::win_net_table`].
+//!
+//! Inode owners are cached across scans, so the expensive fd walk only runs
+//! for sockets that appeared since the previous scan. A single socket whose
+//! owning user is known (from sock_diag) is found by walking only that
+//! user's processes.
+
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use dashmap::DashMap;
+
+use crate::core::process_mapper::{port_key, sync_port_map, PortKey, Protocol};
+
+/// Socket tables scanned on every refresh, with the protocol they map to.
+/// The ICMP tables list unprivileged ping sockets, whose "port" is the echo identifier.
+const SOCKET_TABLES: [(&str, Protocol); 6] = [
+    ("net/tcp", Protocol::Tcp),
+    ("net/udp", Protocol::Udp),
+    ("net/tcp6", Protocol::Tcp),
+    ("net/udp6", Protocol::Udp),
+    ("net/icmp", Protocol::Icmp),
+    ("net/icmp6", Protocol::Icmpv6),
+];
+
+/// One socket row parsed from a `/proc/net` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SocketRow {
+    local_addr: IpAddr,
+    local_port: u16,
+    inode: u64,
+}
+
+/// Scan all TCP, UDP and ICMP tables (IPv4 + IPv6) under `/proc` and update the port map.
+/// `socket_owners` is the inode → PID cache carried between scans.
+pub fn refresh_port_map(port_map: &DashMap<PortKey, u32>, socket_owners: &DashMap<u64, u32>) {
+    refresh_port_map_from(Path::new("/proc"), port_map, socket_owners);
+}
+
+/// Same as [`refresh_port_map`], reading from an arbitrary procfs root.
+/// Used by tests to scan fixture trees.
+pub fn refresh_port_map_from(
+    proc_root: &Path,
+    port_map: &DashMap<PortKey, u32>,
+    socket_owners: &DashMap<u64, u32>,
+) {
+    let mut sockets: Vec<(Protocol, SocketRow)> = Vec::new();
+    for (table, proto) in SOCKET_TABLES {
+        match std::fs::read_to_string(proc_root.join(table)) {
+            Ok(contents) => {
+                sockets.extend(parse_socket_table(&contents).map(|row| (proto, row)));
+            }
+            // The IPv6 tables are absent when IPv6 is disabled.
+            Err(e) => tracing::trace!("Failed to read {table}: {e}"),
+        }
+    }
+
+    // Forget closed sockets, then walk /proc only for the new ones.
+    let live: HashSet<u64> = sockets.iter().map(|(_, r)| r.inode).collect();
+    socket_owners.retain(|inode, _| live.contains(inode));
+    let unknown = live
+        .iter()
+        .copied()
+        .filter(|inode| !socket_owners.contains_key(inode));
+    for (inode, pid) in resolve_socket_owners(proc_root, unknown, None) {
+        socket_owners.insert(inode, pid);
+    }
+
+    let fresh = sockets
+        .into_iter()
+        .filter_map(|(proto, row)| {
+            let pid = *socket_owners.get(&row.inode)?;
+            Some((port_key(proto, row.local_addr, row.local_port), pid))
+        })
+        .collect();
+    sync_port_map(port_map, fresh);
+}
+
+/// Find the process owning socket `inode` by walking `/proc/<pid>/fd` of
+/// the processes of `uid`, the socket's owner, only.
+pub fn find_socket_owner(inode: u64, uid: u32) -> Option<u32> {
+    find_socket_owner_in(Path::new("/proc"), inode, uid)
+}
+
+/// Same as [`find_socket_owner`], reading from an arbitrary procfs root.
+fn find_socket_owner_in(proc_root: &Path, inode: u64, uid: u32) -> Option<u32> {
+    resolve_socket_owners(proc_root, std::iter::once(inode), Some(uid)).remove(&inode)
+}
+
+/// Parse the rows of a `/proc/net/{tcp,udp,icmp}[6]` table, skipping the header.
+///
+/// Rows look like:
+/// `0: 0100007F:0CEA 00000000:0000 0A 00000000:00000000 00:00000000 00000000 113 0 20395 ...`
+/// where the local address and port are hex around the colon and the inode is field 9.
+/// Rows with port 0 or inode 0 (e.g. TIME_WAIT sockets) have no owner and are skipped.
+fn parse_socket_table(contents: &str) -> impl Iterator<Item = SocketRow> + '_ {
+    contents.lines().skip(1).filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        let local = fields.nth(1)?;
+        let (addr_hex, port_hex) = local.rsplit_once(':')?;
+        let local_addr = parse_hex_addr(addr_hex)?;
+        let local_port = u16::from_str_radix(port_hex, 16).ok()?;
+        let inode: u64 = fields.nth(7)?.parse().ok()?;
+        (local_port > 0 && inode > 0).then_some(SocketRow {
+            local_addr,
+            local_port,
+            inode,
+        })
+    })
+}
+
+/// Decode a procfs hex address: 8 digits for IPv4, 32 for IPv6. The kernel
+/// prints each 32-bit word of the network-order address as a native integer.
+fn parse_hex_addr(hex: &str) -> Option<IpAddr> {
+    let word = |i: usize| -> Option<[u8; 4]> {
+        let digits = hex.get(i * 8..i * 8 + 8)?;
+        Some(u32::from_str_radix(digits, 16).ok()?.to_ne_bytes())
+    };
+    match hex.len() {
+        8 => Some(IpAddr::from(word(0)?)),
+        32 => {
+            let mut octets = [0u8; 16];
+            for i in 0..4 {
+                octets[i * 4..i * 4 + 4].copy_from_slice(&word(i)?);
+            }
+            Some(IpAddr::from(octets))
+        }
+        _ => None,
+    }
+}
+
+/// Map socket inodes to owning PIDs by walking `/proc/<pid>/fd`, of the
+/// processes of `uid` only if given.
+///
+/// Processes we lack permission to inspect are skipped. The walk stops early
+/// once every requested inode has been resolved.
+fn resolve_socket_owners(
+    proc_root: &Path,
+    inodes: impl Iterator<Item = u64>,
+    uid: Option<u32>,
+) -> HashMap<u64, u32> {
+    let mut wanted: HashSet<u64> = inodes.collect();
+    let mut owners = HashMap::with_capacity(wanted.len());
+    if wanted.is_empty() {
+        return owners;
+    }
+
+    let Ok(entries) = std::fs::read_dir(proc_root) else {
+        return owners;
+    };
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid == 0 {
+            continue;
+        }
+        if uid.is_some_and(|uid| entry.metadata().map_or(true, |m| m.uid() != uid)) {
+            continue;
+        }
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            if let Some(inode) = parse_socket_link(&target.to_string_lossy()) {
+                if wanted.remove(&inode) {
+                    owners.insert(inode, pid);
+                }
+            }
+        }
+        if wanted.is_empty() {
+            break;
+        }
+    }
+    owners
+}
+
+/// Extract the inode from an fd link target of the form `socket:[12345]`.
+fn parse_socket_link(target: &str) -> Option<u64> {
+    target
+        .strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+    use std::path::PathBuf;
+
+    const TCP_HEADER: &str = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode";
+
+    /// A throwaway procfs tree under the system temp dir, removed on drop.
+    struct FixtureProc {
+        root: PathBuf,
+    }
+
+    impl FixtureProc {
+        fn new(name: &str) -> Self {
+            let root =
+                std::env::temp_dir().join(format!("netguard-proc-{}-{name}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(root.join("net")).unwrap();
+            Self { root }
+        }
+
+        fn table(&self, name: &str, rows: &[&str]) -> &Self {
+            let mut contents = String::from(TCP_HEADER);
+            for row in rows {
+                contents.push('\n');
+                contents.push_str(row);
+            }
+            std::fs::write(self.root.join("net").join(name), contents).unwrap();
+            self
+        }
+
+        fn socket_fd(&self, pid: u32, fd: u32, inode: u64) -> &Self {
+            let dir = self.root.join(pid.to_string()).join("fd");
+            std::fs::create_dir_all(&dir).unwrap();
+            std::os::unix::fs::symlink(format!("socket:[{inode}]"), dir.join(fd.to_string()))
+                .unwrap();
+            self
+        }
+    }
+
+    impl Drop for FixtureProc {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn row(local: &str, inode: u64) -> String {
+        format!(
+            "   0: {local} 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 {inode} 1 0000000000000000 100 0 0 10 0"
+        )
+    }
+
+    #[test]
+    fn test_parse_socket_table_extracts_port_and_inode() {
+        let contents = format!("{TCP_HEADER}\n{}", row("0100007F:0CEA", 20395));
+        let rows: Vec<_> = parse_socket_table(&contents).collect();
+        assert_eq!(
+            rows,
+            vec![SocketRow {
+                local_addr: "127.0.0.1".parse().unwrap(),
+                local_port: 0x0CEA,
+                inode: 20395
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_socket_table_skips_unowned_and_malformed_rows() {
+        let contents = format!(
+            "{TCP_HEADER}\n{}\n{}\ngarbage line\n",
+            row("0100007F:1F90", 0),
+            row("00000000:0000", 77)
+        );
+        assert_eq!(parse_socket_table(&contents).count(), 0);
+    }
+
+    #[test]
+    fn test_parse_socket_table_ipv6_address() {
+        let contents = format!(
+            "{TCP_HEADER}\n{}",
+            row("00000000000000000000000001000000:01BB", 4242)
+        );
+        let rows: Vec<_> = parse_socket_table(&contents).collect();
+        assert_eq!(rows[0].local_addr, "::1".parse::<IpAddr>().unwrap());
+        assert_eq!(rows[0].local_port, 443);
+        assert_eq!(rows[0].inode, 4242);
+    }
+
+    #[test]
+    fn test_parse_hex_addr() {
+        let v4 = parse_hex_addr("0101A8C0").unwrap();
+        assert_eq!(v4, "192.168.1.1".parse::<IpAddr>().unwrap());
+        // IPv4-mapped IPv6 (::ffff:10.0.0.2).
+        let mapped = parse_hex_addr("0000000000000000FFFF00000200000A").unwrap();
+        assert_eq!(mapped, "::ffff:10.0.0.2".parse::<IpAddr>().unwrap());
+        assert!(parse_hex_addr("0101A8").is_none());
+        assert!(parse_hex_addr("zzzzzzzz").is_none());
+    }
+
+    #[test]
+    fn test_parse_socket_link() {
+        assert_eq!(parse_socket_link("socket:[12345]"), Some(12345));
+        assert_eq!(parse_socket_link("pipe:[12345]"), None);
+        assert_eq!(parse_socket_link("/dev/null"), None);
+    }
+
+    #[test]
+    fn test_refresh_port_map_from_fixture_tree() {
+        let proc = FixtureProc::new("refresh");
+        proc.table(
+            "tcp",
+            &[&row("0100007F:1F90", 100), &row("00000000:0016", 200)],
+        )
+        .table("udp", &[&row("00000000:0035", 300)])
+        .table(
+            "tcp6",
+            &[&row("00000000000000000000000000000000:01BB", 400)],
+        )
+        // Ping socket with echo identifier 0x1234.
+        .table("icmp", &[&row("00000000:1234", 500)])
+        .socket_fd(10, 3, 100)
+        .socket_fd(10, 4, 400)
+        .socket_fd(20, 5, 300)
+        .socket_fd(30, 3, 500);
+
+        let port_map = DashMap::new();
+        refresh_port_map_from(&proc.root, &port_map, &DashMap::new());
+
+        let get = |proto, addr: [u8; 4], port| {
+            port_map
+                .get(&port_key(proto, IpAddr::from(addr), port))
+                .map(|r| *r)
+        };
+        let any4 = [0, 0, 0, 0];
+        assert_eq!(get(Protocol::Tcp, [127, 0, 0, 1], 8080), Some(10));
+        assert_eq!(
+            port_map
+                .get(&port_key(Protocol::Tcp, Ipv6Addr::UNSPECIFIED.into(), 443))
+                .map(|r| *r),
+            Some(10)
+        );
+        assert_eq!(get(Protocol::Udp, any4, 53), Some(20));
+        assert_eq!(get(Protocol::Icmp, any4, 0x1234), Some(30));
+        // Inode 200 has no owning fd (e.g. another user's process) → not mapped.
+        assert!(get(Protocol::Tcp, any4, 22).is_none());
+        assert_eq!(port_map.len(), 4);
+    }
+
+    #[test]
+    fn test_refresh_port_map_clears_closed_ports() {
+        let proc = FixtureProc::new("clears");
+        proc.table("tcp", &[]).socket_fd(10, 3, 100);
+
+        let port_map = DashMap::new();
+        port_map.insert((Protocol::Tcp, "0.0.0.0".parse().unwrap(), 9999), 10);
+        refresh_port_map_from(&proc.root, &port_map, &DashMap::new());
+        assert!(port_map.is_empty(), "stale entries should be removed");
+    }
+
+    #[test]
+    fn test_refresh_reuses_cached_owners() {
+        let proc = FixtureProc::new("incremental");
+        proc.table("tcp", &[&row("00000000:0050", 100)])
+            .socket_fd(10, 3, 100);
+        let port_map = DashMap::new();
+        let owners = DashMap::new();
+        refresh_port_map_from(&proc.root, &port_map, &owners);
+        assert_eq!(owners.get(&100).map(|r| *r), Some(10));
+
+        // The known socket keeps its cached owner without an fd walk (its fd
+        // link is gone); only the new socket is resolved.
+        std::fs::remove_dir_all(proc.root.join("10")).unwrap();
+        proc.table(
+            "tcp",
+            &[&row("00000000:0050", 100), &row("00000000:0051", 101)],
+        )
+        .socket_fd(20, 3, 101);
+        refresh_port_map_from(&proc.root, &port_map, &owners);
+        let any = IpAddr::from([0, 0, 0, 0]);
+        assert_eq!(
+            port_map.get(&(Protocol::Tcp, any, 80)).map(|r| *r),
+            Some(10)
+        );
+        assert_eq!(
+            port_map.get(&(Protocol::Tcp, any, 81)).map(|r| *r),
+            Some(20)
+        );
+
+        // Closed sockets leave both the port map and the owner cache.
+        proc.table("tcp", &[&row("00000000:0051", 101)]);
+        refresh_port_map_from(&proc.root, &port_map, &owners);
+        assert_eq!(port_map.len(), 1);
+        assert!(!owners.contains_key(&100));
+    }
+
+    #[test]
+    fn test_find_socket_owner_walks_only_the_owning_user() {
+        let proc = FixtureProc::new("by-uid");
+        proc.socket_fd(10, 3, 100).socket_fd(20, 4, 200);
+        // The fixture's process directories belong to the user running the test.
+        let uid = std::fs::metadata(&proc.root).unwrap().uid();
+
+        assert_eq!(find_socket_owner_in(&proc.root, 200, uid), Some(20));
+        assert_eq!(find_socket_owner_in(&proc.root, 300, uid), None);
+        assert_eq!(find_socket_owner_in(&proc.root, 200, uid + 1), None);
+    }
+}
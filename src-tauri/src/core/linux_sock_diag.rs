@@ -0,0 +1,311 @@
+//! Linux `NETLINK_SOCK_DIAG` single-socket lookups.
+//!
+//! When a packet's local endpoint is missing from the port map, the process
+//! mapper queues its 4-tuple, and its scanner thread asks the kernel for the
+//! one socket owning it instead of waiting for the next `/proc/net` scan. The
+//! reply carries the socket's bound address, owner uid and inode; the inode is
+//! resolved to a PID by the caller.
+//!
+//! Only TCP and UDP are supported (`inet_diag` has no exact lookup for ping sockets).
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::OwnedFd;
+
+use crate::capture::linux_sock::{self, netlink_messages, read_u32_ne, NLMSG_HDRLEN};
+use crate::core::process_mapper::Protocol;
+
+/// Constants from `<linux/sock_diag.h>` and `<linux/inet_diag.h>`.
+mod diag_sys {
+    pub const SOCK_DIAG_BY_FAMILY: u16 = 20;
+    /// `sizeof(struct inet_diag_req_v2)`.
+    pub const REQ_LEN: usize = 56;
+    /// `sizeof(struct inet_diag_msg)`.
+    pub const MSG_LEN: usize = 72;
+    /// Offset of `struct inet_diag_sockid` in the request.
+    pub const REQ_SOCKID_OFFSET: usize = 8;
+    /// Offset of `struct inet_diag_sockid` in the reply.
+    pub const MSG_SOCKID_OFFSET: usize = 4;
+    /// Offset of `idiag_uid` in `struct inet_diag_msg`.
+    pub const UID_OFFSET: usize = 64;
+    /// Offset of `idiag_inode` in `struct inet_diag_msg`.
+    pub const INODE_OFFSET: usize = 68;
+    pub const INET_DIAG_NOCOOKIE: u32 = !0;
+    pub const ALL_STATES: u32 = !0;
+}
+
+/// A socket returned by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiagSocket {
+    /// Address and port the socket is bound to (may be a wildcard address).
+    pub local: SocketAddr,
+    /// User the socket belongs to.
+    pub uid: u32,
+    pub inode: u64,
+}
+
+/// A sock_diag socket, kept open across queries.
+pub struct SockDiag {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl SockDiag {
+    pub fn open() -> io::Result<Self> {
+        let fd =
+            linux_sock::open_socket(libc::AF_NETLINK, libc::SOCK_DGRAM, libc::NETLINK_SOCK_DIAG)?;
+        linux_sock::set_recv_timeout(&fd, linux_sock::RECV_TIMEOUT)?;
+        Ok(Self { fd, seq: 0 })
+    }
+
+    /// Ask the kernel which socket owns the `local` ↔ `remote` flow.
+    /// Returns `Ok(None)` when no socket matches.
+    pub fn query(
+        &mut self,
+        proto: Protocol,
+        local: SocketAddr,
+        remote: SocketAddr,
+    ) -> io::Result<Option<DiagSocket>> {
+        self.seq = self.seq.wrapping_add(1);
+        let request = build_request(proto, local, remote, self.seq)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::Unsupported))?;
+        linux_sock::send(&self.fd, &request)?;
+
+        let mut buf = [0u8; 1024];
+        loop {
+            let len = linux_sock::recv(&self.fd, &mut buf, 0)?;
+            match parse_reply(&buf[..len], self.seq) {
+                Some(Ok(socket)) => return Ok(Some(socket)),
+                Some(Err(errno)) if errno == libc::ENOENT => return Ok(None),
+                Some(Err(errno)) => return Err(io::Error::from_raw_os_error(errno)),
+                // A late reply to an earlier query that timed out.
+                None => {}
+            }
+        }
+    }
+}
+
+/// Build a `SOCK_DIAG_BY_FAMILY` exact-lookup request. `None` for protocols
+/// or address families the kernel cannot look up this way.
+fn build_request(
+    proto: Protocol,
+    local: SocketAddr,
+    remote: SocketAddr,
+    seq: u32,
+) -> Option<Vec<u8>> {
+    let ip_proto = match proto {
+        Protocol::Tcp => libc::IPPROTO_TCP,
+        Protocol::Udp => libc::IPPROTO_UDP,
+        _ => return None,
+    };
+    let family = match (local.ip(), remote.ip()) {
+        (IpAddr::V4(_), IpAddr::V4(_)) => libc::AF_INET,
+        (IpAddr::V6(_), IpAddr::V6(_)) => libc::AF_INET6,
+        _ => return None,
+    };
+    // udp_diag swaps src and dst when looking up a single socket (it uses the
+    // receive-path lookup), so the remote end goes first for UDP.
+    let (src, dst) = match proto {
+        Protocol::Udp => (remote, local),
+        _ => (local, remote),
+    };
+
+    let mut buf = Vec::with_capacity(NLMSG_HDRLEN + diag_sys::REQ_LEN);
+    buf.extend_from_slice(&((NLMSG_HDRLEN + diag_sys::REQ_LEN) as u32).to_ne_bytes());
+    buf.extend_from_slice(&diag_sys::SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+    buf.extend_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes()); // nlmsg_pid: kernel
+
+    buf.push(family as u8);
+    buf.push(ip_proto as u8);
+    buf.push(0); // idiag_ext
+    buf.push(0); // pad
+    buf.extend_from_slice(&diag_sys::ALL_STATES.to_ne_bytes());
+    // struct inet_diag_sockid
+    debug_assert_eq!(buf.len(), NLMSG_HDRLEN + diag_sys::REQ_SOCKID_OFFSET);
+    buf.extend_from_slice(&src.port().to_be_bytes());
+    buf.extend_from_slice(&dst.port().to_be_bytes());
+    buf.extend_from_slice(&addr_words(src.ip()));
+    buf.extend_from_slice(&addr_words(dst.ip()));
+    buf.extend_from_slice(&0u32.to_ne_bytes()); // idiag_if: any
+    buf.extend_from_slice(&diag_sys::INET_DIAG_NOCOOKIE.to_ne_bytes());
+    buf.extend_from_slice(&diag_sys::INET_DIAG_NOCOOKIE.to_ne_bytes());
+    Some(buf)
+}
+
+/// An address as the `__be32[4]` field of `inet_diag_sockid`.
+fn addr_words(addr: IpAddr) -> [u8; 16] {
+    let mut words = [0u8; 16];
+    match addr {
+        IpAddr::V4(v4) => words[..4].copy_from_slice(&v4.octets()),
+        IpAddr::V6(v6) => words = v6.octets(),
+    }
+    words
+}
+
+/// Decode the reply to request `seq`: the matching socket, or the errno of
+/// an `NLMSG_ERROR` reply. `None` when the datagram holds neither.
+fn parse_reply(data: &[u8], seq: u32) -> Option<Result<DiagSocket, i32>> {
+    let (msg_type, _, body) = netlink_messages(data).find(|&(_, msg_seq, _)| msg_seq == seq)?;
+    if msg_type == linux_sock::NLMSG_ERROR {
+        return Some(Err(-(read_u32_ne(body, 0)? as i32)));
+    }
+    if msg_type != diag_sys::SOCK_DIAG_BY_FAMILY || body.len() < diag_sys::MSG_LEN {
+        return None;
+    }
+    let id = &body[diag_sys::MSG_SOCKID_OFFSET..];
+    let port = u16::from_be_bytes([id[0], id[1]]);
+    let ip = match body[0] as i32 {
+        libc::AF_INET => IpAddr::from(<[u8; 4]>::try_from(&id[4..8]).ok()?),
+        libc::AF_INET6 => IpAddr::from(<[u8; 16]>::try_from(&id[4..20]).ok()?),
+        _ => return None,
+    };
+    let uid = read_u32_ne(body, diag_sys::UID_OFFSET)?;
+    let inode = read_u32_ne(body, diag_sys::INODE_OFFSET)?;
+    Some(Ok(DiagSocket {
+        local: SocketAddr::new(ip, port),
+        uid,
+        inode: inode as u64,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream, UdpSocket};
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    /// Build an `inet_diag_msg` reply for `local` with the given uid and inode.
+    fn reply(seq: u32, local: SocketAddr, uid: u32, inode: u32) -> Vec<u8> {
+        let mut body = vec![0u8; diag_sys::MSG_LEN];
+        body[0] = match local {
+            SocketAddr::V4(_) => libc::AF_INET as u8,
+            SocketAddr::V6(_) => libc::AF_INET6 as u8,
+        };
+        let id = diag_sys::MSG_SOCKID_OFFSET;
+        body[id..id + 2].copy_from_slice(&local.port().to_be_bytes());
+        body[id + 4..id + 20].copy_from_slice(&addr_words(local.ip()));
+        body[diag_sys::UID_OFFSET..diag_sys::UID_OFFSET + 4].copy_from_slice(&uid.to_ne_bytes());
+        body[diag_sys::INODE_OFFSET..diag_sys::INODE_OFFSET + 4]
+            .copy_from_slice(&inode.to_ne_bytes());
+        message(diag_sys::SOCK_DIAG_BY_FAMILY, seq, &body)
+    }
+
+    fn message(msg_type: u16, seq: u32, body: &[u8]) -> Vec<u8> {
+        let mut data = ((NLMSG_HDRLEN + body.len()) as u32).to_ne_bytes().to_vec();
+        data.extend_from_slice(&msg_type.to_ne_bytes());
+        data.extend_from_slice(&0u16.to_ne_bytes());
+        data.extend_from_slice(&seq.to_ne_bytes());
+        data.extend_from_slice(&0u32.to_ne_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    #[test]
+    fn test_build_request_layout() {
+        let local = addr("10.0.0.2:5000");
+        let remote = addr("1.2.3.4:443");
+        let req = build_request(Protocol::Tcp, local, remote, 7).unwrap();
+        assert_eq!(req.len(), NLMSG_HDRLEN + diag_sys::REQ_LEN);
+        assert_eq!(read_u32_ne(&req, 0), Some(req.len() as u32));
+        assert_eq!(read_u32_ne(&req, 8), Some(7));
+        let body = &req[NLMSG_HDRLEN..];
+        assert_eq!(body[..2], [libc::AF_INET as u8, libc::IPPROTO_TCP as u8]);
+        let id = &body[diag_sys::REQ_SOCKID_OFFSET..];
+        assert_eq!(id[..4], [0x13, 0x88, 0x01, 0xBB]); // sport 5000, dport 443
+        assert_eq!(id[4..8], [10, 0, 0, 2]);
+        assert_eq!(id[20..24], [1, 2, 3, 4]);
+
+        // UDP puts the remote end in the source fields.
+        let req = build_request(Protocol::Udp, local, remote, 7).unwrap();
+        let id = &req[NLMSG_HDRLEN + diag_sys::REQ_SOCKID_OFFSET..];
+        assert_eq!(id[..4], [0x01, 0xBB, 0x13, 0x88]);
+        assert_eq!(id[4..8], [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_build_request_rejects_unsupported() {
+        let v4 = addr("10.0.0.2:1");
+        let v6 = addr("[::1]:1");
+        assert!(build_request(Protocol::Icmp, v4, v4, 1).is_none());
+        assert!(build_request(Protocol::Tcp, v4, v6, 1).is_none());
+        assert!(build_request(Protocol::Udp, v6, v6, 1).is_some());
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let local = addr("[::]:8080");
+        assert_eq!(
+            parse_reply(&reply(3, local, 1000, 99), 3),
+            Some(Ok(DiagSocket {
+                local,
+                uid: 1000,
+                inode: 99
+            }))
+        );
+        // A reply to another request is ignored.
+        assert_eq!(parse_reply(&reply(4, local, 1000, 99), 3), None);
+
+        let enoent = message(linux_sock::NLMSG_ERROR, 3, &(-libc::ENOENT).to_ne_bytes());
+        assert_eq!(parse_reply(&enoent, 3), Some(Err(libc::ENOENT)));
+        assert_eq!(parse_reply(&[0u8; 8], 3), None);
+    }
+
+    /// True when `inode` is one of this process's open sockets.
+    fn own_socket(inode: u64) -> bool {
+        std::fs::read_dir("/proc/self/fd")
+            .unwrap()
+            .flatten()
+            .any(|fd| {
+                std::fs::read_link(fd.path())
+                    .is_ok_and(|t| t.to_string_lossy() == format!("socket:[{inode}]"))
+            })
+    }
+
+    /// The user this test runs as.
+    fn own_uid() -> u32 {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata("/proc/self").unwrap().uid()
+    }
+
+    #[test]
+    fn test_query_finds_live_sockets() {
+        // One socket serves every query.
+        let mut diag = SockDiag::open().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (client_local, server) = (client.local_addr().unwrap(), client.peer_addr().unwrap());
+        let found = diag
+            .query(Protocol::Tcp, client_local, server)
+            .unwrap()
+            .expect("connected TCP socket");
+        assert_eq!(found.local, client_local);
+        assert_eq!(found.uid, own_uid());
+        assert!(own_socket(found.inode));
+
+        let udp = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let port = udp.local_addr().unwrap().port();
+        let found = diag
+            .query(
+                Protocol::Udp,
+                addr(&format!("127.0.0.1:{port}")),
+                addr("127.0.0.1:9"),
+            )
+            .unwrap()
+            .expect("wildcard-bound UDP socket");
+        assert_eq!(found.local, addr(&format!("0.0.0.0:{port}")));
+        assert!(own_socket(found.inode));
+
+        drop(udp);
+        let gone = diag.query(
+            Protocol::Udp,
+            addr(&format!("127.0.0.1:{port}")),
+            addr("127.0.0.1:9"),
+        );
+        assert!(matches!(gone, Ok(None)));
+    }
+}
@@ -1,17 +1,39 @@
 //! Core logic: traffic accounting, rate limiting, process mapping.
 //!
 //! - [`TrafficTracker`] — per-process byte counters with speed calculation
+//! - [`FlowTable`] — per-connection (5-tuple) counters and TCP state
 //! - [`RateLimiterManager`] / [`BandwidthLimit`] — token bucket rate limiting
+//! - [`allowlist`] — default-deny mode allowing only listed applications
+//! - [`app_rules`] — limits and blocks keyed by application identity
+//! - [`QuotaManager`] — daily/monthly data quotas per application
+//! - [`rule_match`] — exact, glob and regex matching of saved rules
+//! - [`schedule`] — time-of-day windows for bandwidth rules
 //! - [`ProcessMapper`] — PID ↔ port resolution and icon caching
-//! - [`icon_extractor`] — Win32 icon extraction and BMP encoding
-//! - [`win_net_table`] — iphlpapi FFI for TCP/UDP port tables
+//! - [`icon_extractor`] — Win32 icon extraction and BMP encoding (Windows only)
+//! - [`win_net_table`] — iphlpapi FFI for TCP/UDP port tables (Windows only)
+//! - [`linux_net_table`] — procfs reader for TCP/UDP port tables (Linux only)
+//! - [`linux_sock_diag`] — on-demand netlink socket lookups (Linux only)
 
+pub mod allowlist;
+pub mod app_rules;
+pub mod flow_table;
+#[cfg(target_os = "windows")]
 pub mod icon_extractor;
+#[cfg(target_os = "linux")]
+pub mod linux_net_table;
+#[cfg(target_os = "linux")]
+pub mod linux_sock_diag;
 pub mod process_mapper;
+pub mod quota;
 pub mod rate_limiter;
+pub mod rule_match;
+pub mod schedule;
 pub mod traffic;
+#[cfg(target_os = "windows")]
 pub mod win_net_table;
 
+pub use flow_table::FlowTable;
 pub use process_mapper::ProcessMapper;
+pub use quota::QuotaManager;
 pub use rate_limiter::{BandwidthLimit, RateLimiterManager};
 pub use traffic::{ProcessTrafficSnapshot, TrafficTracker};
@@ -1,25 +1,68 @@
 //! Maps network connections (ports) to process IDs.
 //!
-//! Windows: GetExtendedTcpTable/GetExtendedUdpTable from iphlpapi.
-//! Refreshes at configurable intervals via a dedicated OS thread.
+//! Windows: GetExtendedTcpTable/GetExtendedUdpTable from iphlpapi. Windows has
+//! no ping socket table, so ICMP echo there is only ever credited to the
+//! "Other protocols" bucket.
+//! Linux: `/proc/net/{tcp,udp,icmp}[6]` plus `/proc/<pid>/fd` socket inodes,
+//! with `NETLINK_SOCK_DIAG` queries, run by the scanner thread between scans,
+//! for TCP/UDP flows the last scan missed.
+//! Refreshes at configurable intervals via a dedicated OS thread; each scan
+//! updates the map in place instead of rebuilding it.
 //! Results stored in DashMap for lock-free lookup.
 
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::SystemTime;
+#[cfg(target_os = "linux")]
+use std::time::{Duration, Instant};
 
 use dashmap::DashMap;
+#[cfg(target_os = "linux")]
+use parking_lot::Mutex;
 use serde::Serialize;
+use ts_rs::TS;
 
 use crate::config;
+#[cfg(target_os = "windows")]
 use crate::core::icon_extractor;
+#[cfg(target_os = "linux")]
+use crate::core::linux_net_table;
+#[cfg(target_os = "linux")]
+use crate::core::linux_sock_diag::{DiagSocket, SockDiag};
+use crate::core::traffic::TrafficTracker;
+#[cfg(target_os = "windows")]
 use crate::core::win_net_table;
-use sysinfo::System;
+use sha2::{Digest, Sha256};
+use sysinfo::{ProcessRefreshKind, System, UpdateKind};
 
 /// Network protocol.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
 pub enum Protocol {
     Tcp,
     Udp,
+    /// ICMP over IPv4. Echo traffic is keyed by its identifier in place of a port.
+    Icmp,
+    /// ICMP over IPv6. Echo traffic is keyed by its identifier in place of a port.
+    Icmpv6,
+    /// Any other IP protocol (GRE, ESP, IGMP, ...).
+    Other,
+}
+
+/// Port map key: (protocol, local address, local port). An unspecified local
+/// address (`0.0.0.0` / `::`) is a wildcard binding.
+pub type PortKey = (Protocol, IpAddr, u16);
+
+/// Build a [`PortKey`], storing IPv4-mapped IPv6 addresses (dual-stack
+/// sockets talking IPv4) as plain IPv4 so they match IPv4 packets.
+pub fn port_key(proto: Protocol, local_addr: IpAddr, local_port: u16) -> PortKey {
+    let addr = match local_addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(local_addr, IpAddr::V4),
+        v4 => v4,
+    };
+    (proto, addr, local_port)
 }
 
 /// Lightweight process metadata.
@@ -27,16 +70,38 @@ pub enum Protocol {
 pub struct ProcessInfo {
     pub name: String,
     pub exe_path: String,
+    /// Command-line arguments after the program, joined by spaces.
+    pub args: String,
 }
 
-/// Thread-safe mapper from (protocol, local_port) to PID and PID to ProcessInfo.
+/// Thread-safe mapper from (protocol, local_addr, local_port) to PID and PID to ProcessInfo.
 pub struct ProcessMapper {
-    /// (Protocol, local_port) -> owning PID.
-    pub(crate) port_map: DashMap<(Protocol, u16), u32>,
+    /// (Protocol, local_addr, local_port) -> owning PID.
+    pub(crate) port_map: DashMap<PortKey, u32>,
     /// PID -> process metadata.
     pub(crate) process_info: DashMap<u32, ProcessInfo>,
     /// exe_path -> base64-encoded icon data URI, cached per executable (AC-1.6).
     icon_cache: DashMap<String, Option<String>>,
+    /// exe_path -> (modification time, SHA-256 hex digest), for app rules
+    /// pinned to an executable hash.
+    exe_hashes: DashMap<String, (SystemTime, Option<String>)>,
+    /// Socket inode -> owning PID, kept across scans so only new sockets
+    /// need a `/proc/<pid>/fd` walk.
+    #[cfg(target_os = "linux")]
+    socket_owners: DashMap<u64, u32>,
+    /// Flows missing from the port map that wait for a sock_diag query:
+    /// local endpoint -> (local, remote) socket addresses.
+    #[cfg(target_os = "linux")]
+    socket_lookups: DashMap<PortKey, (SocketAddr, SocketAddr)>,
+    /// Local endpoints last queued for a sock_diag query -> when.
+    #[cfg(target_os = "linux")]
+    sock_diag_queued: DashMap<PortKey, Instant>,
+    /// The scanner thread's sock_diag socket, opened on first use.
+    #[cfg(target_os = "linux")]
+    sock_diag: Mutex<Option<SockDiag>>,
+    /// Whether misses may be resolved against the live host's sockets.
+    #[cfg(target_os = "linux")]
+    live_lookups: bool,
 }
 
 impl ProcessMapper {
@@ -45,12 +110,125 @@ impl ProcessMapper {
             port_map: DashMap::new(),
             process_info: DashMap::new(),
             icon_cache: DashMap::new(),
+            exe_hashes: DashMap::new(),
+            #[cfg(target_os = "linux")]
+            socket_owners: DashMap::new(),
+            #[cfg(target_os = "linux")]
+            socket_lookups: DashMap::new(),
+            #[cfg(target_os = "linux")]
+            sock_diag_queued: DashMap::new(),
+            #[cfg(target_os = "linux")]
+            sock_diag: Mutex::new(None),
+            #[cfg(target_os = "linux")]
+            live_lookups: true,
+        }
+    }
+
+    /// A mapper that answers only from its own maps and never queries the
+    /// host's sockets. Used for offline replays, whose flows belong to the
+    /// capturing host rather than this one.
+    pub fn offline() -> Self {
+        Self {
+            #[cfg(target_os = "linux")]
+            live_lookups: false,
+            ..Self::new()
+        }
+    }
+
+    /// Look up the PID that owns the given (protocol, local_addr, local_port).
+    ///
+    /// An exact address binding wins over a wildcard one of the same family.
+    /// IPv4 traffic finally falls back to an IPv6 wildcard (dual-stack socket).
+    pub fn lookup_pid(&self, proto: Protocol, local_addr: IpAddr, local_port: u16) -> Option<u32> {
+        let get = |addr: IpAddr| self.port_map.get(&(proto, addr, local_port)).map(|r| *r);
+        let (_, addr, _) = port_key(proto, local_addr, local_port);
+        get(addr).or_else(|| match addr {
+            IpAddr::V4(_) => {
+                get(Ipv4Addr::UNSPECIFIED.into()).or_else(|| get(Ipv6Addr::UNSPECIFIED.into()))
+            }
+            IpAddr::V6(_) => get(Ipv6Addr::UNSPECIFIED.into()),
+        })
+    }
+
+    /// Look up the PID owning a flow's local endpoint. On a miss, Linux queues
+    /// the flow for a sock_diag query, which the scanner thread runs well
+    /// before its next scan (see [`Self::resolve_socket_lookups`]); meanwhile
+    /// the caller defers the bytes. Repeated misses for the same endpoint are
+    /// not re-queued within `SOCK_DIAG_RETRY_BACKOFF_MS`.
+    pub fn lookup_flow_pid(
+        &self,
+        proto: Protocol,
+        local: SocketAddr,
+        remote: SocketAddr,
+    ) -> Option<u32> {
+        let pid = self.lookup_pid(proto, local.ip(), local.port());
+        if pid.is_none() {
+            self.queue_socket_lookup(proto, local, remote);
+        }
+        pid
+    }
+
+    #[cfg(target_os = "linux")]
+    fn queue_socket_lookup(&self, proto: Protocol, local: SocketAddr, remote: SocketAddr) {
+        if !self.live_lookups || !matches!(proto, Protocol::Tcp | Protocol::Udp) {
+            return;
+        }
+        let key = port_key(proto, local.ip(), local.port());
+        let backoff = Duration::from_millis(config::SOCK_DIAG_RETRY_BACKOFF_MS);
+        if self
+            .sock_diag_queued
+            .get(&key)
+            .is_some_and(|at| at.elapsed() < backoff)
+            || self.socket_lookups.len() >= config::SOCK_DIAG_MAX_QUEUED
+        {
+            return;
+        }
+        self.sock_diag_queued.insert(key, Instant::now());
+        self.socket_lookups.insert(key, (local, remote));
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn queue_socket_lookup(&self, _proto: Protocol, _local: SocketAddr, _remote: SocketAddr) {}
+
+    /// Run the sock_diag queries queued by [`Self::lookup_flow_pid`] and map
+    /// the sockets found. An inode no scan has resolved yet is looked up among
+    /// the processes of the socket's user only. Returns each queued endpoint
+    /// resolved, with its PID.
+    #[cfg(target_os = "linux")]
+    pub fn resolve_socket_lookups(&self) -> Vec<(PortKey, u32)> {
+        let queued: Vec<PortKey> = self.socket_lookups.iter().map(|e| *e.key()).collect();
+        if queued.is_empty() {
+            return Vec::new();
         }
+        let mut diag = self.sock_diag.lock();
+        let mut resolved = Vec::new();
+        for key in queued {
+            let Some((_, (local, remote))) = self.socket_lookups.remove(&key) else {
+                continue;
+            };
+            let (proto, _, _) = key;
+            let Some(socket) = query_socket(&mut diag, proto, local, remote) else {
+                continue;
+            };
+            let owner = self
+                .socket_owners
+                .get(&socket.inode)
+                .map(|r| *r)
+                .or_else(|| linux_net_table::find_socket_owner(socket.inode, socket.uid));
+            let Some(pid) = owner else {
+                continue;
+            };
+            self.socket_owners.insert(socket.inode, pid);
+            let bound = port_key(proto, socket.local.ip(), socket.local.port());
+            self.port_map.insert(bound, pid);
+            resolved.push((key, pid));
+        }
+        resolved
     }
 
-    /// Look up the PID that owns the given (protocol, local_port).
-    pub fn lookup_pid(&self, proto: Protocol, local_port: u16) -> Option<u32> {
-        self.port_map.get(&(proto, local_port)).map(|r| *r)
+    #[cfg(not(target_os = "linux"))]
+    pub fn resolve_socket_lookups(&self) -> Vec<(PortKey, u32)> {
+        Vec::new()
     }
 
     /// Get process info for a PID.
@@ -58,6 +236,39 @@ impl ProcessMapper {
         self.process_info.get(&pid).map(|r| r.clone())
     }
 
+    /// PID and metadata of every known process.
+    pub fn processes(&self) -> Vec<(u32, ProcessInfo)> {
+        self.process_info
+            .iter()
+            .map(|r| (*r.key(), r.value().clone()))
+            .collect()
+    }
+
+    /// Lowercase hex SHA-256 digest of an executable, cached until the file
+    /// changes. `None` if it cannot be read.
+    pub fn exe_sha256(&self, exe_path: &str) -> Option<String> {
+        if exe_path.is_empty() {
+            return None;
+        }
+        let modified = std::fs::metadata(exe_path)
+            .and_then(|m| m.modified())
+            .ok()?;
+        if let Some(cached) = self.exe_hashes.get(exe_path) {
+            if cached.0 == modified {
+                return cached.1.clone();
+            }
+        }
+        let digest = std::fs::read(exe_path).ok().map(|bytes| {
+            Sha256::digest(&bytes)
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>()
+        });
+        self.exe_hashes
+            .insert(exe_path.to_string(), (modified, digest.clone()));
+        digest
+    }
+
     /// Count active connections per PID.
     pub fn connection_counts(&self) -> DashMap<u32, u32> {
         let counts = DashMap::new();
@@ -69,15 +280,22 @@ impl ProcessMapper {
     }
 
     /// Remove entries from `process_info` for PIDs that are no longer alive.
+    /// On Linux, cached socket owners that exited are dropped as well, so a
+    /// socket inherited by another process is re-resolved on the next scan.
     pub fn retain_live_pids(&self, live_pids: &std::collections::HashSet<u32>) {
         self.process_info.retain(|pid, _| live_pids.contains(pid));
+        #[cfg(target_os = "linux")]
+        self.socket_owners.retain(|_, pid| live_pids.contains(pid));
     }
 
     /// Spawn a background thread refreshing the maps at the configured interval.
     /// Returns the thread handle for graceful shutdown.
+    /// Between scans, the thread runs queued socket lookups and credits the
+    /// bytes `tracker` deferred for the flows they resolve.
     pub fn start_scanning(
         self: &Arc<Self>,
         rate_limiter: Arc<crate::core::rate_limiter::RateLimiterManager>,
+        tracker: Arc<TrafficTracker>,
         shutdown: Arc<AtomicBool>,
     ) -> std::thread::JoinHandle<()> {
         let mapper = Arc::clone(self);
@@ -89,8 +307,11 @@ impl ProcessMapper {
                 let step = std::time::Duration::from_millis(50);
                 let mut scan_counter: u64 = 0;
                 while !shutdown.load(Ordering::Relaxed) {
-                    win_net_table::refresh_port_map(&mapper.port_map);
+                    mapper.refresh_port_map();
                     mapper.refresh_process_info(&mut sys);
+                    let processes = mapper.processes();
+                    rate_limiter.resolve_apps(&processes, |exe| mapper.exe_sha256(exe));
+                    rate_limiter.resolve_allowlist(&processes);
 
                     scan_counter += 1;
                     if scan_counter % config::STALE_PID_CLEANUP_INTERVAL == 0 {
@@ -106,6 +327,9 @@ impl ProcessMapper {
                         if shutdown.load(Ordering::Relaxed) {
                             return;
                         }
+                        for (key, pid) in mapper.resolve_socket_lookups() {
+                            tracker.credit_pending(key, pid);
+                        }
                         std::thread::sleep(step);
                         elapsed += step;
                     }
@@ -122,13 +346,38 @@ impl ProcessMapper {
         if let Some(cached) = self.icon_cache.get(exe_path) {
             return cached.value().clone();
         }
+        #[cfg(target_os = "windows")]
         let icon = icon_extractor::extract_icon(exe_path);
+        #[cfg(not(target_os = "windows"))]
+        let icon = None;
         self.icon_cache.insert(exe_path.to_string(), icon.clone());
         icon
     }
 
+    /// Update `port_map` from the platform's socket tables.
+    /// Platforms without a table reader leave the map empty.
+    fn refresh_port_map(&self) {
+        #[cfg(target_os = "windows")]
+        win_net_table::refresh_port_map(&self.port_map);
+        #[cfg(target_os = "linux")]
+        {
+            linux_net_table::refresh_port_map(&self.port_map, &self.socket_owners);
+            let backoff = Duration::from_millis(config::SOCK_DIAG_RETRY_BACKOFF_MS);
+            self.sock_diag_queued.retain(|_, at| at.elapsed() < backoff);
+        }
+    }
+
     fn refresh_process_info(&self, sys: &mut System) {
-        sys.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
+        sys.refresh_processes_specifics(
+            sysinfo::ProcessesToUpdate::All,
+            true,
+            ProcessRefreshKind::new()
+                .with_memory()
+                .with_cpu()
+                .with_disk_usage()
+                .with_exe(UpdateKind::OnlyIfNotSet)
+                .with_cmd(UpdateKind::OnlyIfNotSet),
+        );
         for (pid, process) in sys.processes() {
             let pid_u32 = pid.as_u32();
             self.process_info
@@ -145,11 +394,59 @@ impl ProcessMapper {
                         .exe()
                         .map(|p| p.to_string_lossy().to_string())
                         .unwrap_or_default(),
+                    args: process
+                        .cmd()
+                        .iter()
+                        .skip(1)
+                        .map(|a| a.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(" "),
                 });
         }
     }
 }
 
+/// Query the socket owning a flow through `diag`, opening it first if needed.
+/// A socket that fails is dropped, to be reopened by the next query.
+#[cfg(target_os = "linux")]
+fn query_socket(
+    diag: &mut Option<SockDiag>,
+    proto: Protocol,
+    local: SocketAddr,
+    remote: SocketAddr,
+) -> Option<DiagSocket> {
+    let socket = match diag {
+        Some(socket) => socket,
+        None => match SockDiag::open() {
+            Ok(socket) => diag.insert(socket),
+            Err(e) => {
+                tracing::debug!("Cannot open a sock_diag socket: {e}");
+                return None;
+            }
+        },
+    };
+    match socket.query(proto, local, remote) {
+        Ok(found) => found,
+        Err(e) => {
+            tracing::debug!("sock_diag lookup for {proto:?} {local} failed: {e}");
+            *diag = None;
+            None
+        }
+    }
+}
+
+/// Bring `port_map` in line with a fresh scan: entries that vanished are
+/// removed and new or re-owned ones written, while unchanged entries are left
+/// alone so concurrent lookups never observe an empty map.
+pub(crate) fn sync_port_map(port_map: &DashMap<PortKey, u32>, fresh: HashMap<PortKey, u32>) {
+    port_map.retain(|key, _| fresh.contains_key(key));
+    for (key, pid) in fresh {
+        if port_map.get(&key).map(|r| *r) != Some(pid) {
+            port_map.insert(key, pid);
+        }
+    }
+}
+
 impl Default for ProcessMapper {
     fn default() -> Self {
         Self::new()
@@ -163,8 +460,52 @@ mod tests {
     #[test]
     fn test_new_mapper_empty() {
         let mapper = ProcessMapper::new();
-        assert_eq!(mapper.lookup_pid(Protocol::Tcp, 80), None);
-        assert_eq!(mapper.lookup_pid(Protocol::Udp, 53), None);
+        let any = Ipv4Addr::UNSPECIFIED.into();
+        assert_eq!(mapper.lookup_pid(Protocol::Tcp, any, 80), None);
+        assert_eq!(mapper.lookup_pid(Protocol::Udp, any, 53), None);
+    }
+
+    #[test]
+    fn test_lookup_prefers_exact_address_over_wildcard() {
+        let mapper = ProcessMapper::new();
+        let lan: IpAddr = "192.168.1.10".parse().unwrap();
+        let loopback: IpAddr = "127.0.0.1".parse().unwrap();
+        // Two processes share UDP 5353: one on the LAN address, one wildcard.
+        mapper.port_map.insert((Protocol::Udp, lan, 5353), 1);
+        mapper
+            .port_map
+            .insert((Protocol::Udp, Ipv4Addr::UNSPECIFIED.into(), 5353), 2);
+        // Same port number over IPv6 belongs to a third.
+        mapper
+            .port_map
+            .insert((Protocol::Udp, Ipv6Addr::UNSPECIFIED.into(), 5353), 3);
+
+        assert_eq!(mapper.lookup_pid(Protocol::Udp, lan, 5353), Some(1));
+        assert_eq!(mapper.lookup_pid(Protocol::Udp, loopback, 5353), Some(2));
+        assert_eq!(
+            mapper.lookup_pid(Protocol::Udp, "fe80::1".parse().unwrap(), 5353),
+            Some(3)
+        );
+        assert_eq!(mapper.lookup_pid(Protocol::Tcp, lan, 5353), None);
+    }
+
+    #[test]
+    fn test_lookup_dual_stack_fallbacks() {
+        let mapper = ProcessMapper::new();
+        let lan: IpAddr = "10.0.0.2".parse().unwrap();
+        // A `[::]:8080` listener also accepts IPv4 connections.
+        mapper
+            .port_map
+            .insert((Protocol::Tcp, Ipv6Addr::UNSPECIFIED.into(), 8080), 7);
+        assert_eq!(mapper.lookup_pid(Protocol::Tcp, lan, 8080), Some(7));
+
+        // A dual-stack socket's IPv4-mapped address is keyed as IPv4.
+        let mapped: IpAddr = "::ffff:10.0.0.2".parse().unwrap();
+        let key = port_key(Protocol::Tcp, mapped, 50000);
+        assert_eq!(key, (Protocol::Tcp, lan, 50000));
+        mapper.port_map.insert(key, 8);
+        assert_eq!(mapper.lookup_pid(Protocol::Tcp, lan, 50000), Some(8));
+        assert_eq!(mapper.lookup_pid(Protocol::Tcp, mapped, 50000), Some(8));
     }
 
     #[test]
@@ -195,6 +536,7 @@ mod tests {
             ProcessInfo {
                 name: "alive".into(),
                 exe_path: "/alive".into(),
+                args: String::new(),
             },
         );
         mapper.process_info.insert(
@@ -202,6 +544,7 @@ mod tests {
             ProcessInfo {
                 name: "dead".into(),
                 exe_path: "/dead".into(),
+                args: String::new(),
             },
         );
         mapper.process_info.insert(
@@ -209,6 +552,7 @@ mod tests {
             ProcessInfo {
                 name: "also_alive".into(),
                 exe_path: "/also_alive".into(),
+                args: String::new(),
             },
         );
 
@@ -233,9 +577,73 @@ mod tests {
             ProcessInfo {
                 name: "test".into(),
                 exe_path: "/test".into(),
+                args: String::new(),
             },
         );
         mapper.retain_live_pids(&std::collections::HashSet::new());
         assert!(mapper.get_process_info(1).is_none());
     }
+
+    #[test]
+    fn test_sync_port_map_updates_in_place() {
+        let port_map = DashMap::new();
+        let any: IpAddr = Ipv4Addr::UNSPECIFIED.into();
+        port_map.insert((Protocol::Tcp, any, 80), 1);
+        port_map.insert((Protocol::Tcp, any, 81), 2);
+        port_map.insert((Protocol::Udp, any, 53), 3);
+
+        let fresh = HashMap::from([
+            ((Protocol::Tcp, any, 80), 1),
+            ((Protocol::Udp, any, 53), 4),
+            ((Protocol::Udp, any, 5353), 5),
+        ]);
+        sync_port_map(&port_map, fresh.clone());
+
+        let synced: HashMap<_, _> = port_map.into_iter().collect();
+        assert_eq!(synced, fresh);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_lookup_flow_pid_queries_kernel_on_miss() {
+        let mapper = ProcessMapper::new();
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let local = socket.local_addr().unwrap();
+        let remote: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let key = port_key(Protocol::Udp, local.ip(), local.port());
+
+        // The miss is only queued on the packet path.
+        assert_eq!(mapper.lookup_flow_pid(Protocol::Udp, local, remote), None);
+        assert!(mapper.socket_lookups.contains_key(&key));
+        assert!(mapper.socket_owners.is_empty());
+
+        // The queued query attributes the flow although no scan has seen its
+        // inode, and the answer is cached for plain lookups.
+        let pid = std::process::id();
+        assert_eq!(mapper.resolve_socket_lookups(), vec![(key, pid)]);
+        assert!(mapper.socket_lookups.is_empty());
+        assert_eq!(
+            mapper.lookup_pid(Protocol::Udp, local.ip(), local.port()),
+            Some(pid)
+        );
+        assert_eq!(
+            mapper.lookup_flow_pid(Protocol::Udp, local, remote),
+            Some(pid)
+        );
+
+        // Offline mappers never consult the host's sockets.
+        let offline = ProcessMapper::offline();
+        assert_eq!(offline.lookup_flow_pid(Protocol::Udp, local, remote), None);
+        assert!(offline.socket_lookups.is_empty());
+
+        // A closed socket resolves to nothing, and the endpoint is not
+        // queued again within the backoff.
+        drop(socket);
+        mapper.port_map.clear();
+        mapper.sock_diag_queued.clear();
+        assert_eq!(mapper.lookup_flow_pid(Protocol::Udp, local, remote), None);
+        assert!(mapper.resolve_socket_lookups().is_empty());
+        assert_eq!(mapper.lookup_flow_pid(Protocol::Udp, local, remote), None);
+        assert!(mapper.socket_lookups.is_empty());
+    }
 }
@@ -0,0 +1,531 @@
+//! Daily and monthly data quotas per application.
+//!
+//! A quota caps the bytes (sent + received) an executable may use per
+//! calendar day or month. [`QuotaManager`] accumulates usage from tracker
+//! snapshots; the quota service persists it and passes in the local date, so
+//! usage survives restarts and resets when the day or month changes. While a
+//! quota is used up, its action is applied through [`RateLimiterManager`] to
+//! every running process of the executable.
+
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::rate_limiter::{BandwidthLimit, RateLimiterManager};
+use super::traffic::ProcessTrafficSnapshot;
+
+/// Calendar period a quota budget covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+impl QuotaPeriod {
+    /// Value stored in the `period` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QuotaPeriod::Daily => "daily",
+            QuotaPeriod::Monthly => "monthly",
+        }
+    }
+
+    /// Parse a `period` column value. Unknown values fall back to daily.
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "monthly" => QuotaPeriod::Monthly,
+            _ => QuotaPeriod::Daily,
+        }
+    }
+
+    /// Key of the period containing `date` (local `YYYY-MM-DD`): the date
+    /// itself for daily quotas, `YYYY-MM` for monthly ones.
+    pub fn key(self, date: &str) -> String {
+        match self {
+            QuotaPeriod::Daily => date.to_string(),
+            QuotaPeriod::Monthly => date.get(..7).unwrap_or(date).to_string(),
+        }
+    }
+}
+
+/// What happens to an application once its quota is used up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum QuotaAction {
+    /// Block all of its traffic until the period ends.
+    Block,
+    /// Limit both directions to the quota's `throttle_bps`.
+    Throttle,
+    /// Only emit a `quota-exhausted` event.
+    Notify,
+}
+
+impl QuotaAction {
+    /// Value stored in the `action` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QuotaAction::Block => "block",
+            QuotaAction::Throttle => "throttle",
+            QuotaAction::Notify => "notify",
+        }
+    }
+
+    /// Parse an `action` column value. Unknown values fall back to notify.
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "block" => QuotaAction::Block,
+            "throttle" => QuotaAction::Throttle,
+            _ => QuotaAction::Notify,
+        }
+    }
+}
+
+/// A data budget for one executable. At most one quota per executable and period.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct Quota {
+    pub exe_path: String,
+    pub process_name: String,
+    pub period: QuotaPeriod,
+    /// Bytes (sent + received) allowed per period.
+    #[ts(type = "number")]
+    pub limit_bytes: u64,
+    pub action: QuotaAction,
+    /// Rate in bytes per second for [`QuotaAction::Throttle`] (ignored otherwise).
+    #[serde(default)]
+    #[ts(type = "number")]
+    pub throttle_bps: u64,
+}
+
+/// A quota with its consumption in the current period.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct QuotaStatus {
+    pub quota: Quota,
+    #[ts(type = "number")]
+    pub used_bytes: u64,
+    /// Period the usage belongs to (`YYYY-MM-DD` or `YYYY-MM`; empty until
+    /// the first check).
+    pub period_key: String,
+}
+
+impl QuotaStatus {
+    pub fn is_exhausted(&self) -> bool {
+        self.used_bytes >= self.quota.limit_bytes
+    }
+}
+
+#[derive(Debug)]
+struct QuotaEntry {
+    status: QuotaStatus,
+    /// PIDs the quota's action was applied to, lifted when the period resets
+    /// or the quota changes. Processes that already had a block or limit of
+    /// their own are left alone and never listed here.
+    enforced: HashSet<u32>,
+}
+
+impl QuotaEntry {
+    fn new(status: QuotaStatus) -> Self {
+        Self {
+            status,
+            enforced: HashSet::new(),
+        }
+    }
+
+    /// The limit a throttling quota installs.
+    fn throttle_limit(&self) -> BandwidthLimit {
+        BandwidthLimit {
+            download_bps: self.status.quota.throttle_bps,
+            upload_bps: self.status.quota.throttle_bps,
+            ..Default::default()
+        }
+    }
+
+    fn enforce(&mut self, pids: &[u32], limiter: &RateLimiterManager) {
+        let quota = &self.status.quota;
+        for &pid in pids {
+            let applied = match quota.action {
+                QuotaAction::Block => limiter.block_if_absent(pid),
+                QuotaAction::Throttle => limiter.set_limit_if_absent(pid, self.throttle_limit()),
+                QuotaAction::Notify => continue,
+            };
+            if applied && self.enforced.insert(pid) {
+                tracing::info!(
+                    "Quota for {} exhausted: {} applied to PID {pid}",
+                    quota.process_name,
+                    quota.action.as_str()
+                );
+            }
+        }
+    }
+
+    /// Lift the action from the PIDs it was applied to, unless the user has
+    /// since replaced it with a rule of their own.
+    fn lift(&mut self, limiter: &RateLimiterManager) {
+        let limit = self.throttle_limit();
+        for pid in self.enforced.drain() {
+            match self.status.quota.action {
+                QuotaAction::Block => {
+                    limiter.unblock_if_permanent(pid);
+                }
+                QuotaAction::Throttle => {
+                    limiter.remove_limit_if_unchanged(pid, &limit);
+                }
+                QuotaAction::Notify => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct QuotaState {
+    entries: HashMap<(String, QuotaPeriod), QuotaEntry>,
+    /// Cumulative bytes of each PID at the previous tick.
+    last_totals: HashMap<u32, u64>,
+}
+
+/// In-memory quota table with usage accounting and enforcement.
+pub struct QuotaManager {
+    state: Mutex<QuotaState>,
+}
+
+impl QuotaManager {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(QuotaState::default()),
+        }
+    }
+
+    /// Replace all quotas with `statuses` loaded from the database.
+    pub fn load(&self, statuses: Vec<QuotaStatus>) {
+        let mut state = self.state.lock();
+        state.entries = statuses
+            .into_iter()
+            .map(|s| {
+                (
+                    (s.quota.exe_path.clone(), s.quota.period),
+                    QuotaEntry::new(s),
+                )
+            })
+            .collect();
+    }
+
+    /// Create or update a quota, keeping the usage of an existing one. Any
+    /// action already applied is lifted and reapplied on the next tick if
+    /// the quota is still used up.
+    pub fn set_quota(&self, quota: Quota, limiter: &RateLimiterManager) {
+        let mut state = self.state.lock();
+        let key = (quota.exe_path.clone(), quota.period);
+        match state.entries.get_mut(&key) {
+            Some(entry) => {
+                entry.lift(limiter);
+                entry.status.quota = quota;
+            }
+            None => {
+                state.entries.insert(
+                    key,
+                    QuotaEntry::new(QuotaStatus {
+                        quota,
+                        used_bytes: 0,
+                        period_key: String::new(),
+                    }),
+                );
+            }
+        }
+    }
+
+    /// Remove a quota and lift its action. Returns false if there was none.
+    pub fn remove_quota(
+        &self,
+        exe_path: &str,
+        period: QuotaPeriod,
+        limiter: &RateLimiterManager,
+    ) -> bool {
+        let mut state = self.state.lock();
+        match state.entries.remove(&(exe_path.to_string(), period)) {
+            Some(mut entry) => {
+                entry.lift(limiter);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// All quotas with their usage, ordered by executable and period.
+    pub fn statuses(&self) -> Vec<QuotaStatus> {
+        let state = self.state.lock();
+        let mut statuses: Vec<_> = state.entries.values().map(|e| e.status.clone()).collect();
+        statuses.sort_by(|a, b| {
+            (&a.quota.exe_path, a.quota.period).cmp(&(&b.quota.exe_path, b.quota.period))
+        });
+        statuses
+    }
+
+    /// Account the traffic since the previous tick, reset quotas whose period
+    /// has ended (`today` is the local `YYYY-MM-DD`) and apply the action of
+    /// every used-up quota to the executable's running processes.
+    /// Returns the quotas that ran out during this tick.
+    pub fn tick(
+        &self,
+        snapshot: &[ProcessTrafficSnapshot],
+        today: &str,
+        limiter: &RateLimiterManager,
+    ) -> Vec<QuotaStatus> {
+        let mut state = self.state.lock();
+        let QuotaState {
+            entries,
+            last_totals,
+        } = &mut *state;
+
+        let mut used: HashMap<&str, u64> = HashMap::new();
+        let mut pids: HashMap<&str, Vec<u32>> = HashMap::new();
+        for proc in snapshot {
+            let total = proc.bytes_sent + proc.bytes_recv;
+            let previous = last_totals.insert(proc.pid, total).unwrap_or(0);
+            if proc.unattributed.is_some() || proc.exe_path.is_empty() {
+                continue;
+            }
+            // A smaller total means the PID was reused by a new process.
+            let delta = if total >= previous {
+                total - previous
+            } else {
+                total
+            };
+            *used.entry(&proc.exe_path).or_default() += delta;
+            pids.entry(&proc.exe_path).or_default().push(proc.pid);
+        }
+        let live: HashSet<u32> = snapshot.iter().map(|p| p.pid).collect();
+        last_totals.retain(|pid, _| live.contains(pid));
+
+        let mut exhausted = Vec::new();
+        for entry in entries.values_mut() {
+            let period_key = entry.status.quota.period.key(today);
+            if entry.status.period_key != period_key {
+                if entry.status.is_exhausted() {
+                    tracing::info!(
+                        "Quota period for {} ended; usage reset",
+                        entry.status.quota.process_name
+                    );
+                }
+                entry.lift(limiter);
+                entry.status.period_key = period_key;
+                entry.status.used_bytes = 0;
+            }
+
+            let exe_path = entry.status.quota.exe_path.as_str();
+            let was_exhausted = entry.status.is_exhausted();
+            entry.status.used_bytes += used.get(exe_path).copied().unwrap_or(0);
+            if !entry.status.is_exhausted() {
+                continue;
+            }
+            if !was_exhausted {
+                exhausted.push(entry.status.clone());
+            }
+            if let Some(pids) = pids.get(exe_path) {
+                entry.enforce(pids, limiter);
+            }
+        }
+        exhausted
+    }
+}
+
+impl Default for QuotaManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn quota(exe_path: &str, period: QuotaPeriod, limit_bytes: u64, action: QuotaAction) -> Quota {
+        Quota {
+            exe_path: exe_path.to_string(),
+            process_name: exe_path.rsplit('/').next().unwrap().to_string(),
+            period,
+            limit_bytes,
+            action,
+            throttle_bps: 50_000,
+        }
+    }
+
+    fn proc(pid: u32, exe_path: &str, bytes_sent: u64, bytes_recv: u64) -> ProcessTrafficSnapshot {
+        ProcessTrafficSnapshot {
+            pid,
+            name: String::new(),
+            exe_path: exe_path.to_string(),
+            upload_speed: 0.0,
+            download_speed: 0.0,
+            bytes_sent,
+            bytes_recv,
+            connection_count: 0,
+            unattributed: None,
+        }
+    }
+
+    #[test]
+    fn test_period_keys() {
+        assert_eq!(QuotaPeriod::Daily.key("2026-03-04"), "2026-03-04");
+        assert_eq!(QuotaPeriod::Monthly.key("2026-03-04"), "2026-03");
+        for period in [QuotaPeriod::Daily, QuotaPeriod::Monthly] {
+            assert_eq!(QuotaPeriod::from_db(period.as_str()), period);
+        }
+        for action in [
+            QuotaAction::Block,
+            QuotaAction::Throttle,
+            QuotaAction::Notify,
+        ] {
+            assert_eq!(QuotaAction::from_db(action.as_str()), action);
+        }
+    }
+
+    #[test]
+    fn test_usage_accumulates_deltas_across_processes() {
+        let quotas = QuotaManager::new();
+        let limiter = RateLimiterManager::new();
+        quotas.set_quota(
+            quota("/bin/sync", QuotaPeriod::Daily, 10_000, QuotaAction::Notify),
+            &limiter,
+        );
+
+        let day = "2026-03-04";
+        quotas.tick(&[proc(1, "/bin/sync", 100, 200)], day, &limiter);
+        quotas.tick(
+            &[
+                proc(1, "/bin/sync", 400, 200),
+                proc(2, "/bin/sync", 0, 1_000),
+                proc(3, "/bin/other", 5_000, 5_000),
+            ],
+            day,
+            &limiter,
+        );
+        let status = &quotas.statuses()[0];
+        assert_eq!(status.used_bytes, 300 + 300 + 1_000);
+        assert_eq!(status.period_key, day);
+        assert!(!status.is_exhausted());
+    }
+
+    #[test]
+    fn test_exhausted_quota_blocks_until_period_resets() {
+        let quotas = QuotaManager::new();
+        let limiter = RateLimiterManager::new();
+        quotas.set_quota(
+            quota("/bin/game", QuotaPeriod::Monthly, 1_000, QuotaAction::Block),
+            &limiter,
+        );
+
+        let exhausted = quotas.tick(&[proc(7, "/bin/game", 600, 600)], "2026-03-04", &limiter);
+        assert_eq!(exhausted.len(), 1);
+        assert!(limiter.is_blocked(7));
+
+        // Reported once; a new process of the same app is blocked too.
+        let exhausted = quotas.tick(
+            &[proc(7, "/bin/game", 600, 600), proc(8, "/bin/game", 0, 10)],
+            "2026-03-31",
+            &limiter,
+        );
+        assert!(exhausted.is_empty());
+        assert!(limiter.is_blocked(8));
+
+        // A new month resets usage and lifts the block.
+        quotas.tick(&[proc(7, "/bin/game", 600, 600)], "2026-04-01", &limiter);
+        assert!(!limiter.is_blocked(7));
+        assert!(!limiter.is_blocked(8));
+        assert_eq!(quotas.statuses()[0].used_bytes, 0);
+        assert_eq!(quotas.statuses()[0].period_key, "2026-04");
+    }
+
+    #[test]
+    fn test_user_rules_survive_enforcement_and_reset() {
+        let quotas = QuotaManager::new();
+        let limiter = RateLimiterManager::new();
+        quotas.set_quota(
+            quota("/bin/game", QuotaPeriod::Daily, 100, QuotaAction::Block),
+            &limiter,
+        );
+        quotas.set_quota(
+            quota("/bin/drive", QuotaPeriod::Daily, 100, QuotaAction::Throttle),
+            &limiter,
+        );
+        let strict = BandwidthLimit {
+            download_bps: 1_000,
+            upload_bps: 1_000,
+            ..Default::default()
+        };
+        limiter.block_process(1);
+        limiter.set_limit_until(2, strict.clone(), Instant::now() + Duration::from_secs(600));
+
+        let day = "2026-03-04";
+        let snapshot = [
+            proc(1, "/bin/game", 200, 0),
+            proc(2, "/bin/drive", 200, 0),
+            proc(3, "/bin/drive", 10, 0),
+        ];
+        quotas.tick(&snapshot, day, &limiter);
+        quotas.tick(&snapshot, day, &limiter);
+        // The stricter temporary limit is neither replaced nor made permanent.
+        let limits = limiter.get_active_limits(Instant::now());
+        assert_eq!(limits[&2].limit.download_bps, 1_000);
+        assert!(limits[&2].expires_in_secs.is_some());
+        assert_eq!(limits[&3].limit.download_bps, 50_000);
+
+        // The next day lifts only what the quotas applied.
+        quotas.tick(&snapshot, "2026-03-05", &limiter);
+        assert!(limiter.is_blocked(1));
+        assert_eq!(limiter.get_all_limits()[&2], strict);
+        assert!(!limiter.is_limited(3));
+    }
+
+    #[test]
+    fn test_throttle_and_notify_actions() {
+        let quotas = QuotaManager::new();
+        let limiter = RateLimiterManager::new();
+        quotas.set_quota(
+            quota("/bin/drive", QuotaPeriod::Daily, 100, QuotaAction::Throttle),
+            &limiter,
+        );
+        quotas.set_quota(
+            quota("/bin/chat", QuotaPeriod::Daily, 100, QuotaAction::Notify),
+            &limiter,
+        );
+
+        let exhausted = quotas.tick(
+            &[proc(1, "/bin/drive", 200, 0), proc(2, "/bin/chat", 200, 0)],
+            "2026-03-04",
+            &limiter,
+        );
+        assert_eq!(exhausted.len(), 2);
+        let limits = limiter.get_all_limits();
+        assert_eq!(limits[&1].upload_bps, 50_000);
+        assert_eq!(limits[&1].download_bps, 50_000);
+        assert!(!limits.contains_key(&2), "notify does not limit");
+        assert!(!limiter.is_blocked(2));
+
+        // Removing the quota lifts the throttle.
+        assert!(quotas.remove_quota("/bin/drive", QuotaPeriod::Daily, &limiter));
+        assert!(!limiter.is_limited(1));
+        assert!(!quotas.remove_quota("/bin/drive", QuotaPeriod::Daily, &limiter));
+    }
+
+    #[test]
+    fn test_loaded_usage_survives_restart() {
+        let quotas = QuotaManager::new();
+        let limiter = RateLimiterManager::new();
+        quotas.load(vec![QuotaStatus {
+            quota: quota("/bin/sync", QuotaPeriod::Daily, 1_000, QuotaAction::Block),
+            used_bytes: 1_000,
+            period_key: "2026-03-04".into(),
+        }]);
+
+        // Already used up before the restart: enforced without a new report.
+        let exhausted = quotas.tick(&[proc(3, "/bin/sync", 10, 0)], "2026-03-04", &limiter);
+        assert!(exhausted.is_empty());
+        assert!(limiter.is_blocked(3));
+        assert_eq!(quotas.statuses()[0].used_bytes, 1_010);
+    }
+}
@@ -1,16 +1,123 @@
 //! Token Bucket rate limiter for per-process bandwidth control.
 //!
 //! Each rate-limited process gets independent upload and download buckets.
-//! Burst allowance is 2× the configured rate.
+//! Burst allowance defaults to two seconds of the configured rate and can be
+//! set per rule, either in bytes or as a duration; it never drops below one
+//! MTU-sized packet.
+//!
+//! Process limits can sit under a limit group and a global (host-wide) limit,
+//! hierarchical-token-bucket style: a child borrows unused capacity from its
+//! parents up to its own ceiling, and traffic within a guaranteed rate passes
+//! even when the levels above are exhausted.
+//!
+//! A limit either polices (over-budget packets are dropped) or shapes
+//! (over-budget packets are queued by the intercept loop's shaper and released
+//! as tokens refill), selected per rule by [`LimitMode`].
+//!
+//! Besides rules on a PID, [app rules](super::app_rules) limit or block every
+//! process of an executable; they are resolved to PIDs on each process scan.
+//! So is the [allowlist](super::allowlist), which in allowlist mode blocks
+//! every process it does not allow.
 
 use parking_lot::Mutex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-/// Bandwidth limit configuration for a single process.
-#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+use super::allowlist::AllowlistMatcher;
+use super::app_rules::{AppAction, AppRule, AppRuleStatus};
+use super::process_mapper::ProcessInfo;
+use crate::config::{
+    ALLOWLIST_FRAGMENT_TTL_SECS, DEFAULT_BURST_MS, MAX_ALLOWLIST_FRAGMENTS, MIN_BURST_BYTES,
+};
+
+/// How packets over a limit's budget are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum LimitMode {
+    /// Drop over-budget packets.
+    #[default]
+    Police,
+    /// Delay over-budget packets; once the queue is full, new packets are dropped.
+    ShapeDropTail,
+    /// Delay over-budget packets; CoDel drops from the head of the queue when
+    /// the queueing delay stays high, on top of the drop-tail bound.
+    ShapeCodel,
+}
+
+impl LimitMode {
+    /// True for the modes that delay packets instead of dropping them.
+    pub fn is_shaped(self) -> bool {
+        self != LimitMode::Police
+    }
+
+    /// Value stored in the `limit_mode` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LimitMode::Police => "police",
+            LimitMode::ShapeDropTail => "shape_drop_tail",
+            LimitMode::ShapeCodel => "shape_codel",
+        }
+    }
+
+    /// Parse a `limit_mode` column value. Unknown values fall back to policing.
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "shape_drop_tail" => LimitMode::ShapeDropTail,
+            "shape_codel" => LimitMode::ShapeCodel,
+            _ => LimitMode::Police,
+        }
+    }
+}
+
+/// Scheduling class of a shaped process. When shaped processes compete for
+/// a shared group or global limit, higher classes are served first.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, TS,
+)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum PriorityClass {
+    /// Interactive traffic such as calls and games.
+    High,
+    #[default]
+    Normal,
+    /// Bulk transfers such as updaters and backups.
+    Low,
+}
+
+impl PriorityClass {
+    /// Value stored in the `priority` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PriorityClass::High => "high",
+            PriorityClass::Normal => "normal",
+            PriorityClass::Low => "low",
+        }
+    }
+
+    /// Parse a `priority` column value. Unknown values fall back to normal.
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "high" => PriorityClass::High,
+            "low" => PriorityClass::Low,
+            _ => PriorityClass::Normal,
+        }
+    }
+}
+
+/// Weight of a rule that does not set one.
+pub const DEFAULT_WEIGHT: u32 = 1;
+
+fn default_weight() -> u32 {
+    DEFAULT_WEIGHT
+}
+
+/// Bandwidth limit configuration for a process, a limit group, or the whole host.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
 #[ts(export, export_to = "../../src/bindings.ts")]
 pub struct BandwidthLimit {
     /// Download limit in bytes per second (0 = unlimited).
@@ -19,6 +126,168 @@ pub struct BandwidthLimit {
     /// Upload limit in bytes per second (0 = unlimited).
     #[ts(type = "number")]
     pub upload_bps: u64,
+    /// Policing (drop) or shaping (delay) of over-budget packets.
+    /// Only process limits use it.
+    #[serde(default)]
+    pub mode: LimitMode,
+    /// Guaranteed download rate in bytes per second (0 = none). Traffic within
+    /// it passes even when a group or global limit above is exhausted.
+    #[serde(default)]
+    #[ts(type = "number")]
+    pub guaranteed_download_bps: u64,
+    /// Guaranteed upload rate in bytes per second (0 = none).
+    #[serde(default)]
+    #[ts(type = "number")]
+    pub guaranteed_upload_bps: u64,
+    /// Scheduling class among shaped processes. Only process limits use it.
+    #[serde(default)]
+    pub priority: PriorityClass,
+    /// Share of a contended limit relative to other shaped processes of the
+    /// same class (at least 1). Only process limits use it.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Bucket size in bytes for both directions (0 = derived from `burst_ms`).
+    #[serde(default)]
+    #[ts(type = "number")]
+    pub burst_bytes: u64,
+    /// Bucket size as a duration at each direction's rate, in milliseconds
+    /// (0 = the default of two seconds). Ignored when `burst_bytes` is set.
+    #[serde(default)]
+    #[ts(type = "number")]
+    pub burst_ms: u64,
+}
+
+impl Default for BandwidthLimit {
+    fn default() -> Self {
+        Self {
+            download_bps: 0,
+            upload_bps: 0,
+            mode: LimitMode::Police,
+            guaranteed_download_bps: 0,
+            guaranteed_upload_bps: 0,
+            priority: PriorityClass::Normal,
+            weight: DEFAULT_WEIGHT,
+            burst_bytes: 0,
+            burst_ms: 0,
+        }
+    }
+}
+
+impl BandwidthLimit {
+    /// True if the limit neither caps nor guarantees anything.
+    pub fn is_unlimited(&self) -> bool {
+        self.download_bps == 0
+            && self.upload_bps == 0
+            && self.guaranteed_download_bps == 0
+            && self.guaranteed_upload_bps == 0
+    }
+
+    /// Token bucket size for a direction of this limit running at `rate_bps`,
+    /// never less than [`MIN_BURST_BYTES`] so a full-size packet always fits.
+    pub fn burst_for(&self, rate_bps: u64) -> u64 {
+        let burst = if self.burst_bytes > 0 {
+            self.burst_bytes
+        } else {
+            let burst_ms = if self.burst_ms > 0 {
+                self.burst_ms
+            } else {
+                DEFAULT_BURST_MS
+            };
+            rate_bps.saturating_mul(burst_ms) / 1000
+        };
+        burst.max(MIN_BURST_BYTES)
+    }
+}
+
+/// A process limit with the time left before it lapses.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct ActiveLimit {
+    #[serde(flatten)]
+    pub limit: BandwidthLimit,
+    /// Seconds until a temporary limit lapses (`None` = permanent).
+    #[ts(type = "number | null")]
+    pub expires_in_secs: Option<u64>,
+}
+
+/// A blocked process with the time left before the block lapses.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct BlockedProcess {
+    pub pid: u32,
+    /// Seconds until a temporary block lapses (`None` = permanent).
+    #[ts(type = "number | null")]
+    pub expires_in_secs: Option<u64>,
+}
+
+/// Kind of process rule that can be made temporary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum RuleKind {
+    Limit,
+    Block,
+}
+
+/// A temporary rule that lapsed, as reported in the `rule-expired` event.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct ExpiredRule {
+    pub pid: u32,
+    pub kind: RuleKind,
+}
+
+/// Which kind of rule decides the traffic of a process.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum PolicySource {
+    /// No limit or block applies.
+    None,
+    /// A limit or block set on the PID, by the user, a profile or a quota.
+    Process,
+    /// The app rule of this executable.
+    App { exe_path: String },
+    /// Allowlist mode, which does not allow this executable.
+    Allowlist,
+}
+
+/// What the rate limiter currently enforces on one process.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct EffectivePolicy {
+    pub blocked: bool,
+    /// The process's limit (`None` when blocked or without one).
+    pub limit: Option<BandwidthLimit>,
+    pub source: PolicySource,
+    /// Seconds until a temporary block or limit lapses (`None` = permanent).
+    #[ts(type = "number | null")]
+    pub expires_in_secs: Option<u64>,
+    /// Limit group the process is in, if any.
+    pub group: Option<String>,
+}
+
+/// Whole seconds (rounded up) from `now` until `at`.
+fn secs_until(at: Instant, now: Instant) -> u64 {
+    let left = at.saturating_duration_since(now);
+    left.as_secs() + u64::from(left.subsec_nanos() > 0)
+}
+
+/// How the shaper treats the packets of one process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shaping {
+    pub mode: LimitMode,
+    pub priority: PriorityClass,
+    pub weight: u32,
+}
+
+/// Outcome of offering the head of a shaping queue to its token bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapedSend {
+    /// Tokens were consumed (or the process is no longer shaped): send now.
+    Send,
+    /// Not enough tokens yet; retry after the given delay.
+    Wait(Duration),
+    /// The process was blocked while the packet was queued.
+    Drop,
 }
 
 /// Per-direction token bucket state.
@@ -26,17 +295,18 @@ pub struct BandwidthLimit {
 struct TokenBucket {
     /// Configured rate in bytes/sec.
     rate_bps: u64,
-    /// Current token count.
+    /// Current token count. Goes negative when a parent is charged for
+    /// guaranteed traffic of a child, down to one burst of debt.
     tokens: f64,
-    /// Maximum burst (2x rate as per PRD).
+    /// Maximum burst in bytes.
     max_tokens: f64,
     /// Last refill timestamp.
     last_refill: std::time::Instant,
 }
 
 impl TokenBucket {
-    fn new(rate_bps: u64) -> Self {
-        let max_tokens = (rate_bps.saturating_mul(2)) as f64;
+    fn new(rate_bps: u64, burst_bytes: u64) -> Self {
+        let max_tokens = burst_bytes as f64;
         Self {
             rate_bps,
             tokens: max_tokens, // start full
@@ -45,119 +315,802 @@ impl TokenBucket {
         }
     }
 
-    /// Check if `bytes` can be consumed without exceeding the rate.
-    /// Returns true if the packet should pass, false if it should be dropped.
-    /// Tokens are consumed on pass; on drop, the deficit is NOT accumulated
-    /// (so future packets aren't penalized for drops).
-    fn should_pass(&mut self, bytes: u64) -> bool {
-        if self.rate_bps == 0 {
-            return true; // unlimited
-        }
-
-        let now = std::time::Instant::now();
-        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+    /// Add the tokens earned since the last refill, capped at the burst size.
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
         self.last_refill = now;
-
-        // Refill tokens
         self.tokens = (self.tokens + elapsed * self.rate_bps as f64).min(self.max_tokens);
+    }
 
-        if self.tokens >= bytes as f64 {
-            self.tokens -= bytes as f64;
-            true // within budget — pass
+    /// Check whether the bucket holds `bytes`, without consuming them.
+    /// Otherwise report how long until it will. With `allow_oversize`, a
+    /// packet larger than the whole burst fits once the bucket is full.
+    fn check(&mut self, bytes: u64, now: Instant, allow_oversize: bool) -> Result<(), Duration> {
+        if self.rate_bps == 0 {
+            return Ok(()); // unlimited
+        }
+        self.refill(now);
+        let needed = if allow_oversize {
+            (bytes as f64).min(self.max_tokens)
         } else {
-            false // over budget — drop (don't deduct, so no accumulated debt)
+            bytes as f64
+        };
+        if self.tokens >= needed {
+            return Ok(());
         }
+        Err(Duration::from_secs_f64(
+            (needed - self.tokens) / self.rate_bps as f64,
+        ))
     }
 
-    fn update_rate(&mut self, new_rate_bps: u64) {
+    /// Consume `bytes`, going into debt (at most one burst) if needed.
+    fn charge(&mut self, bytes: u64, now: Instant) {
+        if self.rate_bps == 0 {
+            return;
+        }
+        self.refill(now);
+        self.tokens = (self.tokens - bytes as f64).max(-self.max_tokens);
+    }
+
+    fn update_rate(&mut self, new_rate_bps: u64, burst_bytes: u64) {
         self.rate_bps = new_rate_bps;
-        self.max_tokens = (new_rate_bps.saturating_mul(2)) as f64;
+        self.max_tokens = burst_bytes as f64;
         self.tokens = self.tokens.min(self.max_tokens);
     }
 }
 
-/// Per-process limiter holding download and upload buckets.
+/// One direction of a node in the limiter tree (process, group or global).
+#[derive(Debug)]
+struct NodeBuckets {
+    /// Upper bound on the node's rate (rate 0 = unlimited).
+    ceil: TokenBucket,
+    /// Guaranteed rate, if any. While it has tokens, the node's traffic does
+    /// not need the approval of its ancestors.
+    assured: Option<TokenBucket>,
+}
+
+impl NodeBuckets {
+    fn new(ceil_bps: u64, assured_bps: u64, limit: &BandwidthLimit) -> Self {
+        Self {
+            ceil: TokenBucket::new(ceil_bps, limit.burst_for(ceil_bps)),
+            assured: (assured_bps > 0)
+                .then(|| TokenBucket::new(assured_bps, limit.burst_for(assured_bps))),
+        }
+    }
+
+    fn update(&mut self, ceil_bps: u64, assured_bps: u64, limit: &BandwidthLimit) {
+        self.ceil.update_rate(ceil_bps, limit.burst_for(ceil_bps));
+        let burst = limit.burst_for(assured_bps);
+        match (&mut self.assured, assured_bps) {
+            (_, 0) => self.assured = None,
+            (Some(assured), rate) => assured.update_rate(rate, burst),
+            (None, rate) => self.assured = Some(TokenBucket::new(rate, burst)),
+        }
+    }
+
+    /// True if this node caps the direction at all.
+    fn is_capped(&self) -> bool {
+        self.ceil.rate_bps > 0
+    }
+}
+
+/// Download and upload buckets of one node in the limiter tree.
 #[derive(Debug)]
-struct ProcessLimiter {
-    download: TokenBucket,
-    upload: TokenBucket,
+struct NodeLimiter {
+    download: NodeBuckets,
+    upload: NodeBuckets,
+    shaping: Shaping,
+}
+
+impl NodeLimiter {
+    fn new(limit: &BandwidthLimit) -> Self {
+        Self {
+            download: NodeBuckets::new(limit.download_bps, limit.guaranteed_download_bps, limit),
+            upload: NodeBuckets::new(limit.upload_bps, limit.guaranteed_upload_bps, limit),
+            shaping: Self::shaping_of(limit),
+        }
+    }
+
+    fn update(&mut self, limit: &BandwidthLimit) {
+        self.download
+            .update(limit.download_bps, limit.guaranteed_download_bps, limit);
+        self.upload
+            .update(limit.upload_bps, limit.guaranteed_upload_bps, limit);
+        self.shaping = Self::shaping_of(limit);
+    }
+
+    fn shaping_of(limit: &BandwidthLimit) -> Shaping {
+        Shaping {
+            mode: limit.mode,
+            priority: limit.priority,
+            weight: limit.weight.max(1),
+        }
+    }
+
+    fn bucket(&mut self, is_upload: bool) -> &mut NodeBuckets {
+        if is_upload {
+            &mut self.upload
+        } else {
+            &mut self.download
+        }
+    }
+}
+
+/// Offer `bytes` to a chain of nodes, leaf first (process → group → global).
+///
+/// Every node up to the first one with guaranteed tokens left must be under
+/// its ceiling; nodes above that one are not consulted, so guaranteed traffic
+/// passes even when its ancestors are exhausted. A node without a guarantee
+/// borrows its parent's spare capacity, up to its own ceiling. If the packet
+/// passes, every node is charged (ancestors may go into debt), so guaranteed
+/// traffic still counts against the group and global limits.
+fn admit_chain(
+    chain: &mut [Option<&mut NodeBuckets>; 3],
+    bytes: u64,
+    now: Instant,
+    allow_oversize: bool,
+) -> Result<(), Duration> {
+    for node in chain.iter_mut().flatten() {
+        node.ceil.check(bytes, now, allow_oversize)?;
+        if let Some(assured) = &mut node.assured {
+            if assured.check(bytes, now, false).is_ok() {
+                break;
+            }
+        }
+    }
+    for node in chain.iter_mut().flatten() {
+        node.ceil.charge(bytes, now);
+        if let Some(assured) = &mut node.assured {
+            assured.charge(bytes, now);
+        }
+    }
+    Ok(())
+}
+
+/// A group or global limit with its live buckets.
+#[derive(Debug)]
+struct ConfiguredNode {
+    limit: BandwidthLimit,
+    limiter: NodeLimiter,
+}
+
+impl ConfiguredNode {
+    fn new(limit: BandwidthLimit) -> Self {
+        Self {
+            limiter: NodeLimiter::new(&limit),
+            limit,
+        }
+    }
+
+    fn update(&mut self, limit: BandwidthLimit) {
+        self.limiter.update(&limit);
+        self.limit = limit;
+    }
+}
+
+/// Limits above the per-process ones: an optional host-wide cap and named
+/// groups that processes can be assigned to.
+#[derive(Debug, Default)]
+struct Hierarchy {
+    global: Option<ConfiguredNode>,
+    groups: HashMap<String, ConfiguredNode>,
+    /// PID → name of its limit group.
+    members: HashMap<u32, String>,
+}
+
+/// App rules and the PIDs they currently resolve to.
+#[derive(Debug, Default)]
+struct AppRules {
+    /// Rules by executable path.
+    rules: HashMap<String, AppRule>,
+    /// PID → the app rule it matches.
+    resolved: HashMap<u32, ResolvedApp>,
+}
+
+/// An app rule applied to one process.
+#[derive(Debug)]
+struct ResolvedApp {
+    exe_path: String,
+    /// The process's buckets under a limit rule; `None` under a block rule.
+    limiter: Option<NodeLimiter>,
+}
+
+impl ResolvedApp {
+    fn new(rule: &AppRule) -> Self {
+        let limiter = match &rule.action {
+            AppAction::Limit(limit) => Some(NodeLimiter::new(limit)),
+            AppAction::Block => None,
+        };
+        Self {
+            exe_path: rule.app.exe_path.clone(),
+            limiter,
+        }
+    }
+
+    fn update(&mut self, rule: &AppRule) {
+        match (&rule.action, &mut self.limiter) {
+            (AppAction::Limit(limit), Some(limiter)) => limiter.update(limit),
+            _ => *self = Self::new(rule),
+        }
+    }
+}
+
+/// A fragmented datagram: (source address, destination address, IP
+/// protocol, identification).
+pub type FragmentKey = (IpAddr, IpAddr, u8, u32);
+
+/// Allowlist mode: the compiled list while the mode is on, and the PIDs it
+/// allowed at the last process scan.
+#[derive(Debug, Default)]
+struct AllowlistState {
+    matcher: Option<Arc<AllowlistMatcher>>,
+    allowed: HashSet<u32>,
+    /// Datagrams whose first fragment passed -> when it did.
+    fragments: HashMap<FragmentKey, Instant>,
+}
+
+impl AllowlistState {
+    fn resolve(matcher: AllowlistMatcher, processes: &[(u32, ProcessInfo)]) -> Self {
+        Self {
+            allowed: allowed_pids(&matcher, processes),
+            matcher: Some(Arc::new(matcher)),
+            fragments: HashMap::new(),
+        }
+    }
+
+    fn denies(&self, pid: u32) -> bool {
+        self.matcher.is_some() && !self.allowed.contains(&pid)
+    }
+}
+
+fn allowed_pids(matcher: &AllowlistMatcher, processes: &[(u32, ProcessInfo)]) -> HashSet<u32> {
+    processes
+        .iter()
+        .filter(|(_, info)| matcher.allows(info))
+        .map(|(pid, _)| *pid)
+        .collect()
 }
 
 /// Manages rate limits and blocking for all processes.
+///
+/// Limits form a tree: the global limit (root) → an optional limit group →
+/// the process. A process passes a packet only if every level allows it,
+/// except that traffic within a level's guaranteed rate skips the levels
+/// above (see [`admit_chain`]).
+///
+/// Process limits and blocks are permanent unless set with an expiry; the
+/// rule-expiry service calls [`RateLimiterManager::expire`] to lift them.
+///
+/// A block, on the PID or its app, drops all traffic of a process; otherwise
+/// a limit set on the PID takes precedence over its app's limit. In allowlist
+/// mode, processes the list does not allow are blocked as well.
 pub struct RateLimiterManager {
-    limiters: Mutex<HashMap<u32, ProcessLimiter>>,
+    /// Lapse times of temporary limits and blocks. Locked before the other maps.
+    expiries: Mutex<HashMap<(u32, RuleKind), Instant>>,
+    limiters: Mutex<HashMap<u32, NodeLimiter>>,
     limits_config: Mutex<HashMap<u32, BandwidthLimit>>,
     /// Set of PIDs whose traffic should be silently dropped.
     blocked_pids: Mutex<std::collections::HashSet<u32>>,
+    /// App rules and their resolved PIDs. Locked after `limiters`.
+    apps: Mutex<AppRules>,
+    /// Global and group limits. Locked after `limiters` and `apps`.
+    hierarchy: Mutex<Hierarchy>,
+    /// Allowlist mode. Locked after every other map.
+    allowlist: Mutex<AllowlistState>,
 }
 
 impl RateLimiterManager {
     pub fn new() -> Self {
         Self {
+            expiries: Mutex::new(HashMap::new()),
             limiters: Mutex::new(HashMap::new()),
             limits_config: Mutex::new(HashMap::new()),
             blocked_pids: Mutex::new(std::collections::HashSet::new()),
+            apps: Mutex::new(AppRules::default()),
+            hierarchy: Mutex::new(Hierarchy::default()),
+            allowlist: Mutex::new(AllowlistState::default()),
         }
     }
 
-    /// Set a bandwidth limit for a process.
+    /// Set a permanent bandwidth limit for a process, replacing any
+    /// temporary one.
     pub fn set_limit(&self, pid: u32, limit: BandwidthLimit) {
+        let mut expiries = self.expiries.lock();
+        expiries.remove(&(pid, RuleKind::Limit));
+        self.install_limit(pid, limit);
+    }
+
+    /// Set a bandwidth limit for a process that lapses at `expires_at`.
+    pub fn set_limit_until(&self, pid: u32, limit: BandwidthLimit, expires_at: Instant) {
+        let mut expiries = self.expiries.lock();
+        expiries.insert((pid, RuleKind::Limit), expires_at);
+        self.install_limit(pid, limit);
+    }
+
+    fn install_limit(&self, pid: u32, limit: BandwidthLimit) {
         let mut limiters = self.limiters.lock();
-        let entry = limiters.entry(pid).or_insert_with(|| ProcessLimiter {
-            download: TokenBucket::new(limit.download_bps),
-            upload: TokenBucket::new(limit.upload_bps),
-        });
-        entry.download.update_rate(limit.download_bps);
-        entry.upload.update_rate(limit.upload_bps);
+        limiters
+            .entry(pid)
+            .and_modify(|l| l.update(&limit))
+            .or_insert_with(|| NodeLimiter::new(&limit));
         self.limits_config.lock().insert(pid, limit);
     }
 
     /// Remove the bandwidth limit for a process.
     pub fn remove_limit(&self, pid: u32) {
+        let mut expiries = self.expiries.lock();
+        expiries.remove(&(pid, RuleKind::Limit));
+        self.uninstall_limit(pid);
+    }
+
+    fn uninstall_limit(&self, pid: u32) {
         self.limiters.lock().remove(&pid);
         self.limits_config.lock().remove(&pid);
     }
 
-    /// Get all current limit configurations.
-    pub fn get_all_limits(&self) -> HashMap<u32, BandwidthLimit> {
-        self.limits_config.lock().clone()
+    /// Get all current limits with the time left on temporary ones.
+    pub fn get_active_limits(&self, now: Instant) -> HashMap<u32, ActiveLimit> {
+        let expiries = self.expiries.lock();
+        self.limits_config
+            .lock()
+            .iter()
+            .map(|(&pid, limit)| {
+                let active = ActiveLimit {
+                    limit: limit.clone(),
+                    expires_in_secs: expiries
+                        .get(&(pid, RuleKind::Limit))
+                        .map(|&at| secs_until(at, now)),
+                };
+                (pid, active)
+            })
+            .collect()
+    }
+
+    /// Set the host-wide limit at the root of the tree. Its guaranteed rates
+    /// have no effect, as there is nothing above it.
+    pub fn set_global_limit(&self, limit: BandwidthLimit) {
+        let mut hierarchy = self.hierarchy.lock();
+        match &mut hierarchy.global {
+            Some(global) => global.update(limit),
+            None => hierarchy.global = Some(ConfiguredNode::new(limit)),
+        }
+    }
+
+    /// Remove the host-wide limit.
+    pub fn remove_global_limit(&self) {
+        self.hierarchy.lock().global = None;
+    }
+
+    /// The host-wide limit, if set.
+    pub fn get_global_limit(&self) -> Option<BandwidthLimit> {
+        self.hierarchy
+            .lock()
+            .global
+            .as_ref()
+            .map(|g| g.limit.clone())
+    }
+
+    /// Create or update a named limit group.
+    pub fn set_group_limit(&self, name: &str, limit: BandwidthLimit) {
+        let mut hierarchy = self.hierarchy.lock();
+        match hierarchy.groups.get_mut(name) {
+            Some(group) => group.update(limit),
+            None => {
+                hierarchy
+                    .groups
+                    .insert(name.to_string(), ConfiguredNode::new(limit));
+            }
+        }
+    }
+
+    /// Remove a limit group. Its members fall back to the global limit.
+    pub fn remove_group(&self, name: &str) {
+        let mut hierarchy = self.hierarchy.lock();
+        hierarchy.groups.remove(name);
+        hierarchy.members.retain(|_, group| group != name);
+    }
+
+    /// All limit groups by name.
+    pub fn get_group_limits(&self) -> HashMap<String, BandwidthLimit> {
+        self.hierarchy
+            .lock()
+            .groups
+            .iter()
+            .map(|(name, group)| (name.clone(), group.limit.clone()))
+            .collect()
+    }
+
+    /// Put a process in a limit group, or take it out with `None`.
+    /// Returns `false` if the group does not exist.
+    pub fn assign_group(&self, pid: u32, group: Option<&str>) -> bool {
+        let mut hierarchy = self.hierarchy.lock();
+        match group {
+            Some(name) if !hierarchy.groups.contains_key(name) => false,
+            Some(name) => {
+                hierarchy.members.insert(pid, name.to_string());
+                true
+            }
+            None => {
+                hierarchy.members.remove(&pid);
+                true
+            }
+        }
+    }
+
+    /// PID → limit group of every grouped process.
+    pub fn get_group_members(&self) -> HashMap<u32, String> {
+        self.hierarchy.lock().members.clone()
+    }
+
+    /// Create or replace the rule of an application, keyed by its executable
+    /// path. Processes it already applies to pick up the change at once.
+    pub fn set_app_rule(&self, rule: AppRule) {
+        let mut apps = self.apps.lock();
+        for resolved in apps.resolved.values_mut() {
+            if resolved.exe_path == rule.app.exe_path {
+                resolved.update(&rule);
+            }
+        }
+        apps.rules.insert(rule.app.exe_path.clone(), rule);
+    }
+
+    /// Remove the rule of an application. Returns `false` if it had none.
+    pub fn remove_app_rule(&self, exe_path: &str) -> bool {
+        let mut apps = self.apps.lock();
+        apps.resolved
+            .retain(|_, resolved| resolved.exe_path != exe_path);
+        apps.rules.remove(exe_path).is_some()
+    }
+
+    /// All app rules, by executable path, with the PIDs they apply to.
+    pub fn get_app_rules(&self) -> Vec<AppRuleStatus> {
+        let apps = self.apps.lock();
+        let mut statuses: Vec<AppRuleStatus> = apps
+            .rules
+            .values()
+            .map(|rule| {
+                let mut pids: Vec<u32> = apps
+                    .resolved
+                    .iter()
+                    .filter(|(_, r)| r.exe_path == rule.app.exe_path)
+                    .map(|(&pid, _)| pid)
+                    .collect();
+                pids.sort_unstable();
+                AppRuleStatus {
+                    rule: rule.clone(),
+                    pids,
+                }
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.rule.app.exe_path.cmp(&b.rule.app.exe_path));
+        statuses
+    }
+
+    /// Resolve app rules against the running `processes`: matching PIDs come
+    /// under their app's rule, PIDs that no longer match are released.
+    /// `exe_hash` computes executable digests for rules pinned to one; it runs
+    /// without holding any lock.
+    pub fn resolve_apps(
+        &self,
+        processes: &[(u32, ProcessInfo)],
+        exe_hash: impl Fn(&str) -> Option<String>,
+    ) {
+        let rules: Vec<AppRule> = self.apps.lock().rules.values().cloned().collect();
+        if rules.is_empty() {
+            return;
+        }
+        let matched: Vec<(u32, &str)> = processes
+            .iter()
+            .filter_map(|(pid, info)| {
+                let rule = rules.iter().find(|r| r.app.exe_path == info.exe_path)?;
+                rule.app
+                    .matches(info, &exe_hash)
+                    .then_some((*pid, rule.app.exe_path.as_str()))
+            })
+            .collect();
+
+        let mut apps = self.apps.lock();
+        let AppRules { rules, resolved } = &mut *apps;
+        let mut fresh = HashMap::with_capacity(matched.len());
+        for (pid, exe_path) in matched {
+            // The rule may have been removed while hashing.
+            let Some(rule) = rules.get(exe_path) else {
+                continue;
+            };
+            let entry = match resolved.remove(&pid) {
+                Some(previous) if previous.exe_path == exe_path => previous,
+                _ => ResolvedApp::new(rule),
+            };
+            fresh.insert(pid, entry);
+        }
+        *resolved = fresh;
+    }
+
+    /// Turn allowlist mode on with `list`, or off with `None`. The list and
+    /// the PIDs it allows among `processes` take effect together, so no
+    /// packet is judged by a new list with a stale resolution.
+    pub fn set_allowlist(&self, list: Option<AllowlistMatcher>, processes: &[(u32, ProcessInfo)]) {
+        let state = match list {
+            Some(matcher) => AllowlistState::resolve(matcher, processes),
+            None => AllowlistState::default(),
+        };
+        *self.allowlist.lock() = state;
+    }
+
+    /// True while allowlist mode is on. Traffic no process is found for is
+    /// then dropped too (see also [`admits_portless`](Self::admits_portless)).
+    pub fn allowlist_enabled(&self) -> bool {
+        self.allowlist.lock().matcher.is_some()
+    }
+
+    /// Resolve the allowlist against the running `processes`, so processes
+    /// started since the last scan are let through if the list allows them.
+    pub fn resolve_allowlist(&self, processes: &[(u32, ProcessInfo)]) {
+        let Some(matcher) = self.allowlist.lock().matcher.clone() else {
+            return;
+        };
+        let allowed = allowed_pids(&matcher, processes);
+        let mut state = self.allowlist.lock();
+        // The mode may have been switched meanwhile.
+        if state
+            .matcher
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, &matcher))
+        {
+            state.allowed = allowed;
+        }
+    }
+
+    /// Remember that the first fragment of datagram `key` passed at `now`,
+    /// so that its later fragments, which carry no ports, may follow it. Does
+    /// nothing outside allowlist mode.
+    pub fn admit_fragments(&self, key: FragmentKey, now: Instant) {
+        let mut state = self.allowlist.lock();
+        if state.matcher.is_none() {
+            return;
+        }
+        if state.fragments.len() >= MAX_ALLOWLIST_FRAGMENTS {
+            let ttl = Duration::from_secs(ALLOWLIST_FRAGMENT_TTL_SECS);
+            state
+                .fragments
+                .retain(|_, since| now.saturating_duration_since(*since) < ttl);
+            if state.fragments.len() >= MAX_ALLOWLIST_FRAGMENTS {
+                return;
+            }
+        }
+        state.fragments.insert(key, now);
+    }
+
+    /// Whether a packet no port can be read from may pass at `now`: an
+    /// unparseable packet, a non-first fragment (`fragment` is its datagram)
+    /// or a packet of a protocol without ports. Always true outside allowlist
+    /// mode. In allowlist mode no process can vouch for such a packet, so only
+    /// the later fragments of a datagram whose first fragment passed within
+    /// `ALLOWLIST_FRAGMENT_TTL_SECS` get through.
+    pub fn admits_portless(&self, fragment: Option<FragmentKey>, now: Instant) -> bool {
+        let state = self.allowlist.lock();
+        if state.matcher.is_none() {
+            return true;
+        }
+        let ttl = Duration::from_secs(ALLOWLIST_FRAGMENT_TTL_SECS);
+        fragment
+            .and_then(|key| state.fragments.get(&key))
+            .is_some_and(|since| now.saturating_duration_since(*since) < ttl)
+    }
+
+    /// The block or limit in force on `pid` and the kind of rule it comes
+    /// from, following the precedence described on [`RateLimiterManager`].
+    pub fn effective_policy(&self, pid: u32, now: Instant) -> EffectivePolicy {
+        let expiries = self.expiries.lock();
+        let expires = |kind| expiries.get(&(pid, kind)).map(|&at| secs_until(at, now));
+        let process_limit = self.limits_config.lock().get(&pid).cloned();
+        let process_blocked = self.blocked_pids.lock().contains(&pid);
+        let app_rule = {
+            let apps = self.apps.lock();
+            apps.resolved
+                .get(&pid)
+                .and_then(|resolved| apps.rules.get(&resolved.exe_path))
+                .cloned()
+        };
+        let group = self.hierarchy.lock().members.get(&pid).cloned();
+        let allowlisted_out = self.allowlist.lock().denies(pid);
+
+        let app_source = |rule: &AppRule| PolicySource::App {
+            exe_path: rule.app.exe_path.clone(),
+        };
+        let (blocked, limit, source, expires_in_secs) = if process_blocked {
+            (true, None, PolicySource::Process, expires(RuleKind::Block))
+        } else if let Some(rule) = app_rule.as_ref().filter(|r| r.action == AppAction::Block) {
+            (true, None, app_source(rule), None)
+        } else if allowlisted_out {
+            (true, None, PolicySource::Allowlist, None)
+        } else if let Some(limit) = process_limit {
+            (
+                false,
+                Some(limit),
+                PolicySource::Process,
+                expires(RuleKind::Limit),
+            )
+        } else if let Some(rule) = &app_rule {
+            let limit = match &rule.action {
+                AppAction::Limit(limit) => Some(limit.clone()),
+                AppAction::Block => None,
+            };
+            (false, limit, app_source(rule), None)
+        } else {
+            (false, None, PolicySource::None, None)
+        };
+        EffectivePolicy {
+            blocked,
+            limit,
+            source,
+            expires_in_secs,
+            group,
+        }
+    }
+
+    /// True if all traffic of `pid` is dropped, by a process or an app block
+    /// or by allowlist mode.
+    fn is_denied(&self, pid: u32) -> bool {
+        if self.blocked_pids.lock().contains(&pid) {
+            return true;
+        }
+        let app_blocked = self
+            .apps
+            .lock()
+            .resolved
+            .get(&pid)
+            .is_some_and(|r| r.limiter.is_none());
+        app_blocked || self.allowlist.lock().denies(pid)
+    }
+
+    /// Run `f` on the chain of buckets limiting `pid` in one direction,
+    /// leaf first. Missing levels are `None`.
+    fn with_chain<R>(
+        &self,
+        pid: u32,
+        is_upload: bool,
+        f: impl FnOnce(&mut [Option<&mut NodeBuckets>; 3], Option<Shaping>) -> R,
+    ) -> R {
+        let mut limiters = self.limiters.lock();
+        let mut apps = self.apps.lock();
+        let mut hierarchy = self.hierarchy.lock();
+        let Hierarchy {
+            global,
+            groups,
+            members,
+        } = &mut *hierarchy;
+
+        let process = match limiters.get_mut(&pid) {
+            Some(limiter) => Some(limiter),
+            None => apps.resolved.get_mut(&pid).and_then(|r| r.limiter.as_mut()),
+        };
+        let shaping = process.as_ref().map(|l| l.shaping);
+        let group = members.get(&pid).and_then(|name| groups.get_mut(name));
+        let mut chain = [
+            process.map(|l| l.bucket(is_upload)),
+            group.map(|g| g.limiter.bucket(is_upload)),
+            global.as_mut().map(|g| g.limiter.bucket(is_upload)),
+        ];
+        f(&mut chain, shaping)
     }
 
     /// Decide whether a packet should pass or be dropped (policer mode).
     /// Returns true if within rate budget or no limit is set.
     /// Returns false if rate limit exceeded (packet should be dropped).
-    /// Blocked PIDs always return false.
+    /// Blocked PIDs, and in allowlist mode PIDs the list does not allow,
+    /// always return false.
     pub fn should_pass_packet(&self, pid: u32, bytes: u64, is_upload: bool) -> bool {
         // Check blocked first.
-        if self.blocked_pids.lock().contains(&pid) {
+        if self.is_denied(pid) {
             return false;
         }
 
-        let mut limiters = self.limiters.lock();
-        let Some(limiter) = limiters.get_mut(&pid) else {
-            return true; // no limit → pass
-        };
+        // Over budget → drop, without charging anyone (no accumulated debt).
+        self.with_chain(pid, is_upload, |chain, _| {
+            admit_chain(chain, bytes, Instant::now(), false).is_ok()
+        })
+    }
 
-        if is_upload {
-            limiter.upload.should_pass(bytes)
-        } else {
-            limiter.download.should_pass(bytes)
+    /// How to shape packets of `pid` in this direction, if they should go
+    /// through the shaper rather than [`Self::should_pass_packet`]: the
+    /// process has a shaping limit, some level of its tree caps this
+    /// direction, and it is not blocked.
+    pub fn shaping(&self, pid: u32, is_upload: bool) -> Option<Shaping> {
+        if self.is_denied(pid) {
+            return None;
+        }
+        self.with_chain(pid, is_upload, |chain, shaping| {
+            let shaping = shaping.filter(|s| s.mode.is_shaped())?;
+            let capped = chain.iter().flatten().any(|node| node.is_capped());
+            capped.then_some(shaping)
+        })
+    }
+
+    /// Offer a queued packet of `pid` to its buckets (shaper mode). Packets
+    /// of processes whose shaping limit was removed or switched to policing
+    /// are released immediately.
+    pub fn try_send_shaped(
+        &self,
+        pid: u32,
+        bytes: u64,
+        is_upload: bool,
+        now: Instant,
+    ) -> ShapedSend {
+        if self.is_denied(pid) {
+            return ShapedSend::Drop;
         }
+        self.with_chain(pid, is_upload, |chain, shaping| {
+            if !shaping.is_some_and(|s| s.mode.is_shaped()) {
+                return ShapedSend::Send;
+            }
+            match admit_chain(chain, bytes, now, true) {
+                Ok(()) => ShapedSend::Send,
+                Err(wait) => ShapedSend::Wait(wait),
+            }
+        })
     }
 
-    /// Block all network traffic for a process.
+    /// Block all network traffic for a process until unblocked, replacing
+    /// any temporary block.
     pub fn block_process(&self, pid: u32) {
+        let mut expiries = self.expiries.lock();
+        expiries.remove(&(pid, RuleKind::Block));
+        self.blocked_pids.lock().insert(pid);
+    }
+
+    /// Block all network traffic for a process until `expires_at`.
+    pub fn block_process_until(&self, pid: u32, expires_at: Instant) {
+        let mut expiries = self.expiries.lock();
+        expiries.insert((pid, RuleKind::Block), expires_at);
         self.blocked_pids.lock().insert(pid);
     }
 
     /// Unblock a process, restoring network access.
     pub fn unblock_process(&self, pid: u32) {
+        let mut expiries = self.expiries.lock();
+        expiries.remove(&(pid, RuleKind::Block));
         self.blocked_pids.lock().remove(&pid);
     }
 
-    /// Get all blocked PIDs.
-    pub fn get_blocked_pids(&self) -> Vec<u32> {
-        self.blocked_pids.lock().iter().copied().collect()
+    /// Get all blocked processes with the time left on temporary blocks.
+    pub fn get_blocked_processes(&self, now: Instant) -> Vec<BlockedProcess> {
+        let expiries = self.expiries.lock();
+        self.blocked_pids
+            .lock()
+            .iter()
+            .map(|&pid| BlockedProcess {
+                pid,
+                expires_in_secs: expiries
+                    .get(&(pid, RuleKind::Block))
+                    .map(|&at| secs_until(at, now)),
+            })
+            .collect()
+    }
+
+    /// Lift the temporary limits and blocks that have lapsed by `now`.
+    pub fn expire(&self, now: Instant) -> Vec<ExpiredRule> {
+        let mut expiries = self.expiries.lock();
+        let lapsed: Vec<(u32, RuleKind)> = expiries
+            .iter()
+            .filter(|(_, &at)| at <= now)
+            .map(|(&key, _)| key)
+            .collect();
+        for key @ (pid, kind) in &lapsed {
+            expiries.remove(key);
+            match kind {
+                RuleKind::Limit => self.uninstall_limit(*pid),
+                RuleKind::Block => {
+                    self.blocked_pids.lock().remove(pid);
+                }
+            }
+        }
+        lapsed
+            .into_iter()
+            .map(|(pid, kind)| ExpiredRule { pid, kind })
+            .collect()
     }
 
     /// Atomically block a process only if it is not already blocked.
@@ -174,27 +1127,55 @@ impl RateLimiterManager {
         if config.contains_key(&pid) {
             return false;
         }
-        limiters.insert(
-            pid,
-            ProcessLimiter {
-                download: TokenBucket::new(limit.download_bps),
-                upload: TokenBucket::new(limit.upload_bps),
-            },
-        );
+        limiters.insert(pid, NodeLimiter::new(&limit));
         config.insert(pid, limit);
         true
     }
 
-    /// Clear all limits and blocks (used when switching profiles).
+    /// Unblock a process only if its block is permanent, leaving temporary
+    /// blocks to lapse on their own. Returns `true` if it was unblocked.
+    pub fn unblock_if_permanent(&self, pid: u32) -> bool {
+        let expiries = self.expiries.lock();
+        if expiries.contains_key(&(pid, RuleKind::Block)) {
+            return false;
+        }
+        self.blocked_pids.lock().remove(&pid)
+    }
+
+    /// Remove a process's limit only if it is still exactly `limit` and
+    /// permanent, i.e. nobody replaced it since it was installed.
+    /// Returns `true` if it was removed.
+    pub fn remove_limit_if_unchanged(&self, pid: u32, limit: &BandwidthLimit) -> bool {
+        let expiries = self.expiries.lock();
+        if expiries.contains_key(&(pid, RuleKind::Limit)) {
+            return false;
+        }
+        let mut limiters = self.limiters.lock();
+        let mut config = self.limits_config.lock();
+        if config.get(&pid) != Some(limit) {
+            return false;
+        }
+        limiters.remove(&pid);
+        config.remove(&pid);
+        true
+    }
+
+    /// Clear all process limits and blocks (used when switching profiles).
+    /// App rules, the global limit, groups and group membership are kept.
     pub fn clear_all(&self) {
+        self.expiries.lock().clear();
         self.limiters.lock().clear();
         self.limits_config.lock().clear();
         self.blocked_pids.lock().clear();
     }
 
-    /// Remove limits and blocks for PIDs that are no longer alive.
+    /// Remove limits, blocks, app rule resolutions and group membership for
+    /// PIDs that are no longer alive. App rules themselves are kept.
     /// Prevents stale entries from accumulating and fixes PID-reuse inheritance bugs.
     pub fn remove_stale_pids(&self, live_pids: &std::collections::HashSet<u32>) {
+        self.expiries
+            .lock()
+            .retain(|(pid, _), _| live_pids.contains(pid));
         self.limiters
             .lock()
             .retain(|pid, _| live_pids.contains(pid));
@@ -204,6 +1185,14 @@ impl RateLimiterManager {
         self.blocked_pids
             .lock()
             .retain(|pid| live_pids.contains(pid));
+        self.apps
+            .lock()
+            .resolved
+            .retain(|pid, _| live_pids.contains(pid));
+        self.hierarchy
+            .lock()
+            .members
+            .retain(|pid, _| live_pids.contains(pid));
     }
 }
 
@@ -215,6 +1204,16 @@ impl Default for RateLimiterManager {
 
 #[cfg(test)]
 impl RateLimiterManager {
+    /// Get all current limit configurations (test-only).
+    pub fn get_all_limits(&self) -> HashMap<u32, BandwidthLimit> {
+        self.limits_config.lock().clone()
+    }
+
+    /// Get all blocked PIDs (test-only).
+    pub fn get_blocked_pids(&self) -> Vec<u32> {
+        self.blocked_pids.lock().iter().copied().collect()
+    }
+
     /// Check if a process has any rate limit configured (test-only).
     pub fn is_limited(&self, pid: u32) -> bool {
         self.limits_config.lock().contains_key(&pid)
@@ -253,6 +1252,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 5000,
                 upload_bps: 3000,
+                ..Default::default()
             },
         );
 
@@ -271,6 +1271,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 5000,
                 upload_bps: 3000,
+                ..Default::default()
             },
         );
         assert!(mgr.is_limited(100));
@@ -293,6 +1294,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 1000,
                 upload_bps: 1000,
+                ..Default::default()
             },
         );
         assert!(mgr.is_limited(100), "PID with set limit should be limited");
@@ -334,6 +1336,249 @@ mod tests {
         assert_eq!(blocked, vec![10, 20, 30]);
     }
 
+    #[test]
+    fn test_temporary_limit_and_block_expire() {
+        let mgr = RateLimiterManager::new();
+        let now = Instant::now();
+        let limit = BandwidthLimit {
+            download_bps: 1000,
+            ..Default::default()
+        };
+        mgr.set_limit_until(1, limit.clone(), now + Duration::from_secs(90));
+        mgr.block_process_until(2, now + Duration::from_millis(1500));
+        mgr.block_process(3);
+
+        let limits = mgr.get_active_limits(now);
+        assert_eq!(limits[&1].limit, limit);
+        assert_eq!(limits[&1].expires_in_secs, Some(90));
+        let mut blocked = mgr.get_blocked_processes(now);
+        blocked.sort_by_key(|b| b.pid);
+        assert_eq!(blocked[0].expires_in_secs, Some(2), "rounded up");
+        assert_eq!(blocked[1].expires_in_secs, None);
+
+        assert!(mgr.expire(now + Duration::from_secs(1)).is_empty());
+        assert_eq!(
+            mgr.expire(now + Duration::from_secs(2)),
+            vec![ExpiredRule {
+                pid: 2,
+                kind: RuleKind::Block
+            }]
+        );
+        assert!(!mgr.is_blocked(2));
+        assert!(mgr.is_blocked(3), "permanent block stays");
+
+        assert_eq!(mgr.expire(now + Duration::from_secs(90)).len(), 1);
+        assert!(!mgr.is_limited(1));
+    }
+
+    #[test]
+    fn test_permanent_rule_replaces_temporary_one() {
+        let mgr = RateLimiterManager::new();
+        let now = Instant::now();
+        mgr.set_limit_until(1, BandwidthLimit::default(), now);
+        mgr.set_limit(1, BandwidthLimit::default());
+        mgr.block_process_until(2, now);
+        mgr.unblock_process(2);
+        mgr.block_process(2);
+
+        assert!(mgr.expire(now).is_empty());
+        assert!(mgr.is_limited(1));
+        assert!(mgr.is_blocked(2));
+        assert_eq!(mgr.get_active_limits(now)[&1].expires_in_secs, None);
+    }
+
+    fn app_process(pid: u32, exe_path: &str) -> (u32, ProcessInfo) {
+        let info = ProcessInfo {
+            name: "app".into(),
+            exe_path: exe_path.into(),
+            args: String::new(),
+        };
+        (pid, info)
+    }
+
+    fn app_rule(exe_path: &str, action: AppAction) -> AppRule {
+        AppRule {
+            app: crate::core::app_rules::AppMatch {
+                exe_path: exe_path.into(),
+                args: None,
+                sha256: None,
+            },
+            process_name: "app".into(),
+            action,
+        }
+    }
+
+    #[test]
+    fn test_app_rules_follow_relaunched_processes() {
+        let mgr = RateLimiterManager::new();
+        let limit = BandwidthLimit {
+            upload_bps: 1000,
+            burst_bytes: 1500,
+            ..Default::default()
+        };
+        mgr.set_app_rule(app_rule("/bin/sync", AppAction::Limit(limit)));
+        mgr.set_app_rule(app_rule("/bin/ads", AppAction::Block));
+        mgr.resolve_apps(
+            &[
+                app_process(1, "/bin/sync"),
+                app_process(2, "/bin/sync"),
+                app_process(3, "/bin/ads"),
+                app_process(4, "/bin/other"),
+            ],
+            |_| None,
+        );
+        assert_eq!(mgr.get_app_rules()[1].pids, vec![1, 2]);
+        assert!(mgr.should_pass_packet(1, 1500, true));
+        assert!(!mgr.should_pass_packet(1, 1500, true));
+        assert!(!mgr.should_pass_packet(3, 100, true));
+        assert!(mgr.should_pass_packet(4, 100_000, true));
+
+        // The app restarts under new PIDs; the old PIDs are released.
+        mgr.resolve_apps(
+            &[app_process(5, "/bin/sync"), app_process(6, "/bin/ads")],
+            |_| None,
+        );
+        assert!(mgr.should_pass_packet(3, 100, true));
+        assert!(mgr.should_pass_packet(5, 1500, true));
+        assert!(!mgr.should_pass_packet(5, 1500, true));
+        assert!(!mgr.should_pass_packet(6, 100, true));
+
+        mgr.clear_all();
+        assert!(
+            !mgr.should_pass_packet(6, 100, true),
+            "kept across profiles"
+        );
+        assert!(mgr.remove_app_rule("/bin/ads"));
+        assert!(!mgr.remove_app_rule("/bin/ads"));
+        assert!(mgr.should_pass_packet(6, 100, true));
+    }
+
+    #[test]
+    fn test_process_rule_takes_precedence_over_app_rule() {
+        let mgr = RateLimiterManager::new();
+        mgr.set_app_rule(app_rule(
+            "/bin/sync",
+            AppAction::Limit(BandwidthLimit {
+                upload_bps: 1000,
+                burst_bytes: 1500,
+                ..Default::default()
+            }),
+        ));
+        mgr.resolve_apps(&[app_process(1, "/bin/sync")], |_| None);
+        mgr.set_limit(
+            1,
+            BandwidthLimit {
+                upload_bps: 1_000_000,
+                ..Default::default()
+            },
+        );
+        assert!(mgr.should_pass_packet(1, 10_000, true));
+
+        // Switching the app rule to a block applies to resolved PIDs at once,
+        // and a block drops traffic regardless of the process limit.
+        mgr.set_app_rule(app_rule("/bin/sync", AppAction::Block));
+        assert!(!mgr.should_pass_packet(1, 100, true));
+    }
+
+    #[test]
+    fn test_effective_policy_reports_source() {
+        let mgr = RateLimiterManager::new();
+        let now = Instant::now();
+        let limit = BandwidthLimit {
+            download_bps: 1000,
+            ..Default::default()
+        };
+        assert_eq!(mgr.effective_policy(1, now).source, PolicySource::None);
+
+        mgr.set_app_rule(app_rule("/bin/sync", AppAction::Limit(limit.clone())));
+        mgr.resolve_apps(&[app_process(1, "/bin/sync")], |_| None);
+        let policy = mgr.effective_policy(1, now);
+        assert_eq!(policy.limit, Some(limit.clone()));
+        assert_eq!(
+            policy.source,
+            PolicySource::App {
+                exe_path: "/bin/sync".into()
+            }
+        );
+
+        mgr.set_limit_until(1, BandwidthLimit::default(), now + Duration::from_secs(60));
+        let policy = mgr.effective_policy(1, now);
+        assert_eq!(policy.source, PolicySource::Process);
+        assert_eq!(policy.limit, Some(BandwidthLimit::default()));
+        assert_eq!(policy.expires_in_secs, Some(60));
+
+        mgr.set_app_rule(app_rule("/bin/sync", AppAction::Block));
+        let policy = mgr.effective_policy(1, now);
+        assert!(policy.blocked);
+        assert_eq!(policy.limit, None);
+        assert!(matches!(policy.source, PolicySource::App { .. }));
+
+        mgr.block_process(1);
+        assert_eq!(mgr.effective_policy(1, now).source, PolicySource::Process);
+    }
+
+    #[test]
+    fn test_allowlist_mode_blocks_unlisted_processes() {
+        use crate::core::allowlist::{AllowEntry, Allowlist, AllowlistMatcher};
+        use crate::core::rule_match::MatchType;
+
+        let mgr = RateLimiterManager::new();
+        let now = Instant::now();
+        let list = Allowlist {
+            allowed: vec![AllowEntry {
+                match_type: MatchType::PathGlob,
+                pattern: "/usr/bin/fire*".into(),
+            }],
+            always_allow: Vec::new(),
+        };
+        let matcher = || Some(AllowlistMatcher::new(&list).unwrap());
+        mgr.set_limit(
+            1,
+            BandwidthLimit {
+                upload_bps: 1000,
+                burst_bytes: 1500,
+                ..Default::default()
+            },
+        );
+        mgr.set_allowlist(
+            matcher(),
+            &[
+                app_process(1, "/usr/bin/firefox"),
+                app_process(2, "/bin/sync"),
+            ],
+        );
+        assert!(mgr.allowlist_enabled());
+        assert!(mgr.should_pass_packet(1, 1500, true));
+        assert!(!mgr.should_pass_packet(1, 1500, true), "limits still apply");
+        assert!(!mgr.should_pass_packet(2, 100, true));
+        assert!(
+            !mgr.should_pass_packet(3, 100, true),
+            "not seen by a scan yet"
+        );
+        assert_eq!(mgr.effective_policy(2, now).source, PolicySource::Allowlist);
+
+        // A later scan lets a new process of an allowed application through.
+        mgr.resolve_allowlist(&[
+            app_process(1, "/usr/bin/firefox"),
+            app_process(3, "/usr/bin/firefox"),
+        ]);
+        assert!(mgr.should_pass_packet(3, 100, true));
+        assert!(!mgr.should_pass_packet(2, 100, true));
+
+        // Explicit blocks win over the allowlist.
+        mgr.block_process(3);
+        assert!(!mgr.should_pass_packet(3, 100, true));
+        assert_eq!(mgr.effective_policy(3, now).source, PolicySource::Process);
+
+        mgr.set_allowlist(None, &[]);
+        assert!(!mgr.allowlist_enabled());
+        assert!(mgr.should_pass_packet(2, 100, true));
+        assert_eq!(mgr.effective_policy(2, now).source, PolicySource::None);
+        // Resolving while the mode is off does not turn it back on.
+        mgr.resolve_allowlist(&[app_process(2, "/bin/sync")]);
+        assert!(mgr.should_pass_packet(2, 100, true));
+    }
+
     #[test]
     fn test_clear_all() {
         let mgr = RateLimiterManager::new();
@@ -342,6 +1587,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 1000,
                 upload_bps: 500,
+                ..Default::default()
             },
         );
         mgr.set_limit(
@@ -349,6 +1595,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 2000,
                 upload_bps: 1000,
+                ..Default::default()
             },
         );
         mgr.block_process(3);
@@ -376,6 +1623,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 1000,
                 upload_bps: 500,
+                ..Default::default()
             },
         );
 
@@ -388,6 +1636,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 5000,
                 upload_bps: 2500,
+                ..Default::default()
             },
         );
 
@@ -422,6 +1671,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 1_000_000,
                 upload_bps: 1_000_000,
+                ..Default::default()
             },
         );
 
@@ -432,6 +1682,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_configured_burst_sizes() {
+        let burst = |burst_bytes, burst_ms| BandwidthLimit {
+            download_bps: 10_000,
+            burst_bytes,
+            burst_ms,
+            ..Default::default()
+        };
+        // Default: two seconds at the configured rate.
+        assert_eq!(burst(0, 0).burst_for(10_000), 20_000);
+        assert_eq!(burst(0, 500).burst_for(10_000), 5_000);
+        // Bytes win over a duration.
+        assert_eq!(burst(4_000, 500).burst_for(10_000), 4_000);
+        // Never below one MTU, even for very low rates.
+        assert_eq!(burst(0, 0).burst_for(100), MIN_BURST_BYTES);
+        assert_eq!(burst(0, 10).burst_for(10_000), MIN_BURST_BYTES);
+
+        let mgr = RateLimiterManager::new();
+        mgr.set_limit(1, burst(3_000, 0));
+        assert!(mgr.should_pass_packet(1, 3_000, false));
+        assert!(
+            !mgr.should_pass_packet(1, 100, false),
+            "3000-byte burst used up"
+        );
+
+        // A 100 B/s limit still passes one full-size packet.
+        mgr.set_limit(
+            2,
+            BandwidthLimit {
+                upload_bps: 100,
+                ..Default::default()
+            },
+        );
+        assert!(mgr.should_pass_packet(2, 1_500, true));
+    }
+
     #[test]
     fn test_should_drop_over_budget() {
         let mgr = RateLimiterManager::new();
@@ -441,6 +1727,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 1000,
                 upload_bps: 1000,
+                ..Default::default()
             },
         );
 
@@ -502,6 +1789,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 5000,
                 upload_bps: 3000,
+                ..Default::default()
             },
         );
         assert!(result, "first set_limit_if_absent should return true");
@@ -518,6 +1806,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 1000,
                 upload_bps: 500,
+                ..Default::default()
             },
         );
         let result = mgr.set_limit_if_absent(
@@ -525,6 +1814,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 9999,
                 upload_bps: 9999,
+                ..Default::default()
             },
         );
         assert!(
@@ -536,6 +1826,41 @@ mod tests {
         assert_eq!(limits.get(&100).unwrap().download_bps, 1000);
     }
 
+    #[test]
+    fn test_conditional_lifts_keep_replaced_rules() {
+        let mgr = RateLimiterManager::new();
+        let ours = BandwidthLimit {
+            download_bps: 1000,
+            ..Default::default()
+        };
+        let later = Instant::now() + Duration::from_secs(60);
+
+        mgr.set_limit(1, ours.clone());
+        assert!(mgr.remove_limit_if_unchanged(1, &ours));
+        assert!(!mgr.is_limited(1));
+
+        // Replaced by a different or a temporary limit: kept.
+        mgr.set_limit(2, ours.clone());
+        mgr.set_limit(
+            2,
+            BandwidthLimit {
+                download_bps: 500,
+                ..Default::default()
+            },
+        );
+        assert!(!mgr.remove_limit_if_unchanged(2, &ours));
+        mgr.set_limit_until(3, ours.clone(), later);
+        assert!(!mgr.remove_limit_if_unchanged(3, &ours));
+        assert!(mgr.is_limited(2) && mgr.is_limited(3));
+
+        mgr.block_process(4);
+        mgr.block_process_until(5, later);
+        assert!(mgr.unblock_if_permanent(4));
+        assert!(!mgr.unblock_if_permanent(5));
+        assert!(!mgr.is_blocked(4));
+        assert!(mgr.is_blocked(5));
+    }
+
     #[test]
     fn test_should_pass_refills_after_drop() {
         let mgr = RateLimiterManager::new();
@@ -545,6 +1870,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 10_000,
                 upload_bps: 10_000,
+                ..Default::default()
             },
         );
 
@@ -571,6 +1897,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 1000,
                 upload_bps: 500,
+                ..Default::default()
             },
         );
         mgr.set_limit(
@@ -578,6 +1905,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 2000,
                 upload_bps: 1000,
+                ..Default::default()
             },
         );
         mgr.block_process(300);
@@ -599,6 +1927,7 @@ mod tests {
             BandwidthLimit {
                 download_bps: 100,
                 upload_bps: 50,
+                ..Default::default()
             },
         );
         mgr.block_process(2);
@@ -608,4 +1937,198 @@ mod tests {
         assert!(mgr.get_all_limits().is_empty());
         assert!(mgr.get_blocked_pids().is_empty());
     }
+
+    #[test]
+    fn test_shaping_and_try_send_shaped() {
+        let mgr = RateLimiterManager::new();
+        // Rate: 1000 bps → burst = 2000 tokens, upload only.
+        mgr.set_limit(
+            7,
+            BandwidthLimit {
+                download_bps: 0,
+                upload_bps: 1_000,
+                mode: LimitMode::ShapeDropTail,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            mgr.shaping(7, true).map(|s| s.mode),
+            Some(LimitMode::ShapeDropTail)
+        );
+        assert_eq!(mgr.shaping(7, false).map(|s| s.mode), None); // unlimited direction
+        assert_eq!(mgr.shaping(8, true).map(|s| s.mode), None); // no limit
+
+        let now = Instant::now();
+        assert_eq!(mgr.try_send_shaped(7, 1_500, true, now), ShapedSend::Send);
+        // 500 tokens left: 1000 bytes need another 500 ms.
+        let ShapedSend::Wait(wait) = mgr.try_send_shaped(7, 1_000, true, now) else {
+            panic!("expected to wait for tokens");
+        };
+        assert!(wait > Duration::from_millis(490) && wait <= Duration::from_millis(500));
+        // Oversize packets go out once the bucket is full, leaving debt.
+        let later = now + Duration::from_secs(5);
+        assert_eq!(mgr.try_send_shaped(7, 5_000, true, later), ShapedSend::Send);
+        assert!(matches!(
+            mgr.try_send_shaped(7, 1, true, later),
+            ShapedSend::Wait(_)
+        ));
+
+        // Blocked processes drop; switching to policing releases the queue.
+        mgr.block_process(7);
+        assert_eq!(mgr.shaping(7, true).map(|s| s.mode), None);
+        assert_eq!(mgr.try_send_shaped(7, 1, true, later), ShapedSend::Drop);
+        mgr.unblock_process(7);
+        mgr.set_limit(
+            7,
+            BandwidthLimit {
+                download_bps: 0,
+                upload_bps: 1_000,
+                mode: LimitMode::Police,
+                ..Default::default()
+            },
+        );
+        assert_eq!(mgr.shaping(7, true).map(|s| s.mode), None);
+        assert_eq!(mgr.try_send_shaped(7, 1, true, later), ShapedSend::Send);
+    }
+
+    fn cap(download_bps: u64, upload_bps: u64) -> BandwidthLimit {
+        BandwidthLimit {
+            download_bps,
+            upload_bps,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_global_limit_caps_all_processes_together() {
+        let mgr = RateLimiterManager::new();
+        // Rate: 1000 bps → burst = 2000 tokens shared by every process.
+        mgr.set_global_limit(cap(0, 1_000));
+        assert!(mgr.should_pass_packet(1, 1_500, true));
+        assert!(mgr.should_pass_packet(2, 500, true));
+        assert!(!mgr.should_pass_packet(2, 600, true));
+        // Download is not capped.
+        assert!(mgr.should_pass_packet(2, 1_000_000, false));
+
+        mgr.remove_global_limit();
+        assert!(mgr.get_global_limit().is_none());
+        assert!(mgr.should_pass_packet(2, 600, true));
+    }
+
+    #[test]
+    fn test_group_limit_caps_members_below_global() {
+        let mgr = RateLimiterManager::new();
+        mgr.set_global_limit(cap(0, 100_000));
+        mgr.set_group_limit("backup", cap(0, 1_000));
+        assert!(mgr.assign_group(10, Some("backup")));
+        assert!(!mgr.assign_group(11, Some("missing")));
+
+        // The group's 2000-byte burst binds before the global one.
+        assert!(mgr.should_pass_packet(10, 2_000, true));
+        assert!(!mgr.should_pass_packet(10, 100, true));
+        // Processes outside the group still have the global capacity.
+        assert!(mgr.should_pass_packet(20, 50_000, true));
+
+        // Removing the group releases its members.
+        mgr.remove_group("backup");
+        assert!(mgr.get_group_members().is_empty());
+        assert!(mgr.should_pass_packet(10, 100, true));
+    }
+
+    #[test]
+    fn test_guaranteed_rate_passes_when_parent_exhausted() {
+        let mgr = RateLimiterManager::new();
+        mgr.set_global_limit(cap(0, 1_000));
+        mgr.set_limit(
+            2,
+            BandwidthLimit {
+                guaranteed_upload_bps: 500,
+                ..Default::default()
+            },
+        );
+
+        // PID 1 uses up the whole global burst.
+        assert!(mgr.should_pass_packet(1, 2_000, true));
+        assert!(!mgr.should_pass_packet(1, 100, true));
+        // PID 2 still gets its guaranteed burst (floored at one MTU)...
+        assert!(mgr.should_pass_packet(2, 1_500, true));
+        // ...but nothing beyond it, and its traffic was charged to the global limit.
+        assert!(!mgr.should_pass_packet(2, 100, true));
+        assert!(!mgr.should_pass_packet(1, 100, true));
+    }
+
+    #[test]
+    fn test_borrowing_is_bounded_by_parent_and_own_ceiling() {
+        let mgr = RateLimiterManager::new();
+        // Global burst 6000; the process: guarantee burst 2000, ceiling burst 8000.
+        mgr.set_global_limit(cap(0, 3_000));
+        mgr.set_limit(
+            1,
+            BandwidthLimit {
+                upload_bps: 4_000,
+                guaranteed_upload_bps: 1_000,
+                ..Default::default()
+            },
+        );
+        let sent = (0..10)
+            .take_while(|_| mgr.should_pass_packet(1, 1_000, true))
+            .count();
+        assert_eq!(sent, 6, "guarantee plus what the parent can lend");
+
+        // Without a parent, the process's own ceiling binds.
+        mgr.remove_global_limit();
+        let sent = (0..10)
+            .take_while(|_| mgr.should_pass_packet(1, 1_000, true))
+            .count();
+        assert_eq!(
+            sent, 2,
+            "8000-byte ceiling burst minus the 6000 already sent"
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_survives_clear_all_but_not_stale_pids() {
+        let mgr = RateLimiterManager::new();
+        mgr.set_global_limit(cap(1_000, 1_000));
+        mgr.set_group_limit("bulk", cap(500, 500));
+        mgr.assign_group(1, Some("bulk"));
+        mgr.assign_group(2, Some("bulk"));
+
+        mgr.clear_all();
+        assert_eq!(mgr.get_global_limit(), Some(cap(1_000, 1_000)));
+        assert_eq!(mgr.get_group_limits().len(), 1);
+        assert_eq!(mgr.get_group_members().len(), 2);
+
+        mgr.remove_stale_pids(&std::collections::HashSet::from([2]));
+        let members = mgr.get_group_members();
+        assert_eq!(members, HashMap::from([(2, "bulk".to_string())]));
+    }
+
+    #[test]
+    fn test_shaping_follows_parent_caps() {
+        let mgr = RateLimiterManager::new();
+        mgr.set_limit(
+            3,
+            BandwidthLimit {
+                mode: LimitMode::ShapeCodel,
+                ..Default::default()
+            },
+        );
+        // A shaping rule without any cap in the tree has nothing to shape.
+        assert_eq!(mgr.shaping(3, true).map(|s| s.mode), None);
+
+        mgr.set_global_limit(cap(0, 1_000));
+        assert_eq!(
+            mgr.shaping(3, true).map(|s| s.mode),
+            Some(LimitMode::ShapeCodel)
+        );
+        assert_eq!(mgr.shaping(3, false).map(|s| s.mode), None);
+
+        let now = Instant::now();
+        assert_eq!(mgr.try_send_shaped(3, 2_000, true, now), ShapedSend::Send);
+        assert!(matches!(
+            mgr.try_send_shaped(3, 1_000, true, now),
+            ShapedSend::Wait(_)
+        ));
+    }
 }
@@ -0,0 +1,438 @@
+//! Matching and evaluation of saved rules against running processes.
+//!
+//! A saved rule matches either an exact executable path or a glob or regex
+//! pattern, applied to the executable path or to the process name. When
+//! several rules match one process, exactly one wins. [`RuleSet`] ranks the
+//! matching rules by, in order:
+//!
+//! 1. explicit precedence (higher first),
+//! 2. match type, most specific first (see [`MatchType`]),
+//! 3. pattern length, longer first,
+//! 4. a block before a limit,
+//! 5. pattern text, then position in the rule list,
+//!
+//! so the outcome never depends on the order rules were loaded in, and it can
+//! tell which of these steps decided between two rules.
+
+use std::cmp::Reverse;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::rate_limiter::EffectivePolicy;
+
+/// What a saved rule's pattern is matched against, and how. Variants are
+/// declared from most to least specific.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, TS,
+)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum MatchType {
+    /// The executable path, exactly.
+    #[default]
+    Exact,
+    /// The executable path, with `*`, `?` and `[...]` wildcards.
+    PathGlob,
+    /// The whole executable path, as a regular expression.
+    PathRegex,
+    /// The process name, with wildcards.
+    NameGlob,
+    /// The whole process name, as a regular expression.
+    NameRegex,
+}
+
+impl MatchType {
+    /// Value stored in the `match_type` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MatchType::Exact => "exact",
+            MatchType::PathGlob => "path_glob",
+            MatchType::PathRegex => "path_regex",
+            MatchType::NameGlob => "name_glob",
+            MatchType::NameRegex => "name_regex",
+        }
+    }
+
+    /// Parse a `match_type` column value. Unknown values fall back to exact.
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "path_glob" => MatchType::PathGlob,
+            "path_regex" => MatchType::PathRegex,
+            "name_glob" => MatchType::NameGlob,
+            "name_regex" => MatchType::NameRegex,
+            _ => MatchType::Exact,
+        }
+    }
+
+    fn on_name(self) -> bool {
+        matches!(self, MatchType::NameGlob | MatchType::NameRegex)
+    }
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Exact(String),
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+/// A compiled rule pattern.
+#[derive(Debug)]
+pub struct RuleMatcher {
+    match_type: MatchType,
+    pattern: Pattern,
+}
+
+impl RuleMatcher {
+    /// Compile `pattern` for `match_type`. Fails on an invalid glob or regex.
+    pub fn new(match_type: MatchType, pattern: &str) -> Result<Self> {
+        let compiled = match match_type {
+            MatchType::Exact => Pattern::Exact(pattern.to_string()),
+            MatchType::PathGlob | MatchType::NameGlob => Pattern::Glob(
+                glob::Pattern::new(pattern).with_context(|| format!("invalid glob '{pattern}'"))?,
+            ),
+            MatchType::PathRegex | MatchType::NameRegex => Pattern::Regex(
+                regex::Regex::new(&format!("^(?:{pattern})$"))
+                    .with_context(|| format!("invalid regex '{pattern}'"))?,
+            ),
+        };
+        Ok(Self {
+            match_type,
+            pattern: compiled,
+        })
+    }
+
+    /// True if a process with this executable path and name matches.
+    pub fn matches(&self, exe_path: &str, name: &str) -> bool {
+        let subject = if self.match_type.on_name() {
+            name
+        } else {
+            exe_path
+        };
+        match &self.pattern {
+            Pattern::Exact(path) => path == subject,
+            Pattern::Glob(glob) => glob.matches(subject),
+            Pattern::Regex(regex) => regex.is_match(subject),
+        }
+    }
+}
+
+/// The parts of a saved rule that decide whether and how strongly it matches.
+#[derive(Debug, Clone, Copy)]
+pub struct RuleSpec<'a> {
+    pub match_type: MatchType,
+    pub pattern: &'a str,
+    /// Explicit priority; a higher value wins regardless of specificity.
+    pub precedence: i32,
+    pub blocked: bool,
+}
+
+/// The step of the evaluation order that ranked one matching rule above another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum Tiebreak {
+    Precedence,
+    MatchType,
+    PatternLength,
+    BlockOverLimit,
+    PatternText,
+    RuleOrder,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Position in the rule list.
+    index: usize,
+    precedence: i32,
+    match_type: MatchType,
+    pattern: String,
+    blocked: bool,
+    matcher: RuleMatcher,
+}
+
+impl Entry {
+    /// Rank of each evaluation step (smaller wins), in evaluation order.
+    fn key(&self) -> (Reverse<i32>, MatchType, Reverse<usize>, bool, &str, usize) {
+        (
+            Reverse(self.precedence),
+            self.match_type,
+            Reverse(self.pattern.len()),
+            !self.blocked,
+            &self.pattern,
+            self.index,
+        )
+    }
+
+    /// The first evaluation step on which `self` ranks above `other`.
+    fn outranks(&self, other: &Entry) -> Option<Tiebreak> {
+        let (a, b) = (self.key(), other.key());
+        let step = if a.0 != b.0 {
+            (a.0 < b.0, Tiebreak::Precedence)
+        } else if a.1 != b.1 {
+            (a.1 < b.1, Tiebreak::MatchType)
+        } else if a.2 != b.2 {
+            (a.2 < b.2, Tiebreak::PatternLength)
+        } else if a.3 != b.3 {
+            (self.blocked, Tiebreak::BlockOverLimit)
+        } else if a.4 != b.4 {
+            (a.4 < b.4, Tiebreak::PatternText)
+        } else {
+            (a.5 < b.5, Tiebreak::RuleOrder)
+        };
+        step.0.then_some(step.1)
+    }
+}
+
+/// The compiled rules of a profile, in evaluation order.
+#[derive(Debug)]
+pub struct RuleSet {
+    entries: Vec<Entry>,
+}
+
+impl RuleSet {
+    /// Compile a list of rules. Rules whose pattern does not compile never
+    /// match. Results refer to rules by their position in the list.
+    pub fn new<'a>(rules: impl IntoIterator<Item = RuleSpec<'a>>) -> Self {
+        let mut entries: Vec<Entry> = rules
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, spec)| {
+                let matcher = RuleMatcher::new(spec.match_type, spec.pattern)
+                    .map_err(|e| tracing::debug!("Skipping rule {index}: {e:#}"))
+                    .ok()?;
+                Some(Entry {
+                    index,
+                    precedence: spec.precedence,
+                    match_type: spec.match_type,
+                    pattern: spec.pattern.to_string(),
+                    blocked: spec.blocked,
+                    matcher,
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.key().cmp(&b.key()));
+        Self { entries }
+    }
+
+    /// The rule that applies to a process: the first matching one in
+    /// evaluation order among those for which `is_active` (given a rule's
+    /// position in the list) holds, so a rule that is out of force does not
+    /// hide the ones below it.
+    pub fn find_active(
+        &self,
+        exe_path: &str,
+        name: &str,
+        is_active: impl Fn(usize) -> bool,
+    ) -> Option<usize> {
+        self.entries
+            .iter()
+            .find(|e| is_active(e.index) && e.matcher.matches(exe_path, name))
+            .map(|e| e.index)
+    }
+
+    /// Every rule matching a process: the highest-ranked one for which
+    /// `is_active` holds first (with `None`), then the rest in evaluation
+    /// order, each with the step on which that winner outranks it. Inactive
+    /// rules ranked above the winner get `None` too.
+    pub fn evaluate(
+        &self,
+        exe_path: &str,
+        name: &str,
+        is_active: impl Fn(usize) -> bool,
+    ) -> Vec<(usize, Option<Tiebreak>)> {
+        let matching: Vec<&Entry> = self
+            .entries
+            .iter()
+            .filter(|e| e.matcher.matches(exe_path, name))
+            .collect();
+        let Some(winner) = matching.iter().find(|e| is_active(e.index)) else {
+            return matching.iter().map(|e| (e.index, None)).collect();
+        };
+        std::iter::once((winner.index, None))
+            .chain(
+                matching
+                    .iter()
+                    .filter(|e| e.index != winner.index)
+                    .map(|e| (e.index, winner.outranks(e))),
+            )
+            .collect()
+    }
+}
+
+/// A profile rule matching a process, as reported by an explanation.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct RuleCandidate {
+    #[ts(type = "number")]
+    pub rule_id: i64,
+    pub pattern: String,
+    pub match_type: MatchType,
+    pub precedence: i32,
+    pub blocked: bool,
+    /// False if the rule's schedule has it out of force right now.
+    pub active: bool,
+    /// The step on which the winning rule outranks this one (`None` for the winner).
+    pub lost_on: Option<Tiebreak>,
+}
+
+/// Why a process is treated the way it is.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct PolicyExplanation {
+    pub pid: u32,
+    pub process_name: String,
+    pub exe_path: String,
+    /// What the rate limiter currently enforces, and from which kind of rule.
+    pub effective: EffectivePolicy,
+    /// Id of the active profile's rule that applies to the process, if any.
+    #[ts(type = "number | null")]
+    pub winning_rule_id: Option<i64>,
+    /// Active profile rules matching the process, winner first.
+    pub candidates: Vec<RuleCandidate>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(match_type: MatchType, pattern: &str) -> RuleSpec<'_> {
+        RuleSpec {
+            match_type,
+            pattern,
+            precedence: 0,
+            blocked: false,
+        }
+    }
+
+    #[test]
+    fn test_glob_and_regex_on_path_and_name() {
+        let glob = RuleMatcher::new(MatchType::PathGlob, r"C:\Apps\app-*\app.exe").unwrap();
+        assert!(glob.matches(r"C:\Apps\app-1.2.3\app.exe", "app.exe"));
+        assert!(!glob.matches(r"C:\Apps\app-1.2.3\other.exe", "other.exe"));
+
+        let regex = RuleMatcher::new(MatchType::PathRegex, r".*/app-[0-9.]+/app").unwrap();
+        assert!(regex.matches("/opt/app-2.0/app", "app"));
+        assert!(!regex.matches("/opt/app-2.0/app.bak", "app"), "anchored");
+
+        let name = RuleMatcher::new(MatchType::NameGlob, "chrome*").unwrap();
+        assert!(name.matches("/usr/lib/chromium", "chrome_crashpad"));
+        assert!(!name.matches("/usr/bin/chrome", "firefox"), "name only");
+
+        let exact = RuleMatcher::new(MatchType::Exact, "/usr/bin/app*").unwrap();
+        assert!(exact.matches("/usr/bin/app*", "app"));
+        assert!(!exact.matches("/usr/bin/app2", "app2"));
+
+        assert!(RuleMatcher::new(MatchType::NameRegex, "(").is_err());
+        assert!(RuleMatcher::new(MatchType::PathGlob, "[").is_err());
+    }
+
+    #[test]
+    fn test_precedence_is_deterministic() {
+        let patterns = [
+            (MatchType::NameGlob, "app*"),
+            (MatchType::PathRegex, "/(opt|srv)/.*"),
+            (MatchType::PathGlob, "/opt/*"),
+            (MatchType::PathGlob, "/opt/app-*/app"),
+            (MatchType::Exact, "/opt/app-1/app"),
+            (MatchType::NameRegex, "("),
+        ];
+        let set = RuleSet::new(patterns.map(|(t, p)| spec(t, p)));
+        assert_eq!(
+            set.find_active("/opt/app-1/app", "app", |_| true),
+            Some(4),
+            "exact first"
+        );
+        assert_eq!(
+            set.find_active("/opt/app-2/app", "app", |_| true),
+            Some(3),
+            "longer glob"
+        );
+        assert_eq!(
+            set.find_active("/opt/tool", "tool", |_| true),
+            Some(2),
+            "glob before regex"
+        );
+        assert_eq!(
+            set.find_active("/srv/app", "app", |_| true),
+            Some(1),
+            "path before name"
+        );
+        assert_eq!(set.find_active("/usr/bin/app7", "app7", |_| true), Some(0));
+        assert_eq!(set.find_active("/usr/bin/x", "x", |_| true), None);
+
+        // The same rules in another order pick the same winners.
+        let mut reversed = patterns;
+        reversed.reverse();
+        let set = RuleSet::new(reversed.map(|(t, p)| spec(t, p)));
+        assert_eq!(set.find_active("/opt/app-2/app", "app", |_| true), Some(2));
+    }
+
+    #[test]
+    fn test_precedence_and_block_outrank_specificity() {
+        let rules = [
+            spec(MatchType::Exact, "/opt/app/app"),
+            RuleSpec {
+                blocked: true,
+                ..spec(MatchType::PathGlob, "/opt/app/ap?")
+            },
+            spec(MatchType::PathGlob, "/opt/app/ap*"),
+            RuleSpec {
+                precedence: 10,
+                ..spec(MatchType::NameGlob, "*")
+            },
+        ];
+        let set = RuleSet::new(rules);
+        assert_eq!(
+            set.evaluate("/opt/app/app", "app", |_| true),
+            vec![
+                (3, None),
+                (0, Some(Tiebreak::Precedence)),
+                (1, Some(Tiebreak::Precedence)),
+                (2, Some(Tiebreak::Precedence)),
+            ]
+        );
+        assert_eq!(set.evaluate("/usr/bin/x", "x", |_| true), vec![(3, None)]);
+
+        // Without the catch-all, the exact rule wins on match type and the
+        // block wins over the equally long glob limit.
+        let set = RuleSet::new(rules[..3].iter().copied());
+        assert_eq!(
+            set.evaluate("/opt/app/app", "app", |_| true),
+            vec![
+                (0, None),
+                (1, Some(Tiebreak::MatchType)),
+                (2, Some(Tiebreak::MatchType)),
+            ]
+        );
+        assert!(set.evaluate("/usr/bin/x", "x", |_| true).is_empty());
+        let globs = RuleSet::new(rules[1..3].iter().copied());
+        assert_eq!(
+            globs.evaluate("/opt/app/app", "app", |_| true),
+            vec![(0, None), (1, Some(Tiebreak::BlockOverLimit))]
+        );
+    }
+
+    #[test]
+    fn test_inactive_rules_do_not_win() {
+        let rules = [
+            spec(MatchType::Exact, "/opt/app/app"),
+            RuleSpec {
+                precedence: 10,
+                ..spec(MatchType::NameGlob, "*")
+            },
+        ];
+        let set = RuleSet::new(rules);
+        assert_eq!(set.find_active("/opt/app/app", "app", |_| true), Some(1));
+        assert_eq!(set.find_active("/opt/app/app", "app", |i| i != 1), Some(0));
+        assert_eq!(set.find_active("/usr/bin/x", "x", |i| i != 1), None);
+        assert_eq!(
+            set.evaluate("/opt/app/app", "app", |i| i != 1),
+            vec![(0, None), (1, None)]
+        );
+        assert_eq!(
+            set.evaluate("/opt/app/app", "app", |_| false),
+            vec![(1, None), (0, None)]
+        );
+    }
+}
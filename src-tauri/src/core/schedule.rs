@@ -0,0 +1,166 @@
+//! Time-of-day schedules for bandwidth rules.
+//!
+//! A scheduled rule is only in force during its windows, evaluated in local
+//! time. A window whose end is before its start runs past midnight into the
+//! following day. A rule without windows is always in force.
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// Minutes in a day; a window may end at this value (midnight).
+pub const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Day of the week in local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    /// Convert SQLite's `strftime('%w')` day number (0 = Sunday).
+    pub fn from_sunday_index(index: u32) -> Option<Self> {
+        Some(match index {
+            0 => Weekday::Sun,
+            1 => Weekday::Mon,
+            2 => Weekday::Tue,
+            3 => Weekday::Wed,
+            4 => Weekday::Thu,
+            5 => Weekday::Fri,
+            6 => Weekday::Sat,
+            _ => return None,
+        })
+    }
+
+    fn previous(self) -> Self {
+        match self {
+            Weekday::Mon => Weekday::Sun,
+            Weekday::Tue => Weekday::Mon,
+            Weekday::Wed => Weekday::Tue,
+            Weekday::Thu => Weekday::Wed,
+            Weekday::Fri => Weekday::Thu,
+            Weekday::Sat => Weekday::Fri,
+            Weekday::Sun => Weekday::Sat,
+        }
+    }
+}
+
+/// A recurring time window on a set of weekdays, e.g. 09:00–18:00 Mon–Fri.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct ScheduleWindow {
+    /// Days on which the window starts.
+    pub days: Vec<Weekday>,
+    /// Start, in minutes after local midnight (inclusive).
+    pub start_minute: u16,
+    /// End, in minutes after local midnight (exclusive). Before the start
+    /// for a window that runs past midnight.
+    pub end_minute: u16,
+}
+
+impl ScheduleWindow {
+    /// True if `clock` falls inside this window.
+    pub fn contains(&self, clock: &LocalClock) -> bool {
+        let starts_on = |day| self.days.contains(&day);
+        if self.start_minute <= self.end_minute {
+            starts_on(clock.weekday)
+                && clock.minute >= self.start_minute
+                && clock.minute < self.end_minute
+        } else {
+            (starts_on(clock.weekday) && clock.minute >= self.start_minute)
+                || (starts_on(clock.weekday.previous()) && clock.minute < self.end_minute)
+        }
+    }
+}
+
+/// True if a rule with these windows is in force at `clock`.
+pub fn is_active(schedule: &[ScheduleWindow], clock: &LocalClock) -> bool {
+    schedule.is_empty() || schedule.iter().any(|w| w.contains(clock))
+}
+
+/// The current local date and time, as far as schedules and quotas need it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalClock {
+    /// `YYYY-MM-DD`.
+    pub date: String,
+    pub weekday: Weekday,
+    /// Minutes after local midnight.
+    pub minute: u16,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(weekday: Weekday, hour: u16, minute: u16) -> LocalClock {
+        LocalClock {
+            date: "2026-03-04".into(),
+            weekday,
+            minute: hour * 60 + minute,
+        }
+    }
+
+    const WEEKDAYS: [Weekday; 5] = [
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+    ];
+
+    #[test]
+    fn test_daytime_window() {
+        let office = ScheduleWindow {
+            days: WEEKDAYS.to_vec(),
+            start_minute: 9 * 60,
+            end_minute: 18 * 60,
+        };
+        assert!(office.contains(&at(Weekday::Mon, 9, 0)));
+        assert!(office.contains(&at(Weekday::Fri, 17, 59)));
+        assert!(!office.contains(&at(Weekday::Fri, 18, 0)));
+        assert!(!office.contains(&at(Weekday::Wed, 8, 59)));
+        assert!(!office.contains(&at(Weekday::Sat, 12, 0)));
+    }
+
+    #[test]
+    fn test_overnight_window_continues_into_next_day() {
+        let night = ScheduleWindow {
+            days: vec![Weekday::Fri],
+            start_minute: 22 * 60,
+            end_minute: 6 * 60,
+        };
+        assert!(night.contains(&at(Weekday::Fri, 23, 0)));
+        assert!(night.contains(&at(Weekday::Sat, 5, 59)));
+        assert!(!night.contains(&at(Weekday::Sat, 6, 0)));
+        assert!(!night.contains(&at(Weekday::Fri, 5, 0)));
+        assert!(!night.contains(&at(Weekday::Sat, 23, 0)));
+    }
+
+    #[test]
+    fn test_full_day_and_unscheduled() {
+        let sunday = ScheduleWindow {
+            days: vec![Weekday::Sun],
+            start_minute: 0,
+            end_minute: MINUTES_PER_DAY,
+        };
+        assert!(sunday.contains(&at(Weekday::Sun, 0, 0)));
+        assert!(sunday.contains(&at(Weekday::Sun, 23, 59)));
+        assert!(!sunday.contains(&at(Weekday::Mon, 0, 0)));
+
+        assert!(is_active(&[], &at(Weekday::Mon, 3, 0)));
+        assert!(!is_active(&[sunday], &at(Weekday::Mon, 3, 0)));
+    }
+
+    #[test]
+    fn test_weekday_from_sunday_index() {
+        assert_eq!(Weekday::from_sunday_index(0), Some(Weekday::Sun));
+        assert_eq!(Weekday::from_sunday_index(6), Some(Weekday::Sat));
+        assert_eq!(Weekday::from_sunday_index(7), None);
+    }
+}
@@ -1,9 +1,10 @@
 //! Per-process traffic accounting using DashMap for lock-free concurrent access.
 //!
 //! Tracks bytes sent/received per PID, computes 1-second speed snapshots,
-//! and provides snapshots for the frontend via Tauri events.
+//! and provides snapshots for the frontend via Tauri events. Bytes on ports
+//! not yet in the port map wait in a short deferred attribution queue.
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -14,7 +15,64 @@ use ts_rs::TS;
 use tauri::Emitter;
 
 use crate::config;
-use crate::core::process_mapper::ProcessMapper;
+use crate::core::process_mapper::{PortKey, ProcessMapper};
+
+/// Pseudo-PID for ICMP/ICMPv6/other-protocol traffic that no process owns
+/// (neighbour discovery, GRE/ESP tunnels, kernel pings). Real PIDs never get
+/// this high on Windows or Linux.
+pub const OTHER_PROTOCOLS_PID: u32 = u32::MAX;
+/// Pseudo-PID for TCP/UDP traffic whose local port no socket owns.
+pub const NO_PORT_MATCH_PID: u32 = u32::MAX - 1;
+/// Pseudo-PID for packets whose IP header could not be parsed.
+pub const UNPARSEABLE_PID: u32 = u32::MAX - 2;
+
+/// Why traffic could not be credited to a process. Each reason is tracked
+/// as its own pseudo-process so totals reconcile with the NIC counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub enum UnattributedReason {
+    /// TCP/UDP packet whose local port was not in the last scan: short-lived
+    /// connections, ports closed between scans, non-first fragments.
+    NoPortMatch,
+    /// Packet whose IP header could not be parsed (counted at captured length).
+    Unparseable,
+    /// ICMP/ICMPv6/other-protocol traffic without an owning socket.
+    NonTcpUdp,
+}
+
+impl UnattributedReason {
+    /// Pseudo-PID the reason's bytes are recorded under.
+    pub fn pid(self) -> u32 {
+        match self {
+            Self::NoPortMatch => NO_PORT_MATCH_PID,
+            Self::Unparseable => UNPARSEABLE_PID,
+            Self::NonTcpUdp => OTHER_PROTOCOLS_PID,
+        }
+    }
+
+    /// The reason behind a pseudo-PID, or `None` for real processes.
+    pub fn from_pid(pid: u32) -> Option<Self> {
+        match pid {
+            NO_PORT_MATCH_PID => Some(Self::NoPortMatch),
+            UNPARSEABLE_PID => Some(Self::Unparseable),
+            OTHER_PROTOCOLS_PID => Some(Self::NonTcpUdp),
+            _ => None,
+        }
+    }
+
+    pub fn display_name(self) -> &'static str {
+        match self {
+            Self::NoPortMatch => "Unattributed (no port match)",
+            Self::Unparseable => "Unattributed (unparseable)",
+            Self::NonTcpUdp => "Other protocols",
+        }
+    }
+}
+
+/// Display name for a pseudo-PID bucket, or `None` for real processes.
+pub fn pseudo_process_name(pid: u32) -> Option<&'static str> {
+    UnattributedReason::from_pid(pid).map(UnattributedReason::display_name)
+}
 
 /// Running byte counters for a single process.
 #[derive(Debug)]
@@ -63,17 +121,53 @@ pub struct ProcessTrafficSnapshot {
     pub bytes_recv: u64,
     /// Number of active connections (TCP + UDP).
     pub connection_count: u32,
+    /// Set for the synthetic unattributed buckets, `None` for real processes.
+    pub unattributed: Option<UnattributedReason>,
+}
+
+/// Bytes seen on a local port before the port map knew its owner.
+#[derive(Debug, Default)]
+struct PendingBytes {
+    sent: u64,
+    recv: u64,
+    /// Resolution passes this entry has survived.
+    ticks: u32,
+}
+
+/// Counters of the deferred attribution queue, serializable for the frontend.
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export, export_to = "../../src/bindings.ts")]
+pub struct DeferredAttributionStats {
+    /// (protocol, port) keys currently waiting for an owner.
+    pub pending_ports: u32,
+    /// Bytes currently waiting for an owner.
+    #[ts(type = "number")]
+    pub pending_bytes: u64,
+    /// Bytes credited to a process after a later scan resolved their port.
+    #[ts(type = "number")]
+    pub recovered_bytes: u64,
+    /// Bytes that never resolved (or overflowed the queue) and were counted
+    /// as unattributed.
+    #[ts(type = "number")]
+    pub expired_bytes: u64,
 }
 
 /// Thread-safe traffic tracker. Keyed by PID.
 pub struct TrafficTracker {
     counters: DashMap<u32, TrafficCounters>,
+    /// Deferred attribution queue, keyed by (protocol, local_addr, local_port).
+    pending: DashMap<PortKey, PendingBytes>,
+    recovered_bytes: AtomicU64,
+    expired_bytes: AtomicU64,
 }
 
 impl TrafficTracker {
     pub fn new() -> Self {
         Self {
             counters: DashMap::new(),
+            pending: DashMap::new(),
+            recovered_bytes: AtomicU64::new(0),
+            expired_bytes: AtomicU64::new(0),
         }
     }
 
@@ -92,6 +186,83 @@ impl TrafficTracker {
             });
     }
 
+    /// Record bytes that could not be credited to a process.
+    pub fn record_unattributed(&self, reason: UnattributedReason, sent: u64, recv: u64) {
+        self.record_bytes(reason.pid(), sent, recv);
+    }
+
+    /// Hold bytes for a local endpoint the process mapper does not know yet. New
+    /// connections often send their first packets before the next socket
+    /// table scan; [`Self::resolve_pending`] credits them once it does.
+    pub fn defer_bytes(&self, key: PortKey, sent: u64, recv: u64) {
+        if !self.pending.contains_key(&key)
+            && self.pending.len() >= config::PENDING_ATTRIBUTION_MAX_ENTRIES
+        {
+            self.expired_bytes
+                .fetch_add(sent.saturating_add(recv), Ordering::Relaxed);
+            self.record_unattributed(UnattributedReason::NoPortMatch, sent, recv);
+            return;
+        }
+        let mut entry = self.pending.entry(key).or_default();
+        entry.sent = entry.sent.saturating_add(sent);
+        entry.recv = entry.recv.saturating_add(recv);
+    }
+
+    /// Credit deferred bytes whose port the mapper now resolves. Entries still
+    /// unresolved after `PENDING_ATTRIBUTION_MAX_TICKS` passes are counted as
+    /// unattributed. Called once per aggregator tick.
+    pub fn resolve_pending(&self, mapper: &ProcessMapper) {
+        self.pending.retain(|&(proto, addr, port), entry| {
+            let total = entry.sent.saturating_add(entry.recv);
+            if let Some(pid) = mapper.lookup_pid(proto, addr, port) {
+                self.record_bytes(pid, entry.sent, entry.recv);
+                self.recovered_bytes.fetch_add(total, Ordering::Relaxed);
+                return false;
+            }
+            entry.ticks += 1;
+            if entry.ticks < config::PENDING_ATTRIBUTION_MAX_TICKS {
+                return true;
+            }
+            self.record_unattributed(UnattributedReason::NoPortMatch, entry.sent, entry.recv);
+            self.expired_bytes.fetch_add(total, Ordering::Relaxed);
+            false
+        });
+    }
+
+    /// Credit the bytes deferred for `key` to `pid`, found by an on-demand
+    /// socket lookup ahead of the next scan.
+    pub fn credit_pending(&self, key: PortKey, pid: u32) {
+        if let Some((_, entry)) = self.pending.remove(&key) {
+            self.record_bytes(pid, entry.sent, entry.recv);
+            self.recovered_bytes
+                .fetch_add(entry.sent.saturating_add(entry.recv), Ordering::Relaxed);
+        }
+    }
+
+    /// Count everything still waiting as unattributed (end of an offline replay).
+    pub fn expire_pending(&self) {
+        self.pending.retain(|_, entry| {
+            self.record_unattributed(UnattributedReason::NoPortMatch, entry.sent, entry.recv);
+            self.expired_bytes
+                .fetch_add(entry.sent.saturating_add(entry.recv), Ordering::Relaxed);
+            false
+        });
+    }
+
+    pub fn deferred_attribution_stats(&self) -> DeferredAttributionStats {
+        let pending_bytes = self
+            .pending
+            .iter()
+            .map(|e| e.sent.saturating_add(e.recv))
+            .sum();
+        DeferredAttributionStats {
+            pending_ports: self.pending.len() as u32,
+            pending_bytes,
+            recovered_bytes: self.recovered_bytes.load(Ordering::Relaxed),
+            expired_bytes: self.expired_bytes.load(Ordering::Relaxed),
+        }
+    }
+
     /// Update connection counts from the process mapper.
     pub fn update_connection_counts(&self, mapper: &ProcessMapper) {
         let counts = mapper.connection_counts();
@@ -103,7 +274,12 @@ impl TrafficTracker {
 
     /// Recalculate speeds for all tracked processes. Call once per second.
     pub fn tick_speeds(&self) {
-        let now = Instant::now();
+        self.tick_speeds_at(Instant::now());
+    }
+
+    /// Recalculate speeds as of `now`. Offline replay passes a virtual clock
+    /// derived from capture timestamps instead of the wall clock.
+    pub fn tick_speeds_at(&self, now: Instant) {
         for mut entry in self.counters.iter_mut() {
             let c = entry.value_mut();
             if let Some(last) = c.last_tick {
@@ -143,6 +319,7 @@ impl TrafficTracker {
                     name: info
                         .as_ref()
                         .map(|i| i.name.clone())
+                        .or_else(|| pseudo_process_name(pid).map(String::from))
                         .unwrap_or_else(|| format!("PID {pid}")),
                     exe_path: info
                         .as_ref()
@@ -153,6 +330,7 @@ impl TrafficTracker {
                     bytes_sent: c.bytes_sent,
                     bytes_recv: c.bytes_recv,
                     connection_count: c.connection_count,
+                    unattributed: UnattributedReason::from_pid(pid),
                 }
             })
             .collect()
@@ -183,6 +361,7 @@ impl TrafficTracker {
                         std::thread::sleep(step);
                         elapsed += step;
                     }
+                    tracker.resolve_pending(&process_mapper);
                     tracker.update_connection_counts(&process_mapper);
                     tracker.tick_speeds();
                     tracker.remove_stale(config::STALE_PROCESS_TIMEOUT_SECS);
@@ -206,7 +385,8 @@ impl Default for TrafficTracker {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::process_mapper::ProcessMapper;
+    use crate::core::process_mapper::{port_key, ProcessMapper, Protocol};
+    use std::net::Ipv4Addr;
     use std::thread;
     use std::time::Duration;
 
@@ -395,4 +575,134 @@ mod tests {
         );
         assert_eq!(entry.exe_path, "", "unknown PID should have empty exe_path");
     }
+
+    #[test]
+    fn test_snapshot_pseudo_process_name() {
+        let tracker = TrafficTracker::new();
+        let mapper = empty_mapper();
+        tracker.record_bytes(OTHER_PROTOCOLS_PID, 64, 0);
+
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap[0].name, "Other protocols");
+        assert_eq!(snap[0].exe_path, "");
+        assert_eq!(snap[0].unattributed, Some(UnattributedReason::NonTcpUdp));
+    }
+
+    #[test]
+    fn test_unattributed_reasons_round_trip_pids() {
+        let tracker = TrafficTracker::new();
+        let mapper = empty_mapper();
+        for reason in [
+            UnattributedReason::NoPortMatch,
+            UnattributedReason::Unparseable,
+            UnattributedReason::NonTcpUdp,
+        ] {
+            assert_eq!(UnattributedReason::from_pid(reason.pid()), Some(reason));
+            tracker.record_unattributed(reason, 1, 0);
+        }
+        tracker.record_bytes(1234, 1, 0);
+
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap.len(), 4);
+        let real = snap.iter().find(|s| s.pid == 1234).unwrap();
+        assert_eq!(real.unattributed, None);
+        assert_eq!(snap.iter().filter(|s| s.unattributed.is_some()).count(), 3);
+    }
+
+    #[test]
+    fn test_deferred_bytes_recovered_after_scan() {
+        let tracker = TrafficTracker::new();
+        let mapper = empty_mapper();
+        let key = port_key(Protocol::Tcp, Ipv4Addr::new(10, 0, 0, 2).into(), 5000);
+        tracker.defer_bytes(key, 100, 0);
+        tracker.defer_bytes(key, 0, 300);
+
+        // Not yet scanned: nothing credited.
+        tracker.resolve_pending(&mapper);
+        assert!(tracker.snapshot(&mapper).is_empty());
+        assert_eq!(tracker.deferred_attribution_stats().pending_bytes, 400);
+
+        // A wildcard listener resolves the address-specific entry.
+        mapper.port_map.insert(
+            port_key(Protocol::Tcp, Ipv4Addr::UNSPECIFIED.into(), 5000),
+            42,
+        );
+        tracker.resolve_pending(&mapper);
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap.len(), 1);
+        assert_eq!(
+            (snap[0].pid, snap[0].bytes_sent, snap[0].bytes_recv),
+            (42, 100, 300)
+        );
+
+        let stats = tracker.deferred_attribution_stats();
+        assert_eq!((stats.pending_ports, stats.pending_bytes), (0, 0));
+        assert_eq!((stats.recovered_bytes, stats.expired_bytes), (400, 0));
+
+        // An on-demand lookup credits its endpoint without waiting for a scan.
+        let other = port_key(Protocol::Udp, Ipv4Addr::new(10, 0, 0, 2).into(), 6000);
+        tracker.defer_bytes(other, 50, 0);
+        tracker.credit_pending(other, 43);
+        tracker.credit_pending(other, 44);
+        let credited = tracker.snapshot(&mapper);
+        assert_eq!(
+            credited.iter().find(|s| s.pid == 43).unwrap().bytes_sent,
+            50
+        );
+        assert!(credited.iter().all(|s| s.pid != 44));
+        assert_eq!(tracker.deferred_attribution_stats().recovered_bytes, 450);
+    }
+
+    #[test]
+    fn test_deferred_bytes_expire_to_unattributed() {
+        let tracker = TrafficTracker::new();
+        let mapper = empty_mapper();
+        tracker.defer_bytes(
+            port_key(Protocol::Udp, Ipv4Addr::UNSPECIFIED.into(), 6000),
+            50,
+            0,
+        );
+
+        for _ in 1..config::PENDING_ATTRIBUTION_MAX_TICKS {
+            tracker.resolve_pending(&mapper);
+        }
+        assert!(tracker.snapshot(&mapper).is_empty(), "still pending");
+        tracker.resolve_pending(&mapper);
+
+        let snap = tracker.snapshot(&mapper);
+        assert_eq!(snap.len(), 1);
+        assert_eq!(snap[0].unattributed, Some(UnattributedReason::NoPortMatch));
+        assert_eq!(snap[0].bytes_sent, 50);
+        let stats = tracker.deferred_attribution_stats();
+        assert_eq!((stats.recovered_bytes, stats.expired_bytes), (0, 50));
+    }
+
+    #[test]
+    fn test_deferred_queue_is_bounded() {
+        let tracker = TrafficTracker::new();
+        let mapper = empty_mapper();
+        let any = Ipv4Addr::UNSPECIFIED.into();
+        for port in 0..config::PENDING_ATTRIBUTION_MAX_ENTRIES as u16 {
+            tracker.defer_bytes(port_key(Protocol::Tcp, any, port), 1, 0);
+        }
+        // Known key still accumulates; a new key overflows straight to the bucket.
+        tracker.defer_bytes(port_key(Protocol::Tcp, any, 0), 1, 0);
+        tracker.defer_bytes(port_key(Protocol::Udp, any, 0), 10, 0);
+
+        let stats = tracker.deferred_attribution_stats();
+        assert_eq!(
+            stats.pending_ports as usize,
+            config::PENDING_ATTRIBUTION_MAX_ENTRIES
+        );
+        assert_eq!(stats.expired_bytes, 10);
+        assert_eq!(tracker.snapshot(&mapper)[0].bytes_sent, 10);
+
+        tracker.expire_pending();
+        let stats = tracker.deferred_attribution_stats();
+        assert_eq!(stats.pending_ports, 0);
+        assert_eq!(
+            stats.expired_bytes,
+            10 + config::PENDING_ATTRIBUTION_MAX_ENTRIES as u64 + 1
+        );
+    }
 }
@@ -3,9 +3,12 @@
 //! Wraps `GetExtendedTcpTable` / `GetExtendedUdpTable` from `iphlpapi.dll`
 //! for both IPv4 and IPv6.
 
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+
 use dashmap::DashMap;
 
-use crate::core::process_mapper::Protocol;
+use crate::core::process_mapper::{port_key, sync_port_map, PortKey, Protocol};
 
 pub const AF_INET: u32 = 2;
 pub const AF_INET6: u32 = 23;
@@ -59,6 +62,36 @@ pub struct MibUdp6RowOwnerPid {
     pub owning_pid: u32,
 }
 
+/// Decodes the local bind address of an IP helper row.
+trait LocalAddr {
+    fn local_ip(&self) -> IpAddr;
+}
+
+impl LocalAddr for MibTcpRowOwnerPid {
+    fn local_ip(&self) -> IpAddr {
+        // Stored in network byte order, so the in-memory bytes are the octets.
+        IpAddr::from(self.local_addr.to_ne_bytes())
+    }
+}
+
+impl LocalAddr for MibUdpRowOwnerPid {
+    fn local_ip(&self) -> IpAddr {
+        IpAddr::from(self.local_addr.to_ne_bytes())
+    }
+}
+
+impl LocalAddr for MibTcp6RowOwnerPid {
+    fn local_ip(&self) -> IpAddr {
+        Ipv6Addr::from(self.local_addr).into()
+    }
+}
+
+impl LocalAddr for MibUdp6RowOwnerPid {
+    fn local_ip(&self) -> IpAddr {
+        Ipv6Addr::from(self.local_addr).into()
+    }
+}
+
 #[link(name = "iphlpapi")]
 extern "system" {
     pub fn GetExtendedTcpTable(
@@ -81,59 +114,62 @@ extern "system" {
 }
 
 /// Scans an IP helper table (TCP or UDP, IPv4 or IPv6) and inserts
-/// `(protocol, local_port) → owning_pid` entries into `port_map`.
+/// `(protocol, local_addr, local_port) → owning_pid` entries into `port_map`.
+/// A table that cannot be read is skipped.
 ///
 /// Parameterized over: FFI function, address family, table class, row type,
 /// protocol variant, and a label for log messages.
 macro_rules! scan_table {
-    ($port_map:expr, $ffi_fn:ident, $af:expr, $table_class:expr, $row_ty:ty, $proto:expr, $label:expr) => {{
-        let mut size: u32 = 0;
-        let ret = unsafe { $ffi_fn(std::ptr::null_mut(), &mut size, 0, $af, $table_class, 0) };
-        if ret != ERROR_INSUFFICIENT_BUFFER {
-            return;
-        }
+    ($port_map:expr, $ffi_fn:ident, $af:expr, $table_class:expr, $row_ty:ty, $proto:expr, $label:expr) => {
+        'scan: {
+            let mut size: u32 = 0;
+            let ret = unsafe { $ffi_fn(std::ptr::null_mut(), &mut size, 0, $af, $table_class, 0) };
+            if ret != ERROR_INSUFFICIENT_BUFFER {
+                break 'scan;
+            }
 
-        let alloc_size = size as usize;
-        if alloc_size > MAX_TABLE_BUFFER {
-            tracing::warn!("{} requested {alloc_size} bytes, exceeds cap", $label);
-            return;
-        }
-        let mut buf = vec![0u8; alloc_size];
-        let ret = unsafe { $ffi_fn(buf.as_mut_ptr(), &mut size, 0, $af, $table_class, 0) };
-        if ret != NO_ERROR {
-            tracing::warn!("{} failed with code {ret}", $label);
-            return;
-        }
+            let alloc_size = size as usize;
+            if alloc_size > MAX_TABLE_BUFFER {
+                tracing::warn!("{} requested {alloc_size} bytes, exceeds cap", $label);
+                break 'scan;
+            }
+            let mut buf = vec![0u8; alloc_size];
+            let ret = unsafe { $ffi_fn(buf.as_mut_ptr(), &mut size, 0, $af, $table_class, 0) };
+            if ret != NO_ERROR {
+                tracing::warn!("{} failed with code {ret}", $label);
+                break 'scan;
+            }
 
-        if buf.len() < 4 {
-            return;
-        }
-        let row_size = std::mem::size_of::<$row_ty>();
-        let raw_entries = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
-        let num_entries = raw_entries.min(buf.len().saturating_sub(4) / row_size);
-
-        for i in 0..num_entries {
-            let offset = match 4_usize.checked_add(i.saturating_mul(row_size)) {
-                Some(o) => o,
-                None => break,
-            };
-            if offset.saturating_add(row_size) > buf.len() {
-                break;
+            if buf.len() < 4 {
+                break 'scan;
             }
-            let row = unsafe { &*(buf.as_ptr().add(offset) as *const $row_ty) };
-            let port = u16::from_be(row.local_port as u16);
-            if port > 0 && row.owning_pid > 0 {
-                $port_map.insert(($proto, port), row.owning_pid);
+            let row_size = std::mem::size_of::<$row_ty>();
+            let raw_entries = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
+            let num_entries = raw_entries.min(buf.len().saturating_sub(4) / row_size);
+
+            for i in 0..num_entries {
+                let offset = match 4_usize.checked_add(i.saturating_mul(row_size)) {
+                    Some(o) => o,
+                    None => break,
+                };
+                if offset.saturating_add(row_size) > buf.len() {
+                    break;
+                }
+                let row = unsafe { &*(buf.as_ptr().add(offset) as *const $row_ty) };
+                let port = u16::from_be(row.local_port as u16);
+                if port > 0 && row.owning_pid > 0 {
+                    $port_map.insert(port_key($proto, row.local_ip(), port), row.owning_pid);
+                }
             }
         }
-    }};
+    };
 }
 
-/// Scan all TCP and UDP tables (IPv4 + IPv6) and populate the port map.
-pub fn refresh_port_map(port_map: &DashMap<(Protocol, u16), u32>) {
-    port_map.clear();
+/// Scan all TCP and UDP tables (IPv4 + IPv6) and update the port map in place.
+pub fn refresh_port_map(port_map: &DashMap<PortKey, u32>) {
+    let mut fresh = HashMap::new();
     scan_table!(
-        port_map,
+        fresh,
         GetExtendedTcpTable,
         AF_INET,
         TCP_TABLE_OWNER_PID_ALL,
@@ -142,7 +178,7 @@ pub fn refresh_port_map(port_map: &DashMap<(Protocol, u16), u32>) {
         "GetExtendedTcpTable"
     );
     scan_table!(
-        port_map,
+        fresh,
         GetExtendedUdpTable,
         AF_INET,
         UDP_TABLE_OWNER_PID,
@@ -151,7 +187,7 @@ pub fn refresh_port_map(port_map: &DashMap<(Protocol, u16), u32>) {
         "GetExtendedUdpTable"
     );
     scan_table!(
-        port_map,
+        fresh,
         GetExtendedTcpTable,
         AF_INET6,
         TCP_TABLE_OWNER_PID_ALL,
@@ -160,7 +196,7 @@ pub fn refresh_port_map(port_map: &DashMap<(Protocol, u16), u32>) {
         "GetExtendedTcpTable(AF_INET6)"
     );
     scan_table!(
-        port_map,
+        fresh,
         GetExtendedUdpTable,
         AF_INET6,
         UDP_TABLE_OWNER_PID,
@@ -168,4 +204,5 @@ pub fn refresh_port_map(port_map: &DashMap<(Protocol, u16), u32>) {
         Protocol::Udp,
         "GetExtendedUdpTable(AF_INET6)"
     );
+    sync_port_map(port_map, fresh);
 }
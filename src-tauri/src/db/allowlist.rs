@@ -0,0 +1,79 @@
+//! Allowlist mode persistence: a single row with the mode and both lists.
+
+use anyhow::Result;
+use rusqlite::{params, OptionalExtension};
+
+use super::Database;
+use crate::core::allowlist::Allowlist;
+
+impl Database {
+    /// Save the allowlist and whether allowlist mode is on.
+    pub fn save_allowlist(&self, list: &Allowlist, enabled: bool) -> Result<()> {
+        let conn = self.conn.lock();
+        conn.execute(
+            "INSERT OR REPLACE INTO allowlist (id, enabled, allowed, always_allow)
+             VALUES (1, ?1, ?2, ?3)",
+            params![
+                enabled,
+                serde_json::to_string(&list.allowed)?,
+                serde_json::to_string(&list.always_allow)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Load the allowlist and whether allowlist mode is on. Until one is
+    /// saved, the mode is off and the list is [`Allowlist::default`].
+    pub fn load_allowlist(&self) -> Result<(Allowlist, bool)> {
+        let conn = self.conn.lock();
+        let row = conn
+            .query_row(
+                "SELECT enabled, allowed, always_allow FROM allowlist WHERE id = 1",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, bool>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((enabled, allowed, always_allow)) = row else {
+            return Ok((Allowlist::default(), false));
+        };
+        let list = Allowlist {
+            allowed: serde_json::from_str(&allowed)?,
+            always_allow: serde_json::from_str(&always_allow)?,
+        };
+        Ok((list, enabled))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::open_memory_db;
+    use super::*;
+    use crate::core::allowlist::AllowEntry;
+    use crate::core::rule_match::MatchType;
+
+    #[test]
+    fn test_allowlist_defaults_and_round_trip() {
+        let db = open_memory_db();
+        assert_eq!(db.load_allowlist().unwrap(), (Allowlist::default(), false));
+
+        let list = Allowlist {
+            allowed: vec![AllowEntry {
+                match_type: MatchType::PathGlob,
+                pattern: r"C:\Program Files\Mozilla Firefox\*.exe".into(),
+            }],
+            always_allow: Vec::new(),
+        };
+        db.save_allowlist(&list, true).unwrap();
+        assert_eq!(db.load_allowlist().unwrap(), (list.clone(), true));
+
+        // An emptied always-allow list stays empty rather than reverting.
+        db.save_allowlist(&list, false).unwrap();
+        assert_eq!(db.load_allowlist().unwrap(), (list, false));
+    }
+}
@@ -0,0 +1,121 @@
+//! App rule table CRUD operations.
+
+use anyhow::Result;
+use rusqlite::params;
+
+use super::Database;
+use crate::core::app_rules::{AppMatch, AppRule};
+
+impl Database {
+    /// Create or replace the rule of an application.
+    pub fn save_app_rule(&self, rule: &AppRule) -> Result<()> {
+        let conn = self.conn.lock();
+        conn.execute(
+            "INSERT OR REPLACE INTO app_rules (exe_path, args, sha256, process_name, action)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                rule.app.exe_path,
+                rule.app.args,
+                rule.app.sha256,
+                rule.process_name,
+                serde_json::to_string(&rule.action)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Delete the rule of an application. Returns the number of rows removed.
+    pub fn delete_app_rule(&self, exe_path: &str) -> Result<usize> {
+        let conn = self.conn.lock();
+        let deleted = conn.execute(
+            "DELETE FROM app_rules WHERE exe_path = ?1",
+            params![exe_path],
+        )?;
+        Ok(deleted)
+    }
+
+    /// Load all app rules.
+    pub fn load_app_rules(&self) -> Result<Vec<AppRule>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare_cached(
+            "SELECT exe_path, args, sha256, process_name, action
+             FROM app_rules ORDER BY exe_path",
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok(AppRule {
+                app: AppMatch {
+                    exe_path: row.get(0)?,
+                    args: row.get(1)?,
+                    sha256: row.get(2)?,
+                },
+                process_name: row.get(3)?,
+                action: serde_json::from_str(&row.get::<_, String>(4)?).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        4,
+                        rusqlite::types::Type::Text,
+                        Box::new(e),
+                    )
+                })?,
+            })
+        })?;
+
+        let mut results = Vec::new();
+        for row in rows {
+            results.push(row?);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::open_memory_db;
+    use super::*;
+    use crate::core::app_rules::AppAction;
+    use crate::core::BandwidthLimit;
+
+    #[test]
+    fn test_save_load_and_delete_app_rules() {
+        let db = open_memory_db();
+        let limited = AppRule {
+            app: AppMatch {
+                exe_path: "C:\\sync.exe".into(),
+                args: Some("--background".into()),
+                sha256: None,
+            },
+            process_name: "sync.exe".into(),
+            action: AppAction::Limit(BandwidthLimit {
+                upload_bps: 50_000,
+                ..Default::default()
+            }),
+        };
+        let blocked = AppRule {
+            app: AppMatch {
+                exe_path: "C:\\ads.exe".into(),
+                args: None,
+                sha256: Some("ab".repeat(32)),
+            },
+            process_name: "ads.exe".into(),
+            action: AppAction::Block,
+        };
+        db.save_app_rule(&limited).unwrap();
+        db.save_app_rule(&blocked).unwrap();
+        assert_eq!(
+            db.load_app_rules().unwrap(),
+            vec![blocked.clone(), limited.clone()]
+        );
+
+        // Saving again replaces the rule of the same executable.
+        let unblocked = AppRule {
+            action: AppAction::Limit(BandwidthLimit::default()),
+            ..blocked
+        };
+        db.save_app_rule(&unblocked).unwrap();
+        assert_eq!(db.load_app_rules().unwrap()[0], unblocked);
+
+        assert_eq!(db.delete_app_rule("C:\\ads.exe").unwrap(), 1);
+        assert_eq!(db.delete_app_rule("C:\\ads.exe").unwrap(), 0);
+        assert_eq!(db.load_app_rules().unwrap(), vec![limited]);
+    }
+}
@@ -4,6 +4,7 @@ use anyhow::Result;
 use rusqlite::params;
 
 use super::{chrono_timestamp, Database, TrafficRecord, TrafficSummary};
+use crate::core::traffic::UnattributedReason;
 
 impl Database {
     /// Insert a batch of traffic snapshots (called every 5 seconds).
@@ -138,15 +139,17 @@ impl Database {
 }
 
 fn map_traffic_row(row: &rusqlite::Row) -> rusqlite::Result<TrafficRecord> {
+    let pid = row.get(1)?;
     Ok(TrafficRecord {
         timestamp: row.get(0)?,
-        pid: row.get(1)?,
+        pid,
         process_name: row.get(2)?,
         exe_path: row.get(3)?,
         bytes_sent: row.get(4)?,
         bytes_recv: row.get(5)?,
         upload_speed: row.get(6)?,
         download_speed: row.get(7)?,
+        unattributed: UnattributedReason::from_pid(pid),
     })
 }
 
@@ -173,6 +176,22 @@ mod tests {
         assert_eq!(results[1].bytes_sent, 150);
     }
 
+    #[test]
+    fn test_query_history_marks_unattributed_rows() {
+        let db = open_memory_db();
+        let reason = UnattributedReason::NoPortMatch;
+        db.insert_traffic_batch(&[
+            make_record(1000, 1, "chrome.exe", "C:\\chrome.exe", 100, 200),
+            make_record(1000, reason.pid(), reason.display_name(), "", 10, 20),
+        ])
+        .unwrap();
+
+        let results = db.query_history(0, 2000, None).unwrap();
+        assert_eq!(results[0].unattributed, None);
+        assert_eq!(results[1].unattributed, Some(reason));
+        assert_eq!(results[1].process_name, "Unattributed (no port match)");
+    }
+
     #[test]
     fn test_query_history_with_process_filter() {
         let db = open_memory_db();
@@ -3,9 +3,15 @@
 //! Uses `rusqlite` with bundled SQLite. Handles:
 //! - Per-process traffic history (5-second granularity)
 //! - Bandwidth rule profiles
+//! - Data quotas and their usage in the current period
+//! - App rules (limits and blocks keyed by executable)
+//! - The allowlist mode setting and its lists
 //! - Auto-pruning of data older than 90 days
 
+mod allowlist;
+mod app_rules;
 mod history;
+mod quotas;
 mod rules;
 
 use parking_lot::Mutex;
@@ -13,9 +19,14 @@ use std::path::Path;
 
 use anyhow::Result;
 use rusqlite::Connection;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
+use crate::core::rate_limiter::{BandwidthLimit, LimitMode, PriorityClass};
+use crate::core::rule_match::{MatchType, RuleSet, RuleSpec};
+use crate::core::schedule::{self, LocalClock, ScheduleWindow, Weekday};
+use crate::core::traffic::UnattributedReason;
+
 /// Manages the SQLite database for traffic history.
 pub struct Database {
     pub(super) conn: Mutex<Connection>,
@@ -36,6 +47,8 @@ pub struct TrafficRecord {
     pub bytes_recv: u64,
     pub upload_speed: f64,
     pub download_speed: f64,
+    /// Set for unattributed-traffic buckets. Derived from `pid`, not stored.
+    pub unattributed: Option<UnattributedReason>,
 }
 
 /// Summary of a process's total traffic over a time window.
@@ -53,16 +66,79 @@ pub struct TrafficSummary {
 }
 
 /// A saved bandwidth rule from the database.
-#[derive(Debug, Clone, Serialize, TS)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[ts(export, export_to = "../../src/bindings.ts")]
 pub struct SavedRule {
+    /// Row id of the rule (0 for a rule that is not saved yet).
+    #[serde(default)]
+    #[ts(type = "number")]
+    pub id: i64,
+    /// Executable path, or the glob or regex pattern given by `match_type`.
     pub exe_path: String,
+    /// How `exe_path` is matched against processes.
+    #[serde(default)]
+    pub match_type: MatchType,
+    /// Explicit priority among rules matching the same process; a higher
+    /// value wins regardless of how specific the rules are.
+    #[serde(default)]
+    pub precedence: i32,
     pub process_name: String,
     #[ts(type = "number")]
     pub download_bps: u64,
     #[ts(type = "number")]
     pub upload_bps: u64,
     pub blocked: bool,
+    pub mode: LimitMode,
+    #[ts(type = "number")]
+    pub guaranteed_download_bps: u64,
+    #[ts(type = "number")]
+    pub guaranteed_upload_bps: u64,
+    pub priority: PriorityClass,
+    pub weight: u32,
+    #[ts(type = "number")]
+    pub burst_bytes: u64,
+    #[ts(type = "number")]
+    pub burst_ms: u64,
+    /// Windows in which the rule is in force (empty = always).
+    #[serde(default)]
+    pub schedule: Vec<ScheduleWindow>,
+}
+
+impl SavedRule {
+    /// True if the rule is in force at `clock`.
+    pub fn is_active(&self, clock: &LocalClock) -> bool {
+        schedule::is_active(&self.schedule, clock)
+    }
+
+    /// The fields that decide which of several matching rules wins.
+    pub fn spec(&self) -> RuleSpec<'_> {
+        RuleSpec {
+            match_type: self.match_type,
+            pattern: &self.exe_path,
+            precedence: self.precedence,
+            blocked: self.blocked,
+        }
+    }
+
+    /// Compile rules for evaluation; results refer to positions in `rules`.
+    pub fn rule_set(rules: &[SavedRule]) -> RuleSet {
+        RuleSet::new(rules.iter().map(SavedRule::spec))
+    }
+
+    /// The bandwidth limit this rule applies.
+    pub fn limit(&self) -> BandwidthLimit {
+        BandwidthLimit {
+            download_bps: self.download_bps,
+            upload_bps: self.upload_bps,
+            mode: self.mode,
+            guaranteed_download_bps: self.guaranteed_download_bps,
+            guaranteed_upload_bps: self.guaranteed_upload_bps,
+            priority: self.priority,
+            weight: self.weight,
+            burst_bytes: self.burst_bytes,
+            burst_ms: self.burst_ms,
+        }
+    }
 }
 
 impl Database {
@@ -94,11 +170,64 @@ impl Database {
                 download_bps INTEGER NOT NULL DEFAULT 0,
                 upload_bps INTEGER NOT NULL DEFAULT 0,
                 blocked INTEGER NOT NULL DEFAULT 0,
+                limit_mode TEXT NOT NULL DEFAULT 'police',
+                guaranteed_download_bps INTEGER NOT NULL DEFAULT 0,
+                guaranteed_upload_bps INTEGER NOT NULL DEFAULT 0,
+                priority TEXT NOT NULL DEFAULT 'normal',
+                weight INTEGER NOT NULL DEFAULT 1,
+                burst_bytes INTEGER NOT NULL DEFAULT 0,
+                burst_ms INTEGER NOT NULL DEFAULT 0,
+                schedule TEXT NOT NULL DEFAULT '',
+                match_type TEXT NOT NULL DEFAULT 'exact',
+                precedence INTEGER NOT NULL DEFAULT 0,
                 UNIQUE(profile_name, exe_path)
             );
+
+            CREATE TABLE IF NOT EXISTS quotas (
+                exe_path TEXT NOT NULL,
+                period TEXT NOT NULL,
+                process_name TEXT NOT NULL DEFAULT '',
+                limit_bytes INTEGER NOT NULL,
+                action TEXT NOT NULL DEFAULT 'notify',
+                throttle_bps INTEGER NOT NULL DEFAULT 0,
+                used_bytes INTEGER NOT NULL DEFAULT 0,
+                period_key TEXT NOT NULL DEFAULT '',
+                PRIMARY KEY(exe_path, period)
+            );
+
+            CREATE TABLE IF NOT EXISTS app_rules (
+                exe_path TEXT PRIMARY KEY,
+                args TEXT,
+                sha256 TEXT,
+                process_name TEXT NOT NULL DEFAULT '',
+                action TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS allowlist (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                enabled INTEGER NOT NULL DEFAULT 0,
+                allowed TEXT NOT NULL,
+                always_allow TEXT NOT NULL
+            );
             ",
         )?;
 
+        // Columns added after the first release.
+        for (column, definition) in [
+            ("limit_mode", "TEXT NOT NULL DEFAULT 'police'"),
+            ("guaranteed_download_bps", "INTEGER NOT NULL DEFAULT 0"),
+            ("guaranteed_upload_bps", "INTEGER NOT NULL DEFAULT 0"),
+            ("priority", "TEXT NOT NULL DEFAULT 'normal'"),
+            ("weight", "INTEGER NOT NULL DEFAULT 1"),
+            ("burst_bytes", "INTEGER NOT NULL DEFAULT 0"),
+            ("burst_ms", "INTEGER NOT NULL DEFAULT 0"),
+            ("schedule", "TEXT NOT NULL DEFAULT ''"),
+            ("match_type", "TEXT NOT NULL DEFAULT 'exact'"),
+            ("precedence", "INTEGER NOT NULL DEFAULT 0"),
+        ] {
+            ensure_column(&conn, "bandwidth_rules", column, definition)?;
+        }
+
         // Enable WAL mode for better concurrent read performance.
         conn.execute_batch("PRAGMA journal_mode=WAL;")?;
 
@@ -106,6 +235,43 @@ impl Database {
             conn: Mutex::new(conn),
         })
     }
+
+    /// The current local date, weekday and time of day, which decide rule
+    /// schedules and quota periods.
+    pub fn local_clock(&self) -> Result<LocalClock> {
+        let conn = self.conn.lock();
+        let (date, weekday, minute) = conn.query_row(
+            "SELECT date('now', 'localtime'),
+                    CAST(strftime('%w', 'now', 'localtime') AS INTEGER),
+                    CAST(strftime('%H', 'now', 'localtime') AS INTEGER) * 60
+                        + CAST(strftime('%M', 'now', 'localtime') AS INTEGER)",
+            [],
+            |row| Ok((row.get(0)?, row.get::<_, u32>(1)?, row.get(2)?)),
+        )?;
+        let weekday = Weekday::from_sunday_index(weekday)
+            .ok_or_else(|| anyhow::anyhow!("invalid weekday {weekday}"))?;
+        Ok(LocalClock {
+            date,
+            weekday,
+            minute,
+        })
+    }
+}
+
+/// Add `column` to `table` if a database created by an older version lacks it.
+fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+        ))?;
+    }
+    Ok(())
 }
 
 /// Current Unix timestamp in seconds.
@@ -144,6 +310,7 @@ mod tests {
             bytes_recv,
             upload_speed: bytes_sent as f64,
             download_speed: bytes_recv as f64,
+            unattributed: UnattributedReason::from_pid(pid),
         }
     }
 
@@ -156,4 +323,38 @@ mod tests {
         let rules = db.load_rules("default");
         assert!(rules.is_ok());
     }
+
+    #[test]
+    fn test_local_clock() {
+        let clock = open_memory_db().local_clock().unwrap();
+        assert_eq!(clock.date.len(), 10);
+        assert_eq!(&clock.date[4..5], "-");
+        assert!(clock.minute < schedule::MINUTES_PER_DAY);
+    }
+
+    #[test]
+    fn test_ensure_column_migrates_old_rules_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE bandwidth_rules (exe_path TEXT NOT NULL);
+             INSERT INTO bandwidth_rules (exe_path) VALUES ('C:\\app.exe');",
+        )
+        .unwrap();
+
+        for _ in 0..2 {
+            ensure_column(
+                &conn,
+                "bandwidth_rules",
+                "limit_mode",
+                "TEXT NOT NULL DEFAULT 'police'",
+            )
+            .unwrap();
+        }
+        let mode: String = conn
+            .query_row("SELECT limit_mode FROM bandwidth_rules", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(mode, "police");
+    }
 }
@@ -0,0 +1,168 @@
+//! Data quota table CRUD operations and usage persistence.
+
+use anyhow::Result;
+use rusqlite::params;
+
+use super::Database;
+use crate::core::quota::{Quota, QuotaAction, QuotaPeriod, QuotaStatus};
+
+impl Database {
+    /// Create or update a quota definition. Usage of an existing quota is kept.
+    pub fn save_quota(&self, quota: &Quota) -> Result<()> {
+        let conn = self.conn.lock();
+        conn.execute(
+            "INSERT INTO quotas (exe_path, period, process_name, limit_bytes, action, throttle_bps)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(exe_path, period) DO UPDATE SET
+                process_name = excluded.process_name,
+                limit_bytes = excluded.limit_bytes,
+                action = excluded.action,
+                throttle_bps = excluded.throttle_bps",
+            params![
+                quota.exe_path,
+                quota.period.as_str(),
+                quota.process_name,
+                quota.limit_bytes,
+                quota.action.as_str(),
+                quota.throttle_bps
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a quota. Returns the number of rows removed.
+    pub fn delete_quota(&self, exe_path: &str, period: QuotaPeriod) -> Result<usize> {
+        let conn = self.conn.lock();
+        let deleted = conn.execute(
+            "DELETE FROM quotas WHERE exe_path = ?1 AND period = ?2",
+            params![exe_path, period.as_str()],
+        )?;
+        Ok(deleted)
+    }
+
+    /// Load all quotas with their stored usage.
+    pub fn load_quotas(&self) -> Result<Vec<QuotaStatus>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare_cached(
+            "SELECT exe_path, period, process_name, limit_bytes, action, throttle_bps,
+                    used_bytes, period_key
+             FROM quotas ORDER BY exe_path, period",
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok(QuotaStatus {
+                quota: Quota {
+                    exe_path: row.get(0)?,
+                    period: QuotaPeriod::from_db(&row.get::<_, String>(1)?),
+                    process_name: row.get(2)?,
+                    limit_bytes: row.get(3)?,
+                    action: QuotaAction::from_db(&row.get::<_, String>(4)?),
+                    throttle_bps: row.get(5)?,
+                },
+                used_bytes: row.get(6)?,
+                period_key: row.get(7)?,
+            })
+        })?;
+
+        let mut results = Vec::new();
+        for row in rows {
+            results.push(row?);
+        }
+        Ok(results)
+    }
+
+    /// Store the current usage of each quota. Quotas deleted in the meantime
+    /// are skipped.
+    pub fn save_quota_usage(&self, statuses: &[QuotaStatus]) -> Result<()> {
+        let conn = self.conn.lock();
+        conn.execute_batch("BEGIN")?;
+        let result = (|| {
+            let mut stmt = conn.prepare_cached(
+                "UPDATE quotas SET used_bytes = ?3, period_key = ?4
+                 WHERE exe_path = ?1 AND period = ?2",
+            )?;
+            for s in statuses {
+                stmt.execute(params![
+                    s.quota.exe_path,
+                    s.quota.period.as_str(),
+                    s.used_bytes,
+                    s.period_key,
+                ])?;
+            }
+            Ok(())
+        })();
+        match &result {
+            Ok(()) => conn.execute_batch("COMMIT")?,
+            Err(_) => {
+                let _ = conn.execute_batch("ROLLBACK");
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::open_memory_db;
+    use super::*;
+
+    fn quota(exe_path: &str, period: QuotaPeriod, limit_bytes: u64) -> Quota {
+        Quota {
+            exe_path: exe_path.to_string(),
+            process_name: "app".to_string(),
+            period,
+            limit_bytes,
+            action: QuotaAction::Throttle,
+            throttle_bps: 50_000,
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_quotas() {
+        let db = open_memory_db();
+        db.save_quota(&quota("C:\\app.exe", QuotaPeriod::Daily, 1_000))
+            .unwrap();
+        db.save_quota(&quota("C:\\app.exe", QuotaPeriod::Monthly, 30_000))
+            .unwrap();
+
+        let quotas = db.load_quotas().unwrap();
+        assert_eq!(quotas.len(), 2);
+        assert_eq!(
+            quotas[0].quota,
+            quota("C:\\app.exe", QuotaPeriod::Daily, 1_000)
+        );
+        assert_eq!(quotas[0].used_bytes, 0);
+        assert_eq!(quotas[0].period_key, "");
+        assert_eq!(quotas[1].quota.period, QuotaPeriod::Monthly);
+
+        assert_eq!(
+            db.delete_quota("C:\\app.exe", QuotaPeriod::Daily).unwrap(),
+            1
+        );
+        assert_eq!(
+            db.delete_quota("C:\\app.exe", QuotaPeriod::Daily).unwrap(),
+            0
+        );
+        assert_eq!(db.load_quotas().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_usage_is_kept_when_quota_is_updated() {
+        let db = open_memory_db();
+        db.save_quota(&quota("C:\\app.exe", QuotaPeriod::Daily, 1_000))
+            .unwrap();
+        db.save_quota_usage(&[QuotaStatus {
+            quota: quota("C:\\app.exe", QuotaPeriod::Daily, 1_000),
+            used_bytes: 700,
+            period_key: "2026-03-04".into(),
+        }])
+        .unwrap();
+
+        db.save_quota(&quota("C:\\app.exe", QuotaPeriod::Daily, 5_000))
+            .unwrap();
+        let quotas = db.load_quotas().unwrap();
+        assert_eq!(quotas[0].quota.limit_bytes, 5_000);
+        assert_eq!(quotas[0].used_bytes, 700);
+        assert_eq!(quotas[0].period_key, "2026-03-04");
+    }
+}
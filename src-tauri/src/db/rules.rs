@@ -4,42 +4,120 @@ use anyhow::Result;
 use rusqlite::params;
 
 use super::{Database, SavedRule};
+use crate::core::rate_limiter::{LimitMode, PriorityClass};
+use crate::core::rule_match::MatchType;
+use crate::core::schedule::ScheduleWindow;
 
 impl Database {
-    /// Save a bandwidth rule to a profile.
-    pub fn save_rule(
+    /// Save a bandwidth rule to a profile. A rule that already exists keeps
+    /// its schedule and precedence; change them with
+    /// [`Database::set_rule_schedule`] and [`Database::set_rule_precedence`].
+    pub fn save_rule(&self, profile: &str, rule: &SavedRule) -> Result<()> {
+        let conn = self.conn.lock();
+        conn.execute(
+            "INSERT INTO bandwidth_rules (profile_name, exe_path, process_name, download_bps, upload_bps, blocked, limit_mode, guaranteed_download_bps, guaranteed_upload_bps, priority, weight, burst_bytes, burst_ms, schedule, match_type, precedence)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)
+             ON CONFLICT(profile_name, exe_path) DO UPDATE SET
+                match_type = excluded.match_type,
+                process_name = excluded.process_name,
+                download_bps = excluded.download_bps,
+                upload_bps = excluded.upload_bps,
+                blocked = excluded.blocked,
+                limit_mode = excluded.limit_mode,
+                guaranteed_download_bps = excluded.guaranteed_download_bps,
+                guaranteed_upload_bps = excluded.guaranteed_upload_bps,
+                priority = excluded.priority,
+                weight = excluded.weight,
+                burst_bytes = excluded.burst_bytes,
+                burst_ms = excluded.burst_ms",
+            params![
+                profile,
+                rule.exe_path,
+                rule.process_name,
+                rule.download_bps,
+                rule.upload_bps,
+                rule.blocked as i32,
+                rule.mode.as_str(),
+                rule.guaranteed_download_bps,
+                rule.guaranteed_upload_bps,
+                rule.priority.as_str(),
+                rule.weight,
+                rule.burst_bytes,
+                rule.burst_ms,
+                schedule_to_db(&rule.schedule)?,
+                rule.match_type.as_str(),
+                rule.precedence
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Replace the schedule of a rule. Returns the number of rules updated
+    /// (0 if the profile has no rule for `exe_path`).
+    pub fn set_rule_schedule(
         &self,
         profile: &str,
         exe_path: &str,
-        process_name: &str,
-        download_bps: u64,
-        upload_bps: u64,
-        blocked: bool,
-    ) -> Result<()> {
+        schedule: &[ScheduleWindow],
+    ) -> Result<usize> {
         let conn = self.conn.lock();
-        conn.execute(
-            "INSERT OR REPLACE INTO bandwidth_rules (profile_name, exe_path, process_name, download_bps, upload_bps, blocked)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![profile, exe_path, process_name, download_bps, upload_bps, blocked as i32],
+        let updated = conn.execute(
+            "UPDATE bandwidth_rules SET schedule = ?3 WHERE profile_name = ?1 AND exe_path = ?2",
+            params![profile, exe_path, schedule_to_db(schedule)?],
         )?;
-        Ok(())
+        Ok(updated)
     }
 
-    /// Load all rules for a profile.
+    /// Replace the precedence of a rule. Returns the number of rules updated
+    /// (0 if the profile has no rule for `exe_path`).
+    pub fn set_rule_precedence(
+        &self,
+        profile: &str,
+        exe_path: &str,
+        precedence: i32,
+    ) -> Result<usize> {
+        let conn = self.conn.lock();
+        let updated = conn.execute(
+            "UPDATE bandwidth_rules SET precedence = ?3 WHERE profile_name = ?1 AND exe_path = ?2",
+            params![profile, exe_path, precedence],
+        )?;
+        Ok(updated)
+    }
+
+    /// Load all rules for a profile, oldest first.
     pub fn load_rules(&self, profile: &str) -> Result<Vec<SavedRule>> {
         let conn = self.conn.lock();
         let mut stmt = conn.prepare_cached(
-            "SELECT exe_path, process_name, download_bps, upload_bps, blocked
-             FROM bandwidth_rules WHERE profile_name = ?1",
+            "SELECT exe_path, process_name, download_bps, upload_bps, blocked, limit_mode,
+                    guaranteed_download_bps, guaranteed_upload_bps, priority, weight,
+                    burst_bytes, burst_ms, schedule, match_type, precedence, id
+             FROM bandwidth_rules WHERE profile_name = ?1 ORDER BY id",
         )?;
 
         let rows = stmt.query_map(params![profile], |row| {
             Ok(SavedRule {
+                id: row.get(15)?,
                 exe_path: row.get(0)?,
+                match_type: MatchType::from_db(&row.get::<_, String>(13)?),
+                precedence: row.get(14)?,
                 process_name: row.get(1)?,
                 download_bps: row.get(2)?,
                 upload_bps: row.get(3)?,
                 blocked: row.get::<_, i32>(4)? != 0,
+                mode: LimitMode::from_db(&row.get::<_, String>(5)?),
+                guaranteed_download_bps: row.get(6)?,
+                guaranteed_upload_bps: row.get(7)?,
+                priority: PriorityClass::from_db(&row.get::<_, String>(8)?),
+                weight: row.get(9)?,
+                burst_bytes: row.get(10)?,
+                burst_ms: row.get(11)?,
+                schedule: schedule_from_db(&row.get::<_, String>(12)?).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        12,
+                        rusqlite::types::Type::Text,
+                        Box::new(e),
+                    )
+                })?,
             })
         })?;
 
@@ -64,6 +142,16 @@ impl Database {
         Ok(results)
     }
 
+    /// Delete one rule of a profile. Returns the number of rules removed.
+    pub fn delete_rule(&self, profile: &str, exe_path: &str) -> Result<usize> {
+        let conn = self.conn.lock();
+        let deleted = conn.execute(
+            "DELETE FROM bandwidth_rules WHERE profile_name = ?1 AND exe_path = ?2",
+            params![profile, exe_path],
+        )?;
+        Ok(deleted)
+    }
+
     /// Delete an entire profile and all its rules.
     pub fn delete_profile(&self, profile: &str) -> Result<usize> {
         let conn = self.conn.lock();
@@ -75,9 +163,46 @@ impl Database {
     }
 }
 
+/// Encode a schedule for the `schedule` column: JSON, or empty for none.
+fn schedule_to_db(schedule: &[ScheduleWindow]) -> Result<String> {
+    if schedule.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(serde_json::to_string(schedule)?)
+}
+
+fn schedule_from_db(value: &str) -> serde_json::Result<Vec<ScheduleWindow>> {
+    if value.is_empty() {
+        return Ok(Vec::new());
+    }
+    serde_json::from_str(value)
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::tests::open_memory_db;
+    use super::*;
+
+    fn rule(exe_path: &str, name: &str, dl: u64, ul: u64, blocked: bool) -> SavedRule {
+        SavedRule {
+            id: 0,
+            exe_path: exe_path.to_string(),
+            match_type: MatchType::Exact,
+            precedence: 0,
+            process_name: name.to_string(),
+            download_bps: dl,
+            upload_bps: ul,
+            blocked,
+            mode: LimitMode::Police,
+            guaranteed_download_bps: 0,
+            guaranteed_upload_bps: 0,
+            priority: PriorityClass::Normal,
+            weight: 1,
+            burst_bytes: 0,
+            burst_ms: 0,
+            schedule: Vec::new(),
+        }
+    }
 
     #[test]
     fn test_save_and_load_rules() {
@@ -85,20 +210,12 @@ mod tests {
 
         db.save_rule(
             "default",
-            "C:\\chrome.exe",
-            "chrome.exe",
-            1_000_000,
-            500_000,
-            false,
+            &rule("C:\\chrome.exe", "chrome.exe", 1_000_000, 500_000, false),
         )
         .unwrap();
         db.save_rule(
             "default",
-            "C:\\firefox.exe",
-            "firefox.exe",
-            2_000_000,
-            1_000_000,
-            true,
+            &rule("C:\\firefox.exe", "firefox.exe", 2_000_000, 1_000_000, true),
         )
         .unwrap();
 
@@ -134,11 +251,11 @@ mod tests {
     fn test_list_profiles() {
         let db = open_memory_db();
 
-        db.save_rule("gaming", "C:\\game.exe", "game.exe", 0, 0, false)
+        db.save_rule("gaming", &rule("C:\\game.exe", "game.exe", 0, 0, false))
             .unwrap();
-        db.save_rule("work", "C:\\slack.exe", "slack.exe", 0, 0, false)
+        db.save_rule("work", &rule("C:\\slack.exe", "slack.exe", 0, 0, false))
             .unwrap();
-        db.save_rule("gaming", "C:\\steam.exe", "steam.exe", 0, 0, false)
+        db.save_rule("gaming", &rule("C:\\steam.exe", "steam.exe", 0, 0, false))
             .unwrap();
 
         let profiles = db.list_profiles().unwrap();
@@ -152,9 +269,9 @@ mod tests {
     fn test_delete_profile() {
         let db = open_memory_db();
 
-        db.save_rule("temp", "C:\\app.exe", "app.exe", 100, 200, false)
+        db.save_rule("temp", &rule("C:\\app.exe", "app.exe", 100, 200, false))
             .unwrap();
-        db.save_rule("temp", "C:\\other.exe", "other.exe", 300, 400, true)
+        db.save_rule("temp", &rule("C:\\other.exe", "other.exe", 300, 400, true))
             .unwrap();
 
         // Verify the profile exists.
@@ -181,26 +298,18 @@ mod tests {
         // Save a rule.
         db.save_rule(
             "default",
-            "C:\\chrome.exe",
-            "chrome.exe",
-            1_000_000,
-            500_000,
-            false,
+            &rule("C:\\chrome.exe", "chrome.exe", 1_000_000, 500_000, false),
         )
         .unwrap();
 
         // Save again with updated values for the same profile + exe_path.
         db.save_rule(
             "default",
-            "C:\\chrome.exe",
-            "chrome.exe",
-            2_000_000,
-            750_000,
-            true,
+            &rule("C:\\chrome.exe", "chrome.exe", 2_000_000, 750_000, true),
         )
         .unwrap();
 
-        // Should still be one rule, not two (UNIQUE constraint + INSERT OR REPLACE).
+        // Should still be one rule, not two (UNIQUE constraint + upsert).
         let rules = db.load_rules("default").unwrap();
         assert_eq!(rules.len(), 1);
 
@@ -210,4 +319,130 @@ mod tests {
         assert_eq!(rule.upload_bps, 750_000);
         assert!(rule.blocked);
     }
+
+    #[test]
+    fn test_rule_shaping_fields_round_trip() {
+        let db = open_memory_db();
+        let shaped = SavedRule {
+            mode: LimitMode::ShapeCodel,
+            guaranteed_upload_bps: 100_000,
+            priority: PriorityClass::High,
+            weight: 5,
+            burst_ms: 250,
+            ..rule("C:\\zoom.exe", "zoom.exe", 0, 250_000, false)
+        };
+        db.save_rule("default", &shaped).unwrap();
+        db.save_rule("default", &rule("C:\\app.exe", "app.exe", 1, 1, false))
+            .unwrap();
+
+        let rules = db.load_rules("default").unwrap();
+        let find = |exe: &str| rules.iter().find(|r| r.exe_path == exe).unwrap();
+        assert_eq!(find("C:\\zoom.exe").mode, LimitMode::ShapeCodel);
+        assert_eq!(find("C:\\zoom.exe").guaranteed_upload_bps, 100_000);
+        assert_eq!(find("C:\\zoom.exe").priority, PriorityClass::High);
+        assert_eq!(find("C:\\zoom.exe").weight, 5);
+        assert_eq!(find("C:\\zoom.exe").burst_ms, 250);
+        assert_eq!(find("C:\\app.exe").mode, LimitMode::Police);
+        assert_eq!(find("C:\\app.exe").guaranteed_upload_bps, 0);
+        assert_eq!(find("C:\\app.exe").priority, PriorityClass::Normal);
+        assert_eq!(find("C:\\app.exe").burst_bytes, 0);
+    }
+
+    #[test]
+    fn test_pattern_rule_round_trip_and_delete() {
+        let db = open_memory_db();
+        let pattern = SavedRule {
+            match_type: MatchType::PathGlob,
+            ..rule(r"C:\Apps\app-*\app.exe", "app.exe", 0, 0, true)
+        };
+        db.save_rule("default", &pattern).unwrap();
+        db.save_rule("default", &rule("C:\\other.exe", "other.exe", 1, 1, false))
+            .unwrap();
+
+        let rules = db.load_rules("default").unwrap();
+        let loaded = rules.iter().find(|r| r.blocked).unwrap();
+        assert_eq!(loaded.exe_path, pattern.exe_path);
+        assert_eq!(loaded.match_type, MatchType::PathGlob);
+        assert_eq!(
+            rules.iter().find(|r| !r.blocked).unwrap().match_type,
+            MatchType::Exact
+        );
+
+        assert_eq!(db.delete_rule("default", &pattern.exe_path).unwrap(), 1);
+        assert_eq!(db.delete_rule("default", &pattern.exe_path).unwrap(), 0);
+        assert_eq!(db.load_rules("default").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_rule_schedule_is_kept_on_resave() {
+        use crate::core::schedule::Weekday;
+
+        let db = open_memory_db();
+        let window = ScheduleWindow {
+            days: vec![Weekday::Mon, Weekday::Fri],
+            start_minute: 9 * 60,
+            end_minute: 18 * 60,
+        };
+        db.save_rule(
+            "work",
+            &rule("C:\\steam.exe", "steam.exe", 1_000_000, 0, false),
+        )
+        .unwrap();
+        assert_eq!(
+            db.set_rule_schedule("work", "C:\\steam.exe", std::slice::from_ref(&window))
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            db.set_rule_schedule("work", "C:\\other.exe", &[]).unwrap(),
+            0
+        );
+
+        // Saving the profile again updates the limit but keeps the schedule.
+        db.save_rule(
+            "work",
+            &rule("C:\\steam.exe", "steam.exe", 500_000, 0, false),
+        )
+        .unwrap();
+        let rules = db.load_rules("work").unwrap();
+        assert_eq!(rules[0].download_bps, 500_000);
+        assert_eq!(rules[0].schedule, vec![window]);
+
+        db.set_rule_schedule("work", "C:\\steam.exe", &[]).unwrap();
+        assert!(db.load_rules("work").unwrap()[0].schedule.is_empty());
+    }
+
+    #[test]
+    fn test_rule_precedence_is_kept_on_resave() {
+        let db = open_memory_db();
+        let first = SavedRule {
+            precedence: 5,
+            ..rule("C:\\steam.exe", "steam.exe", 1_000_000, 0, false)
+        };
+        db.save_rule("work", &first).unwrap();
+        db.save_rule("work", &rule("C:\\other.exe", "other.exe", 0, 0, true))
+            .unwrap();
+
+        let rules = db.load_rules("work").unwrap();
+        assert_eq!(rules[0].precedence, 5);
+        assert_eq!(rules[1].precedence, 0);
+        assert!(rules[0].id > 0 && rules[0].id < rules[1].id);
+
+        // Saving the profile again keeps the precedence and the row id.
+        db.save_rule("work", &rule("C:\\steam.exe", "steam.exe", 0, 0, false))
+            .unwrap();
+        let resaved = db.load_rules("work").unwrap();
+        assert_eq!(resaved[0].precedence, 5);
+        assert_eq!(resaved[0].id, rules[0].id);
+
+        assert_eq!(
+            db.set_rule_precedence("work", "C:\\steam.exe", -1).unwrap(),
+            1
+        );
+        assert_eq!(
+            db.set_rule_precedence("work", "C:\\none.exe", 1).unwrap(),
+            0
+        );
+        assert_eq!(db.load_rules("work").unwrap()[0].precedence, -1);
+    }
 }
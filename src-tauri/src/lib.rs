@@ -14,7 +14,7 @@ use parking_lot::Mutex;
 use tauri::Manager;
 
 use commands::AppState;
-use core::{ProcessMapper, RateLimiterManager, TrafficTracker};
+use core::{FlowTable, ProcessMapper, QuotaManager, RateLimiterManager, TrafficTracker};
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -33,7 +33,10 @@ pub fn run() {
 
     let process_mapper = Arc::new(ProcessMapper::new());
     let traffic_tracker = Arc::new(TrafficTracker::new());
+    let flow_table = Arc::new(FlowTable::new());
     let rate_limiter = Arc::new(RateLimiterManager::new());
+    let quotas = Arc::new(QuotaManager::new());
+    let packet_recorder = Arc::new(capture::recorder::PacketRecorder::new());
     let notification_threshold = Arc::new(AtomicU64::new(0));
     let persistent_rules: Arc<Mutex<Vec<db::SavedRule>>> = Arc::new(Mutex::new(Vec::new()));
 
@@ -44,17 +47,49 @@ pub fn run() {
             commands::traffic::get_process_icon,
             commands::traffic::get_traffic_history,
             commands::traffic::get_top_consumers,
+            commands::traffic::get_process_connections,
+            commands::traffic::get_deferred_attribution_stats,
+            commands::traffic::replay_capture_file,
+            commands::capture::start_process_capture,
+            commands::capture::stop_process_capture,
+            commands::capture::get_process_captures,
+            commands::capture::dump_flight_recorder,
+            commands::capture::set_flight_recorder_limit,
+            commands::capture::get_flight_recorder_status,
             commands::rules::set_bandwidth_limit,
             commands::rules::remove_bandwidth_limit,
             commands::rules::get_bandwidth_limits,
+            commands::rules::set_global_limit,
+            commands::rules::remove_global_limit,
+            commands::rules::get_global_limit,
+            commands::rules::set_limit_group,
+            commands::rules::remove_limit_group,
+            commands::rules::get_limit_groups,
+            commands::rules::assign_limit_group,
+            commands::rules::get_limit_group_members,
             commands::rules::block_process,
             commands::rules::unblock_process,
             commands::rules::get_blocked_pids,
+            commands::rules::set_app_limit,
+            commands::rules::block_app,
+            commands::rules::remove_app_rule,
+            commands::rules::get_app_rules,
             commands::rules::save_profile,
             commands::rules::apply_profile,
             commands::rules::list_profiles,
             commands::rules::delete_profile,
             commands::rules::get_profile_rules,
+            commands::rules::set_rule_schedule,
+            commands::rules::save_profile_rule,
+            commands::rules::remove_profile_rule,
+            commands::rules::explain_policy,
+            commands::quotas::set_quota,
+            commands::quotas::remove_quota,
+            commands::quotas::get_quotas,
+            commands::allowlist::get_allowlist,
+            commands::allowlist::set_allowlist,
+            commands::allowlist::set_allowlist_mode,
+            commands::allowlist::preview_allowlist,
             commands::system::set_notification_threshold,
             commands::system::get_notification_threshold,
             commands::system::set_autostart,
@@ -70,11 +105,35 @@ pub fn run() {
                 .expect("failed to resolve app data dir");
             std::fs::create_dir_all(&app_data_dir)?;
             let database = Arc::new(db::Database::open(&app_data_dir.join("netguard.db"))?);
+            match database.load_quotas() {
+                Ok(loaded) => quotas.load(loaded),
+                Err(e) => tracing::warn!("Failed to load data quotas: {e}"),
+            }
+            match database.load_app_rules() {
+                Ok(loaded) => {
+                    for rule in loaded {
+                        rate_limiter.set_app_rule(rule);
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to load app rules: {e}"),
+            }
+            match database.load_allowlist() {
+                Ok((list, true)) => match core::allowlist::AllowlistMatcher::new(&list) {
+                    Ok(matcher) => {
+                        rate_limiter.set_allowlist(Some(matcher), &process_mapper.processes());
+                    }
+                    Err(e) => tracing::warn!("Allowlist mode left off: {e:#}"),
+                },
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Failed to load the allowlist: {e}"),
+            }
 
             // Start packet capture before AppState so we can move the engine in.
             let sniff_engine = match capture::CaptureEngine::start_sniff(
                 Arc::clone(&process_mapper),
                 Arc::clone(&traffic_tracker),
+                Arc::clone(&flow_table),
+                Arc::clone(&packet_recorder),
             ) {
                 Ok(engine) => {
                     tracing::info!("SNIFF mode started");
@@ -89,23 +148,20 @@ pub fn run() {
             app.manage(AppState {
                 process_mapper: Arc::clone(&process_mapper),
                 traffic_tracker: Arc::clone(&traffic_tracker),
+                flow_table: Arc::clone(&flow_table),
                 rate_limiter: Arc::clone(&rate_limiter),
+                quotas: Arc::clone(&quotas),
+                packet_recorder: Arc::clone(&packet_recorder),
                 database: Arc::clone(&database),
                 notification_threshold_bps: Arc::clone(&notification_threshold),
                 persistent_rules: Arc::clone(&persistent_rules),
+                active_profile: Mutex::new(None),
                 sniff_engine: Mutex::new(sniff_engine),
                 intercept_engine: Mutex::new(None),
             });
 
-            let bg = services::BackgroundServices::start(
-                &process_mapper,
-                &traffic_tracker,
-                &rate_limiter,
-                &database,
-                &notification_threshold,
-                &persistent_rules,
-                app.handle().clone(),
-            );
+            let bg =
+                services::BackgroundServices::start(&app.state::<AppState>(), app.handle().clone());
             app.manage(bg);
             services::setup_tray(app)?;
             Ok(())
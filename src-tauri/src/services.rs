@@ -3,7 +3,7 @@
 //! `BackgroundServices` owns all background threads spawned during app setup,
 //! starting them in the correct dependency order and providing clean shutdown.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 
@@ -15,9 +15,14 @@ use tauri::{
     Emitter, Manager,
 };
 
+use crate::capture::flight_recorder::DumpFormat;
+use crate::commands::AppState;
 use crate::config;
+use crate::core::flow_table::FlowTable;
 use crate::core::process_mapper::ProcessMapper;
-use crate::core::rate_limiter::{BandwidthLimit, RateLimiterManager};
+use crate::core::quota::QuotaManager;
+use crate::core::rate_limiter::RateLimiterManager;
+use crate::core::schedule::LocalClock;
 use crate::core::traffic::{ProcessTrafficSnapshot, TrafficTracker};
 use crate::db;
 
@@ -28,7 +33,10 @@ use crate::db;
 /// 2. Stats aggregator (1s speed ticks + event emission)
 /// 3. History recorder (5s database snapshots + daily pruning)
 /// 4. Tray updater (2s tooltip/menu + threshold notifications)
-/// 5. Persistent-rules applier (3s auto-apply to new processes)
+/// 5. Persistent-rules applier (3s auto-apply to new processes, rule schedules)
+/// 6. Flow sweeper (5s idle-connection expiry)
+/// 7. Quota enforcer (5s data quota accounting, resets and actions)
+/// 8. Rule expirer (1s lapse of temporary limits and blocks)
 ///
 /// Implements `Drop` to signal all threads to stop and join them.
 pub struct BackgroundServices {
@@ -39,21 +47,20 @@ pub struct BackgroundServices {
 impl BackgroundServices {
     /// Start all background services in the correct dependency order.
     /// Returns an owned `BackgroundServices` that will shut down all threads on drop.
-    pub fn start(
-        process_mapper: &Arc<ProcessMapper>,
-        traffic_tracker: &Arc<TrafficTracker>,
-        rate_limiter: &Arc<RateLimiterManager>,
-        database: &Arc<db::Database>,
-        notification_threshold: &Arc<AtomicU64>,
-        persistent_rules: &Arc<Mutex<Vec<db::SavedRule>>>,
-        app_handle: tauri::AppHandle,
-    ) -> Self {
+    pub fn start(state: &AppState, app_handle: tauri::AppHandle) -> Self {
+        let process_mapper = &state.process_mapper;
+        let traffic_tracker = &state.traffic_tracker;
+        let rate_limiter = &state.rate_limiter;
         let shutdown = Arc::new(AtomicBool::new(false));
 
         // Start all services in dependency order, collecting their JoinHandles.
         let handles = vec![
             // 1. Process scanner — must start first so port-PID map is populated.
-            process_mapper.start_scanning(Arc::clone(rate_limiter), Arc::clone(&shutdown)),
+            process_mapper.start_scanning(
+                Arc::clone(rate_limiter),
+                Arc::clone(traffic_tracker),
+                Arc::clone(&shutdown),
+            ),
             // 2. Stats aggregator — depends on process_mapper for connection counts.
             traffic_tracker.start_aggregator(
                 Arc::clone(process_mapper),
@@ -64,15 +71,15 @@ impl BackgroundServices {
             Self::start_history_recorder(
                 Arc::clone(traffic_tracker),
                 Arc::clone(process_mapper),
-                Arc::clone(database),
+                Arc::clone(&state.database),
                 Arc::clone(&shutdown),
             ),
             // 4. Tray updater — depends on traffic_tracker snapshots.
             Self::start_tray_updater(
                 Arc::clone(traffic_tracker),
                 Arc::clone(process_mapper),
-                Arc::clone(notification_threshold),
-                app_handle,
+                Arc::clone(&state.notification_threshold_bps),
+                app_handle.clone(),
                 Arc::clone(&shutdown),
             ),
             // 5. Persistent-rules applier — depends on traffic_tracker + rate_limiter.
@@ -80,9 +87,24 @@ impl BackgroundServices {
                 Arc::clone(traffic_tracker),
                 Arc::clone(process_mapper),
                 Arc::clone(rate_limiter),
-                Arc::clone(persistent_rules),
+                Arc::clone(&state.persistent_rules),
+                Arc::clone(&state.database),
+                Arc::clone(&shutdown),
+            ),
+            // 6. Flow sweeper — independent; expires idle connections.
+            Self::start_flow_sweeper(Arc::clone(&state.flow_table), Arc::clone(&shutdown)),
+            // 7. Quota enforcer — depends on traffic_tracker + rate_limiter.
+            Self::start_quota_enforcer(
+                Arc::clone(traffic_tracker),
+                Arc::clone(process_mapper),
+                Arc::clone(rate_limiter),
+                Arc::clone(&state.quotas),
+                Arc::clone(&state.database),
+                app_handle.clone(),
                 Arc::clone(&shutdown),
             ),
+            // 8. Rule expirer — depends on rate_limiter.
+            Self::start_rule_expirer(Arc::clone(rate_limiter), app_handle, Arc::clone(&shutdown)),
         ];
 
         Self { shutdown, handles }
@@ -112,21 +134,7 @@ impl BackgroundServices {
                     }
 
                     let snapshot = tracker.snapshot(&mapper);
-                    let now = db::chrono_timestamp();
-                    let records: Vec<db::TrafficRecord> = snapshot
-                        .iter()
-                        .filter(|s| s.upload_speed > 0.0 || s.download_speed > 0.0)
-                        .map(|s| db::TrafficRecord {
-                            timestamp: now,
-                            pid: s.pid,
-                            process_name: s.name.clone(),
-                            exe_path: s.exe_path.clone(),
-                            bytes_sent: s.bytes_sent,
-                            bytes_recv: s.bytes_recv,
-                            upload_speed: s.upload_speed,
-                            download_speed: s.download_speed,
-                        })
-                        .collect();
+                    let records = history_records(&snapshot, db::chrono_timestamp());
                     if !records.is_empty() {
                         if let Err(e) = db.insert_traffic_batch(&records) {
                             tracing::warn!("Failed to record traffic history: {e}");
@@ -183,11 +191,13 @@ impl BackgroundServices {
         mapper: Arc<ProcessMapper>,
         limiter: Arc<RateLimiterManager>,
         rules: Arc<Mutex<Vec<db::SavedRule>>>,
+        db: Arc<db::Database>,
         shutdown: Arc<AtomicBool>,
     ) -> std::thread::JoinHandle<()> {
         std::thread::Builder::new()
             .name("persistent-rules".into())
             .spawn(move || {
+                let mut scheduled: HashMap<u32, db::SavedRule> = HashMap::new();
                 let interval =
                     std::time::Duration::from_secs(config::PERSISTENT_RULES_INTERVAL_SECS);
                 let step = std::time::Duration::from_millis(50);
@@ -200,11 +210,113 @@ impl BackgroundServices {
                         std::thread::sleep(step);
                         elapsed += step;
                     }
-                    apply_persistent_rules(&tracker, &mapper, &limiter, &rules);
+                    let clock = match db.local_clock() {
+                        Ok(clock) => clock,
+                        Err(e) => {
+                            tracing::warn!("Cannot read the local time for rule schedules: {e}");
+                            continue;
+                        }
+                    };
+                    apply_persistent_rules(
+                        &tracker,
+                        &mapper,
+                        &limiter,
+                        &rules,
+                        &clock,
+                        &mut scheduled,
+                    );
                 }
             })
             .expect("failed to spawn persistent rules thread")
     }
+
+    fn start_flow_sweeper(
+        flow_table: Arc<FlowTable>,
+        shutdown: Arc<AtomicBool>,
+    ) -> std::thread::JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("flow-sweeper".into())
+            .spawn(move || {
+                let interval = std::time::Duration::from_secs(config::FLOW_SWEEP_INTERVAL_SECS);
+                let step = std::time::Duration::from_millis(50);
+                while !shutdown.load(Ordering::Relaxed) {
+                    let mut elapsed = std::time::Duration::ZERO;
+                    while elapsed < interval {
+                        if shutdown.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        std::thread::sleep(step);
+                        elapsed += step;
+                    }
+                    let expired = flow_table.expire_idle();
+                    if expired > 0 {
+                        tracing::debug!("Expired {expired} idle flows");
+                    }
+                }
+            })
+            .expect("failed to spawn flow sweeper thread")
+    }
+
+    fn start_quota_enforcer(
+        tracker: Arc<TrafficTracker>,
+        mapper: Arc<ProcessMapper>,
+        limiter: Arc<RateLimiterManager>,
+        quotas: Arc<QuotaManager>,
+        db: Arc<db::Database>,
+        handle: tauri::AppHandle,
+        shutdown: Arc<AtomicBool>,
+    ) -> std::thread::JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("quota-enforcer".into())
+            .spawn(move || {
+                let interval = std::time::Duration::from_secs(config::QUOTA_CHECK_INTERVAL_SECS);
+                let step = std::time::Duration::from_millis(50);
+                while !shutdown.load(Ordering::Relaxed) {
+                    let mut elapsed = std::time::Duration::ZERO;
+                    while elapsed < interval {
+                        if shutdown.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        std::thread::sleep(step);
+                        elapsed += step;
+                    }
+                    enforce_quotas(&handle, &tracker, &mapper, &limiter, &quotas, &db);
+                }
+            })
+            .expect("failed to spawn quota enforcer thread")
+    }
+
+    fn start_rule_expirer(
+        limiter: Arc<RateLimiterManager>,
+        handle: tauri::AppHandle,
+        shutdown: Arc<AtomicBool>,
+    ) -> std::thread::JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("rule-expirer".into())
+            .spawn(move || {
+                let interval = std::time::Duration::from_secs(config::RULE_EXPIRY_INTERVAL_SECS);
+                let step = std::time::Duration::from_millis(50);
+                while !shutdown.load(Ordering::Relaxed) {
+                    let mut elapsed = std::time::Duration::ZERO;
+                    while elapsed < interval {
+                        if shutdown.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        std::thread::sleep(step);
+                        elapsed += step;
+                    }
+                    for expired in limiter.expire(std::time::Instant::now()) {
+                        tracing::info!(
+                            "Temporary {:?} on PID {} lapsed",
+                            expired.kind,
+                            expired.pid
+                        );
+                        let _ = handle.emit("rule-expired", &expired);
+                    }
+                }
+            })
+            .expect("failed to spawn rule expirer thread")
+    }
 }
 
 impl Drop for BackgroundServices {
@@ -225,8 +337,15 @@ impl Drop for BackgroundServices {
 /// Create the system tray icon with menu and event handlers.
 pub fn setup_tray(app: &tauri::App) -> anyhow::Result<()> {
     let show_item = MenuItem::with_id(app, "show", "Show NetGuard", true, None::<&str>)?;
+    let save_item = MenuItem::with_id(
+        app,
+        "save_packets",
+        "Save Recent Packets",
+        true,
+        None::<&str>,
+    )?;
     let quit_item = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
-    let menu = Menu::with_items(app, &[&show_item, &quit_item])?;
+    let menu = Menu::with_items(app, &[&show_item, &save_item, &quit_item])?;
 
     let _tray = TrayIconBuilder::with_id("main")
         .icon(
@@ -245,6 +364,7 @@ pub fn setup_tray(app: &tauri::App) -> anyhow::Result<()> {
                     let _ = window.set_focus();
                 }
             }
+            "save_packets" => save_recent_packets(app),
             "quit" => app.exit(0),
             _ => {}
         })
@@ -343,45 +463,142 @@ pub fn update_tray_and_notify(
     notified_pids.retain(|pid| snapshot_pids.contains(pid));
 }
 
+/// Convert a tracker snapshot into history rows stamped with `timestamp`.
+/// Idle processes (zero speed in both directions) are not recorded.
+pub fn history_records(
+    snapshot: &[ProcessTrafficSnapshot],
+    timestamp: i64,
+) -> Vec<db::TrafficRecord> {
+    snapshot
+        .iter()
+        .filter(|s| s.upload_speed > 0.0 || s.download_speed > 0.0)
+        .map(|s| db::TrafficRecord {
+            timestamp,
+            pid: s.pid,
+            process_name: s.name.clone(),
+            exe_path: s.exe_path.clone(),
+            bytes_sent: s.bytes_sent,
+            bytes_recv: s.bytes_recv,
+            upload_speed: s.upload_speed,
+            download_speed: s.download_speed,
+            unattributed: s.unattributed,
+        })
+        .collect()
+}
+
 /// Apply persistent rules to running processes (F7, AC-7.2, AC-7.3).
+///
+/// Each process gets the rule that wins among those matching it and in force
+/// at `clock` (see [`RuleSet`](crate::core::rule_match::RuleSet)); scheduled
+/// rules are only in force inside their windows. `scheduled` holds, per PID,
+/// the scheduled rule that applied its block or limit, which is lifted again
+/// once that rule stops winning, unless it has been replaced since.
 pub fn apply_persistent_rules(
     tracker: &TrafficTracker,
     mapper: &ProcessMapper,
     limiter: &RateLimiterManager,
     rules: &Mutex<Vec<db::SavedRule>>,
+    clock: &LocalClock,
+    scheduled: &mut HashMap<u32, db::SavedRule>,
 ) {
     let rules_guard = rules.lock();
     if rules_guard.is_empty() {
+        scheduled.clear();
         return;
     }
 
     let snapshot = tracker.snapshot(mapper);
+    let rule_set = db::SavedRule::rule_set(&rules_guard);
     for proc in &snapshot {
-        for rule in rules_guard.iter() {
-            if proc.exe_path == rule.exe_path {
-                if rule.blocked {
-                    if limiter.block_if_absent(proc.pid) {
-                        tracing::debug!("Auto-applied block to {} (PID {})", proc.name, proc.pid);
-                    }
-                } else if (rule.download_bps > 0 || rule.upload_bps > 0)
-                    && limiter.set_limit_if_absent(
-                        proc.pid,
-                        BandwidthLimit {
-                            download_bps: rule.download_bps,
-                            upload_bps: rule.upload_bps,
-                        },
-                    )
-                {
-                    tracing::debug!(
-                        "Auto-applied limit to {} (PID {}): DL={} UL={}",
-                        proc.name,
-                        proc.pid,
-                        rule.download_bps,
-                        rule.upload_bps
-                    );
+        let winner = rule_set
+            .find_active(&proc.exe_path, &proc.name, |i| {
+                rules_guard[i].is_active(clock)
+            })
+            .map(|i| &rules_guard[i]);
+        if let Some(previous) = scheduled.remove(&proc.pid) {
+            if winner.is_some_and(|rule| rule.id == previous.id) {
+                scheduled.insert(proc.pid, previous);
+            } else {
+                // Lifted only if nobody has replaced what the rule installed.
+                if previous.blocked {
+                    limiter.unblock_if_permanent(proc.pid);
+                } else {
+                    limiter.remove_limit_if_unchanged(proc.pid, &previous.limit());
                 }
+                tracing::debug!(
+                    "Schedule window closed for {} (PID {})",
+                    proc.name,
+                    proc.pid
+                );
             }
         }
+        let Some(rule) = winner else {
+            continue;
+        };
+        let applied = if rule.blocked {
+            let applied = limiter.block_if_absent(proc.pid);
+            if applied {
+                tracing::debug!(
+                    "Auto-applied block to {} (PID {}) from rule {}",
+                    proc.name,
+                    proc.pid,
+                    rule.id
+                );
+            }
+            applied
+        } else if !rule.limit().is_unlimited()
+            && limiter.set_limit_if_absent(proc.pid, rule.limit())
+        {
+            tracing::debug!(
+                "Auto-applied limit to {} (PID {}) from rule {}: DL={} UL={}",
+                proc.name,
+                proc.pid,
+                rule.id,
+                rule.download_bps,
+                rule.upload_bps
+            );
+            true
+        } else {
+            false
+        };
+        if applied && !rule.schedule.is_empty() {
+            scheduled.insert(proc.pid, rule.clone());
+        }
+    }
+
+    let live: HashSet<u32> = snapshot.iter().map(|p| p.pid).collect();
+    scheduled.retain(|pid, _| live.contains(pid));
+}
+
+/// Account quota usage, persist it, and notify the frontend of quotas that
+/// ran out since the last check.
+fn enforce_quotas(
+    app: &tauri::AppHandle,
+    tracker: &TrafficTracker,
+    mapper: &ProcessMapper,
+    limiter: &RateLimiterManager,
+    quotas: &QuotaManager,
+    db: &db::Database,
+) {
+    let clock = match db.local_clock() {
+        Ok(clock) => clock,
+        Err(e) => {
+            tracing::warn!("Cannot determine the quota period: {e}");
+            return;
+        }
+    };
+    let snapshot = tracker.snapshot(mapper);
+    for status in quotas.tick(&snapshot, &clock.date, limiter) {
+        tracing::info!(
+            "{} quota exhausted for {} ({} bytes)",
+            status.quota.period.as_str(),
+            status.quota.process_name,
+            status.used_bytes
+        );
+        let _ = app.emit("quota-exhausted", &status);
+    }
+    if let Err(e) = db.save_quota_usage(&quotas.statuses()) {
+        tracing::warn!("Failed to save quota usage: {e}");
     }
 }
 
@@ -414,11 +631,58 @@ pub fn build_tray_menu(
         true,
         None::<&str>,
     )?)?;
+    menu.append(&MenuItem::with_id(
+        app,
+        "save_packets",
+        "Save Recent Packets",
+        true,
+        None::<&str>,
+    )?)?;
     menu.append(&MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?)?;
 
     Ok(menu)
 }
 
+/// Dump the flight recorder's last few seconds to a pcapng file under the app
+/// data directory and tell the frontend where it went.
+fn save_recent_packets(app: &tauri::AppHandle) {
+    let dir = match app.path().app_data_dir() {
+        Ok(dir) => dir.join("flight-recorder"),
+        Err(e) => {
+            tracing::warn!("Cannot resolve app data dir for packet dump: {e}");
+            return;
+        }
+    };
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        tracing::warn!("Cannot create {}: {e}", dir.display());
+        return;
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = dir.join(format!("packets-{timestamp}.pcapng"));
+
+    let state = app.state::<AppState>();
+    match state.packet_recorder.flight_recorder().dump_last(
+        &state.process_mapper,
+        std::time::Duration::from_secs(config::FLIGHT_RECORDER_TRAY_DUMP_SECS),
+        DumpFormat::Pcapng,
+        &path,
+    ) {
+        Ok(records) => {
+            let _ = app.emit(
+                "flight-recorder-dumped",
+                serde_json::json!({
+                    "path": path.display().to_string(),
+                    "records": records,
+                }),
+            );
+        }
+        Err(e) => tracing::warn!("Flight recorder dump failed: {e:#}"),
+    }
+}
+
 /// Format a speed value in a compact human-readable form.
 pub fn format_speed_compact(bps: f64) -> String {
     if bps < 1024.0 {
@@ -433,6 +697,17 @@ pub fn format_speed_compact(bps: f64) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::rate_limiter::{BandwidthLimit, LimitMode, PriorityClass};
+    use crate::core::rule_match::MatchType;
+    use crate::core::schedule::{ScheduleWindow, Weekday};
+
+    fn monday_at(hour: u16) -> LocalClock {
+        LocalClock {
+            date: "2026-03-02".into(),
+            weekday: Weekday::Mon,
+            minute: hour * 60,
+        }
+    }
 
     #[test]
     fn test_format_speed_compact_bytes() {
@@ -459,11 +734,22 @@ mod tests {
         let mapper = ProcessMapper::new();
         let limiter = RateLimiterManager::new();
         let rules = Mutex::new(vec![db::SavedRule {
+            id: 0,
             exe_path: "/usr/bin/app".into(),
+            match_type: MatchType::Exact,
+            precedence: 0,
             process_name: "app".into(),
             download_bps: 5000,
             upload_bps: 3000,
             blocked: false,
+            mode: LimitMode::ShapeDropTail,
+            guaranteed_download_bps: 0,
+            guaranteed_upload_bps: 0,
+            priority: PriorityClass::Normal,
+            weight: 1,
+            burst_bytes: 0,
+            burst_ms: 0,
+            schedule: Vec::new(),
         }]);
 
         // Simulate a process with matching exe_path
@@ -472,11 +758,19 @@ mod tests {
             crate::core::process_mapper::ProcessInfo {
                 name: "app".into(),
                 exe_path: "/usr/bin/app".into(),
+                args: String::new(),
             },
         );
         tracker.record_bytes(10, 100, 0);
 
-        apply_persistent_rules(&tracker, &mapper, &limiter, &rules);
+        apply_persistent_rules(
+            &tracker,
+            &mapper,
+            &limiter,
+            &rules,
+            &monday_at(12),
+            &mut HashMap::new(),
+        );
 
         let limits = limiter.get_all_limits();
         assert!(
@@ -485,6 +779,7 @@ mod tests {
         );
         assert_eq!(limits[&10].download_bps, 5000);
         assert_eq!(limits[&10].upload_bps, 3000);
+        assert_eq!(limits[&10].mode, LimitMode::ShapeDropTail);
     }
 
     #[test]
@@ -493,11 +788,22 @@ mod tests {
         let mapper = ProcessMapper::new();
         let limiter = RateLimiterManager::new();
         let rules = Mutex::new(vec![db::SavedRule {
+            id: 0,
             exe_path: "/other/app".into(),
+            match_type: MatchType::Exact,
+            precedence: 0,
             process_name: "other".into(),
             download_bps: 1000,
             upload_bps: 500,
             blocked: false,
+            mode: LimitMode::Police,
+            guaranteed_download_bps: 0,
+            guaranteed_upload_bps: 0,
+            priority: PriorityClass::Normal,
+            weight: 1,
+            burst_bytes: 0,
+            burst_ms: 0,
+            schedule: Vec::new(),
         }]);
 
         mapper.process_info.insert(
@@ -505,11 +811,19 @@ mod tests {
             crate::core::process_mapper::ProcessInfo {
                 name: "app".into(),
                 exe_path: "/usr/bin/app".into(),
+                args: String::new(),
             },
         );
         tracker.record_bytes(10, 100, 0);
 
-        apply_persistent_rules(&tracker, &mapper, &limiter, &rules);
+        apply_persistent_rules(
+            &tracker,
+            &mapper,
+            &limiter,
+            &rules,
+            &monday_at(12),
+            &mut HashMap::new(),
+        );
 
         assert!(
             limiter.get_all_limits().is_empty(),
@@ -523,11 +837,22 @@ mod tests {
         let mapper = ProcessMapper::new();
         let limiter = RateLimiterManager::new();
         let rules = Mutex::new(vec![db::SavedRule {
+            id: 0,
             exe_path: "/usr/bin/blocked_app".into(),
+            match_type: MatchType::Exact,
+            precedence: 0,
             process_name: "blocked_app".into(),
             download_bps: 0,
             upload_bps: 0,
             blocked: true,
+            mode: LimitMode::Police,
+            guaranteed_download_bps: 0,
+            guaranteed_upload_bps: 0,
+            priority: PriorityClass::Normal,
+            weight: 1,
+            burst_bytes: 0,
+            burst_ms: 0,
+            schedule: Vec::new(),
         }]);
 
         mapper.process_info.insert(
@@ -535,15 +860,190 @@ mod tests {
             crate::core::process_mapper::ProcessInfo {
                 name: "blocked_app".into(),
                 exe_path: "/usr/bin/blocked_app".into(),
+                args: String::new(),
             },
         );
         tracker.record_bytes(20, 50, 0);
 
-        apply_persistent_rules(&tracker, &mapper, &limiter, &rules);
+        apply_persistent_rules(
+            &tracker,
+            &mapper,
+            &limiter,
+            &rules,
+            &monday_at(12),
+            &mut HashMap::new(),
+        );
 
         assert!(
             limiter.get_blocked_pids().contains(&20),
             "PID 20 should be blocked"
         );
     }
+
+    #[test]
+    fn test_apply_persistent_rules_follows_schedule() {
+        let tracker = TrafficTracker::new();
+        let mapper = ProcessMapper::new();
+        let limiter = RateLimiterManager::new();
+        let rules = Mutex::new(vec![db::SavedRule {
+            id: 0,
+            exe_path: "/usr/bin/steam".into(),
+            match_type: MatchType::Exact,
+            precedence: 0,
+            process_name: "steam".into(),
+            download_bps: 1_000_000,
+            upload_bps: 0,
+            blocked: false,
+            mode: LimitMode::Police,
+            guaranteed_download_bps: 0,
+            guaranteed_upload_bps: 0,
+            priority: PriorityClass::Normal,
+            weight: 1,
+            burst_bytes: 0,
+            burst_ms: 0,
+            schedule: vec![ScheduleWindow {
+                days: vec![Weekday::Mon],
+                start_minute: 9 * 60,
+                end_minute: 18 * 60,
+            }],
+        }]);
+        mapper.process_info.insert(
+            30,
+            crate::core::process_mapper::ProcessInfo {
+                name: "steam".into(),
+                exe_path: "/usr/bin/steam".into(),
+                args: String::new(),
+            },
+        );
+        tracker.record_bytes(30, 100, 0);
+        let mut scheduled = HashMap::new();
+
+        apply_persistent_rules(
+            &tracker,
+            &mapper,
+            &limiter,
+            &rules,
+            &monday_at(8),
+            &mut scheduled,
+        );
+        assert!(limiter.get_all_limits().is_empty(), "before the window");
+
+        apply_persistent_rules(
+            &tracker,
+            &mapper,
+            &limiter,
+            &rules,
+            &monday_at(9),
+            &mut scheduled,
+        );
+        assert_eq!(limiter.get_all_limits()[&30].download_bps, 1_000_000);
+
+        apply_persistent_rules(
+            &tracker,
+            &mapper,
+            &limiter,
+            &rules,
+            &monday_at(18),
+            &mut scheduled,
+        );
+        assert!(limiter.get_all_limits().is_empty(), "after the window");
+        assert!(scheduled.is_empty());
+
+        // A limit the user set over the rule's outlives the window.
+        apply_persistent_rules(
+            &tracker,
+            &mapper,
+            &limiter,
+            &rules,
+            &monday_at(9),
+            &mut scheduled,
+        );
+        let edited = BandwidthLimit {
+            download_bps: 5_000,
+            ..Default::default()
+        };
+        limiter.set_limit(30, edited.clone());
+        apply_persistent_rules(
+            &tracker,
+            &mapper,
+            &limiter,
+            &rules,
+            &monday_at(18),
+            &mut scheduled,
+        );
+        assert_eq!(limiter.get_all_limits()[&30], edited);
+    }
+
+    #[test]
+    fn test_apply_persistent_rules_skips_inactive_winner() {
+        let tracker = TrafficTracker::new();
+        let mapper = ProcessMapper::new();
+        let limiter = RateLimiterManager::new();
+        let always = db::SavedRule {
+            id: 1,
+            exe_path: "/usr/bin/steam".into(),
+            match_type: MatchType::Exact,
+            precedence: 0,
+            process_name: "steam".into(),
+            download_bps: 2_000,
+            upload_bps: 0,
+            blocked: false,
+            mode: LimitMode::Police,
+            guaranteed_download_bps: 0,
+            guaranteed_upload_bps: 0,
+            priority: PriorityClass::Normal,
+            weight: 1,
+            burst_bytes: 0,
+            burst_ms: 0,
+            schedule: Vec::new(),
+        };
+        let office = db::SavedRule {
+            id: 2,
+            precedence: 10,
+            download_bps: 1_000_000,
+            schedule: vec![ScheduleWindow {
+                days: vec![Weekday::Mon],
+                start_minute: 9 * 60,
+                end_minute: 18 * 60,
+            }],
+            ..always.clone()
+        };
+        let rules = Mutex::new(vec![always, office]);
+        mapper.process_info.insert(
+            30,
+            crate::core::process_mapper::ProcessInfo {
+                name: "steam".into(),
+                exe_path: "/usr/bin/steam".into(),
+                args: String::new(),
+            },
+        );
+        tracker.record_bytes(30, 100, 0);
+        let mut scheduled = HashMap::new();
+        let run = |hour, scheduled: &mut HashMap<u32, db::SavedRule>| {
+            apply_persistent_rules(
+                &tracker,
+                &mapper,
+                &limiter,
+                &rules,
+                &monday_at(hour),
+                scheduled,
+            );
+            limiter.get_all_limits()[&30].download_bps
+        };
+
+        // The out-of-window rule outranks the always-on one but does not hide it.
+        assert_eq!(run(8, &mut scheduled), 2_000);
+        assert!(scheduled.is_empty());
+
+        // Once the window opens, the always-on limit is already in place and
+        // stays; after the user clears it, the scheduled rule takes over.
+        assert_eq!(run(9, &mut scheduled), 2_000);
+        limiter.remove_limit(30);
+        assert_eq!(run(9, &mut scheduled), 1_000_000);
+
+        // When the window closes, its limit is lifted and the always-on rule
+        // applies again.
+        assert_eq!(run(18, &mut scheduled), 2_000);
+        assert!(scheduled.is_empty());
+    }
 }
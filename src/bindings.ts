@@ -1,7 +1,129 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
 /**
- * Bandwidth limit configuration for a single process.
+ * A process limit with the time left before it lapses.
+ */
+export type ActiveLimit = { 
+/**
+ * Seconds until a temporary limit lapses (`None` = permanent).
+ */
+expires_in_secs: number | null, 
+/**
+ * Download limit in bytes per second (0 = unlimited).
+ */
+download_bps: number, 
+/**
+ * Upload limit in bytes per second (0 = unlimited).
+ */
+upload_bps: number, 
+/**
+ * Policing (drop) or shaping (delay) of over-budget packets.
+ * Only process limits use it.
+ */
+mode: LimitMode, 
+/**
+ * Guaranteed download rate in bytes per second (0 = none). Traffic within
+ * it passes even when a group or global limit above is exhausted.
+ */
+guaranteed_download_bps: number, 
+/**
+ * Guaranteed upload rate in bytes per second (0 = none).
+ */
+guaranteed_upload_bps: number, 
+/**
+ * Scheduling class among shaped processes. Only process limits use it.
+ */
+priority: PriorityClass, 
+/**
+ * Share of a contended limit relative to other shaped processes of the
+ * same class (at least 1). Only process limits use it.
+ */
+weight: number, 
+/**
+ * Bucket size in bytes for both directions (0 = derived from `burst_ms`).
+ */
+burst_bytes: number, 
+/**
+ * Bucket size as a duration at each direction's rate, in milliseconds
+ * (0 = the default of two seconds). Ignored when `burst_bytes` is set.
+ */
+burst_ms: number, };
+
+/**
+ * One allowed executable, or a pattern on the path or process name.
+ */
+export type AllowEntry = { match_type: MatchType, pattern: string, };
+
+/**
+ * The applications allowed network access in allowlist mode.
+ */
+export type Allowlist = { 
+/**
+ * Applications the user allows.
+ */
+allowed: Array<AllowEntry>, 
+/**
+ * Essential system processes, allowed whatever `allowed` says.
+ */
+always_allow: Array<AllowEntry>, };
+
+/**
+ * What allowlist mode would cut off, as reported by a preview.
+ */
+export type AllowlistPreview = { 
+/**
+ * Running processes the list does not allow.
+ */
+would_block: Array<WouldBlock>, 
+/**
+ * Bytes seen so far that no process was found for. The mode drops such
+ * traffic too.
+ */
+unowned_bytes: number, };
+
+/**
+ * Whether allowlist mode is on, and its lists.
+ */
+export type AllowlistStatus = { enabled: boolean, list: Allowlist, };
+
+/**
+ * What an app rule does to the matching processes.
+ */
+export type AppAction = { "Limit": BandwidthLimit } | "Block";
+
+/**
+ * Which processes an app rule applies to.
+ */
+export type AppMatch = { 
+/**
+ * Full path of the executable.
+ */
+exe_path: string, 
+/**
+ * Only match processes whose arguments contain this text.
+ */
+args: string | null, 
+/**
+ * Only match if the executable's SHA-256 digest (lowercase hex) is this.
+ */
+sha256: string | null, };
+
+/**
+ * A limit or block on every process of an application.
+ */
+export type AppRule = { app: AppMatch, 
+/**
+ * Display name of the application.
+ */
+process_name: string, action: AppAction, };
+
+/**
+ * An app rule with the PIDs it currently applies to.
+ */
+export type AppRuleStatus = { rule: AppRule, pids: Array<number>, };
+
+/**
+ * Bandwidth limit configuration for a process, a limit group, or the whole host.
  */
 export type BandwidthLimit = { 
 /**
@@ -11,7 +133,222 @@ download_bps: number,
 /**
  * Upload limit in bytes per second (0 = unlimited).
  */
-upload_bps: number, };
+upload_bps: number, 
+/**
+ * Policing (drop) or shaping (delay) of over-budget packets.
+ * Only process limits use it.
+ */
+mode: LimitMode, 
+/**
+ * Guaranteed download rate in bytes per second (0 = none). Traffic within
+ * it passes even when a group or global limit above is exhausted.
+ */
+guaranteed_download_bps: number, 
+/**
+ * Guaranteed upload rate in bytes per second (0 = none).
+ */
+guaranteed_upload_bps: number, 
+/**
+ * Scheduling class among shaped processes. Only process limits use it.
+ */
+priority: PriorityClass, 
+/**
+ * Share of a contended limit relative to other shaped processes of the
+ * same class (at least 1). Only process limits use it.
+ */
+weight: number, 
+/**
+ * Bucket size in bytes for both directions (0 = derived from `burst_ms`).
+ */
+burst_bytes: number, 
+/**
+ * Bucket size as a duration at each direction's rate, in milliseconds
+ * (0 = the default of two seconds). Ignored when `burst_bytes` is set.
+ */
+burst_ms: number, };
+
+/**
+ * A blocked process with the time left before the block lapses.
+ */
+export type BlockedProcess = { pid: number, 
+/**
+ * Seconds until a temporary block lapses (`None` = permanent).
+ */
+expires_in_secs: number | null, };
+
+/**
+ * One connection of a process, serializable for the frontend.
+ */
+export type ConnectionSnapshot = { protocol: Protocol, local_addr: string, local_port: number, remote_addr: string, remote_port: number, pid: number | null, bytes_sent: number, bytes_recv: number, packets_sent: number, packets_recv: number, 
+/**
+ * Unix timestamp (milliseconds) of the first packet.
+ */
+first_seen_ms: number, 
+/**
+ * Unix timestamp (milliseconds) of the latest packet.
+ */
+last_seen_ms: number, 
+/**
+ * `None` for non-TCP flows.
+ */
+tcp_state: TcpState | null, };
+
+/**
+ * Counters of the deferred attribution queue, serializable for the frontend.
+ */
+export type DeferredAttributionStats = { 
+/**
+ * (protocol, port) keys currently waiting for an owner.
+ */
+pending_ports: number, 
+/**
+ * Bytes currently waiting for an owner.
+ */
+pending_bytes: number, 
+/**
+ * Bytes credited to a process after a later scan resolved their port.
+ */
+recovered_bytes: number, 
+/**
+ * Bytes that never resolved (or overflowed the queue) and were counted
+ * as unattributed.
+ */
+expired_bytes: number, };
+
+/**
+ * Output format of a flight recorder dump.
+ */
+export type DumpFormat = "Pcapng" | "Json";
+
+/**
+ * What the rate limiter currently enforces on one process.
+ */
+export type EffectivePolicy = { blocked: boolean, 
+/**
+ * The process's limit (`None` when blocked or without one).
+ */
+limit: BandwidthLimit | null, source: PolicySource, 
+/**
+ * Seconds until a temporary block or limit lapses (`None` = permanent).
+ */
+expires_in_secs: number | null, 
+/**
+ * Limit group the process is in, if any.
+ */
+group: string | null, };
+
+/**
+ * A temporary rule that lapsed, as reported in the `rule-expired` event.
+ */
+export type ExpiredRule = { pid: number, kind: RuleKind, };
+
+/**
+ * Why an export session ended.
+ */
+export type ExportStopReason = "Duration" | "Size" | "Stopped" | "WriteError";
+
+/**
+ * Header summary of one captured packet.
+ */
+export type FlightRecord = { 
+/**
+ * Capture time in microseconds since the Unix epoch.
+ */
+timestamp_us: number, protocol: Protocol, 
+/**
+ * Raw IP protocol number.
+ */
+ip_protocol: number, src_addr: string, 
+/**
+ * 0 for packets without ports (fragments, other protocols); ICMP echo
+ * carries its identifier in both ports.
+ */
+src_port: number, dst_addr: string, dst_port: number, 
+/**
+ * IP packet length in bytes.
+ */
+size: number, outbound: boolean, 
+/**
+ * Process the packet was attributed to, if any.
+ */
+pid: number | null, };
+
+/**
+ * Current size and budget of the flight recorder.
+ */
+export type FlightRecorderStatus = { 
+/**
+ * Memory budget in bytes (0 = disabled).
+ */
+max_bytes: number, 
+/**
+ * Number of records the budget allows.
+ */
+capacity: number, 
+/**
+ * Number of records currently held.
+ */
+len: number, 
+/**
+ * Timestamp of the oldest record held, in microseconds since the epoch.
+ */
+oldest_timestamp_us: number | null, };
+
+/**
+ * How packets over a limit's budget are handled.
+ */
+export type LimitMode = "Police" | "ShapeDropTail" | "ShapeCodel";
+
+/**
+ * What a saved rule's pattern is matched against, and how. Variants are
+ * declared from most to least specific.
+ */
+export type MatchType = "Exact" | "PathGlob" | "PathRegex" | "NameGlob" | "NameRegex";
+
+/**
+ * Why a process is treated the way it is.
+ */
+export type PolicyExplanation = { pid: number, process_name: string, exe_path: string, 
+/**
+ * What the rate limiter currently enforces, and from which kind of rule.
+ */
+effective: EffectivePolicy, 
+/**
+ * Id of the active profile's rule that applies to the process, if any.
+ */
+winning_rule_id: number | null, 
+/**
+ * Active profile rules matching the process, winner first.
+ */
+candidates: Array<RuleCandidate>, };
+
+/**
+ * Which kind of rule decides the traffic of a process.
+ */
+export type PolicySource = "None" | "Process" | { "App": { exe_path: string, } } | "Allowlist";
+
+/**
+ * Scheduling class of a shaped process. When shaped processes compete for
+ * a shared group or global limit, higher classes are served first.
+ */
+export type PriorityClass = "High" | "Normal" | "Low";
+
+/**
+ * Progress of an export session, active or finished.
+ */
+export type ProcessCaptureStatus = { id: number, target_pid: number | null, target_exe_path: string | null, output_path: string, 
+/**
+ * Unix timestamp (seconds) the session started.
+ */
+started_at: number, packets: number, 
+/**
+ * Size of the pcapng file so far, including its header blocks.
+ */
+bytes_written: number, 
+/**
+ * `None` while the session is still recording.
+ */
+stop_reason: ExportStopReason | null, };
 
 /**
  * Snapshot of one process's traffic state, serializable for the frontend.
@@ -36,19 +373,186 @@ bytes_recv: number,
 /**
  * Number of active connections (TCP + UDP).
  */
-connection_count: number, };
+connection_count: number, 
+/**
+ * Set for the synthetic unattributed buckets, `None` for real processes.
+ */
+unattributed: UnattributedReason | null, };
+
+/**
+ * Network protocol.
+ */
+export type Protocol = "Tcp" | "Udp" | "Icmp" | "Icmpv6" | "Other";
+
+/**
+ * A data budget for one executable. At most one quota per executable and period.
+ */
+export type Quota = { exe_path: string, process_name: string, period: QuotaPeriod, 
+/**
+ * Bytes (sent + received) allowed per period.
+ */
+limit_bytes: number, action: QuotaAction, 
+/**
+ * Rate in bytes per second for [`QuotaAction::Throttle`] (ignored otherwise).
+ */
+throttle_bps: number, };
+
+/**
+ * What happens to an application once its quota is used up.
+ */
+export type QuotaAction = "Block" | "Throttle" | "Notify";
+
+/**
+ * Calendar period a quota budget covers.
+ */
+export type QuotaPeriod = "Daily" | "Monthly";
+
+/**
+ * A quota with its consumption in the current period.
+ */
+export type QuotaStatus = { quota: Quota, used_bytes: number, 
+/**
+ * Period the usage belongs to (`YYYY-MM-DD` or `YYYY-MM`; empty until
+ * the first check).
+ */
+period_key: string, };
+
+/**
+ * Outcome of a replay, returned to the frontend.
+ */
+export type ReplaySummary = { 
+/**
+ * Frames read from the capture file.
+ */
+total_packets: number, 
+/**
+ * Packets credited to a mapped process.
+ */
+attributed_packets: number, 
+/**
+ * Frames skipped because they were not IP or used an unsupported link type.
+ */
+unsupported_packets: number, 
+/**
+ * Packets skipped because neither the link layer, the local addresses
+ * nor a mapped port told which way they went.
+ */
+undirected_packets: number, 
+/**
+ * Unix timestamp (seconds) of the first packet, 0 if the file was empty.
+ */
+first_timestamp: number, 
+/**
+ * Unix timestamp (seconds) of the last packet, 0 if the file was empty.
+ */
+last_timestamp: number, 
+/**
+ * Rows written to the replay's history database.
+ */
+history_records: number, 
+/**
+ * Final per-process totals.
+ */
+processes: Array<ProcessTrafficSnapshot>, };
+
+/**
+ * A profile rule matching a process, as reported by an explanation.
+ */
+export type RuleCandidate = { rule_id: number, pattern: string, match_type: MatchType, precedence: number, blocked: boolean, 
+/**
+ * False if the rule's schedule has it out of force right now.
+ */
+active: boolean, 
+/**
+ * The step on which the winning rule outranks this one (`None` for the winner).
+ */
+lost_on: Tiebreak | null, };
+
+/**
+ * Kind of process rule that can be made temporary.
+ */
+export type RuleKind = "Limit" | "Block";
 
 /**
  * A saved bandwidth rule from the database.
  */
-export type SavedRule = { exe_path: string, process_name: string, download_bps: number, upload_bps: number, blocked: boolean, };
+export type SavedRule = { 
+/**
+ * Row id of the rule (0 for a rule that is not saved yet).
+ */
+id: number, 
+/**
+ * Executable path, or the glob or regex pattern given by `match_type`.
+ */
+exe_path: string, 
+/**
+ * How `exe_path` is matched against processes.
+ */
+match_type: MatchType, 
+/**
+ * Explicit priority among rules matching the same process; a higher
+ * value wins regardless of how specific the rules are.
+ */
+precedence: number, process_name: string, download_bps: number, upload_bps: number, blocked: boolean, mode: LimitMode, guaranteed_download_bps: number, guaranteed_upload_bps: number, priority: PriorityClass, weight: number, burst_bytes: number, burst_ms: number, 
+/**
+ * Windows in which the rule is in force (empty = always).
+ */
+schedule: Array<ScheduleWindow>, };
+
+/**
+ * A recurring time window on a set of weekdays, e.g. 09:00–18:00 Mon–Fri.
+ */
+export type ScheduleWindow = { 
+/**
+ * Days on which the window starts.
+ */
+days: Array<Weekday>, 
+/**
+ * Start, in minutes after local midnight (inclusive).
+ */
+start_minute: number, 
+/**
+ * End, in minutes after local midnight (exclusive). Before the start
+ * for a window that runs past midnight.
+ */
+end_minute: number, };
+
+/**
+ * TCP connection state inferred from observed flags.
+ */
+export type TcpState = "SynSent" | "SynReceived" | "Established" | "Closing" | "Closed" | "Reset";
+
+/**
+ * The step of the evaluation order that ranked one matching rule above another.
+ */
+export type Tiebreak = "Precedence" | "MatchType" | "PatternLength" | "BlockOverLimit" | "PatternText" | "RuleOrder";
 
 /**
  * A single traffic history record.
  */
-export type TrafficRecord = { timestamp: number, pid: number, process_name: string, exe_path: string, bytes_sent: number, bytes_recv: number, upload_speed: number, download_speed: number, };
+export type TrafficRecord = { timestamp: number, pid: number, process_name: string, exe_path: string, bytes_sent: number, bytes_recv: number, upload_speed: number, download_speed: number, 
+/**
+ * Set for unattributed-traffic buckets. Derived from `pid`, not stored.
+ */
+unattributed: UnattributedReason | null, };
 
 /**
  * Summary of a process's total traffic over a time window.
  */
 export type TrafficSummary = { process_name: string, exe_path: string, total_sent: number, total_recv: number, total_bytes: number, };
+
+/**
+ * Why traffic could not be credited to a process. Each reason is tracked
+ * as its own pseudo-process so totals reconcile with the NIC counters.
+ */
+export type UnattributedReason = "NoPortMatch" | "Unparseable" | "NonTcpUdp";
+
+/**
+ * Day of the week in local time.
+ */
+export type Weekday = "Mon" | "Tue" | "Wed" | "Thu" | "Fri" | "Sat" | "Sun";
+
+/**
+ * A process allowlist mode would cut off, as reported by a preview.
+ */
+export type WouldBlock = { pid: number, name: string, exe_path: string, };
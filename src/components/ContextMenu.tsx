@@ -1,13 +1,13 @@
 import { invoke } from "@tauri-apps/api/core";
 import { CtxItem } from "./ui/CtxItem";
-import type { ProcessTrafficSnapshot as ProcessTraffic, BandwidthLimit } from "../bindings";
+import type { ProcessTrafficSnapshot as ProcessTraffic, ActiveLimit } from "../bindings";
 
 export interface ContextMenuProps {
   contextMenu: { x: number; y: number; process: ProcessTraffic } | null;
-  limits: Record<number, BandwidthLimit>;
+  limits: Record<number, ActiveLimit>;
   blockedPids: Set<number>;
   setEditingCell: (cell: { pid: number; field: "dl" | "ul" } | null) => void;
-  setLimits: React.Dispatch<React.SetStateAction<Record<number, BandwidthLimit>>>;
+  setLimits: React.Dispatch<React.SetStateAction<Record<number, ActiveLimit>>>;
   toggleBlock: (pid: number) => void;
   setContextMenu: (menu: { x: number; y: number; process: ProcessTraffic } | null) => void;
 }
@@ -2,13 +2,13 @@ import { formatSpeed, formatBytes, hasNonAscii } from "../utils";
 import { Th } from "./ui/Th";
 import { LimitCell } from "./ui/LimitCell";
 import { Toggle } from "./ui/Toggle";
-import type { ProcessTrafficSnapshot as ProcessTraffic, BandwidthLimit } from "../bindings";
+import type { ProcessTrafficSnapshot as ProcessTraffic, ActiveLimit } from "../bindings";
 import type { SortKey } from "../hooks/useTrafficData";
 
 export interface ProcessTableProps {
   sorted: ProcessTraffic[];
   processCount: number;
-  limits: Record<number, BandwidthLimit>;
+  limits: Record<number, ActiveLimit>;
   blockedPids: Set<number>;
   icons: Record<string, string>;
   showPidColumn: boolean;
@@ -1,10 +1,10 @@
 import { Badge } from "./ui/Badge";
-import type { BandwidthLimit } from "../bindings";
+import type { ActiveLimit } from "../bindings";
 
 interface StatusBarProps {
   processCount: number;
   shownCount: number;
-  limits: Record<number, BandwidthLimit>;
+  limits: Record<number, ActiveLimit>;
   blockedPids: Set<number>;
   interceptActive: boolean;
 }
@@ -1,10 +1,10 @@
 import { useState, useEffect, useCallback, useRef } from "react";
 import { invoke } from "@tauri-apps/api/core";
-import type { BandwidthLimit } from "../bindings";
+import type { ActiveLimit, BlockedProcess } from "../bindings";
 import { validateProfileName } from "../utils";
 
 interface UseProfilesParams {
-  setLimits: React.Dispatch<React.SetStateAction<Record<number, BandwidthLimit>>>;
+  setLimits: React.Dispatch<React.SetStateAction<Record<number, ActiveLimit>>>;
   setBlockedPids: React.Dispatch<React.SetStateAction<Set<number>>>;
 }
 
@@ -49,11 +49,11 @@ export function useProfiles({ setLimits, setBlockedPids }: UseProfilesParams) {
     await invoke<number>("apply_profile", { profileName: name });
     setActiveProfile(name);
     const [newLimits, newBlocked] = await Promise.all([
-      invoke<Record<number, BandwidthLimit>>("get_bandwidth_limits"),
-      invoke<number[]>("get_blocked_pids"),
+      invoke<Record<number, ActiveLimit>>("get_bandwidth_limits"),
+      invoke<BlockedProcess[]>("get_blocked_pids"),
     ]);
     setLimits(newLimits);
-    setBlockedPids(new Set(newBlocked));
+    setBlockedPids(new Set(newBlocked.map((b) => b.pid)));
   }, [setLimits, setBlockedPids]);
 
   const deleteProfile = useCallback(async (name: string) => {
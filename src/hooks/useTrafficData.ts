@@ -4,7 +4,10 @@ import { listen } from "@tauri-apps/api/event";
 import { parseLimitInput } from "../utils";
 import type {
   ProcessTrafficSnapshot as ProcessTraffic,
+  ActiveLimit,
   BandwidthLimit,
+  BlockedProcess,
+  ExpiredRule,
 } from "../bindings";
 
 export type SortKey = keyof ProcessTraffic;
@@ -16,7 +19,7 @@ export function useTrafficData() {
   const [sortDir, setSortDir] = useState<SortDir>("desc");
   const [filter, setFilter] = useState("");
   const [selectedPid, setSelectedPid] = useState<number | null>(null);
-  const [limits, setLimits] = useState<Record<number, BandwidthLimit>>({});
+  const [limits, setLimits] = useState<Record<number, ActiveLimit>>({});
   const [blockedPids, setBlockedPids] = useState<Set<number>>(new Set());
   const [editingCell, setEditingCell] = useState<{ pid: number; field: "dl" | "ul" } | null>(null);
   const editRef = useRef<HTMLInputElement>(null);
@@ -38,8 +41,21 @@ export function useTrafficData() {
   // Initial data fetch
   useEffect(() => {
     invoke<ProcessTraffic[]>("get_traffic_stats").then(setProcesses);
-    invoke<Record<number, BandwidthLimit>>("get_bandwidth_limits").then(setLimits);
-    invoke<number[]>("get_blocked_pids").then((pids) => setBlockedPids(new Set(pids)));
+    invoke<Record<number, ActiveLimit>>("get_bandwidth_limits").then(setLimits);
+    invoke<BlockedProcess[]>("get_blocked_pids").then((blocked) => setBlockedPids(new Set(blocked.map((b) => b.pid))));
+  }, []);
+
+  // Drop temporary limits and blocks when they lapse
+  useEffect(() => {
+    const unlisten = listen<ExpiredRule>("rule-expired", (event) => {
+      const { pid, kind } = event.payload;
+      if (kind === "Block") {
+        setBlockedPids((prev) => { const next = new Set(prev); next.delete(pid); return next; });
+      } else {
+        setLimits((prev) => { const next = { ...prev }; delete next[pid]; return next; });
+      }
+    });
+    return () => { unlisten.then((fn) => fn()); };
   }, []);
 
   // Focus edit input when editing starts
@@ -78,23 +94,29 @@ export function useTrafficData() {
     else { setSortKey(key); setSortDir("desc"); }
   }, [sortKey]);
 
-  // Apply a bandwidth limit
+  // Apply a bandwidth limit; a temporary limit keeps the time it has left
   const applyLimit = useCallback(async (pid: number, field: "dl" | "ul", value: string) => {
     const bps = parseLimitInput(value);
-    const existing = limits[pid] || { download_bps: 0, upload_bps: 0 };
-    const newLimit = {
+    const current = (await invoke<Record<number, ActiveLimit>>("get_bandwidth_limits"))[pid];
+    const { expires_in_secs, ...existing }: ActiveLimit = current || {
+      download_bps: 0, upload_bps: 0, mode: "Police", guaranteed_download_bps: 0, guaranteed_upload_bps: 0,
+      priority: "Normal", weight: 1, burst_bytes: 0, burst_ms: 0, expires_in_secs: null,
+    };
+    const newLimit: BandwidthLimit = {
+      ...existing,
       download_bps: field === "dl" ? (bps ?? 0) : existing.download_bps,
       upload_bps: field === "ul" ? (bps ?? 0) : existing.upload_bps,
     };
-    if (newLimit.download_bps === 0 && newLimit.upload_bps === 0) {
+    if (newLimit.download_bps === 0 && newLimit.upload_bps === 0
+      && newLimit.guaranteed_download_bps === 0 && newLimit.guaranteed_upload_bps === 0) {
       await invoke("remove_bandwidth_limit", { pid });
       setLimits((prev) => { const next = { ...prev }; delete next[pid]; return next; });
     } else {
-      await invoke("set_bandwidth_limit", { pid, downloadBps: newLimit.download_bps, uploadBps: newLimit.upload_bps });
-      setLimits((prev) => ({ ...prev, [pid]: newLimit }));
+      await invoke("set_bandwidth_limit", { pid, limit: newLimit, durationSecs: expires_in_secs });
+      setLimits((prev) => ({ ...prev, [pid]: { ...newLimit, expires_in_secs } }));
     }
     setEditingCell(null);
-  }, [limits]);
+  }, []);
 
   // Toggle process block
   const toggleBlock = useCallback(async (pid: number) => {